@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE
+  insight_settings (
+    user_id VARCHAR(255) PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    mood_drop_threshold DOUBLE PRECISION NOT NULL DEFAULT 1.0,
+    low_mood_level INTEGER NOT NULL DEFAULT 1,
+    low_mood_days INTEGER NOT NULL DEFAULT 3,
+    notifications_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at BIGINT NOT NULL
+  );
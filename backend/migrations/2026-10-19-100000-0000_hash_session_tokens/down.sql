@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+-- tokens can not be recovered from their hashes, so all sessions are removed
+DELETE FROM sessions;
+DROP INDEX IF EXISTS sessions_token_hash_idx;
+ALTER TABLE sessions DROP COLUMN token_hash;
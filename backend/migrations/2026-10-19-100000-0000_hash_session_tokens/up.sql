@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE sessions ADD COLUMN token_hash VARCHAR(255);
+
+-- existing tokens keep working, their ids are replaced so they are no longer secret
+UPDATE sessions SET token_hash = encode(sha256(id::bytea), 'hex'), id = gen_random_uuid()::text;
+
+ALTER TABLE sessions ALTER COLUMN token_hash SET NOT NULL;
+CREATE UNIQUE INDEX sessions_token_hash_idx ON sessions (token_hash);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE login_attempts (
+  key VARCHAR(255) PRIMARY KEY,
+  failures INT NOT NULL,
+  last_failure_at BIGINT NOT NULL,
+  locked_until BIGINT NOT NULL
+);
+
+CREATE INDEX login_attempts_last_failure_at_idx ON login_attempts (last_failure_at);
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE credentials (
+  id VARCHAR(1400) PRIMARY KEY,
+  user_id VARCHAR(255) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name VARCHAR(255) NOT NULL,
+  public_key VARCHAR(1023) NOT NULL,
+  sign_count BIGINT NOT NULL DEFAULT 0,
+  created_at BIGINT NOT NULL,
+  last_used_at BIGINT
+);
+
+CREATE INDEX credentials_user_id_idx ON credentials (user_id);
+
+CREATE TABLE webauthn_challenges (
+  challenge VARCHAR(255) PRIMARY KEY,
+  user_id VARCHAR(255) REFERENCES users(id) ON DELETE CASCADE,
+  ceremony VARCHAR(32) NOT NULL,
+  expires_at BIGINT NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE used_tokens (
+  token_hash VARCHAR(255) PRIMARY KEY,
+  expires_at BIGINT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE oidc_states;
+DROP TABLE oidc_identities;
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TABLE oidc_identities (
+  provider VARCHAR(255) NOT NULL,
+  subject VARCHAR(255) NOT NULL,
+  user_id VARCHAR(255) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  email VARCHAR(255) NOT NULL,
+  created_at BIGINT NOT NULL,
+  last_used_at BIGINT,
+  PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX oidc_identities_user_id_idx ON oidc_identities (user_id);
+
+CREATE TABLE oidc_states (
+  state VARCHAR(255) PRIMARY KEY,
+  provider VARCHAR(255) NOT NULL,
+  code_verifier VARCHAR(255) NOT NULL,
+  nonce VARCHAR(255) NOT NULL,
+  expires_at BIGINT NOT NULL
+);
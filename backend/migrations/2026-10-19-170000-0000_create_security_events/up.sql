@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE security_events (
+  id VARCHAR(255) PRIMARY KEY,
+  user_id VARCHAR(255) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  kind VARCHAR(255) NOT NULL,
+  created_at BIGINT NOT NULL,
+  ip_address VARCHAR(255) NOT NULL,
+  user_agent VARCHAR(255) NOT NULL,
+  device VARCHAR(255) NOT NULL,
+  location VARCHAR(255),
+  details VARCHAR(255)
+);
+
+CREATE INDEX security_events_user_id_created_at_idx ON security_events (user_id, created_at);
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE insight_notifications (
+  user_id VARCHAR(255) NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  kind VARCHAR(255) NOT NULL,
+  from_date DATE NOT NULL,
+  to_date DATE NOT NULL,
+  PRIMARY KEY (user_id, kind, from_date)
+);
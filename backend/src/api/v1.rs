@@ -1,12 +1,16 @@
+pub mod api_token;
 pub mod auth;
 pub mod category;
 pub mod entries;
 pub mod entry;
 pub mod health;
 pub mod index;
+pub mod insights;
 pub mod metrics;
+pub mod passkey;
 pub mod session;
 pub mod sessions;
 pub mod stats;
 pub mod tag;
+pub mod two_factor;
 pub mod user;
@@ -0,0 +1,87 @@
+use crate::{
+  services::{
+    api_token,
+    api_token::{CreateApiToken, UpdateApiToken},
+    auth::authorize_request,
+    security_event,
+    security_event::SecurityEventKind,
+  },
+  util::{clock::SystemClock, error::error_response, response::response},
+};
+use poem::{
+  handler,
+  http::StatusCode,
+  web::{Json, Path},
+  Request, Response,
+};
+
+/// Lists the user's personal API tokens, browser sessions are listed by
+/// `GET /v1/sessions`
+#[handler]
+pub async fn get_api_tokens(request: &Request) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match api_token::get_user_api_tokens(&session.user_id) {
+    Ok(tokens) => response(StatusCode::OK, &tokens),
+    Err(error) => error_response(error),
+  }
+}
+
+#[handler]
+pub async fn create_api_token(
+  Json(api_token): Json<CreateApiToken>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match api_token::create_api_token(&session.user_id, api_token, &SystemClock) {
+    Ok(api_token) => response(StatusCode::CREATED, &api_token),
+    Err(error) => error_response(error),
+  }
+}
+
+#[handler]
+pub async fn update_api_token(
+  Path(id): Path<String>,
+  Json(update): Json<UpdateApiToken>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match api_token::update_api_token(&session.user_id, &id, update) {
+    Ok(api_token) => response(StatusCode::OK, &api_token),
+    Err(error) => error_response(error),
+  }
+}
+
+#[handler]
+pub async fn delete_api_token(Path(id): Path<String>, request: &Request) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match api_token::revoke_api_token(&session.user_id, &id) {
+    Ok(_) => {
+      security_event::record_request(
+        request,
+        &session.user_id,
+        SecurityEventKind::ApiTokenRevoked,
+        Some(id),
+        &SystemClock,
+      )
+      .await;
+      response(StatusCode::NO_CONTENT, &())
+    }
+    Err(error) => error_response(error),
+  }
+}
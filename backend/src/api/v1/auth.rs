@@ -1,38 +1,178 @@
 use crate::{
   services::{
     auth,
-    auth::{AuthConfig, UserCredentials},
-    user,
+    auth::{AuthConfig, AuthRequest, LoginResult, NewSession, UserCredentials},
+    mail, oidc,
+    oidc::{OidcCallback, OidcConfig},
+    passkey,
+    passkey::{AuthenticationResponse, WebAuthnConfig},
+    password_policy, password_reset,
+    password_reset::{PasswordReset, PasswordResetRequest},
+    security_event,
+    security_event::SecurityEventKind,
+    session_cookie::{self, CookieSession, SessionCookieConfig},
+    two_factor,
   },
   util::{
+    clock::SystemClock,
     error::{error_response, APIError},
     response::response,
   },
 };
 use dotenvy::dotenv;
-use poem::{handler, http::StatusCode, web::Json, Request, Response};
+use poem::{
+  handler,
+  http::{header::SET_COOKIE, HeaderValue, StatusCode},
+  web::{Json, Path},
+  Request, Response,
+};
 use std::env;
 use validator::Validate;
 
+/// Adds a `Set-Cookie` header to a response
+fn with_cookie(mut response: Response, cookie: &str) -> Response {
+  match HeaderValue::from_str(cookie) {
+    Ok(cookie) => {
+      response.headers_mut().append(SET_COOKIE, cookie);
+      response
+    }
+    Err(_) => error_response(APIError::InternalServerError),
+  }
+}
+
+/// Responds with a new session. In cookie mode the token is set as an
+/// HttpOnly cookie and the body has the CSRF token instead
+pub fn session_response(status_code: StatusCode, session: NewSession) -> Response {
+  let config = SessionCookieConfig::from_env();
+  if !config.enabled {
+    return response(status_code, &session);
+  }
+
+  match CookieSession::new(session) {
+    Ok((session, token)) => with_cookie(response(status_code, &session), &config.cookie(&token)),
+    Err(error) => error_response(error),
+  }
+}
+
+/// Logs in with an email and password, or completes a two-factor challenge.
+/// Users with two-factor authentication get a challenge instead of a session
 #[handler]
-pub async fn authenticate_user(Json(user): Json<user::AuthUser>, request: &Request) -> Response {
+pub async fn authenticate_user(Json(body): Json<AuthRequest>, request: &Request) -> Response {
+  let metadata = auth::session_metadata(request).await;
+
+  let user = match body {
+    AuthRequest::Password(user) => user,
+    AuthRequest::Challenge(challenge) => {
+      return match two_factor::complete_challenge(challenge, metadata, &SystemClock) {
+        Ok(session) => session_response(StatusCode::CREATED, session),
+        Err(error) => error_response(error),
+      }
+    }
+  };
+
   match user.validate() {
     Ok(_) => (),
     Err(_) => return error_response(APIError::BadRequest),
   }
 
-  let metadata = auth::session_metadata(request).await;
-
-  let session = auth::create_user_session(
+  let login = auth::log_in(
     UserCredentials {
       email: String::from(&user.email),
       password: String::from(&user.password),
     },
     metadata,
+    &SystemClock,
   );
 
-  match session {
-    Ok(session) => response(StatusCode::CREATED, &session),
+  match login {
+    Ok(LoginResult::Session(session)) => session_response(StatusCode::CREATED, session),
+    Ok(LoginResult::TwoFactorChallenge(challenge)) => response(StatusCode::OK, &challenge),
+    Err(error) => error_response(error),
+  }
+}
+
+/// Returns the options for `navigator.credentials.get()`
+#[handler]
+pub async fn begin_passkey_login() -> Response {
+  match passkey::begin_authentication(&WebAuthnConfig::from_env(), &SystemClock) {
+    Ok(options) => response(StatusCode::OK, &options),
+    Err(error) => error_response(error),
+  }
+}
+
+/// Logs in with a passkey, like a password login a two-factor
+/// challenge is returned when the passkey did not verify the user
+#[handler]
+pub async fn finish_passkey_login(
+  Json(assertion): Json<AuthenticationResponse>,
+  request: &Request,
+) -> Response {
+  let metadata = auth::session_metadata(request).await;
+
+  match passkey::finish_authentication(
+    assertion,
+    metadata,
+    &WebAuthnConfig::from_env(),
+    &SystemClock,
+  ) {
+    Ok(LoginResult::Session(session)) => session_response(StatusCode::CREATED, session),
+    Ok(LoginResult::TwoFactorChallenge(challenge)) => response(StatusCode::OK, &challenge),
+    Err(error) => error_response(error),
+  }
+}
+
+/// Returns the provider URL to send the user to for an OpenID Connect login
+#[handler]
+pub async fn begin_oidc_login(Path(provider): Path<String>) -> Response {
+  match oidc::begin_login(&provider, &OidcConfig::from_env(), &SystemClock).await {
+    Ok(authorization) => with_cookie(
+      response(StatusCode::OK, &authorization),
+      &oidc::state_cookie(&authorization.state_cookie),
+    ),
+    Err(error) => error_response(error),
+  }
+}
+
+/// Logs in with the code a provider redirected back with, creating or
+/// linking the account on first use. Like a passkey login a two-factor
+/// challenge is returned when the user has it enabled
+#[handler]
+pub async fn finish_oidc_login(
+  Json(mut callback): Json<OidcCallback>,
+  request: &Request,
+) -> Response {
+  let metadata = auth::session_metadata(request).await;
+  callback.state_cookie = session_cookie::cookie_value(request, oidc::OIDC_STATE_COOKIE_NAME);
+
+  let response =
+    match oidc::finish_login(callback, metadata, &OidcConfig::from_env(), &SystemClock).await {
+      Ok(LoginResult::Session(session)) => session_response(StatusCode::CREATED, session),
+      Ok(LoginResult::TwoFactorChallenge(challenge)) => response(StatusCode::OK, &challenge),
+      Err(error) => error_response(error),
+    };
+  with_cookie(response, &oidc::removal_state_cookie())
+}
+
+/// Logs out by deleting the session of the request's token,
+/// in cookie mode the session cookie is removed as well
+#[handler]
+pub async fn logout(request: &Request) -> Response {
+  let session = match auth::authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match auth::revoke_user_session(&session.user_id, &session.id) {
+    Ok(_) => {
+      let config = SessionCookieConfig::from_env();
+      match config.enabled {
+        true => with_cookie(
+          response(StatusCode::NO_CONTENT, &""),
+          &config.removal_cookie(),
+        ),
+        false => response(StatusCode::NO_CONTENT, &""),
+      }
+    }
     Err(error) => error_response(error),
   }
 }
@@ -43,7 +183,38 @@ pub async fn auth_config() -> Response {
 
   let auth_config: AuthConfig = AuthConfig {
     invite_required: env::var("INVITE_REQUIRED").unwrap_or("false".to_string()) == "true",
+    password: password_policy::policy().requirements(),
+    oidc_providers: OidcConfig::from_env().provider_info(),
   };
 
   response(StatusCode::OK, &auth_config)
 }
+
+/// Emails a password reset link. Always accepted, so the response does not
+/// reveal whether the email has an account
+#[handler]
+pub async fn request_password_reset(Json(request): Json<PasswordResetRequest>) -> Response {
+  match password_reset::request_password_reset(request, mail::queued_mailer(), &SystemClock) {
+    Ok(_) => response(StatusCode::ACCEPTED, &()),
+    Err(error) => error_response(error),
+  }
+}
+
+/// Sets a new password with the token from a reset link
+#[handler]
+pub async fn reset_password(Json(reset): Json<PasswordReset>, request: &Request) -> Response {
+  match password_reset::reset_password(reset, &SystemClock) {
+    Ok(user_id) => {
+      security_event::record_request(
+        request,
+        &user_id,
+        SecurityEventKind::PasswordReset,
+        None,
+        &SystemClock,
+      )
+      .await;
+      response(StatusCode::NO_CONTENT, &())
+    }
+    Err(error) => error_response(error),
+  }
+}
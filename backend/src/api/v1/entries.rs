@@ -1,6 +1,7 @@
 use crate::{
   services::{
-    auth::authorize_request,
+    api_token::Scope,
+    auth::authorize_request_with_scope,
     entry,
     entry::{EntryOptionsOrder, GetEntriesOptions},
   },
@@ -23,7 +24,7 @@ pub struct EntryParams {
 
 #[handler]
 pub async fn get_entries(Query(_options): Query<EntryParams>, request: &Request) -> Response {
-  let session = match authorize_request(request).await {
+  let session = match authorize_request_with_scope(request, Scope::EntriesRead).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
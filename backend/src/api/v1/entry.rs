@@ -1,8 +1,10 @@
 use crate::{
   services::{
-    auth::authorize_request,
+    api_token::Scope,
+    auth::authorize_request_with_scope,
     entry,
     entry::{CreateEntry, EditEntry},
+    insights::notify_insights,
   },
   util::{
     error::{error_response, APIError},
@@ -35,7 +37,7 @@ struct EditEntryRequest {
 
 #[handler]
 pub async fn create_entry(Json(entry): Json<CreateEntryRequest>, request: &Request) -> Response {
-  let session = match authorize_request(request).await {
+  let session = match authorize_request_with_scope(request, Scope::EntriesWrite).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
@@ -45,11 +47,14 @@ pub async fn create_entry(Json(entry): Json<CreateEntryRequest>, request: &Reque
     mood: entry.mood,
     entry: entry.entry,
     selected_tags: entry.selected_tags,
-    user_id: session.user_id,
+    user_id: session.user_id.clone(),
   });
 
   match created_entry {
-    Ok(created_entry) => response(StatusCode::CREATED, &created_entry),
+    Ok(created_entry) => {
+      notify_insights(&session.user_id).ok();
+      response(StatusCode::CREATED, &created_entry)
+    }
     Err(error) => error_response(error),
   }
 }
@@ -60,7 +65,7 @@ pub async fn edit_entry(
   Json(entry): Json<EditEntryRequest>,
   request: &Request,
 ) -> Response {
-  let session = match authorize_request(request).await {
+  let session = match authorize_request_with_scope(request, Scope::EntriesWrite).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
@@ -71,18 +76,21 @@ pub async fn edit_entry(
     mood: entry.mood,
     entry: entry.entry,
     selected_tags: entry.selected_tags,
-    user_id: session.user_id,
+    user_id: session.user_id.clone(),
   });
 
   match edited_entry {
-    Ok(edited_entry) => response(StatusCode::OK, &edited_entry),
+    Ok(edited_entry) => {
+      notify_insights(&session.user_id).ok();
+      response(StatusCode::OK, &edited_entry)
+    }
     Err(error) => error_response(error),
   }
 }
 
 #[handler]
 pub async fn delete_entry(Path(id): Path<String>, request: &Request) -> Response {
-  let session = match authorize_request(request).await {
+  let session = match authorize_request_with_scope(request, Scope::EntriesWrite).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
@@ -1,8 +1,15 @@
-use crate::api::v1;
-use poem::{delete, get, patch, post, Route};
+use crate::{
+  api::v1,
+  middleware::rate_limit::{LoginRateLimit, MailRateLimit},
+};
+use poem::{delete, get, patch, post, EndpointExt, Route};
 
 #[rustfmt::skip]
 pub fn endpoint() -> poem::Route {
+  // one limiter per kind, so attempts on different routes count together
+  let login_rate_limit = LoginRateLimit::from_env();
+  let mail_rate_limit = MailRateLimit::from_env();
+
   Route::new()
     .at("/user", post(v1::user::create_user)
     .patch(v1::user::update_user)
@@ -11,6 +18,14 @@ pub fn endpoint() -> poem::Route {
     )
 
     .at("/user/password", patch(v1::user::update_password))
+    .at("/user/security-events", get(v1::user::get_security_events))
+    .at("/user/verify-email", post(v1::user::send_verification_email.with(mail_rate_limit.clone())))
+    .at("/user/verify-email/confirm", post(v1::user::verify_email))
+
+    .at("/user/2fa", get(v1::two_factor::get_status)
+    .post(v1::two_factor::begin_enrolment)
+    .delete(v1::two_factor::disable))
+    .at("/user/2fa/verify", post(v1::two_factor::confirm_enrolment))
 
     .at("/user/categories", get(v1::user::get_user_categories_with_tags))
 
@@ -28,17 +43,50 @@ pub fn endpoint() -> poem::Route {
     .at("/entries", get(v1::entries::get_entries))
 
     .at("/session/:id", delete(v1::session::delete_session))
-    .at("/sessions", get(v1::sessions::get_sessions))
+    .at("/sessions", get(v1::sessions::get_sessions)
+    .delete(v1::sessions::delete_sessions))
+
+    .at("/token/:id", patch(v1::api_token::update_api_token)
+    .delete(v1::api_token::delete_api_token))
+    .at("/tokens", get(v1::api_token::get_api_tokens)
+    .post(v1::api_token::create_api_token))
 
-    .at("/auth", post(v1::auth::authenticate_user))
+    .at("/passkey/:id", delete(v1::passkey::delete_passkey))
+    .at("/passkeys", get(v1::passkey::get_passkeys))
+    .at("/passkeys/register/begin", post(v1::passkey::begin_registration))
+    .at("/passkeys/register/finish", post(v1::passkey::finish_registration))
+
+    .at("/auth", post(v1::auth::authenticate_user.with(login_rate_limit.clone()))
+    .delete(v1::auth::logout))
     .at("/auth/config", get(v1::auth::auth_config))
+    .at("/auth/oidc/finish", post(v1::auth::finish_oidc_login.with(login_rate_limit.clone())))
+    .at("/auth/oidc/:provider/begin", post(v1::auth::begin_oidc_login))
+    .at("/auth/passkey/begin", post(v1::auth::begin_passkey_login))
+    .at("/auth/passkey/finish", post(v1::auth::finish_passkey_login.with(login_rate_limit.clone())))
+    .at("/auth/password-reset", post(v1::auth::request_password_reset.with(mail_rate_limit.clone())))
+    .at("/auth/password-reset/confirm", post(v1::auth::reset_password))
 
     .at("/stats/mood", get(v1::stats::mood_stats))
     .at("/stats/mood/count", get(v1::stats::mood_stats_with_count))
     .at("/stats/tags", get(v1::stats::tag_stats))
     .at("/stats/tags/count", get(v1::stats::tag_stats_with_count))
+    .at("/stats/tags/categories", get(v1::stats::tag_stats_by_category))
+    .at("/stats/tags/categories/count", get(v1::stats::tag_stats_with_count_by_category))
+    .at("/stats/tags/pairs", get(v1::stats::tag_cooccurrence))
+    .at("/stats/tags/lagged", get(v1::stats::lagged_tag_stats))
+    .at("/stats/categories", get(v1::stats::category_stats))
+    .at("/stats/categories/count", get(v1::stats::category_stats_with_count))
+    .at("/stats/compare", get(v1::stats::compare_periods))
+    .at("/stats/words", get(v1::stats::word_stats))
     .at("/stats/weekday", get(v1::stats::weekday_stats))
     .at("/stats/weekday/count", get(v1::stats::weekday_stats_with_count))
+    .at("/stats/calendar", get(v1::stats::calendar_stats))
+    .at("/stats/review/:year", get(v1::stats::year_review))
+    .at("/stats/review/:year/html", get(v1::stats::year_review_html))
+
+    .at("/insights", get(v1::insights::get_insights))
+    .at("/insights/settings", get(v1::insights::get_insight_settings)
+    .patch(v1::insights::update_insight_settings))
 
     .at("/metrics", get(v1::metrics::metrics))
     .at("/health", get(v1::health::health))
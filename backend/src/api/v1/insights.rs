@@ -0,0 +1,52 @@
+use crate::{
+  services::{
+    api_token::Scope,
+    auth::{authorize_request, authorize_request_with_scope},
+    insights,
+    insights::UpdateInsightSettings,
+  },
+  util::{error::error_response, response::response},
+};
+use poem::{handler, http::StatusCode, web::Json, Request, Response};
+
+#[handler]
+pub async fn get_insights(request: &Request) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match insights::active_insights(&session.user_id) {
+    Ok(insights) => response(StatusCode::OK, &insights),
+    Err(error) => error_response(error),
+  }
+}
+
+#[handler]
+pub async fn get_insight_settings(request: &Request) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match insights::get_insight_settings(&session.user_id) {
+    Ok(settings) => response(StatusCode::OK, &settings),
+    Err(error) => error_response(error),
+  }
+}
+
+#[handler]
+pub async fn update_insight_settings(
+  Json(settings): Json<UpdateInsightSettings>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match insights::update_insight_settings(&session.user_id, settings) {
+    Ok(settings) => response(StatusCode::OK, &settings),
+    Err(error) => error_response(error),
+  }
+}
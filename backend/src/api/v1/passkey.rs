@@ -0,0 +1,75 @@
+use crate::{
+  services::{
+    auth::authorize_request,
+    passkey,
+    passkey::{RegistrationResponse, WebAuthnConfig},
+  },
+  util::{clock::SystemClock, error::error_response, response::response},
+};
+use poem::{
+  handler,
+  http::StatusCode,
+  web::{Json, Path},
+  Request, Response,
+};
+
+#[handler]
+pub async fn get_passkeys(request: &Request) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match passkey::get_user_credentials(&session.user_id) {
+    Ok(credentials) => response(StatusCode::OK, &credentials),
+    Err(error) => error_response(error),
+  }
+}
+
+/// Returns the options for `navigator.credentials.create()`
+#[handler]
+pub async fn begin_registration(request: &Request) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match passkey::begin_registration(&session.user_id, &WebAuthnConfig::from_env(), &SystemClock) {
+    Ok(options) => response(StatusCode::OK, &options),
+    Err(error) => error_response(error),
+  }
+}
+
+#[handler]
+pub async fn finish_registration(
+  Json(registration): Json<RegistrationResponse>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match passkey::finish_registration(
+    &session.user_id,
+    registration,
+    &WebAuthnConfig::from_env(),
+    &SystemClock,
+  ) {
+    Ok(credential) => response(StatusCode::CREATED, &credential),
+    Err(error) => error_response(error),
+  }
+}
+
+#[handler]
+pub async fn delete_passkey(Path(id): Path<String>, request: &Request) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match passkey::revoke_credential(&session.user_id, &id) {
+    Ok(_) => response(StatusCode::NO_CONTENT, &()),
+    Err(error) => error_response(error),
+  }
+}
@@ -1,18 +1,28 @@
 use crate::{
-  services::{auth, auth::authorize_request},
-  util::{error::error_response, response::response},
+  services::{auth, auth::authorize_request, security_event, security_event::SecurityEventKind},
+  util::{clock::SystemClock, error::error_response, response::response},
 };
 use poem::{handler, http::StatusCode, web::Path, Request, Response};
 
 #[handler]
 pub async fn delete_session(Path(id): Path<String>, request: &Request) -> Response {
-  match authorize_request(request).await {
+  let session = match authorize_request(request).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
 
-  match auth::delete_user_session(&id) {
-    Ok(_) => response(StatusCode::NO_CONTENT, &""),
+  match auth::revoke_user_session(&session.user_id, &id) {
+    Ok(_) => {
+      security_event::record_request(
+        request,
+        &session.user_id,
+        SecurityEventKind::SessionRevoked,
+        Some(id),
+        &SystemClock,
+      )
+      .await;
+      response(StatusCode::NO_CONTENT, &"")
+    }
     Err(error) => error_response(error),
   }
 }
@@ -1,8 +1,19 @@
 use crate::{
-  services::{auth, auth::authorize_request},
-  util::{error::error_response, response::response},
+  services::{auth, auth::authorize_request, security_event, security_event::SecurityEventKind},
+  util::{
+    clock::SystemClock,
+    error::{error_response, APIError},
+    response::response,
+  },
 };
-use poem::{handler, http::StatusCode, Request, Response};
+use poem::{handler, http::StatusCode, web::Query, Request, Response};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteSessionsParams {
+  /// Only `current` is supported, keeps the session making the request
+  pub except: Option<String>,
+}
 
 #[handler]
 pub async fn get_sessions(request: &Request) -> Response {
@@ -16,3 +27,40 @@ pub async fn get_sessions(request: &Request) -> Response {
     Err(error) => error_response(error),
   }
 }
+
+#[handler]
+pub async fn delete_sessions(
+  Query(params): Query<DeleteSessionsParams>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  let except = match params.except.as_deref() {
+    None => None,
+    Some("current") => Some(session.id.as_str()),
+    Some(_) => return error_response(APIError::BadRequest),
+  };
+
+  let details = match except {
+    Some(_) => "other sessions",
+    None => "all sessions",
+  };
+
+  match auth::delete_all_user_sessions(&session.user_id, except) {
+    Ok(_) => {
+      security_event::record_request(
+        request,
+        &session.user_id,
+        SecurityEventKind::SessionsRevoked,
+        Some(details.to_string()),
+        &SystemClock,
+      )
+      .await;
+      response(StatusCode::NO_CONTENT, &"")
+    }
+    Err(error) => error_response(error),
+  }
+}
@@ -1,83 +1,314 @@
 use crate::{
-  services::{auth::authorize_request, stats},
-  util::{error::error_response, response::response},
+  services::{
+    api_token::Scope, auth::authorize_request_with_scope, review, stats, stats::StatsOptions,
+    stats_cache, text_stats,
+  },
+  util::{
+    error::{error_response, APIError},
+    response::{conditional_attachment_response, conditional_response},
+  },
 };
-use poem::{handler, http::StatusCode, Request, Response};
+use chrono::Datelike;
+use poem::{
+  handler,
+  web::{Path, Query},
+  Request, Response,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarParams {
+  pub year: Option<i32>,
+  pub tags: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompareParams {
+  pub first_from_date: Option<String>,
+  pub first_to_date: Option<String>,
+  pub second_from_date: Option<String>,
+  pub second_to_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LaggedStatsParams {
+  pub lag: Option<i32>,
+  pub from_date: Option<String>,
+  pub to_date: Option<String>,
+  pub category_id: Option<String>,
+}
+
+/// Stats are cached by the request path and query
+fn cache_key(request: &Request) -> &str {
+  match request.uri().path_and_query() {
+    Some(path_and_query) => path_and_query.as_str(),
+    None => request.uri().path(),
+  }
+}
+
+/// Respond with stats from the per-user stats cache
+fn cached_response<T: Serialize>(
+  request: &Request,
+  user_id: &str,
+  compute: impl FnOnce() -> Result<T, APIError>,
+) -> Response {
+  match stats_cache::cached(user_id, cache_key(request), compute) {
+    Ok(stats) => conditional_response(request, stats.body, &stats.etag, stats.last_modified),
+    Err(error) => error_response(error),
+  }
+}
 
 #[handler]
 pub async fn mood_stats(request: &Request) -> Response {
-  let session = match authorize_request(request).await {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
 
-  match stats::mood_stats(&session.user_id) {
-    Ok(mood_stats) => response(StatusCode::OK, &mood_stats),
-    Err(error) => error_response(error),
-  }
+  cached_response(request, &session.user_id, || {
+    stats::mood_stats(&session.user_id)
+  })
 }
 
 #[handler]
 pub async fn mood_stats_with_count(request: &Request) -> Response {
-  let session = match authorize_request(request).await {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
 
-  match stats::mood_stats_with_count(&session.user_id) {
-    Ok(mood_stats_with_count) => response(StatusCode::OK, &mood_stats_with_count),
-    Err(error) => error_response(error),
-  }
+  cached_response(request, &session.user_id, || {
+    stats::mood_stats_with_count(&session.user_id)
+  })
 }
 
 #[handler]
-pub async fn tag_stats(request: &Request) -> Response {
-  let session = match authorize_request(request).await {
+pub async fn tag_stats(Query(options): Query<StatsOptions>, request: &Request) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
 
-  match stats::tag_stats(&session.user_id) {
-    Ok(tag_stats) => response(StatusCode::OK, &tag_stats),
-    Err(error) => error_response(error),
-  }
+  cached_response(request, &session.user_id, || {
+    stats::tag_stats(&session.user_id, options)
+  })
 }
 
 #[handler]
-pub async fn tag_stats_with_count(request: &Request) -> Response {
-  let session = match authorize_request(request).await {
+pub async fn tag_stats_with_count(
+  Query(options): Query<StatsOptions>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
 
-  match stats::tag_stats_with_count(&session.user_id) {
-    Ok(tag_stats_with_count) => response(StatusCode::OK, &tag_stats_with_count),
-    Err(error) => error_response(error),
-  }
+  cached_response(request, &session.user_id, || {
+    stats::tag_stats_with_count(&session.user_id, options)
+  })
+}
+
+#[handler]
+pub async fn tag_stats_by_category(
+  Query(options): Query<StatsOptions>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  cached_response(request, &session.user_id, || {
+    stats::tag_stats_by_category(&session.user_id, options)
+  })
+}
+
+#[handler]
+pub async fn tag_stats_with_count_by_category(
+  Query(options): Query<StatsOptions>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  cached_response(request, &session.user_id, || {
+    stats::tag_stats_with_count_by_category(&session.user_id, options)
+  })
+}
+
+#[handler]
+pub async fn category_stats(Query(options): Query<StatsOptions>, request: &Request) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  cached_response(request, &session.user_id, || {
+    stats::category_stats(&session.user_id, options)
+  })
+}
+
+#[handler]
+pub async fn category_stats_with_count(
+  Query(options): Query<StatsOptions>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  cached_response(request, &session.user_id, || {
+    stats::category_stats_with_count(&session.user_id, options)
+  })
+}
+
+#[handler]
+pub async fn tag_cooccurrence(Query(options): Query<StatsOptions>, request: &Request) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  cached_response(request, &session.user_id, || {
+    stats::tag_cooccurrence(&session.user_id, options)
+  })
+}
+
+#[handler]
+pub async fn lagged_tag_stats(
+  Query(params): Query<LaggedStatsParams>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  let options = StatsOptions {
+    from_date: params.from_date,
+    to_date: params.to_date,
+    category_id: params.category_id,
+  };
+
+  cached_response(request, &session.user_id, || {
+    stats::lagged_tag_stats(&session.user_id, params.lag.unwrap_or(1), options)
+  })
+}
+
+#[handler]
+pub async fn compare_periods(Query(params): Query<CompareParams>, request: &Request) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  cached_response(request, &session.user_id, || {
+    stats::compare_periods(
+      &session.user_id,
+      StatsOptions {
+        from_date: params.first_from_date,
+        to_date: params.first_to_date,
+        category_id: None,
+      },
+      StatsOptions {
+        from_date: params.second_from_date,
+        to_date: params.second_to_date,
+        category_id: None,
+      },
+    )
+  })
+}
+
+#[handler]
+pub async fn word_stats(Query(options): Query<StatsOptions>, request: &Request) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  cached_response(request, &session.user_id, || {
+    text_stats::text_stats(&session.user_id, options)
+  })
 }
 
 #[handler]
 pub async fn weekday_stats(request: &Request) -> Response {
-  let session = match authorize_request(request).await {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
 
-  match stats::weekday_stats(&session.user_id) {
-    Ok(weekday_stats) => response(StatusCode::OK, &weekday_stats),
-    Err(error) => error_response(error),
-  }
+  cached_response(request, &session.user_id, || {
+    stats::weekday_stats(&session.user_id)
+  })
 }
 
 #[handler]
 pub async fn weekday_stats_with_count(request: &Request) -> Response {
-  let session = match authorize_request(request).await {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  cached_response(request, &session.user_id, || {
+    stats::weekday_stats_with_count(&session.user_id)
+  })
+}
+
+#[handler]
+pub async fn calendar_stats(Query(params): Query<CalendarParams>, request: &Request) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  let year = params.year.unwrap_or_else(|| chrono::Utc::now().year());
+  let tags = params
+    .tags
+    .map(|tags| tags.split(',').map(|s| s.to_string()).collect());
+
+  cached_response(request, &session.user_id, || {
+    stats::calendar_stats(&session.user_id, year, tags)
+  })
+}
+
+#[handler]
+pub async fn year_review(Path(year): Path<i32>, request: &Request) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::StatsRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  cached_response(request, &session.user_id, || {
+    review::year_review(&session.user_id, year)
+  })
+}
+
+/// The year in review as a downloadable page. It needs `Scope::Export` rather
+/// than `Scope::StatsRead` like the json review, as the page is a standalone
+/// file meant to be shared outside the app, with the tag names and colors
+#[handler]
+pub async fn year_review_html(Path(year): Path<i32>, request: &Request) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::Export).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
 
-  match stats::weekday_stats_with_count(&session.user_id) {
-    Ok(weekday_stats_with_count) => response(StatusCode::OK, &weekday_stats_with_count),
+  match stats_cache::cached_body(&session.user_id, cache_key(request), || {
+    review::year_review_html(&session.user_id, year)
+  }) {
+    Ok(html) => conditional_attachment_response(
+      request,
+      "text/html; charset=utf-8",
+      &format!("diary-{year}.html"),
+      html.body,
+      &html.etag,
+      html.last_modified,
+    ),
     Err(error) => error_response(error),
   }
 }
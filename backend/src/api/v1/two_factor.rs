@@ -0,0 +1,67 @@
+use crate::{
+  services::{auth::authorize_request, two_factor},
+  util::{clock::SystemClock, error::error_response, response::response},
+};
+use poem::{handler, http::StatusCode, web::Json, Request, Response};
+
+#[handler]
+pub async fn get_status(request: &Request) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match two_factor::status(&session.user_id) {
+    Ok(status) => response(StatusCode::OK, &status),
+    Err(error) => error_response(error),
+  }
+}
+
+/// Starts enrolment, 2FA is enabled once a code is confirmed
+#[handler]
+pub async fn begin_enrolment(request: &Request) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match two_factor::begin_enrolment(&session.user_id, &SystemClock) {
+    Ok(enrolment) => response(StatusCode::CREATED, &enrolment),
+    Err(error) => error_response(error),
+  }
+}
+
+/// Enables 2FA with a code from the authenticator app, returns the recovery codes
+#[handler]
+pub async fn confirm_enrolment(
+  Json(code): Json<two_factor::TotpCode>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match two_factor::confirm_enrolment(&session.user_id, &code.code, &SystemClock) {
+    Ok(recovery_codes) => response(StatusCode::OK, &recovery_codes),
+    Err(error) => error_response(error),
+  }
+}
+
+#[handler]
+pub async fn disable(
+  body: Option<Json<two_factor::DisableTwoFactor>>,
+  request: &Request,
+) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  let body = body.map(|Json(body)| body).unwrap_or_default();
+
+  match two_factor::disable(&session.user_id, body.current_password.as_deref()) {
+    Ok(_) => response(StatusCode::NO_CONTENT, &()),
+    Err(error) => error_response(error),
+  }
+}
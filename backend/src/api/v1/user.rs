@@ -1,18 +1,37 @@
 use crate::{
+  api::v1::auth::session_response,
   services::{
+    api_token::Scope,
     auth,
-    auth::{authorize_request, UserCredentials},
-    category, invite, user,
+    auth::{authorize_request, authorize_request_with_scope, UserCredentials},
+    category, email_verification,
+    email_verification::VerifyEmail,
+    invite, mail, security_event,
+    security_event::SecurityEventKind,
+    user,
   },
   util::{
+    clock::SystemClock,
     error::{error_response, APIError},
     response::response,
   },
 };
 use dotenvy::dotenv;
-use poem::{handler, http::StatusCode, web::Json, Request, Response};
+use poem::{
+  handler,
+  http::StatusCode,
+  web::{Json, Query},
+  Request, Response,
+};
+use serde::Deserialize;
 use std::env;
 
+#[derive(Debug, Deserialize)]
+pub struct SecurityEventParams {
+  pub limit: Option<i64>,
+  pub offset: Option<i64>,
+}
+
 #[handler]
 pub async fn create_user(Json(user): Json<user::CreateUser>, request: &Request) -> Response {
   dotenv().ok();
@@ -28,11 +47,38 @@ pub async fn create_user(Json(user): Json<user::CreateUser>, request: &Request)
   }
 
   let password = user.password.clone();
+  let email = user.email.clone();
   let created_user = match user::create_user(user) {
     Ok(user) => user,
+    Err(APIError::EmailAlreadyInUse) if user::hide_account_existence() => {
+      // hash and email anyway, so existing emails take as long as new accounts
+      auth::hash_password(&password).ok();
+      let notice = user::get_user_id(&email).and_then(|existing_user_id| {
+        email_verification::send_email_in_use_notice(&existing_user_id, mail::queued_mailer())
+      });
+      if notice.is_err() {
+        tracing::event!(tracing::Level::ERROR, "could not send email in use notice");
+      }
+      return response(StatusCode::ACCEPTED, &());
+    }
     Err(error) => return error_response(error),
   };
 
+  // the account works without a verified email, so a failed email is not an error
+  if email_verification::send_verification_email(
+    &created_user.id,
+    mail::queued_mailer(),
+    &SystemClock,
+  )
+  .is_err()
+  {
+    tracing::event!(tracing::Level::ERROR, "could not send verification email");
+  }
+
+  if user::hide_account_existence() {
+    return response(StatusCode::ACCEPTED, &());
+  }
+
   match auth::create_user_session(
     UserCredentials {
       email: created_user.email,
@@ -40,7 +86,7 @@ pub async fn create_user(Json(user): Json<user::CreateUser>, request: &Request)
     },
     auth::session_metadata(request).await,
   ) {
-    Ok(session) => response(StatusCode::CREATED, &session),
+    Ok(session) => session_response(StatusCode::CREATED, session),
     Err(error) => error_response(error),
   }
 }
@@ -59,13 +105,15 @@ pub async fn get_current_user(request: &Request) -> Response {
 }
 
 #[handler]
-pub async fn delete_user(request: &Request) -> Response {
+pub async fn delete_user(body: Option<Json<user::DeleteUser>>, request: &Request) -> Response {
   let session = match authorize_request(request).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
 
-  match user::delete_user(&session.user_id) {
+  let user = body.map(|Json(user)| user).unwrap_or_default();
+
+  match user::delete_user_with_password(&session.user_id, user) {
     Ok(deleted) => match deleted {
       true => response(StatusCode::NO_CONTENT, &()),
       false => error_response(APIError::UserNotFound),
@@ -81,11 +129,61 @@ pub async fn update_user(Json(user): Json<user::UpdateUser>, request: &Request)
     Err(error) => return error_response(error),
   };
 
-  match user::update_user(&session.user_id, user) {
-    Ok(user) => match user {
-      true => response(StatusCode::NO_CONTENT, &()),
-      false => error_response(APIError::UserNotFound),
-    },
+  let previous_email = match user::get_user(&session.user_id) {
+    Ok(user) => user.email,
+    Err(error) => return error_response(error),
+  };
+
+  match user::update_user(&session.user_id, user, user::email_in_use_mailer()) {
+    Ok(true) => (),
+    Ok(false) => return error_response(APIError::UserNotFound),
+    Err(error) => return error_response(error),
+  }
+
+  // an email in use is left unchanged when HIDE_ACCOUNT_EXISTENCE is set
+  let email_changed = match user::get_user(&session.user_id) {
+    Ok(user) => previous_email != user.email,
+    Err(error) => return error_response(error),
+  };
+
+  if email_changed
+    && email_verification::send_verification_email(
+      &session.user_id,
+      mail::queued_mailer(),
+      &SystemClock,
+    )
+    .is_err()
+  {
+    tracing::event!(tracing::Level::ERROR, "could not send verification email");
+  }
+
+  response(StatusCode::NO_CONTENT, &())
+}
+
+/// Sends a new verification link to the user's email
+#[handler]
+pub async fn send_verification_email(request: &Request) -> Response {
+  let session = match authorize_request(request).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
+  match email_verification::send_verification_email(
+    &session.user_id,
+    mail::queued_mailer(),
+    &SystemClock,
+  ) {
+    Ok(_) => response(StatusCode::ACCEPTED, &()),
+    Err(error) => error_response(error),
+  }
+}
+
+/// Verifies an email with the token from a verification link, works without a
+/// session so the link can be opened on any device
+#[handler]
+pub async fn verify_email(Json(verification): Json<VerifyEmail>) -> Response {
+  match email_verification::verify_email(verification, &SystemClock) {
+    Ok(_) => response(StatusCode::NO_CONTENT, &()),
     Err(error) => error_response(error),
   }
 }
@@ -100,22 +198,49 @@ pub async fn update_password(
     Err(error) => return error_response(error),
   };
 
-  match user::update_password(&session.user_id, password) {
+  match user::update_password(&session.user_id, password, Some(&session.id)) {
     Ok(updated) => match updated {
-      true => response(StatusCode::NO_CONTENT, &()),
+      true => {
+        security_event::record_request(
+          request,
+          &session.user_id,
+          SecurityEventKind::PasswordChanged,
+          None,
+          &SystemClock,
+        )
+        .await;
+        response(StatusCode::NO_CONTENT, &())
+      }
       false => error_response(APIError::UserNotFound),
     },
     Err(error) => error_response(error),
   }
 }
 
+/// Lists the user's logins, password changes and revocations, newest first
 #[handler]
-pub async fn get_user_categories_with_tags(request: &Request) -> Response {
+pub async fn get_security_events(
+  Query(params): Query<SecurityEventParams>,
+  request: &Request,
+) -> Response {
   let session = match authorize_request(request).await {
     Ok(session) => session,
     Err(error) => return error_response(error),
   };
 
+  match security_event::get_security_events(&session.user_id, params.limit, params.offset) {
+    Ok(events) => response(StatusCode::OK, &events),
+    Err(error) => error_response(error),
+  }
+}
+
+#[handler]
+pub async fn get_user_categories_with_tags(request: &Request) -> Response {
+  let session = match authorize_request_with_scope(request, Scope::EntriesRead).await {
+    Ok(session) => session,
+    Err(error) => return error_response(error),
+  };
+
   match category::get_user_categories_with_tags(&session.user_id) {
     Ok(categories) => response(StatusCode::OK, &categories),
     Err(error) => error_response(error),
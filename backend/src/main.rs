@@ -1,4 +1,4 @@
-use diarycomputer::{api, middleware, run_migrations};
+use diarycomputer::{api, middleware, run_migrations, services};
 use dotenvy::dotenv;
 use poem::{
   endpoint::StaticFilesEndpoint,
@@ -54,15 +54,132 @@ async fn main() -> Result<(), std::io::Error> {
       Ok(_) => tracing::event!(tracing::Level::DEBUG, "ENVIRONMENT: SET"),
       Err(_) => tracing::event!(tracing::Level::DEBUG, "ENVIRONMENT: NOT SET"),
     }
+    // PASSWORD_HASHER
+    match env::var("PASSWORD_HASHER") {
+      Ok(hasher) => tracing::event!(tracing::Level::DEBUG, "PASSWORD_HASHER: {hasher}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "PASSWORD_HASHER: NOT SET"),
+    }
+    // ARGON2_MEMORY_KIB
+    match env::var("ARGON2_MEMORY_KIB") {
+      Ok(memory) => tracing::event!(tracing::Level::DEBUG, "ARGON2_MEMORY_KIB: {memory}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "ARGON2_MEMORY_KIB: NOT SET"),
+    }
     // BCRYPT_COST
     match env::var("BCRYPT_COST") {
       Ok(_) => tracing::event!(tracing::Level::DEBUG, "BCRYPT_COST: SET"),
       Err(_) => tracing::event!(tracing::Level::DEBUG, "BCRYPT_COST: NOT SET"),
     }
+    // PASSWORD_MIN_LENGTH
+    match env::var("PASSWORD_MIN_LENGTH") {
+      Ok(length) => tracing::event!(tracing::Level::DEBUG, "PASSWORD_MIN_LENGTH: {length}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "PASSWORD_MIN_LENGTH: NOT SET"),
+    }
+    // PASSWORD_MIN_SCORE
+    match env::var("PASSWORD_MIN_SCORE") {
+      Ok(score) => tracing::event!(tracing::Level::DEBUG, "PASSWORD_MIN_SCORE: {score}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "PASSWORD_MIN_SCORE: NOT SET"),
+    }
+    // BREACHED_PASSWORDS_FILE
+    match env::var("BREACHED_PASSWORDS_FILE") {
+      Ok(path) => tracing::event!(tracing::Level::DEBUG, "BREACHED_PASSWORDS_FILE: {path}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "BREACHED_PASSWORDS_FILE: NOT SET"),
+    }
+    // HIDE_ACCOUNT_EXISTENCE
+    match env::var("HIDE_ACCOUNT_EXISTENCE") {
+      Ok(val) => tracing::event!(tracing::Level::DEBUG, "HIDE_ACCOUNT_EXISTENCE: {val}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "HIDE_ACCOUNT_EXISTENCE: NOT SET"),
+    }
+    // SESSION_MAX_AGE
+    match env::var("SESSION_MAX_AGE") {
+      Ok(_) => tracing::event!(tracing::Level::DEBUG, "SESSION_MAX_AGE: SET"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "SESSION_MAX_AGE: NOT SET"),
+    }
+    // SESSION_IDLE_TIMEOUT
+    match env::var("SESSION_IDLE_TIMEOUT") {
+      Ok(_) => tracing::event!(tracing::Level::DEBUG, "SESSION_IDLE_TIMEOUT: SET"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "SESSION_IDLE_TIMEOUT: NOT SET"),
+    }
+    // SESSION_COOKIE
+    match env::var("SESSION_COOKIE") {
+      Ok(val) => tracing::event!(tracing::Level::DEBUG, "SESSION_COOKIE: {val}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "SESSION_COOKIE: NOT SET"),
+    }
+    // TRUSTED_ORIGINS
+    match env::var("TRUSTED_ORIGINS") {
+      Ok(origins) => tracing::event!(tracing::Level::DEBUG, "TRUSTED_ORIGINS: {origins}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "TRUSTED_ORIGINS: NOT SET"),
+    }
+    // LOGIN_RATE_LIMIT_STORE
+    match env::var("LOGIN_RATE_LIMIT_STORE") {
+      Ok(store) => tracing::event!(tracing::Level::DEBUG, "LOGIN_RATE_LIMIT_STORE: {store}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "LOGIN_RATE_LIMIT_STORE: NOT SET"),
+    }
+    // TRUSTED_PROXIES
+    match env::var("TRUSTED_PROXIES") {
+      Ok(proxies) => tracing::event!(tracing::Level::DEBUG, "TRUSTED_PROXIES: {proxies}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "TRUSTED_PROXIES: NOT SET"),
+    }
+    // WEBAUTHN_RP_ID
+    match env::var("WEBAUTHN_RP_ID") {
+      Ok(rp_id) => tracing::event!(tracing::Level::DEBUG, "WEBAUTHN_RP_ID: {rp_id}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "WEBAUTHN_RP_ID: NOT SET"),
+    }
+    // WEBAUTHN_ORIGINS
+    match env::var("WEBAUTHN_ORIGINS") {
+      Ok(origins) => tracing::event!(tracing::Level::DEBUG, "WEBAUTHN_ORIGINS: {origins}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "WEBAUTHN_ORIGINS: NOT SET"),
+    }
+    // MAIL_TRANSPORT
+    match env::var("MAIL_TRANSPORT") {
+      Ok(transport) => tracing::event!(tracing::Level::DEBUG, "MAIL_TRANSPORT: {transport}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "MAIL_TRANSPORT: NOT SET"),
+    }
+    // SMTP_HOST
+    match env::var("SMTP_HOST") {
+      Ok(_) => tracing::event!(tracing::Level::DEBUG, "SMTP_HOST: SET"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "SMTP_HOST: NOT SET"),
+    }
+    // SMTP_TLS
+    match env::var("SMTP_TLS") {
+      Ok(tls) => tracing::event!(tracing::Level::DEBUG, "SMTP_TLS: {tls}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "SMTP_TLS: NOT SET"),
+    }
+    // GEOIP_DATABASE
+    match env::var("GEOIP_DATABASE") {
+      Ok(path) => tracing::event!(tracing::Level::DEBUG, "GEOIP_DATABASE: {path}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "GEOIP_DATABASE: NOT SET"),
+    }
+    // NEW_DEVICE_EMAIL
+    match env::var("NEW_DEVICE_EMAIL") {
+      Ok(val) => tracing::event!(tracing::Level::DEBUG, "NEW_DEVICE_EMAIL: {val}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "NEW_DEVICE_EMAIL: NOT SET"),
+    }
+    // APP_URL
+    match env::var("APP_URL") {
+      Ok(url) => tracing::event!(tracing::Level::DEBUG, "APP_URL: {url}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "APP_URL: NOT SET"),
+    }
+    // OIDC_PROVIDERS
+    match env::var("OIDC_PROVIDERS") {
+      Ok(providers) => tracing::event!(tracing::Level::DEBUG, "OIDC_PROVIDERS: {providers}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "OIDC_PROVIDERS: NOT SET"),
+    }
+    // OIDC_REDIRECT_URL
+    match env::var("OIDC_REDIRECT_URL") {
+      Ok(url) => tracing::event!(tracing::Level::DEBUG, "OIDC_REDIRECT_URL: {url}"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "OIDC_REDIRECT_URL: NOT SET"),
+    }
+    // TOKEN_SECRET
+    match env::var("TOKEN_SECRET") {
+      Ok(_) => tracing::event!(tracing::Level::DEBUG, "TOKEN_SECRET: SET"),
+      Err(_) => tracing::event!(tracing::Level::DEBUG, "TOKEN_SECRET: NOT SET"),
+    }
   }
 
   run_migrations().ok();
 
+  services::auth::spawn_session_sweeper();
+
   // no allow_origin means all origins are allowed, dev allows all
   let dev_cors = Cors::new();
   // #TODO: restrict in production
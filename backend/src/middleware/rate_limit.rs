@@ -0,0 +1,190 @@
+use crate::{
+  services::{auth::authorize_request, rate_limit::LoginRateLimiter, user},
+  util::{
+    client_ip::client_ip,
+    error::{error_response, APIError},
+  },
+};
+use poem::{
+  http::{header, StatusCode},
+  Body, Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct LoginEmail {
+  email: String,
+}
+
+/// Rate limits login requests per IP address and per email,
+/// see `services::rate_limit::LoginRateLimiter`
+///
+/// Locked out requests are rejected with `429 Too Many Requests` and a
+/// `Retry-After` header, `401 Unauthorized` responses count as failed logins.
+#[derive(Clone)]
+pub struct LoginRateLimit {
+  limiter: Arc<LoginRateLimiter>,
+}
+
+impl LoginRateLimit {
+  pub fn new(limiter: LoginRateLimiter) -> Self {
+    LoginRateLimit {
+      limiter: Arc::new(limiter),
+    }
+  }
+
+  pub fn from_env() -> Self {
+    LoginRateLimit::new(LoginRateLimiter::from_env())
+  }
+}
+
+impl<E: Endpoint> Middleware<E> for LoginRateLimit {
+  type Output = LoginRateLimitEndpoint<E>;
+
+  fn transform(&self, ep: E) -> Self::Output {
+    LoginRateLimitEndpoint {
+      inner: ep,
+      limiter: self.limiter.clone(),
+    }
+  }
+}
+
+pub struct LoginRateLimitEndpoint<E> {
+  inner: E,
+  limiter: Arc<LoginRateLimiter>,
+}
+
+fn too_many_attempts(error: APIError, retry_after_ms: i64) -> Response {
+  let mut response = error_response(error);
+  let seconds = (retry_after_ms + 999) / 1000;
+  if let Ok(value) = seconds.to_string().parse() {
+    response.headers_mut().insert(header::RETRY_AFTER, value);
+  }
+  response
+}
+
+impl<E: Endpoint> Endpoint for LoginRateLimitEndpoint<E> {
+  type Output = Response;
+
+  async fn call(&self, mut req: Request) -> Result<Self::Output> {
+    let ip_address = client_ip(&req);
+
+    // the body is read for the email and put back for the handler
+    let body = req.take_body().into_bytes().await?;
+    let email = serde_json::from_slice::<LoginEmail>(&body)
+      .ok()
+      .map(|login| login.email);
+    req.set_body(Body::from(body));
+
+    // counted before the handler runs, so parallel requests can not all get
+    // past the limit, and taken back unless the login failed
+    let counted = match self.limiter.begin_attempt(&ip_address, email.as_deref()) {
+      Ok(Some(retry_after)) => {
+        return Ok(too_many_attempts(
+          APIError::TooManyLoginAttempts,
+          retry_after,
+        ))
+      }
+      Ok(None) => true,
+      // fail open, a broken store should not lock everyone out
+      Err(error) => {
+        tracing::event!(
+          tracing::Level::ERROR,
+          "error checking login rate limit: {error:?}"
+        );
+        false
+      }
+    };
+
+    let response = self.inner.call(req).await?.into_response();
+
+    let recorded = match response.status() {
+      _ if !counted => Ok(()),
+      StatusCode::UNAUTHORIZED => Ok(()),
+      status if status.is_success() => match &email {
+        Some(email) => self
+          .limiter
+          .cancel_attempt(&ip_address, None)
+          .and_then(|_| self.limiter.record_success(email)),
+        None => self.limiter.cancel_attempt(&ip_address, None),
+      },
+      _ => self.limiter.cancel_attempt(&ip_address, email.as_deref()),
+    };
+
+    if let Err(error) = recorded {
+      tracing::event!(
+        tracing::Level::ERROR,
+        "error recording login attempt: {error:?}"
+      );
+    }
+
+    Ok(response)
+  }
+}
+
+/// Rate limits requests that send emails per IP address and per recipient,
+/// counting every request. The recipient is the `email` in the body, or the
+/// current user's email without one. Locked out requests are rejected with
+/// `429 Too Many Requests` and a `Retry-After` header
+#[derive(Clone)]
+pub struct MailRateLimit {
+  limiter: Arc<LoginRateLimiter>,
+}
+
+impl MailRateLimit {
+  pub fn new(limiter: LoginRateLimiter) -> Self {
+    MailRateLimit {
+      limiter: Arc::new(limiter),
+    }
+  }
+
+  pub fn from_env() -> Self {
+    MailRateLimit::new(LoginRateLimiter::mail_from_env())
+  }
+}
+
+impl<E: Endpoint> Middleware<E> for MailRateLimit {
+  type Output = MailRateLimitEndpoint<E>;
+
+  fn transform(&self, ep: E) -> Self::Output {
+    MailRateLimitEndpoint {
+      inner: ep,
+      limiter: self.limiter.clone(),
+    }
+  }
+}
+
+pub struct MailRateLimitEndpoint<E> {
+  inner: E,
+  limiter: Arc<LoginRateLimiter>,
+}
+
+impl<E: Endpoint> Endpoint for MailRateLimitEndpoint<E> {
+  type Output = Response;
+
+  async fn call(&self, mut req: Request) -> Result<Self::Output> {
+    let ip_address = client_ip(&req);
+
+    let body = req.take_body().into_bytes().await?;
+    let email = match serde_json::from_slice::<LoginEmail>(&body) {
+      Ok(request) => Some(request.email),
+      Err(_) => match authorize_request(&req).await {
+        Ok(session) => user::get_user(&session.user_id).ok().map(|user| user.email),
+        Err(_) => None,
+      },
+    };
+    req.set_body(Body::from(body));
+
+    match self.limiter.begin_attempt(&ip_address, email.as_deref()) {
+      Ok(Some(retry_after)) => return Ok(too_many_attempts(APIError::TooManyEmails, retry_after)),
+      Ok(None) => (),
+      Err(error) => tracing::event!(
+        tracing::Level::ERROR,
+        "error checking mail rate limit: {error:?}"
+      ),
+    }
+
+    Ok(self.inner.call(req).await?.into_response())
+  }
+}
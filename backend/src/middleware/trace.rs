@@ -1,6 +1,5 @@
-use poem::{
-  web::RealIp, Endpoint, FromRequest, IntoResponse, Middleware, Request, Response, Result,
-};
+use crate::util::client_ip::client_ip;
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
 use std::time::Instant;
 use tracing::Level;
 
@@ -27,12 +26,7 @@ impl<E: Endpoint> Endpoint for TraceEndpoint<E> {
   type Output = Response;
 
   async fn call(&self, req: Request) -> Result<Self::Output> {
-    let remote_addr = RealIp::from_request_without_body(&req)
-      .await
-      .ok()
-      .and_then(|real_ip| real_ip.0)
-      .map(|addr| addr.to_string())
-      .unwrap_or_else(|| req.remote_addr().to_string());
+    let remote_addr = client_ip(&req);
 
     let method = req.method().to_string();
     let uri = req.uri().to_string();
@@ -1,5 +1,23 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_tokens (id) {
+        #[max_length = 255]
+        id -> Varchar,
+        #[max_length = 255]
+        user_id -> Varchar,
+        #[max_length = 255]
+        name -> Varchar,
+        #[max_length = 255]
+        scopes -> Varchar,
+        #[max_length = 255]
+        token_hash -> Varchar,
+        created_at -> Int8,
+        expires_at -> Nullable<Int8>,
+        last_used_at -> Nullable<Int8>,
+    }
+}
+
 diesel::table! {
     categories (id) {
         #[max_length = 255]
@@ -12,6 +30,22 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    credentials (id) {
+        #[max_length = 1400]
+        id -> Varchar,
+        #[max_length = 255]
+        user_id -> Varchar,
+        #[max_length = 255]
+        name -> Varchar,
+        #[max_length = 1023]
+        public_key -> Varchar,
+        sign_count -> Int8,
+        created_at -> Int8,
+        last_used_at -> Nullable<Int8>,
+    }
+}
+
 diesel::table! {
     entries (id) {
         #[max_length = 255]
@@ -37,6 +71,29 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    insight_notifications (user_id, kind, from_date) {
+        #[max_length = 255]
+        user_id -> Varchar,
+        #[max_length = 255]
+        kind -> Varchar,
+        from_date -> Date,
+        to_date -> Date,
+    }
+}
+
+diesel::table! {
+    insight_settings (user_id) {
+        #[max_length = 255]
+        user_id -> Varchar,
+        mood_drop_threshold -> Float8,
+        low_mood_level -> Int4,
+        low_mood_days -> Int4,
+        notifications_enabled -> Bool,
+        updated_at -> Int8,
+    }
+}
+
 diesel::table! {
     invites (id) {
         #[max_length = 255]
@@ -48,6 +105,79 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    login_attempts (key) {
+        #[max_length = 255]
+        key -> Varchar,
+        failures -> Int4,
+        last_failure_at -> Int8,
+        locked_until -> Int8,
+    }
+}
+
+diesel::table! {
+    oidc_identities (provider, subject) {
+        #[max_length = 255]
+        provider -> Varchar,
+        #[max_length = 255]
+        subject -> Varchar,
+        #[max_length = 255]
+        user_id -> Varchar,
+        #[max_length = 255]
+        email -> Varchar,
+        created_at -> Int8,
+        last_used_at -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    oidc_states (state) {
+        #[max_length = 255]
+        state -> Varchar,
+        #[max_length = 255]
+        provider -> Varchar,
+        #[max_length = 255]
+        code_verifier -> Varchar,
+        #[max_length = 255]
+        nonce -> Varchar,
+        expires_at -> Int8,
+    }
+}
+
+diesel::table! {
+    recovery_codes (id) {
+        #[max_length = 255]
+        id -> Varchar,
+        #[max_length = 255]
+        user_id -> Varchar,
+        #[max_length = 255]
+        code_hash -> Varchar,
+        used_at -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    security_events (id) {
+        #[max_length = 255]
+        id -> Varchar,
+        #[max_length = 255]
+        user_id -> Varchar,
+        #[max_length = 255]
+        kind -> Varchar,
+        created_at -> Int8,
+        #[max_length = 255]
+        ip_address -> Varchar,
+        #[max_length = 255]
+        user_agent -> Varchar,
+        #[max_length = 255]
+        device -> Varchar,
+        #[max_length = 255]
+        location -> Nullable<Varchar>,
+        #[max_length = 255]
+        details -> Nullable<Varchar>,
+    }
+}
+
 diesel::table! {
     sessions (id) {
         #[max_length = 255]
@@ -60,6 +190,8 @@ diesel::table! {
         ip_address -> Varchar,
         #[max_length = 255]
         user_agent -> Varchar,
+        #[max_length = 255]
+        token_hash -> Varchar,
     }
 }
 
@@ -79,6 +211,37 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    totp_credentials (user_id) {
+        #[max_length = 255]
+        user_id -> Varchar,
+        #[max_length = 255]
+        secret -> Varchar,
+        enabled -> Bool,
+        created_at -> Int8,
+        last_used_step -> Int8,
+    }
+}
+
+diesel::table! {
+    two_factor_challenges (token_hash) {
+        #[max_length = 255]
+        token_hash -> Varchar,
+        #[max_length = 255]
+        user_id -> Varchar,
+        expires_at -> Int8,
+        attempts -> Int4,
+    }
+}
+
+diesel::table! {
+    used_tokens (token_hash) {
+        #[max_length = 255]
+        token_hash -> Varchar,
+        expires_at -> Int8,
+    }
+}
+
 diesel::table! {
     users (id) {
         #[max_length = 255]
@@ -92,18 +255,60 @@ diesel::table! {
         password -> Varchar,
         #[max_length = 255]
         invite -> Nullable<Varchar>,
+        email_verified -> Bool,
+    }
+}
+
+diesel::table! {
+    webauthn_challenges (challenge) {
+        #[max_length = 255]
+        challenge -> Varchar,
+        #[max_length = 255]
+        user_id -> Nullable<Varchar>,
+        #[max_length = 32]
+        ceremony -> Varchar,
+        expires_at -> Int8,
     }
 }
 
 diesel::joinable!(categories -> users (user_id));
+diesel::joinable!(api_tokens -> users (user_id));
+diesel::joinable!(credentials -> users (user_id));
 diesel::joinable!(entries -> users (user_id));
 diesel::joinable!(entry_tags -> entries (entry_id));
 diesel::joinable!(entry_tags -> tags (tag_id));
+diesel::joinable!(insight_notifications -> users (user_id));
+diesel::joinable!(insight_settings -> users (user_id));
+diesel::joinable!(oidc_identities -> users (user_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(security_events -> users (user_id));
 diesel::joinable!(sessions -> users (user_id));
 diesel::joinable!(tags -> categories (category_id));
 diesel::joinable!(tags -> users (user_id));
+diesel::joinable!(totp_credentials -> users (user_id));
+diesel::joinable!(two_factor_challenges -> users (user_id));
 diesel::joinable!(users -> invites (invite));
+diesel::joinable!(webauthn_challenges -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
-  categories, entries, entry_tags, invites, sessions, tags, users,
+  api_tokens,
+  categories,
+  credentials,
+  entries,
+  entry_tags,
+  insight_notifications,
+  insight_settings,
+  invites,
+  login_attempts,
+  oidc_identities,
+  oidc_states,
+  recovery_codes,
+  security_events,
+  sessions,
+  tags,
+  totp_credentials,
+  two_factor_challenges,
+  used_tokens,
+  users,
+  webauthn_challenges,
 );
@@ -1,10 +1,26 @@
+pub mod api_token;
 pub mod auth;
 pub mod category;
+pub mod email_verification;
 pub mod entry;
 pub mod health;
+pub mod insights;
 pub mod invite;
 pub mod log;
+pub mod mail;
+pub mod oidc;
 pub mod pagination;
+pub mod passkey;
+pub mod password_policy;
+pub mod password_reset;
+pub mod rate_limit;
+pub mod review;
+pub mod security_event;
+pub mod session_cookie;
+pub mod signed_token;
 pub mod stats;
+pub mod stats_cache;
 pub mod tag;
+pub mod text_stats;
+pub mod two_factor;
 pub mod user;
@@ -0,0 +1,364 @@
+use crate::{
+  establish_connection,
+  schema::api_tokens,
+  services::{
+    auth::{self, Session},
+    user,
+  },
+  util::{clock::Clock, error::APIError},
+};
+use diesel::{
+  deserialize::Queryable, ExpressionMethods, Insertable, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use poem::Request;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Prefix of personal API tokens, tells them apart from session tokens
+pub const API_TOKEN_PREFIX: &str = "dc_";
+
+/// What a personal API token may access. Handlers that accept tokens declare
+/// the scope they need with `auth::authorize_request_with_scope`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+  #[serde(rename = "entries:read")]
+  EntriesRead,
+  #[serde(rename = "entries:write")]
+  EntriesWrite,
+  #[serde(rename = "stats:read")]
+  StatsRead,
+  /// Downloads that leave the app, such as the html year in review
+  #[serde(rename = "export")]
+  Export,
+}
+
+impl Scope {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Scope::EntriesRead => "entries:read",
+      Scope::EntriesWrite => "entries:write",
+      Scope::StatsRead => "stats:read",
+      Scope::Export => "export",
+    }
+  }
+
+  pub fn parse(value: &str) -> Option<Scope> {
+    match value {
+      "entries:read" => Some(Scope::EntriesRead),
+      "entries:write" => Some(Scope::EntriesWrite),
+      "stats:read" => Some(Scope::StatsRead),
+      "export" => Some(Scope::Export),
+      _ => None,
+    }
+  }
+}
+
+/// Scopes are stored space separated, unknown scopes are dropped
+fn parse_scopes(scopes: &str) -> Vec<Scope> {
+  scopes.split_whitespace().filter_map(Scope::parse).collect()
+}
+
+fn format_scopes(scopes: &[Scope]) -> String {
+  let mut names: Vec<&str> = scopes.iter().map(Scope::as_str).collect();
+  names.sort_unstable();
+  names.dedup();
+  names.join(" ")
+}
+
+#[derive(Debug, Insertable, Queryable)]
+#[diesel(table_name = api_tokens)]
+struct StoredApiToken {
+  id: String,
+  user_id: String,
+  name: String,
+  scopes: String,
+  token_hash: String,
+  created_at: i64,
+  expires_at: Option<i64>,
+  last_used_at: Option<i64>,
+}
+
+/// A personal API token, only the SHA-256 of the token is stored
+#[derive(Debug, Serialize)]
+pub struct ApiToken {
+  pub id: String,
+  pub user_id: String,
+  pub name: String,
+  pub scopes: Vec<Scope>,
+  pub created_at: i64,
+  /// None for tokens that do not expire
+  pub expires_at: Option<i64>,
+  pub last_used_at: Option<i64>,
+}
+
+impl From<StoredApiToken> for ApiToken {
+  fn from(stored: StoredApiToken) -> Self {
+    ApiToken {
+      id: stored.id,
+      user_id: stored.user_id,
+      name: stored.name,
+      scopes: parse_scopes(&stored.scopes),
+      created_at: stored.created_at,
+      expires_at: stored.expires_at,
+      last_used_at: stored.last_used_at,
+    }
+  }
+}
+
+/// A newly created API token with its secret, the token is only returned once
+#[derive(Debug, Serialize)]
+pub struct NewApiToken {
+  #[serde(flatten)]
+  pub api_token: ApiToken,
+  pub token: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateApiToken {
+  #[validate(length(min = 1, max = 255))]
+  pub name: String,
+  #[validate(length(min = 1))]
+  pub scopes: Vec<Scope>,
+  /// Unix time in milliseconds, None for a token that does not expire
+  pub expires_at: Option<i64>,
+  pub current_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateApiToken {
+  #[validate(length(min = 1, max = 255))]
+  pub name: Option<String>,
+  #[validate(length(min = 1))]
+  pub scopes: Option<Vec<Scope>>,
+}
+
+pub fn is_api_token(token: &str) -> bool {
+  token.starts_with(API_TOKEN_PREFIX)
+}
+
+/// Creates a token after checking the user's current password
+pub fn create_api_token(
+  user_id: &str,
+  api_token: CreateApiToken,
+  clock: &dyn Clock,
+) -> Result<NewApiToken, APIError> {
+  match api_token.validate() {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::BadRequest),
+  }
+  if api_token
+    .expires_at
+    .is_some_and(|expires_at| expires_at <= clock.now_ms())
+  {
+    return Err(APIError::BadRequest);
+  }
+  user::verify_current_password(user_id, api_token.current_password.as_deref())?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let token = format!("{API_TOKEN_PREFIX}{}", auth::generate_token());
+
+  let stored = StoredApiToken {
+    id: Uuid::new_v4().to_string(),
+    user_id: user_id.to_string(),
+    name: api_token.name,
+    scopes: format_scopes(&api_token.scopes),
+    token_hash: auth::hash_token(&token),
+    created_at: clock.now_ms(),
+    expires_at: api_token.expires_at,
+    last_used_at: None,
+  };
+
+  match diesel::insert_into(api_tokens::table)
+    .values(&stored)
+    .execute(&mut conn)
+  {
+    Ok(_) => Ok(NewApiToken {
+      api_token: ApiToken::from(stored),
+      token,
+    }),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+pub fn get_user_api_tokens(user_id: &str) -> Result<Vec<ApiToken>, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match api_tokens::table
+    .filter(api_tokens::user_id.eq(user_id))
+    .order(api_tokens::created_at.asc())
+    .load::<StoredApiToken>(&mut conn)
+  {
+    Ok(tokens) => Ok(tokens.into_iter().map(ApiToken::from).collect()),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Renames a token or changes its scopes, other users' tokens are not found
+pub fn update_api_token(
+  user_id: &str,
+  token_id: &str,
+  update: UpdateApiToken,
+) -> Result<ApiToken, APIError> {
+  match update.validate() {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::BadRequest),
+  }
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let current = match api_tokens::table
+    .filter(api_tokens::id.eq(token_id))
+    .filter(api_tokens::user_id.eq(user_id))
+    .first::<StoredApiToken>(&mut conn)
+    .optional()
+  {
+    Ok(Some(token)) => token,
+    Ok(None) => return Err(APIError::ApiTokenNotFound),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let name = update.name.unwrap_or(current.name);
+  let scopes = match update.scopes {
+    Some(scopes) => format_scopes(&scopes),
+    None => current.scopes,
+  };
+
+  match diesel::update(api_tokens::table.filter(api_tokens::id.eq(token_id)))
+    .set((api_tokens::name.eq(name), api_tokens::scopes.eq(scopes)))
+    .get_result::<StoredApiToken>(&mut conn)
+  {
+    Ok(token) => Ok(ApiToken::from(token)),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Deletes a token of a user, other users' tokens are not found
+pub fn revoke_api_token(user_id: &str, token_id: &str) -> Result<(), APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::delete(
+    api_tokens::table
+      .filter(api_tokens::id.eq(token_id))
+      .filter(api_tokens::user_id.eq(user_id)),
+  )
+  .execute(&mut conn)
+  {
+    Ok(0) => Err(APIError::ApiTokenNotFound),
+    Ok(_) => Ok(()),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Deletes all tokens of a user, used when their password changes
+pub fn revoke_all_api_tokens(user_id: &str) -> Result<usize, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::delete(api_tokens::table.filter(api_tokens::user_id.eq(user_id))).execute(&mut conn)
+  {
+    Ok(rows_affected) => Ok(rows_affected),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Authorizes a request made with an API token for a scope and records its
+/// use. Handlers get a `Session` with the token's ID, they only use `user_id`
+pub async fn authorize(
+  token: &str,
+  scope: Scope,
+  request: &Request,
+  clock: &dyn Clock,
+) -> Result<Session, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let api_token = match api_tokens::table
+    .filter(api_tokens::token_hash.eq(auth::hash_token(token)))
+    .first::<StoredApiToken>(&mut conn)
+    .optional()
+  {
+    Ok(Some(api_token)) => api_token,
+    Ok(None) => return Err(APIError::Unauthorized),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let now = clock.now_ms();
+  if api_token
+    .expires_at
+    .is_some_and(|expires_at| expires_at <= now)
+  {
+    return Err(APIError::ApiTokenExpired);
+  }
+  if !parse_scopes(&api_token.scopes).contains(&scope) {
+    return Err(APIError::InsufficientScope);
+  }
+
+  match diesel::update(api_tokens::table.filter(api_tokens::id.eq(&api_token.id)))
+    .set(api_tokens::last_used_at.eq(now))
+    .execute(&mut conn)
+  {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  }
+
+  let metadata = auth::session_metadata(request).await;
+
+  Ok(Session {
+    id: api_token.id,
+    user_id: api_token.user_id,
+    created_at: api_token.created_at,
+    accessed_at: now,
+    ip_address: metadata.ip_address,
+    user_agent: metadata.user_agent,
+    token_hash: api_token.token_hash,
+  })
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  #[test]
+  fn test_scopes() {
+    let scopes = format_scopes(&[Scope::StatsRead, Scope::EntriesRead, Scope::StatsRead]);
+    assert_eq!(scopes, "entries:read stats:read");
+    assert_eq!(
+      parse_scopes(&scopes),
+      vec![Scope::EntriesRead, Scope::StatsRead]
+    );
+    assert_eq!(parse_scopes("export unknown"), vec![Scope::Export]);
+
+    for scope in [
+      Scope::EntriesRead,
+      Scope::EntriesWrite,
+      Scope::StatsRead,
+      Scope::Export,
+    ] {
+      assert_eq!(Scope::parse(scope.as_str()), Some(scope));
+      assert_eq!(
+        serde_json::to_string(&scope).unwrap(),
+        format!("\"{}\"", scope.as_str())
+      );
+    }
+    assert!(is_api_token("dc_abc"));
+    assert!(!is_api_token("abc"));
+  }
+}
@@ -1,15 +1,58 @@
 use crate::{
   establish_connection,
   schema::{self, sessions},
-  services::user,
-  util,
-  util::error::APIError,
+  services::{
+    api_token::{self, Scope},
+    oidc::OidcProviderInfo,
+    password_policy::PasswordRequirements,
+    security_event,
+    session_cookie::{self, SessionCookieConfig},
+    two_factor::{self, ChallengeResponse, TwoFactorChallenge},
+    user,
+  },
+  util::{
+    client_ip,
+    clock::{Clock, SystemClock},
+    error::APIError,
+  },
 };
-use diesel::{deserialize::Queryable, ExpressionMethods, Insertable, QueryDsl, RunQueryDsl};
-use poem::{web::RealIp, FromRequest, Request};
+use argon2::{
+  password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString},
+  Argon2,
+};
+use diesel::{
+  deserialize::Queryable, BoolExpressionMethods, ExpressionMethods, Insertable, QueryDsl,
+  RunQueryDsl,
+};
+use dotenvy::dotenv;
+use poem::Request;
+use rand::{rngs::OsRng, RngCore};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{env, sync::OnceLock, time::Duration};
 use uuid::Uuid;
 
+/// Default absolute session lifetime in seconds (90 days)
+pub const DEFAULT_SESSION_MAX_AGE: i64 = 60 * 60 * 24 * 90;
+/// Default session idle timeout in seconds (30 days)
+pub const DEFAULT_SESSION_IDLE_TIMEOUT: i64 = 60 * 60 * 24 * 30;
+/// Default interval between expired session sweeps in seconds (1 hour)
+pub const DEFAULT_SESSION_SWEEP_INTERVAL: u64 = 60 * 60;
+/// Number of random bytes in a session token, hex encoded for the client
+pub const SESSION_TOKEN_BYTES: usize = 32;
+/// Default Argon2id memory cost in KiB, the OWASP minimum of 19 MiB
+pub const DEFAULT_ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+/// Default Argon2id passes over the memory
+pub const DEFAULT_ARGON2_ITERATIONS: u32 = 2;
+/// Default Argon2id lanes
+pub const DEFAULT_ARGON2_PARALLELISM: u32 = 1;
+/// Longest password accepted by Argon2id, long passwords only make hashing slower
+pub const ARGON2_MAX_PASSWORD_BYTES: usize = 1024;
+/// bcrypt ignores everything after 72 bytes
+pub const BCRYPT_MAX_PASSWORD_BYTES: usize = 72;
+
+/// A user session, `id` is not secret and is used to list and revoke sessions.
+/// Only the SHA-256 of the bearer token is stored.
 #[derive(Debug, Deserialize, Serialize, Insertable, Queryable)]
 pub struct Session {
   pub id: String,
@@ -18,6 +61,16 @@ pub struct Session {
   pub accessed_at: i64,
   pub ip_address: String,
   pub user_agent: String,
+  #[serde(skip)]
+  pub token_hash: String,
+}
+
+/// A newly created session with its bearer token, the token is only returned once
+#[derive(Debug, Serialize)]
+pub struct NewSession {
+  #[serde(flatten)]
+  pub session: Session,
+  pub token: String,
 }
 
 pub struct SessionMetadata {
@@ -34,18 +87,60 @@ pub struct UserCredentials {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AuthConfig {
   pub invite_required: bool,
+  pub password: PasswordRequirements,
+  /// Providers users can log in with, empty when OpenID Connect is not set up
+  pub oidc_providers: Vec<OidcProviderInfo>,
 }
 
-pub async fn session_metadata(request: &Request) -> SessionMetadata {
-  let remote_addr = RealIp::from_request_without_body(request)
-    .await
-    .ok()
-    .and_then(|real_ip| real_ip.0)
-    .map(|addr| addr.to_string())
-    .unwrap_or_else(|| request.remote_addr().to_string());
+/// Session lifetimes in milliseconds, None means the limit is disabled
+#[derive(Debug, Clone, Copy)]
+pub struct SessionConfig {
+  /// Time after creation when a session expires
+  pub max_age_ms: Option<i64>,
+  /// Time without requests after which a session expires,
+  /// renewed by every authorized request
+  pub idle_timeout_ms: Option<i64>,
+}
+
+/// Reads a duration in seconds from an environment variable, 0 disables the limit
+fn env_seconds(name: &str, default: i64) -> Option<i64> {
+  let seconds = match env::var(name) {
+    Ok(val) => val.parse::<i64>().unwrap_or(default),
+    Err(_) => default,
+  };
+
+  match seconds {
+    seconds if seconds > 0 => Some(seconds * 1000),
+    _ => None,
+  }
+}
 
+impl SessionConfig {
+  /// Reads SESSION_MAX_AGE and SESSION_IDLE_TIMEOUT (in seconds)
+  pub fn from_env() -> Self {
+    dotenv().ok();
+
+    SessionConfig {
+      max_age_ms: env_seconds("SESSION_MAX_AGE", DEFAULT_SESSION_MAX_AGE),
+      idle_timeout_ms: env_seconds("SESSION_IDLE_TIMEOUT", DEFAULT_SESSION_IDLE_TIMEOUT),
+    }
+  }
+
+  pub fn is_expired(&self, session: &Session, now_ms: i64) -> bool {
+    let too_old = self
+      .max_age_ms
+      .is_some_and(|max_age| now_ms - session.created_at > max_age);
+    let idle = self
+      .idle_timeout_ms
+      .is_some_and(|idle_timeout| now_ms - session.accessed_at > idle_timeout);
+
+    too_old || idle
+  }
+}
+
+pub async fn session_metadata(request: &Request) -> SessionMetadata {
   SessionMetadata {
-    ip_address: remote_addr,
+    ip_address: client_ip::client_ip(request),
     user_agent: request
       .header("user-agent")
       .unwrap_or("unknown")
@@ -53,6 +148,193 @@ pub async fn session_metadata(request: &Request) -> SessionMetadata {
   }
 }
 
+/// Generates a random session token with the OS CSPRNG
+pub fn generate_token() -> String {
+  let mut bytes = [0u8; SESSION_TOKEN_BYTES];
+  OsRng.fill_bytes(&mut bytes);
+  bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Hex encoded SHA-256 of a session token, as stored in `sessions.token_hash`
+pub fn hash_token(token: &str) -> String {
+  Sha256::digest(token.as_bytes())
+    .iter()
+    .map(|byte| format!("{byte:02x}"))
+    .collect()
+}
+
+/// Hashes and verifies passwords. Hashes carry their algorithm and
+/// parameters, so hashes of an earlier algorithm keep working and are
+/// replaced on the next login
+pub trait PasswordHasher: Send + Sync {
+  fn hash(&self, password: &str) -> Result<String, APIError>;
+  /// Checks a password, None when the hash is not from this algorithm
+  fn verify(&self, password: &str, hash: &str) -> Option<bool>;
+  /// Whether a hash is from another algorithm or other parameters
+  fn needs_rehash(&self, hash: &str) -> bool;
+  /// Longest password in bytes the algorithm uses completely
+  fn max_password_bytes(&self) -> usize;
+}
+
+/// bcrypt with the cost from BCRYPT_COST, the hasher before Argon2id
+pub struct BcryptHasher {
+  pub cost: u32,
+}
+
+impl PasswordHasher for BcryptHasher {
+  fn hash(&self, password: &str) -> Result<String, APIError> {
+    match bcrypt::hash(password, self.cost) {
+      Ok(hash) => Ok(hash),
+      Err(_) => Err(APIError::InternalServerError),
+    }
+  }
+
+  fn verify(&self, password: &str, hash: &str) -> Option<bool> {
+    hash.parse::<bcrypt::HashParts>().ok()?;
+    bcrypt::verify(password, hash).ok()
+  }
+
+  fn needs_rehash(&self, hash: &str) -> bool {
+    match hash.parse::<bcrypt::HashParts>() {
+      Ok(parts) => parts.get_cost() != self.cost,
+      Err(_) => true,
+    }
+  }
+
+  fn max_password_bytes(&self) -> usize {
+    BCRYPT_MAX_PASSWORD_BYTES
+  }
+}
+
+/// Argon2id, hashes are PHC strings like `$argon2id$v=19$m=19456,t=2,p=1$...`
+pub struct Argon2Hasher {
+  pub memory_kib: u32,
+  pub iterations: u32,
+  pub parallelism: u32,
+}
+
+impl Argon2Hasher {
+  /// Parameters from ARGON2_MEMORY_KIB, ARGON2_ITERATIONS and ARGON2_PARALLELISM
+  pub fn from_env() -> Self {
+    dotenv().ok();
+
+    let parse = |name: &str, default: u32| {
+      env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<u32>().ok())
+        .unwrap_or(default)
+    };
+
+    Argon2Hasher {
+      memory_kib: parse("ARGON2_MEMORY_KIB", DEFAULT_ARGON2_MEMORY_KIB),
+      iterations: parse("ARGON2_ITERATIONS", DEFAULT_ARGON2_ITERATIONS),
+      parallelism: parse("ARGON2_PARALLELISM", DEFAULT_ARGON2_PARALLELISM),
+    }
+  }
+
+  fn argon2(&self) -> Result<Argon2<'static>, APIError> {
+    match argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None) {
+      Ok(params) => Ok(Argon2::new(
+        argon2::Algorithm::Argon2id,
+        argon2::Version::V0x13,
+        params,
+      )),
+      Err(_) => Err(APIError::InternalServerError),
+    }
+  }
+}
+
+impl PasswordHasher for Argon2Hasher {
+  fn hash(&self, password: &str) -> Result<String, APIError> {
+    let salt = SaltString::generate(&mut argon2::password_hash::rand_core::OsRng);
+
+    match self.argon2()?.hash_password(password.as_bytes(), &salt) {
+      Ok(hash) => Ok(hash.to_string()),
+      Err(_) => Err(APIError::InternalServerError),
+    }
+  }
+
+  fn verify(&self, password: &str, hash: &str) -> Option<bool> {
+    let hash = PasswordHash::new(hash).ok()?;
+    if argon2::Algorithm::try_from(hash.algorithm).is_err() {
+      return None;
+    }
+
+    // the parameters of the hash are used, not the configured ones
+    Some(
+      Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok(),
+    )
+  }
+
+  fn needs_rehash(&self, hash: &str) -> bool {
+    let hash = match PasswordHash::new(hash) {
+      Ok(hash) => hash,
+      Err(_) => return true,
+    };
+
+    match argon2::Params::try_from(&hash) {
+      Ok(params) => {
+        hash.algorithm != argon2::Algorithm::Argon2id.ident()
+          || params.m_cost() != self.memory_kib
+          || params.t_cost() != self.iterations
+          || params.p_cost() != self.parallelism
+      }
+      Err(_) => true,
+    }
+  }
+
+  fn max_password_bytes(&self) -> usize {
+    ARGON2_MAX_PASSWORD_BYTES
+  }
+}
+
+/// Hasher for new passwords, PASSWORD_HASHER selects `argon2id` (default) or `bcrypt`
+pub fn password_hasher() -> Box<dyn PasswordHasher> {
+  dotenv().ok();
+
+  match env::var("PASSWORD_HASHER").as_deref() {
+    Ok("bcrypt") => Box::new(BcryptHasher {
+      cost: user::bcrypt_cost(),
+    }),
+    _ => Box::new(Argon2Hasher::from_env()),
+  }
+}
+
+/// Hashes a new password with the configured hasher
+pub fn hash_password(password: &str) -> Result<String, APIError> {
+  password_hasher().hash(password)
+}
+
+/// Checks a password against a hash of any supported algorithm
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, APIError> {
+  let hashers: [Box<dyn PasswordHasher>; 2] = [
+    Box::new(Argon2Hasher::from_env()),
+    Box::new(BcryptHasher {
+      cost: user::bcrypt_cost(),
+    }),
+  ];
+
+  hashers
+    .iter()
+    .find_map(|hasher| hasher.verify(password, hash))
+    .ok_or(APIError::InternalServerError)
+}
+
+/// Whether a hash should be replaced with one of the configured hasher
+pub fn needs_rehash(hash: &str) -> bool {
+  password_hasher().needs_rehash(hash)
+}
+
+/// Hash verified against when a login's email is unknown, hashed with the
+/// configured hasher so the verify takes as long as for real users
+pub fn dummy_password_hash() -> &'static str {
+  static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+  DUMMY_HASH.get_or_init(|| hash_password(&Uuid::new_v4().to_string()).unwrap_or_default())
+}
+
 /// Extracts the Bearer token from the Authorization header
 pub fn token_from_header(request: &Request) -> Option<String> {
   let token = request.header("Authorization");
@@ -60,36 +342,89 @@ pub fn token_from_header(request: &Request) -> Option<String> {
 }
 
 /// Authorizes a request by validating the session token from the Authorization header
-/// and updates the session metadata
+/// or the session cookie and updates the session metadata. Personal API tokens
+/// are rejected, handlers that allow them use `authorize_request_with_scope`
 pub async fn authorize_request(request: &Request) -> Result<Session, APIError> {
+  authorize_request_with_clock(request, &SystemClock, &SessionConfig::from_env()).await
+}
+
+/// Authorizes a request with a session, or a personal API token that has the scope
+pub async fn authorize_request_with_scope(
+  request: &Request,
+  scope: Scope,
+) -> Result<Session, APIError> {
+  authorize(
+    request,
+    Some(scope),
+    &SystemClock,
+    &SessionConfig::from_env(),
+  )
+  .await
+}
+
+/// Authorizes a request using the given clock and session lifetimes,
+/// expired sessions are deleted and rejected with `APIError::SessionExpired`
+pub async fn authorize_request_with_clock(
+  request: &Request,
+  clock: &dyn Clock,
+  config: &SessionConfig,
+) -> Result<Session, APIError> {
+  authorize(request, None, clock, config).await
+}
+
+/// Authorizes a request, API tokens are accepted when a scope is given
+pub async fn authorize(
+  request: &Request,
+  scope: Option<Scope>,
+  clock: &dyn Clock,
+  config: &SessionConfig,
+) -> Result<Session, APIError> {
+  // the browser sends cookies on its own, so cookie sessions are checked for CSRF
   let token = match token_from_header(request) {
     Some(token) => token,
-    None => return Err(APIError::Unauthorized),
+    None => match session_cookie::token_from_cookie(request) {
+      Some(token) => {
+        session_cookie::check_csrf(request, &token, &SessionCookieConfig::from_env())?;
+        token
+      }
+      None => return Err(APIError::Unauthorized),
+    },
   };
 
-  match get_user_session_by_id(&token) {
-    Ok(_) => (),
+  if api_token::is_api_token(&token) {
+    return match scope {
+      Some(scope) => api_token::authorize(&token, scope, request, clock).await,
+      None => Err(APIError::InsufficientScope),
+    };
+  }
+
+  let session = match get_user_session_by_token(&token) {
+    Ok(session) => session,
     Err(_) => return Err(APIError::Unauthorized),
+  };
+
+  if config.is_expired(&session, clock.now_ms()) {
+    delete_user_session(&session.id).ok();
+    return Err(APIError::SessionExpired);
   }
 
-  match update_session(&token, request).await {
+  match update_session(&session.id, request, clock.now_ms()).await {
     Ok(session) => Ok(session),
     Err(error) => Err(error),
   }
 }
 
-pub fn create_user_session(
-  user_credentials: UserCredentials,
-  metadata: SessionMetadata,
-) -> Result<Session, APIError> {
-  let mut conn = match establish_connection() {
-    Ok(connection) => connection,
-    Err(_) => return Err(APIError::DatabaseError),
-  };
-
+/// Checks an email and password, returns the user's ID. Unknown emails and
+/// wrong passwords both return `APIError::InvalidCredentials` so they can not
+/// be told apart
+pub fn verify_credentials(user_credentials: &UserCredentials) -> Result<String, APIError> {
   let user_id = match user::get_user_id(&user_credentials.email) {
     Ok(id) => id,
-    Err(_) => return Err(APIError::UserNotFound),
+    Err(_) => {
+      // verify anyway so unknown emails take as long as wrong passwords
+      verify_password(&user_credentials.password, dummy_password_hash()).ok();
+      return Err(APIError::InvalidCredentials);
+    }
   };
 
   let password_hash = match user::get_password_hash(&user_id) {
@@ -97,34 +432,132 @@ pub fn create_user_session(
     Err(_) => return Err(APIError::DatabaseError),
   };
 
-  match bcrypt::verify(&user_credentials.password, &password_hash) {
-    Ok(valid) => match valid {
-      true => (),
-      false => return Err(APIError::InvalidPassword),
-    },
-    Err(_) => return Err(APIError::InternalServerError),
+  match verify_password(&user_credentials.password, &password_hash) {
+    Ok(true) => (),
+    Ok(false) => return Err(APIError::InvalidCredentials),
+    Err(error) => return Err(error),
+  }
+
+  // the password is only known here, so bcrypt hashes and hashes with old
+  // parameters are upgraded on login
+  if needs_rehash(&password_hash) {
+    if let Ok(new_hash) = hash_password(&user_credentials.password) {
+      user::set_password_hash(&user_id, &new_hash).ok();
+    }
+  }
+
+  Ok(user_id)
+}
+
+/// Creates a session for a user that has already been authenticated. The
+/// login is recorded as a security event, see `security_event::record_login`
+pub fn create_session_for_user(
+  user_id: &str,
+  metadata: SessionMetadata,
+  clock: &dyn Clock,
+) -> Result<NewSession, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
   };
 
+  let token = generate_token();
+  let now = clock.now_ms();
+
   let session = Session {
     id: Uuid::new_v4().to_string(),
-    user_id,
-    created_at: util::unix_time::unix_ms(),
-    accessed_at: util::unix_time::unix_ms(),
+    user_id: user_id.to_string(),
+    created_at: now,
+    accessed_at: now,
     ip_address: metadata.ip_address,
     user_agent: metadata.user_agent,
+    token_hash: hash_token(&token),
   };
 
   match diesel::insert_into(schema::sessions::table)
     .values(&session)
     .execute(&mut conn)
   {
-    Ok(_) => Ok(session),
-    Err(_) => Err(APIError::DatabaseError),
+    Ok(_) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let login = security_event::record_login(
+    user_id,
+    &SessionMetadata {
+      ip_address: session.ip_address.clone(),
+      user_agent: session.user_agent.clone(),
+    },
+    security_event::geoip_database(),
+    security_event::new_device_mailer(),
+    clock,
+  );
+  if let Err(error) = login {
+    tracing::event!(
+      tracing::Level::ERROR,
+      "could not record login security event: {error:?}"
+    );
+  }
+
+  Ok(NewSession { session, token })
+}
+
+/// Logs a user in with only their password, users with two-factor
+/// authentication get `APIError::TwoFactorRequired` and should use `log_in`
+pub fn create_user_session(
+  user_credentials: UserCredentials,
+  metadata: SessionMetadata,
+) -> Result<NewSession, APIError> {
+  let user_id = verify_credentials(&user_credentials)?;
+
+  if two_factor::is_enabled(&user_id)? {
+    return Err(APIError::TwoFactorRequired);
+  }
+
+  create_session_for_user(&user_id, metadata, &SystemClock)
+}
+
+/// Body of a login request, a password or the answer to a two-factor challenge
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum AuthRequest {
+  Challenge(ChallengeResponse),
+  Password(user::AuthUser),
+}
+
+/// Result of the password step of a login
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResult {
+  Session(NewSession),
+  TwoFactorChallenge(TwoFactorChallenge),
+}
+
+/// Logs a user in, returning a challenge instead of a session
+/// when the user has two-factor authentication enabled
+pub fn log_in(
+  user_credentials: UserCredentials,
+  metadata: SessionMetadata,
+  clock: &dyn Clock,
+) -> Result<LoginResult, APIError> {
+  let user_id = verify_credentials(&user_credentials)?;
+
+  match two_factor::is_enabled(&user_id)? {
+    true => Ok(LoginResult::TwoFactorChallenge(
+      two_factor::create_challenge(&user_id, clock)?,
+    )),
+    false => Ok(LoginResult::Session(create_session_for_user(
+      &user_id, metadata, clock,
+    )?)),
   }
 }
 
 /// Updates the session metadata (accessed_at, ip_address, user_agent)
-async fn update_session(session_id: &str, request: &Request) -> Result<Session, APIError> {
+async fn update_session(
+  session_id: &str,
+  request: &Request,
+  accessed_at: i64,
+) -> Result<Session, APIError> {
   let mut conn = match establish_connection() {
     Ok(connection) => connection,
     Err(_) => return Err(APIError::DatabaseError),
@@ -134,7 +567,7 @@ async fn update_session(session_id: &str, request: &Request) -> Result<Session,
 
   match diesel::update(schema::sessions::table.filter(schema::sessions::id.eq(session_id)))
     .set((
-      schema::sessions::accessed_at.eq(util::unix_time::unix_ms()),
+      schema::sessions::accessed_at.eq(accessed_at),
       schema::sessions::ip_address.eq(session_metadata.ip_address),
       schema::sessions::user_agent.eq(session_metadata.user_agent),
     ))
@@ -161,6 +594,22 @@ pub fn get_user_session_by_id(session_id: &str) -> Result<Session, APIError> {
   }
 }
 
+/// Retrieves a user session by its bearer token, looked up by the token's hash
+pub fn get_user_session_by_token(token: &str) -> Result<Session, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match schema::sessions::table
+    .filter(schema::sessions::token_hash.eq(hash_token(token)))
+    .first::<Session>(&mut conn)
+  {
+    Ok(session) => Ok(session),
+    Err(_) => Err(APIError::SessionNotFound),
+  }
+}
+
 pub fn get_all_user_sessions(user_id: &str) -> Result<Vec<Session>, APIError> {
   let mut conn = match establish_connection() {
     Ok(connection) => connection,
@@ -191,16 +640,120 @@ pub fn delete_user_session(session_id: &str) -> Result<bool, APIError> {
   }
 }
 
-pub fn delete_all_user_sessions(user_id: &str) -> Result<bool, APIError> {
+/// Revokes a session owned by `user_id`, sessions of other users are
+/// reported as `APIError::SessionNotFound` so their IDs are not revealed
+pub fn revoke_user_session(user_id: &str, session_id: &str) -> Result<(), APIError> {
   let mut conn = match establish_connection() {
     Ok(connection) => connection,
     Err(_) => return Err(APIError::DatabaseError),
   };
 
-  match diesel::delete(schema::sessions::table.filter(schema::sessions::user_id.eq(user_id)))
-    .execute(&mut conn)
+  match diesel::delete(
+    schema::sessions::table
+      .filter(schema::sessions::id.eq(session_id))
+      .filter(schema::sessions::user_id.eq(user_id)),
+  )
+  .execute(&mut conn)
   {
+    Ok(0) => Err(APIError::SessionNotFound),
+    Ok(_) => Ok(()),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Deletes all sessions of a user, except the session with the ID `except` if given
+pub fn delete_all_user_sessions(user_id: &str, except: Option<&str>) -> Result<bool, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut query = diesel::delete(schema::sessions::table)
+    .filter(schema::sessions::user_id.eq(user_id))
+    .into_boxed();
+
+  if let Some(except) = except {
+    query = query.filter(schema::sessions::id.ne(except));
+  }
+
+  match query.execute(&mut conn) {
     Ok(rows_affected) => Ok(rows_affected > 0),
     Err(_) => Err(APIError::DatabaseError),
   }
 }
+
+/// Deletes sessions that are past their absolute lifetime or idle timeout
+pub fn delete_expired_sessions(
+  clock: &dyn Clock,
+  config: &SessionConfig,
+) -> Result<usize, APIError> {
+  let now = clock.now_ms();
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let expired = match (config.max_age_ms, config.idle_timeout_ms) {
+    (Some(max_age), Some(idle_timeout)) => diesel::delete(
+      schema::sessions::table.filter(
+        schema::sessions::created_at
+          .lt(now - max_age)
+          .or(schema::sessions::accessed_at.lt(now - idle_timeout)),
+      ),
+    )
+    .execute(&mut conn),
+    (Some(max_age), None) => {
+      diesel::delete(schema::sessions::table.filter(schema::sessions::created_at.lt(now - max_age)))
+        .execute(&mut conn)
+    }
+    (None, Some(idle_timeout)) => diesel::delete(
+      schema::sessions::table.filter(schema::sessions::accessed_at.lt(now - idle_timeout)),
+    )
+    .execute(&mut conn),
+    (None, None) => Ok(0),
+  };
+
+  match expired {
+    Ok(rows_affected) => Ok(rows_affected),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Starts a background task deleting expired sessions,
+/// runs every SESSION_SWEEP_INTERVAL seconds
+pub fn spawn_session_sweeper() {
+  dotenv().ok();
+
+  let interval = match env::var("SESSION_SWEEP_INTERVAL") {
+    Ok(val) => val.parse::<u64>().unwrap_or(DEFAULT_SESSION_SWEEP_INTERVAL),
+    Err(_) => DEFAULT_SESSION_SWEEP_INTERVAL,
+  }
+  .max(1);
+
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval));
+
+    loop {
+      interval.tick().await;
+
+      let deleted = tokio::task::spawn_blocking(|| {
+        delete_expired_sessions(&SystemClock, &SessionConfig::from_env())
+      })
+      .await;
+
+      match deleted {
+        Ok(Ok(0)) => (),
+        Ok(Ok(count)) => tracing::event!(tracing::Level::INFO, "deleted {count} expired sessions"),
+        Ok(Err(error)) => tracing::event!(
+          tracing::Level::ERROR,
+          "error deleting expired sessions: {error:?}"
+        ),
+        Err(error) => tracing::event!(
+          tracing::Level::ERROR,
+          "session sweeper task failed: {error}"
+        ),
+      }
+    }
+  });
+}
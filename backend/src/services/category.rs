@@ -2,6 +2,7 @@ use crate::{
   establish_connection,
   schema::categories,
   services::{
+    stats_cache,
     tag::{delete_all_category_tags, get_category_tags, Tag},
     user::get_user,
   },
@@ -79,7 +80,10 @@ pub fn create_category(category: CreateCategory) -> Result<Category, APIError> {
     .values(&new_category)
     .execute(&mut conn)
   {
-    Ok(_) => Ok(new_category),
+    Ok(_) => {
+      stats_cache::invalidate(&new_category.user_id);
+      Ok(new_category)
+    }
     Err(_) => Err(APIError::DatabaseError),
   }
 }
@@ -109,7 +113,10 @@ pub fn edit_category(category: EditCategory) -> Result<Category, APIError> {
   .set(categories::name.eq(&category.name))
   .execute(&mut conn)
   {
-    Ok(_) => get_category(&category.id, &category.user_id),
+    Ok(_) => {
+      stats_cache::invalidate(&category.user_id);
+      get_category(&category.id, &category.user_id)
+    }
     Err(_) => Err(APIError::DatabaseError),
   }
 }
@@ -210,7 +217,10 @@ pub fn delete_category(category_id: &str, user_id: &str) -> Result<bool, APIErro
   )
   .execute(&mut conn)
   {
-    Ok(count) => Ok(count > 0),
+    Ok(count) => {
+      stats_cache::invalidate(user_id);
+      Ok(count > 0)
+    }
     Err(_) => Err(APIError::DatabaseError),
   }
 }
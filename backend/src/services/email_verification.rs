@@ -0,0 +1,97 @@
+use crate::{
+  establish_connection, schema,
+  services::{
+    mail::{app_url, Email, Mailer},
+    signed_token::{self, TokenPurpose},
+    user,
+  },
+  util::{clock::Clock, error::APIError},
+};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+/// How long an email verification link works
+pub const VERIFICATION_TOKEN_LIFETIME_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VerifyEmail {
+  pub token: String,
+}
+
+/// Emails a link verifying the user's current email
+pub fn send_verification_email(
+  user_id: &str,
+  mailer: &dyn Mailer,
+  clock: &dyn Clock,
+) -> Result<(), APIError> {
+  let user = user::get_user(user_id)?;
+
+  if user.email_verified {
+    return Err(APIError::EmailAlreadyVerified);
+  }
+
+  let token = signed_token::sign(
+    TokenPurpose::EmailVerification,
+    &user.id,
+    &signed_token::binding(&user.email),
+    VERIFICATION_TOKEN_LIFETIME_MS,
+    clock,
+  )?;
+
+  mailer.send(&Email {
+    to: user.email,
+    subject: "Verify your diary.computer email".to_string(),
+    body: format!(
+      "Hi {},\n\nOpen this link within a day to verify the email of your diary.computer account:\n\n{}/verify-email?token={token}\n\nIf you did not create an account, you can ignore this email.",
+      user.name,
+      app_url(),
+    ),
+  })
+}
+
+/// Tells the owner of an email that someone tried to register it or change
+/// their account to it, sent instead of revealing that the email is in use
+pub fn send_email_in_use_notice(user_id: &str, mailer: &dyn Mailer) -> Result<(), APIError> {
+  let user = user::get_user(user_id)?;
+
+  mailer.send(&Email {
+    to: user.email,
+    subject: "Your email was used on diary.computer".to_string(),
+    body: format!(
+      "Hi {},\n\nSomeone tried to register a diary.computer account or change the email of an account to this email, which already belongs to your account. If you forgot your password, you can reset it here:\n\n{}/reset-password\n\nIf this was not you, you can ignore this email.",
+      user.name,
+      app_url(),
+    ),
+  })
+}
+
+/// Marks the email a verification token was sent to as verified, the token
+/// is rejected if the email changed since
+pub fn verify_email(verification: VerifyEmail, clock: &dyn Clock) -> Result<(), APIError> {
+  let claims = signed_token::verify(&verification.token, TokenPurpose::EmailVerification, clock)?;
+
+  let user = match user::get_user(&claims.user_id) {
+    Ok(user) => user,
+    Err(APIError::UserNotFound) => return Err(APIError::InvalidToken),
+    Err(error) => return Err(error),
+  };
+
+  if claims.binding != signed_token::binding(&user.email) {
+    return Err(APIError::InvalidToken);
+  }
+
+  signed_token::consume(&verification.token, &claims, clock)?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::update(schema::users::table.filter(schema::users::id.eq(&user.id)))
+    .set(schema::users::email_verified.eq(true))
+    .execute(&mut conn)
+  {
+    Ok(_) => Ok(()),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
@@ -3,6 +3,7 @@ use crate::{
   schema::{self},
   services::{
     pagination::{Paginated, PaginationObject},
+    stats_cache,
     tag::{get_tag, Tag},
     user::get_user,
   },
@@ -161,6 +162,8 @@ pub fn create_entry(entry: CreateEntry) -> Result<EntryWithTags, APIError> {
     selected_tags: entry.selected_tags,
   };
 
+  stats_cache::invalidate(&entry_with_tags.user_id);
+
   Ok(entry_with_tags)
 }
 
@@ -237,6 +240,8 @@ pub fn edit_entry(entry: EditEntry) -> Result<EntryWithTags, APIError> {
     }
   }
 
+  stats_cache::invalidate(&entry.user_id);
+
   get_entry_with_tags(&entry.id, &entry.user_id)
 }
 
@@ -316,7 +321,10 @@ pub fn delete_entry(entry_id: &str, user_id: &str) -> Result<bool, APIError> {
   )
   .execute(&mut conn)
   {
-    Ok(count) => Ok(count > 0),
+    Ok(count) => {
+      stats_cache::invalidate(user_id);
+      Ok(count > 0)
+    }
     Err(_) => Err(APIError::DatabaseError),
   }
 }
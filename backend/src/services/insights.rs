@@ -0,0 +1,380 @@
+use crate::{
+  establish_connection,
+  schema::{self, insight_notifications, insight_settings},
+  services::user::get_user,
+  util::{
+    self,
+    error::APIError,
+    math::{average, round_to},
+  },
+};
+use chrono::{Duration, NaiveDate};
+use diesel::{
+  prelude::{Insertable, Queryable},
+  ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::OnceLock};
+use validator::Validate;
+
+/// Number of days in the rolling window compared against the baseline
+pub const ROLLING_WINDOW_DAYS: i64 = 7;
+/// Number of days before the rolling window used as the baseline
+pub const BASELINE_DAYS: i64 = 90;
+/// Entries needed in the rolling window before a mood drop is reported
+pub const MIN_WINDOW_ENTRIES: usize = 3;
+/// Entries needed in the baseline before a mood drop is reported
+pub const MIN_BASELINE_ENTRIES: usize = 7;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Insertable, Queryable)]
+#[diesel(table_name = insight_settings)]
+pub struct InsightSettings {
+  pub user_id: String,
+  pub mood_drop_threshold: f64,
+  pub low_mood_level: i32,
+  pub low_mood_days: i32,
+  pub notifications_enabled: bool,
+  pub updated_at: i64,
+}
+
+impl InsightSettings {
+  fn default_for(user_id: &str) -> Self {
+    InsightSettings {
+      user_id: user_id.to_string(),
+      mood_drop_threshold: 1.0,
+      low_mood_level: 1,
+      low_mood_days: 3,
+      notifications_enabled: true,
+      updated_at: 0,
+    }
+  }
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct UpdateInsightSettings {
+  #[validate(range(min = 0.1, max = 4.0))]
+  pub mood_drop_threshold: f64,
+  #[validate(range(min = 1, max = 4))]
+  pub low_mood_level: i32,
+  #[validate(range(min = 2, max = 30))]
+  pub low_mood_days: i32,
+  pub notifications_enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Insight {
+  /// The rolling window average is more than `mood_drop_threshold` below the baseline
+  MoodDrop {
+    from_date: NaiveDate,
+    to_date: NaiveDate,
+    average_mood: f64,
+    baseline_mood: f64,
+    difference: f64,
+  },
+  /// At least `low_mood_days` consecutive days at or below `low_mood_level`
+  LowMoodStreak {
+    from_date: NaiveDate,
+    to_date: NaiveDate,
+    length: i64,
+  },
+}
+
+impl Insight {
+  fn kind(&self) -> &'static str {
+    match self {
+      Insight::MoodDrop { .. } => "mood_drop",
+      Insight::LowMoodStreak { .. } => "low_mood_streak",
+    }
+  }
+
+  fn dates(&self) -> (NaiveDate, NaiveDate) {
+    match self {
+      Insight::MoodDrop {
+        from_date, to_date, ..
+      }
+      | Insight::LowMoodStreak {
+        from_date, to_date, ..
+      } => (*from_date, *to_date),
+    }
+  }
+}
+
+/// An insight that was reported, kept until the user is removed
+#[derive(Debug, Clone, Insertable, Queryable)]
+#[diesel(table_name = insight_notifications)]
+struct InsightNotification {
+  user_id: String,
+  kind: String,
+  from_date: NaiveDate,
+  to_date: NaiveDate,
+}
+
+/// Receives insights when they become active for a user
+pub trait InsightNotifier: Send + Sync {
+  fn notify(&self, user_id: &str, insights: &[Insight]);
+}
+
+/// Default notifier, writes insights to the server log
+pub struct LogNotifier;
+
+impl InsightNotifier for LogNotifier {
+  fn notify(&self, user_id: &str, insights: &[Insight]) {
+    for insight in insights {
+      tracing::event!(
+        tracing::Level::INFO,
+        "insight for user {user_id}: {insight:?}"
+      );
+    }
+  }
+}
+
+static NOTIFIER: OnceLock<Box<dyn InsightNotifier>> = OnceLock::new();
+
+/// Set the notifier used by `notify_insights`, can only be set once
+/// and returns false if a notifier was already set
+pub fn set_notifier(notifier: Box<dyn InsightNotifier>) -> bool {
+  NOTIFIER.set(notifier).is_ok()
+}
+
+pub fn get_insight_settings(user_id: &str) -> Result<InsightSettings, APIError> {
+  let user = get_user(user_id);
+
+  if user.is_err() {
+    return Err(APIError::UserNotFound);
+  }
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match insight_settings::table
+    .filter(insight_settings::user_id.eq(user_id))
+    .first::<InsightSettings>(&mut conn)
+    .optional()
+  {
+    Ok(Some(settings)) => Ok(settings),
+    Ok(None) => Ok(InsightSettings::default_for(user_id)),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+pub fn update_insight_settings(
+  user_id: &str,
+  settings: UpdateInsightSettings,
+) -> Result<InsightSettings, APIError> {
+  match settings.validate() {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::BadRequest),
+  }
+
+  let user = get_user(user_id);
+
+  if user.is_err() {
+    return Err(APIError::UserNotFound);
+  }
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let new_settings = InsightSettings {
+    user_id: user_id.to_string(),
+    mood_drop_threshold: settings.mood_drop_threshold,
+    low_mood_level: settings.low_mood_level,
+    low_mood_days: settings.low_mood_days,
+    notifications_enabled: settings.notifications_enabled,
+    updated_at: util::unix_time::unix_ms(),
+  };
+
+  match diesel::insert_into(insight_settings::table)
+    .values(&new_settings)
+    .on_conflict(insight_settings::user_id)
+    .do_update()
+    .set((
+      insight_settings::mood_drop_threshold.eq(new_settings.mood_drop_threshold),
+      insight_settings::low_mood_level.eq(new_settings.low_mood_level),
+      insight_settings::low_mood_days.eq(new_settings.low_mood_days),
+      insight_settings::notifications_enabled.eq(new_settings.notifications_enabled),
+      insight_settings::updated_at.eq(new_settings.updated_at),
+    ))
+    .execute(&mut conn)
+  {
+    Ok(_) => Ok(new_settings),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Get the insights active for a user as of today
+pub fn active_insights(user_id: &str) -> Result<Vec<Insight>, APIError> {
+  active_insights_at(user_id, chrono::Utc::now().date_naive())
+}
+
+/// Get the insights active for a user as of `date`
+/// - mood drop: the last `ROLLING_WINDOW_DAYS` days against the `BASELINE_DAYS` before them
+/// - low mood streak: consecutive low days ending on `date` or the day before,
+///   so a streak stays active until the day's entry has been written
+pub fn active_insights_at(user_id: &str, date: NaiveDate) -> Result<Vec<Insight>, APIError> {
+  let settings = get_insight_settings(user_id)?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let window_start = date - Duration::days(ROLLING_WINDOW_DAYS - 1);
+  let baseline_start = window_start - Duration::days(BASELINE_DAYS);
+
+  let entries = match schema::entries::table
+    .filter(schema::entries::user_id.eq(user_id))
+    .filter(schema::entries::date.ge(baseline_start))
+    .filter(schema::entries::date.le(date))
+    .select((schema::entries::date, schema::entries::mood))
+    .load::<(NaiveDate, i32)>(&mut conn)
+  {
+    Ok(entries) => entries,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut insights = vec![];
+
+  let window: Vec<i32> = entries
+    .iter()
+    .filter(|(entry_date, _)| *entry_date >= window_start)
+    .map(|(_, mood)| *mood)
+    .collect();
+  let baseline: Vec<i32> = entries
+    .iter()
+    .filter(|(entry_date, _)| *entry_date < window_start)
+    .map(|(_, mood)| *mood)
+    .collect();
+
+  if window.len() >= MIN_WINDOW_ENTRIES && baseline.len() >= MIN_BASELINE_ENTRIES {
+    let average_mood = average(&window).unwrap_or(0.0);
+    let baseline_mood = average(&baseline).unwrap_or(0.0);
+
+    if baseline_mood - average_mood > settings.mood_drop_threshold {
+      insights.push(Insight::MoodDrop {
+        from_date: window_start,
+        to_date: date,
+        average_mood: round_to(average_mood, 2),
+        baseline_mood: round_to(baseline_mood, 2),
+        difference: round_to(baseline_mood - average_mood, 2),
+      });
+    }
+  }
+
+  // entries are unique per date
+  let moods: HashMap<NaiveDate, i32> = entries.into_iter().collect();
+  let is_low = |day: NaiveDate| {
+    moods
+      .get(&day)
+      .is_some_and(|mood| *mood <= settings.low_mood_level)
+  };
+
+  let streak_end = if moods.contains_key(&date) {
+    date
+  } else {
+    date - Duration::days(1)
+  };
+  let mut streak_start = streak_end;
+  while is_low(streak_start) {
+    streak_start -= Duration::days(1);
+  }
+  let length = (streak_end - streak_start).num_days();
+
+  if length >= settings.low_mood_days as i64 {
+    insights.push(Insight::LowMoodStreak {
+      from_date: streak_start + Duration::days(1),
+      to_date: streak_end,
+      length,
+    });
+  }
+
+  Ok(insights)
+}
+
+/// Remembers that an insight was reported, returns false when it already was.
+/// Insights of the same kind are the same as a reported one when their dates
+/// overlap or follow on from it, so a streak that grows or a drop that lasts
+/// into the next days is only reported once
+fn mark_notified(user_id: &str, insight: &Insight) -> Result<bool, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let (from_date, to_date) = insight.dates();
+
+  let reported = match insight_notifications::table
+    .filter(insight_notifications::user_id.eq(user_id))
+    .filter(insight_notifications::kind.eq(insight.kind()))
+    .filter(insight_notifications::from_date.le(to_date))
+    .filter(insight_notifications::to_date.ge(from_date - Duration::days(1)))
+    .order(insight_notifications::from_date.desc())
+    .first::<InsightNotification>(&mut conn)
+    .optional()
+  {
+    Ok(reported) => reported,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  if let Some(reported) = reported {
+    return match diesel::update(
+      insight_notifications::table
+        .filter(insight_notifications::user_id.eq(user_id))
+        .filter(insight_notifications::kind.eq(insight.kind()))
+        .filter(insight_notifications::from_date.eq(reported.from_date)),
+    )
+    .set(insight_notifications::to_date.eq(reported.to_date.max(to_date)))
+    .execute(&mut conn)
+    {
+      Ok(_) => Ok(false),
+      Err(_) => Err(APIError::DatabaseError),
+    };
+  }
+
+  // the key makes a report checked at the same time by another request a no-op
+  match diesel::insert_into(insight_notifications::table)
+    .values(&InsightNotification {
+      user_id: user_id.to_string(),
+      kind: insight.kind().to_string(),
+      from_date,
+      to_date,
+    })
+    .on_conflict_do_nothing()
+    .execute(&mut conn)
+  {
+    Ok(inserted) => Ok(inserted > 0),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Check a user's insights and pass any that were not already reported to the notifier,
+/// returns the insights that were sent
+pub fn notify_insights(user_id: &str) -> Result<Vec<Insight>, APIError> {
+  let settings = get_insight_settings(user_id)?;
+
+  if !settings.notifications_enabled {
+    return Ok(vec![]);
+  }
+
+  let insights = active_insights(user_id)?;
+
+  let mut new_insights = vec![];
+  for insight in insights {
+    if mark_notified(user_id, &insight)? {
+      new_insights.push(insight);
+    }
+  }
+
+  if !new_insights.is_empty() {
+    NOTIFIER
+      .get_or_init(|| Box::new(LogNotifier))
+      .notify(user_id, &new_insights);
+  }
+
+  Ok(new_insights)
+}
@@ -3,6 +3,7 @@ use crate::{
   schema::{categories, entries, tags},
   services::{
     category::{create_category, CreateCategory},
+    stats_cache,
     tag::{create_tag, CreateTag},
     user::get_user,
   },
@@ -88,5 +89,7 @@ pub fn delete_all_user_data(user_id: &str) -> Result<bool, APIError> {
     return Err(APIError::DatabaseError);
   }
 
+  stats_cache::invalidate(user_id);
+
   Ok(true)
 }
@@ -0,0 +1,314 @@
+use crate::util::{error::APIError, unix_time::unix_ms};
+use dotenvy::dotenv;
+use lettre::{
+  address::Envelope,
+  transport::smtp::{
+    authentication::Credentials,
+    client::{Tls, TlsParameters},
+    extension::ClientId,
+  },
+  Address, SmtpTransport, Transport,
+};
+use std::{
+  env, fs,
+  path::PathBuf,
+  sync::{
+    mpsc::{self, SyncSender},
+    OnceLock,
+  },
+  thread,
+  time::Duration,
+};
+use uuid::Uuid;
+
+/// Default sender address
+pub const DEFAULT_MAIL_FROM: &str = "diary.computer <no-reply@localhost>";
+/// Default frontend URL used in emailed links
+pub const DEFAULT_APP_URL: &str = "http://localhost:3000";
+/// Default SMTP port, the port MailHog and Mailpit listen on
+pub const DEFAULT_SMTP_PORT: u16 = 1025;
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Emails waiting to be sent before new ones are refused
+const MAIL_QUEUE_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+  pub to: String,
+  pub subject: String,
+  /// Plain text body
+  pub body: String,
+}
+
+impl Email {
+  /// RFC 5322 message with headers, lines end in CRLF
+  pub fn to_message(&self, from: &str) -> String {
+    let body = self.body.replace("\r\n", "\n").replace('\n', "\r\n");
+    format!(
+      "From: {from}\r\nTo: {}\r\nSubject: {}\r\nMessage-ID: <{}@diary.computer>\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{body}\r\n",
+      self.to,
+      self.subject,
+      Uuid::new_v4(),
+    )
+  }
+}
+
+/// Sends emails, selected with MAIL_TRANSPORT
+pub trait Mailer: Send + Sync {
+  fn send(&self, email: &Email) -> Result<(), APIError>;
+}
+
+/// Logs emails instead of sending them, the default for development
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+  fn send(&self, email: &Email) -> Result<(), APIError> {
+    tracing::event!(
+      tracing::Level::INFO,
+      "email to {}: {}\n{}",
+      email.to,
+      email.subject,
+      email.body
+    );
+    Ok(())
+  }
+}
+
+/// Writes every email as an `.eml` file to a directory
+pub struct FileMailer {
+  pub directory: PathBuf,
+  pub from: String,
+}
+
+impl Mailer for FileMailer {
+  fn send(&self, email: &Email) -> Result<(), APIError> {
+    if fs::create_dir_all(&self.directory).is_err() {
+      return Err(APIError::MailError);
+    }
+
+    let path = self
+      .directory
+      .join(format!("{}-{}.eml", unix_ms(), Uuid::new_v4()));
+
+    match fs::write(path, email.to_message(&self.from)) {
+      Ok(_) => Ok(()),
+      Err(_) => Err(APIError::MailError),
+    }
+  }
+}
+
+/// How the SMTP connection is encrypted, from SMTP_TLS
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmtpTls {
+  /// Plain SMTP, for a local relay or a stand-in like MailHog. Refused when
+  /// credentials are configured
+  None,
+  /// Upgrades the connection with STARTTLS and fails if the server does not
+  /// offer it, usually port 587
+  StartTls,
+  /// TLS from the start, usually port 465
+  Tls,
+}
+
+/// Sends emails over SMTP with optional AUTH PLAIN or LOGIN. Credentials are
+/// only sent over an encrypted connection
+pub struct SmtpMailer {
+  pub host: String,
+  pub port: u16,
+  pub from: String,
+  pub credentials: Option<(String, String)>,
+  pub tls: SmtpTls,
+}
+
+/// Address part of `Name <address>`
+fn envelope_address(address: &str) -> &str {
+  match (address.rfind('<'), address.rfind('>')) {
+    (Some(start), Some(end)) if start < end => &address[start + 1..end],
+    _ => address.trim(),
+  }
+}
+
+impl SmtpMailer {
+  fn transport(&self) -> Result<SmtpTransport, APIError> {
+    let tls_parameters = || match TlsParameters::new(self.host.clone()) {
+      Ok(parameters) => Ok(parameters),
+      Err(_) => Err(APIError::MailError),
+    };
+    let tls = match (self.tls, &self.credentials) {
+      (SmtpTls::None, Some(_)) => {
+        tracing::event!(
+          tracing::Level::ERROR,
+          "SMTP credentials are only sent with SMTP_TLS=starttls or tls"
+        );
+        return Err(APIError::MailError);
+      }
+      (SmtpTls::None, None) => Tls::None,
+      (SmtpTls::StartTls, _) => Tls::Required(tls_parameters()?),
+      (SmtpTls::Tls, _) => Tls::Wrapper(tls_parameters()?),
+    };
+
+    let builder = SmtpTransport::builder_dangerous(&self.host)
+      .port(self.port)
+      .tls(tls)
+      .timeout(Some(SMTP_TIMEOUT))
+      .hello_name(ClientId::Domain("diary.computer".to_string()));
+
+    Ok(match &self.credentials {
+      Some((username, password)) => builder
+        .credentials(Credentials::new(username.clone(), password.clone()))
+        .build(),
+      None => builder.build(),
+    })
+  }
+}
+
+impl Mailer for SmtpMailer {
+  fn send(&self, email: &Email) -> Result<(), APIError> {
+    let envelope = match (
+      envelope_address(&self.from).parse::<Address>(),
+      envelope_address(&email.to).parse::<Address>(),
+    ) {
+      (Ok(from), Ok(to)) => match Envelope::new(Some(from), vec![to]) {
+        Ok(envelope) => envelope,
+        Err(_) => return Err(APIError::MailError),
+      },
+      _ => return Err(APIError::MailError),
+    };
+
+    match self
+      .transport()?
+      .send_raw(&envelope, email.to_message(&self.from).as_bytes())
+    {
+      Ok(_) => Ok(()),
+      Err(error) => {
+        tracing::event!(tracing::Level::ERROR, "Failed to send email: {error}");
+        Err(APIError::MailError)
+      }
+    }
+  }
+}
+
+/// Hands emails to a background thread which sends them with another mailer.
+/// Requests do not wait for the transport, so a request that sends an email
+/// takes as long as one that does not
+pub struct QueuedMailer {
+  queue: SyncSender<Email>,
+}
+
+impl QueuedMailer {
+  pub fn new(mailer: Box<dyn Mailer>) -> Self {
+    let (queue, emails) = mpsc::sync_channel::<Email>(MAIL_QUEUE_SIZE);
+    thread::spawn(move || {
+      for email in emails {
+        if mailer.send(&email).is_err() {
+          tracing::event!(
+            tracing::Level::ERROR,
+            "could not send email: {}",
+            email.subject
+          );
+        }
+      }
+    });
+
+    QueuedMailer { queue }
+  }
+}
+
+impl Mailer for QueuedMailer {
+  /// Queues the email, fails only when the queue is full
+  fn send(&self, email: &Email) -> Result<(), APIError> {
+    match self.queue.try_send(email.clone()) {
+      Ok(_) => Ok(()),
+      Err(_) => Err(APIError::MailError),
+    }
+  }
+}
+
+/// The mailer from the environment behind a queue, what request handlers
+/// send emails with
+pub fn queued_mailer() -> &'static QueuedMailer {
+  static MAILER: OnceLock<QueuedMailer> = OnceLock::new();
+  MAILER.get_or_init(|| QueuedMailer::new(mailer_from_env()))
+}
+
+/// Frontend URL emailed links point to, from APP_URL
+pub fn app_url() -> String {
+  dotenv().ok();
+
+  env::var("APP_URL")
+    .unwrap_or(DEFAULT_APP_URL.to_string())
+    .trim_end_matches('/')
+    .to_string()
+}
+
+/// Mailer configured from the environment. MAIL_TRANSPORT selects `log`
+/// (default), `file` (MAIL_DIRECTORY) or `smtp` (SMTP_HOST, SMTP_PORT,
+/// SMTP_TLS and optionally SMTP_USERNAME and SMTP_PASSWORD), MAIL_FROM sets
+/// the sender. SMTP_TLS is `none`, `starttls` or `tls`, it defaults to
+/// `starttls` with credentials and `none` without
+pub fn mailer_from_env() -> Box<dyn Mailer> {
+  dotenv().ok();
+
+  let from = env::var("MAIL_FROM").unwrap_or(DEFAULT_MAIL_FROM.to_string());
+
+  match env::var("MAIL_TRANSPORT").as_deref() {
+    Ok("smtp") => {
+      let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+        (Ok(username), Ok(password)) => Some((username, password)),
+        _ => None,
+      };
+      let tls = match env::var("SMTP_TLS")
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+      {
+        "none" => SmtpTls::None,
+        "starttls" => SmtpTls::StartTls,
+        "tls" => SmtpTls::Tls,
+        _ if credentials.is_some() => SmtpTls::StartTls,
+        _ => SmtpTls::None,
+      };
+
+      Box::new(SmtpMailer {
+        host: env::var("SMTP_HOST").unwrap_or("localhost".to_string()),
+        port: env::var("SMTP_PORT")
+          .ok()
+          .and_then(|port| port.parse::<u16>().ok())
+          .unwrap_or(DEFAULT_SMTP_PORT),
+        from,
+        credentials,
+        tls,
+      })
+    }
+    Ok("file") => Box::new(FileMailer {
+      directory: PathBuf::from(env::var("MAIL_DIRECTORY").unwrap_or("mail".to_string())),
+      from,
+    }),
+    _ => Box::new(LogMailer),
+  }
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  #[test]
+  fn test_message() {
+    let email = Email {
+      to: "user@example.com".to_string(),
+      subject: "Hello".to_string(),
+      body: "line one\nline two".to_string(),
+    };
+    let message = email.to_message(DEFAULT_MAIL_FROM);
+
+    assert!(message.starts_with("From: diary.computer <no-reply@localhost>\r\n"));
+    assert!(message.contains("\r\nTo: user@example.com\r\nSubject: Hello\r\n"));
+    assert!(message.ends_with("\r\n\r\nline one\r\nline two\r\n"));
+  }
+
+  #[test]
+  fn test_envelope_address() {
+    assert_eq!(envelope_address(DEFAULT_MAIL_FROM), "no-reply@localhost");
+    assert_eq!(envelope_address(" user@example.com "), "user@example.com");
+  }
+}
@@ -0,0 +1,877 @@
+use crate::{
+  establish_connection,
+  schema::{oidc_identities, oidc_states},
+  services::{
+    auth::{self, LoginResult, SessionMetadata},
+    invite, mail, two_factor, user,
+  },
+  util::{clock::Clock, error::APIError},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use diesel::{
+  deserialize::Queryable, ExpressionMethods, Insertable, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use dotenvy::dotenv;
+use p256::{
+  ecdsa::{signature::Verifier, Signature, VerifyingKey},
+  EncodedPoint, FieldBytes,
+};
+use rand::{rngs::OsRng, RngCore};
+use rsa::{pkcs1v15, traits::PublicKeyParts, BigUint, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+  collections::HashMap,
+  env,
+  sync::{Mutex, OnceLock},
+  time::Duration,
+};
+
+/// Time to complete a login at the provider (10 minutes)
+pub const OIDC_STATE_LIFETIME_MS: i64 = 10 * 60 * 1000;
+/// Name of the HttpOnly cookie holding the hash of a login's state
+pub const OIDC_STATE_COOKIE_NAME: &str = "diary_oidc_state";
+/// How long discovery documents and keys are cached (1 hour)
+pub const OIDC_METADATA_CACHE_MS: i64 = 60 * 60 * 1000;
+/// Allowed difference between our clock and the provider's
+pub const OIDC_CLOCK_SKEW_MS: i64 = 60 * 1000;
+const OIDC_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_OIDC_SCOPES: &str = "openid email profile";
+/// RS256 keys shorter than 2048 bits are rejected
+const MIN_RSA_KEY_BYTES: usize = 256;
+
+/// An OpenID Connect provider users can log in with
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+  /// Used in URLs and to remember which provider an account is linked to
+  pub id: String,
+  /// Shown on the login button
+  pub name: String,
+  pub issuer: String,
+  pub client_id: String,
+  /// Sent to the token endpoint when set, public clients rely on PKCE alone
+  pub client_secret: Option<String>,
+  pub scopes: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+  pub providers: Vec<OidcProvider>,
+  /// Where providers send users back to, the frontend's callback page
+  pub redirect_url: String,
+  /// First-time sign-ups need an invite, read from INVITE_REQUIRED
+  pub invite_required: bool,
+}
+
+/// Provider shown on the login page
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcProviderInfo {
+  pub id: String,
+  pub name: String,
+}
+
+impl OidcConfig {
+  /// Reads OIDC_PROVIDERS, comma separated provider IDs, and for each ID
+  /// OIDC_<ID>_ISSUER, OIDC_<ID>_CLIENT_ID, OIDC_<ID>_CLIENT_SECRET,
+  /// OIDC_<ID>_NAME and OIDC_<ID>_SCOPES. Providers without an issuer or
+  /// client ID are skipped. OIDC_REDIRECT_URL defaults to APP_URL/login/oidc
+  pub fn from_env() -> Self {
+    dotenv().ok();
+
+    let providers = env::var("OIDC_PROVIDERS")
+      .unwrap_or_default()
+      .split(',')
+      .map(|id| id.trim().to_lowercase())
+      .filter(|id| !id.is_empty())
+      .filter_map(|id| {
+        let var = |name: &str| {
+          env::var(format!("OIDC_{}_{name}", id.to_uppercase()))
+            .ok()
+            .filter(|value| !value.is_empty())
+        };
+
+        let (issuer, client_id) = match (var("ISSUER"), var("CLIENT_ID")) {
+          (Some(issuer), Some(client_id)) => (issuer, client_id),
+          _ => {
+            tracing::event!(
+              tracing::Level::WARN,
+              "OIDC provider {id} needs an issuer and client ID, skipped"
+            );
+            return None;
+          }
+        };
+
+        Some(OidcProvider {
+          name: var("NAME").unwrap_or(id.clone()),
+          issuer: issuer.trim_end_matches('/').to_string(),
+          client_id,
+          client_secret: var("CLIENT_SECRET"),
+          scopes: var("SCOPES").unwrap_or(DEFAULT_OIDC_SCOPES.to_string()),
+          id,
+        })
+      })
+      .collect();
+
+    OidcConfig {
+      providers,
+      redirect_url: env::var("OIDC_REDIRECT_URL")
+        .unwrap_or(format!("{}/login/oidc", mail::app_url())),
+      invite_required: env::var("INVITE_REQUIRED").unwrap_or("false".to_string()) == "true",
+    }
+  }
+
+  pub fn provider(&self, id: &str) -> Result<&OidcProvider, APIError> {
+    self
+      .providers
+      .iter()
+      .find(|provider| provider.id == id)
+      .ok_or(APIError::OidcProviderNotFound)
+  }
+
+  pub fn provider_info(&self) -> Vec<OidcProviderInfo> {
+    self
+      .providers
+      .iter()
+      .map(|provider| OidcProviderInfo {
+        id: provider.id.clone(),
+        name: provider.name.clone(),
+      })
+      .collect()
+  }
+}
+
+/// The parts of a provider's discovery document that are used
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+  issuer: String,
+  authorization_endpoint: String,
+  token_endpoint: String,
+  jwks_uri: String,
+}
+
+/// A public key from the provider's JWKS, RSA (`n`, `e`) or P-256 (`x`, `y`)
+#[derive(Debug, Clone, Deserialize)]
+struct Jwk {
+  kty: String,
+  kid: Option<String>,
+  #[serde(rename = "use")]
+  key_use: Option<String>,
+  n: Option<String>,
+  e: Option<String>,
+  crv: Option<String>,
+  x: Option<String>,
+  y: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwks {
+  keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedProvider {
+  fetched_at: i64,
+  metadata: ProviderMetadata,
+  keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Insertable, Queryable)]
+#[diesel(table_name = oidc_states)]
+struct StoredState {
+  state: String,
+  provider: String,
+  code_verifier: String,
+  nonce: String,
+  expires_at: i64,
+}
+
+#[derive(Debug, Insertable, Queryable)]
+#[diesel(table_name = oidc_identities)]
+struct Identity {
+  provider: String,
+  subject: String,
+  user_id: String,
+  email: String,
+  created_at: i64,
+  last_used_at: Option<i64>,
+}
+
+/// Where to send the user to log in at the provider
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcAuthorization {
+  pub authorization_url: String,
+  /// Hash of the state for the `diary_oidc_state` cookie, not sent in the body
+  #[serde(skip)]
+  pub state_cookie: String,
+}
+
+/// Parameters the provider redirected back with, plus an invite code for
+/// sign-ups when invites are required
+#[derive(Debug, Deserialize)]
+pub struct OidcCallback {
+  pub state: String,
+  pub code: String,
+  pub invite: Option<String>,
+  /// The `diary_oidc_state` cookie of the browser finishing the login
+  #[serde(skip)]
+  pub state_cookie: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+  id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenHeader {
+  alg: String,
+  kid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+  One(String),
+  Many(Vec<String>),
+}
+
+impl Audience {
+  fn contains(&self, client_id: &str) -> bool {
+    match self {
+      Audience::One(audience) => audience == client_id,
+      Audience::Many(audiences) => audiences.iter().any(|audience| audience == client_id),
+    }
+  }
+
+  fn len(&self) -> usize {
+    match self {
+      Audience::One(_) => 1,
+      Audience::Many(audiences) => audiences.len(),
+    }
+  }
+}
+
+/// Some providers send `email_verified` as a string
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Flag {
+  Bool(bool),
+  Text(String),
+}
+
+/// Claims of a verified ID token, times in seconds
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+  pub iss: String,
+  pub sub: String,
+  aud: Audience,
+  pub azp: Option<String>,
+  pub exp: i64,
+  pub iat: i64,
+  pub nonce: Option<String>,
+  pub email: Option<String>,
+  email_verified: Option<Flag>,
+  pub name: Option<String>,
+}
+
+impl IdTokenClaims {
+  pub fn email_verified(&self) -> bool {
+    match &self.email_verified {
+      Some(Flag::Bool(verified)) => *verified,
+      Some(Flag::Text(verified)) => verified == "true",
+      None => false,
+    }
+  }
+}
+
+fn random_string() -> String {
+  let mut bytes = [0u8; 32];
+  OsRng.fill_bytes(&mut bytes);
+  URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// PKCE S256 challenge of a code verifier
+pub fn code_challenge(code_verifier: &str) -> String {
+  URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+fn http_client() -> Result<reqwest::Client, APIError> {
+  reqwest::Client::builder()
+    .timeout(OIDC_REQUEST_TIMEOUT)
+    .build()
+    .map_err(|_| APIError::InternalServerError)
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, APIError> {
+  let response = match http_client()?.get(url).send().await {
+    Ok(response) if response.status().is_success() => response,
+    _ => {
+      tracing::event!(tracing::Level::ERROR, "OIDC request to {url} failed");
+      return Err(APIError::OidcProviderError);
+    }
+  };
+
+  response
+    .json::<T>()
+    .await
+    .map_err(|_| APIError::OidcProviderError)
+}
+
+fn provider_cache() -> &'static Mutex<HashMap<String, CachedProvider>> {
+  static CACHE: OnceLock<Mutex<HashMap<String, CachedProvider>>> = OnceLock::new();
+  CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Discovery document and keys of a provider, cached for an hour. `refresh`
+/// fetches them again, used when a token is signed with an unknown key
+async fn provider_metadata(
+  provider: &OidcProvider,
+  refresh: bool,
+  clock: &dyn Clock,
+) -> Result<CachedProvider, APIError> {
+  let cached = match provider_cache().lock() {
+    Ok(cache) => cache.get(&provider.issuer).cloned(),
+    Err(_) => None,
+  };
+  if let Some(cached) = cached {
+    if !refresh && clock.now_ms() - cached.fetched_at < OIDC_METADATA_CACHE_MS {
+      return Ok(cached);
+    }
+  }
+
+  let metadata: ProviderMetadata = get_json(&format!(
+    "{}/.well-known/openid-configuration",
+    provider.issuer
+  ))
+  .await?;
+  if metadata.issuer.trim_end_matches('/') != provider.issuer {
+    tracing::event!(
+      tracing::Level::ERROR,
+      "OIDC provider {} reports issuer {}",
+      provider.id,
+      metadata.issuer
+    );
+    return Err(APIError::OidcProviderError);
+  }
+  let jwks: Jwks = get_json(&metadata.jwks_uri).await?;
+
+  let cached = CachedProvider {
+    fetched_at: clock.now_ms(),
+    metadata,
+    keys: jwks.keys,
+  };
+  if let Ok(mut cache) = provider_cache().lock() {
+    cache.insert(provider.issuer.clone(), cached.clone());
+  }
+  Ok(cached)
+}
+
+/// Starts a login at a provider, returns the URL to send the user to.
+/// The state, nonce and PKCE verifier are kept until the user comes back,
+/// the browser gets the state's hash for the `diary_oidc_state` cookie
+pub async fn begin_login(
+  provider_id: &str,
+  config: &OidcConfig,
+  clock: &dyn Clock,
+) -> Result<OidcAuthorization, APIError> {
+  let provider = config.provider(provider_id)?;
+  let metadata = provider_metadata(provider, false, clock).await?.metadata;
+
+  let stored = StoredState {
+    state: random_string(),
+    provider: provider.id.clone(),
+    code_verifier: random_string(),
+    nonce: random_string(),
+    expires_at: clock.now_ms() + OIDC_STATE_LIFETIME_MS,
+  };
+
+  let authorization_url = match reqwest::Url::parse_with_params(
+    &metadata.authorization_endpoint,
+    &[
+      ("response_type", "code"),
+      ("client_id", &provider.client_id),
+      ("redirect_uri", &config.redirect_url),
+      ("scope", &provider.scopes),
+      ("state", &stored.state),
+      ("nonce", &stored.nonce),
+      ("code_challenge", &code_challenge(&stored.code_verifier)),
+      ("code_challenge_method", "S256"),
+    ],
+  ) {
+    Ok(url) => url.to_string(),
+    Err(_) => return Err(APIError::OidcProviderError),
+  };
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::delete(oidc_states::table.filter(oidc_states::expires_at.le(clock.now_ms())))
+    .execute(&mut conn)
+  {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::insert_into(oidc_states::table)
+    .values(&stored)
+    .execute(&mut conn)
+  {
+    Ok(_) => Ok(OidcAuthorization {
+      authorization_url,
+      state_cookie: state_hash(&stored.state),
+    }),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Hash of a login's state. The browser starting the login keeps it in a
+/// cookie, so a callback URL opened in another browser can not finish it
+fn state_hash(state: &str) -> String {
+  URL_SAFE_NO_PAD.encode(Sha256::digest(state.as_bytes()))
+}
+
+/// `Set-Cookie` value binding a login to the browser that started it
+pub fn state_cookie(state_hash: &str) -> String {
+  format!(
+    "{OIDC_STATE_COOKIE_NAME}={state_hash}; Path=/; Max-Age={}; Secure; HttpOnly; SameSite=Lax",
+    OIDC_STATE_LIFETIME_MS / 1000
+  )
+}
+
+/// `Set-Cookie` value removing the state cookie
+pub fn removal_state_cookie() -> String {
+  format!("{OIDC_STATE_COOKIE_NAME}=; Path=/; Max-Age=0; Secure; HttpOnly; SameSite=Lax")
+}
+
+/// Deletes a login state so it can only be used once
+fn take_state(state: &str, clock: &dyn Clock) -> Result<StoredState, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::delete(oidc_states::table.filter(oidc_states::state.eq(state)))
+    .get_result::<StoredState>(&mut conn)
+    .optional()
+  {
+    Ok(Some(stored)) if stored.expires_at > clock.now_ms() => Ok(stored),
+    Ok(_) => Err(APIError::OidcLoginExpired),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Exchanges the authorization code and PKCE verifier for an ID token
+async fn exchange_code(
+  provider: &OidcProvider,
+  metadata: &ProviderMetadata,
+  code: &str,
+  code_verifier: &str,
+  redirect_url: &str,
+) -> Result<String, APIError> {
+  let mut form = vec![
+    ("grant_type", "authorization_code"),
+    ("code", code),
+    ("redirect_uri", redirect_url),
+    ("client_id", &provider.client_id),
+    ("code_verifier", code_verifier),
+  ];
+  if let Some(client_secret) = &provider.client_secret {
+    form.push(("client_secret", client_secret));
+  }
+
+  let response = match http_client()?
+    .post(&metadata.token_endpoint)
+    .form(&form)
+    .send()
+    .await
+  {
+    Ok(response) => response,
+    Err(_) => return Err(APIError::OidcProviderError),
+  };
+
+  // a rejected code is the user's problem, not the provider's
+  match response.status() {
+    status if status.is_success() => (),
+    status if status.is_client_error() => return Err(APIError::OidcLoginExpired),
+    _ => return Err(APIError::OidcProviderError),
+  }
+
+  match response.json::<TokenResponse>().await {
+    Ok(tokens) => Ok(tokens.id_token),
+    Err(_) => Err(APIError::OidcProviderError),
+  }
+}
+
+/// Checks a JWS signature with a key from the provider's JWKS
+fn verify_signature(alg: &str, key: &Jwk, message: &[u8], signature: &[u8]) -> bool {
+  let decode = |value: &Option<String>| {
+    value
+      .as_deref()
+      .and_then(|value| URL_SAFE_NO_PAD.decode(value).ok())
+  };
+
+  match (alg, key.kty.as_str()) {
+    ("RS256", "RSA") => match (decode(&key.n), decode(&key.e)) {
+      // the rsa crate bounds the modulus size and the exponent
+      (Some(n), Some(e)) => {
+        match RsaPublicKey::new(BigUint::from_bytes_be(&n), BigUint::from_bytes_be(&e)) {
+          Ok(public_key) if public_key.size() >= MIN_RSA_KEY_BYTES => {
+            pkcs1v15::Signature::try_from(signature).is_ok_and(|signature| {
+              pkcs1v15::VerifyingKey::<Sha256>::new(public_key)
+                .verify(message, &signature)
+                .is_ok()
+            })
+          }
+          _ => false,
+        }
+      }
+      _ => false,
+    },
+    ("ES256", "EC") if key.crv.as_deref() == Some("P-256") => {
+      // JWS signatures are the fixed size r || s, not DER
+      match (
+        decode(&key.x),
+        decode(&key.y),
+        Signature::from_slice(signature),
+      ) {
+        (Some(x), Some(y), Ok(signature)) if x.len() == 32 && y.len() == 32 => {
+          let point = EncodedPoint::from_affine_coordinates(
+            FieldBytes::from_slice(&x),
+            FieldBytes::from_slice(&y),
+            false,
+          );
+          VerifyingKey::from_encoded_point(&point)
+            .is_ok_and(|public_key| public_key.verify(message, &signature).is_ok())
+        }
+        _ => false,
+      }
+    }
+    _ => false,
+  }
+}
+
+fn find_key<'a>(keys: &'a [Jwk], kid: Option<&str>, alg: &str) -> Option<&'a Jwk> {
+  let kty = match alg {
+    "RS256" => "RSA",
+    "ES256" => "EC",
+    _ => return None,
+  };
+
+  keys.iter().find(|key| {
+    key.kty == kty
+      && key
+        .key_use
+        .as_deref()
+        .is_none_or(|key_use| key_use == "sig")
+      && (kid.is_none() || key.kid.as_deref() == kid)
+  })
+}
+
+/// Verifies an ID token's signature and claims. Only RS256 and ES256 are
+/// accepted, so unsigned and HMAC tokens are rejected
+fn verify_id_token(
+  id_token: &str,
+  keys_for: &dyn Fn(Option<&str>, &str) -> Option<Jwk>,
+  issuer: &str,
+  client_id: &str,
+  nonce: &str,
+  clock: &dyn Clock,
+) -> Result<IdTokenClaims, APIError> {
+  let parts: Vec<&str> = id_token.trim().split('.').collect();
+  let (header, payload, signature) = match parts.as_slice() {
+    [header, payload, signature] => (*header, *payload, *signature),
+    _ => return Err(APIError::InvalidIdToken),
+  };
+
+  let decode = |part: &str| {
+    URL_SAFE_NO_PAD
+      .decode(part)
+      .map_err(|_| APIError::InvalidIdToken)
+  };
+  let header: IdTokenHeader =
+    serde_json::from_slice(&decode(header)?).map_err(|_| APIError::InvalidIdToken)?;
+
+  let key = keys_for(header.kid.as_deref(), &header.alg).ok_or(APIError::InvalidIdToken)?;
+  let message = format!("{}.{payload}", parts[0]);
+  if !verify_signature(&header.alg, &key, message.as_bytes(), &decode(signature)?) {
+    return Err(APIError::InvalidIdToken);
+  }
+
+  let claims: IdTokenClaims =
+    serde_json::from_slice(&decode(payload)?).map_err(|_| APIError::InvalidIdToken)?;
+
+  let now = clock.now_ms();
+  let valid = claims.iss.trim_end_matches('/') == issuer
+    && claims.aud.contains(client_id)
+    && (claims.aud.len() == 1 || claims.azp.as_deref() == Some(client_id))
+    && claims.exp * 1000 > now - OIDC_CLOCK_SKEW_MS
+    && claims.iat * 1000 <= now + OIDC_CLOCK_SKEW_MS
+    && claims.nonce.as_deref() == Some(nonce);
+
+  match valid {
+    true => Ok(claims),
+    false => Err(APIError::InvalidIdToken),
+  }
+}
+
+/// Finds the user of a provider account. Unknown accounts are linked to the
+/// user with the same email if both sides verified it, otherwise a user is
+/// created, which needs an invite when invites are required
+fn link_user(
+  provider: &OidcProvider,
+  invite_required: bool,
+  claims: &IdTokenClaims,
+  invite: Option<&str>,
+  clock: &dyn Clock,
+) -> Result<String, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let identity = oidc_identities::table
+    .filter(oidc_identities::provider.eq(&provider.id))
+    .filter(oidc_identities::subject.eq(&claims.sub));
+
+  match identity.first::<Identity>(&mut conn).optional() {
+    Ok(Some(identity)) => {
+      return match diesel::update(
+        oidc_identities::table
+          .filter(oidc_identities::provider.eq(&identity.provider))
+          .filter(oidc_identities::subject.eq(&identity.subject)),
+      )
+      .set(oidc_identities::last_used_at.eq(clock.now_ms()))
+      .execute(&mut conn)
+      {
+        Ok(_) => Ok(identity.user_id),
+        Err(_) => Err(APIError::DatabaseError),
+      };
+    }
+    Ok(None) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  }
+
+  let email = match &claims.email {
+    Some(email) if claims.email_verified() => email.trim().to_string(),
+    _ => return Err(APIError::OidcEmailNotVerified),
+  };
+
+  let user_id = match user::get_user_id(&email) {
+    // anyone can sign up with an email they do not own, so only verified
+    // accounts are linked
+    Ok(user_id) => match user::get_user(&user_id)?.email_verified {
+      true => user_id,
+      false => return Err(APIError::OidcAccountNotVerified),
+    },
+    Err(APIError::UserNotFound) => {
+      // users reference the invite by its ID
+      let invite_id = match (invite_required, invite) {
+        (true, Some(invite)) => match invite::use_invite(invite) {
+          Ok(invite) => Some(invite.id),
+          Err(_) => return Err(APIError::InviteNotFound),
+        },
+        (true, None) => return Err(APIError::InviteNotFound),
+        (false, _) => None,
+      };
+
+      let name = claims
+        .name
+        .clone()
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or(email.split('@').next().unwrap_or_default().to_string())
+        .chars()
+        .take(255)
+        .collect();
+      user::create_provider_user(name, email.clone(), invite_id)?.id
+    }
+    Err(error) => return Err(error),
+  };
+
+  match diesel::insert_into(oidc_identities::table)
+    .values(&Identity {
+      provider: provider.id.clone(),
+      subject: claims.sub.clone(),
+      user_id: user_id.clone(),
+      email,
+      created_at: clock.now_ms(),
+      last_used_at: Some(clock.now_ms()),
+    })
+    .execute(&mut conn)
+  {
+    Ok(_) => Ok(user_id),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Completes a login after the provider redirected back. Like a passkey
+/// login a two-factor challenge is returned when the user has it enabled
+pub async fn finish_login(
+  callback: OidcCallback,
+  metadata: SessionMetadata,
+  config: &OidcConfig,
+  clock: &dyn Clock,
+) -> Result<LoginResult, APIError> {
+  // without the cookie the callback may have been opened by someone else,
+  // who would be logged in to the account that started the login
+  if callback.state_cookie.as_deref() != Some(state_hash(&callback.state).as_str()) {
+    return Err(APIError::OidcLoginExpired);
+  }
+  let stored = take_state(&callback.state, clock)?;
+  let provider = config.provider(&stored.provider)?;
+  let cached = provider_metadata(provider, false, clock).await?;
+
+  let id_token = exchange_code(
+    provider,
+    &cached.metadata,
+    &callback.code,
+    &stored.code_verifier,
+    &config.redirect_url,
+  )
+  .await?;
+
+  // providers rotate keys, an unknown key fetches them again once
+  let header_kid = id_token
+    .split('.')
+    .next()
+    .and_then(|header| URL_SAFE_NO_PAD.decode(header).ok())
+    .and_then(|header| serde_json::from_slice::<IdTokenHeader>(&header).ok());
+  let keys = match &header_kid {
+    Some(header) if find_key(&cached.keys, header.kid.as_deref(), &header.alg).is_none() => {
+      provider_metadata(provider, true, clock).await?.keys
+    }
+    _ => cached.keys,
+  };
+
+  let claims = verify_id_token(
+    &id_token,
+    &|kid, alg| find_key(&keys, kid, alg).cloned(),
+    &provider.issuer,
+    &provider.client_id,
+    &stored.nonce,
+    clock,
+  )?;
+
+  let user_id = link_user(
+    provider,
+    config.invite_required,
+    &claims,
+    callback.invite.as_deref(),
+    clock,
+  )?;
+
+  if two_factor::is_enabled(&user_id)? {
+    return Ok(LoginResult::TwoFactorChallenge(
+      two_factor::create_challenge(&user_id, clock)?,
+    ));
+  }
+
+  Ok(LoginResult::Session(auth::create_session_for_user(
+    &user_id, metadata, clock,
+  )?))
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+  use crate::util::clock::ManualClock;
+  use p256::ecdsa::{signature::Signer, SigningKey};
+
+  #[test]
+  fn test_code_challenge() {
+    // RFC 7636 appendix B
+    assert_eq!(
+      code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+      "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+    );
+  }
+
+  #[test]
+  fn test_verify_id_token() {
+    let private_key = SigningKey::random(&mut OsRng);
+    let point = private_key.verifying_key().to_encoded_point(false);
+    let (x, y) = (point.x().unwrap(), point.y().unwrap());
+    let key = Jwk {
+      kty: "EC".to_string(),
+      kid: Some("key".to_string()),
+      key_use: Some("sig".to_string()),
+      n: None,
+      e: None,
+      crv: Some("P-256".to_string()),
+      x: Some(URL_SAFE_NO_PAD.encode(x)),
+      y: Some(URL_SAFE_NO_PAD.encode(y)),
+    };
+    let keys = vec![key];
+    let keys_for = |kid: Option<&str>, alg: &str| find_key(&keys, kid, alg).cloned();
+    let clock = ManualClock::new(1_700_000_000_000);
+
+    let sign = |header: serde_json::Value, claims: serde_json::Value| {
+      let message = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+      );
+      let signature: Signature = private_key.sign(message.as_bytes());
+      format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    };
+    let header = serde_json::json!({ "alg": "ES256", "kid": "key" });
+    let claims = serde_json::json!({
+      "iss": "https://id.example",
+      "sub": "subject",
+      "aud": "client",
+      "exp": 1_700_000_300,
+      "iat": 1_700_000_000,
+      "nonce": "nonce",
+      "email": "user@example.com",
+      "email_verified": "true",
+    });
+    let verify = |token: &str| {
+      verify_id_token(
+        token,
+        &keys_for,
+        "https://id.example",
+        "client",
+        "nonce",
+        &clock,
+      )
+    };
+
+    let claims_of = verify(&sign(header.clone(), claims.clone())).unwrap();
+    assert_eq!(claims_of.sub, "subject");
+    assert!(claims_of.email_verified());
+
+    // every claim is checked
+    for (name, value) in [
+      ("iss", serde_json::json!("https://other.example")),
+      ("aud", serde_json::json!("other")),
+      ("aud", serde_json::json!(["client", "other"])),
+      ("exp", serde_json::json!(1_699_999_000)),
+      ("iat", serde_json::json!(1_700_000_300)),
+      ("nonce", serde_json::json!("other")),
+    ] {
+      let mut claims = claims.clone();
+      claims[name] = value;
+      assert_eq!(
+        verify(&sign(header.clone(), claims)).unwrap_err(),
+        APIError::InvalidIdToken,
+        "{name}"
+      );
+    }
+
+    // unsigned tokens, other algorithms and unknown keys are rejected
+    let token = sign(header.clone(), claims.clone());
+    let (unsigned, _) = token.rsplit_once('.').unwrap();
+    assert!(verify(&format!("{unsigned}.")).is_err());
+    for header in [
+      serde_json::json!({ "alg": "none" }),
+      serde_json::json!({ "alg": "HS256", "kid": "key" }),
+      serde_json::json!({ "alg": "ES256", "kid": "other" }),
+    ] {
+      assert_eq!(
+        verify(&sign(header, claims.clone())).unwrap_err(),
+        APIError::InvalidIdToken
+      );
+    }
+  }
+}
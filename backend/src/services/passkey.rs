@@ -0,0 +1,619 @@
+use crate::{
+  establish_connection,
+  schema::{credentials, webauthn_challenges},
+  services::{
+    auth::{self, LoginResult, SessionMetadata},
+    two_factor, user,
+  },
+  util::{cbor, clock::Clock, error::APIError},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use diesel::{
+  prelude::{Insertable, Queryable},
+  BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use dotenvy::dotenv;
+use p256::{
+  ecdsa::{signature::Verifier, DerSignature, VerifyingKey},
+  EncodedPoint, FieldBytes,
+};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+
+/// Relying party name shown by authenticators
+pub const RP_NAME: &str = "diary.computer";
+/// Time to complete a registration or login ceremony in milliseconds (5 minutes)
+pub const CEREMONY_TIMEOUT_MS: i64 = 5 * 60 * 1000;
+/// ES256, ECDSA with P-256 and SHA-256, the only supported algorithm
+pub const COSE_ALG_ES256: i64 = -7;
+pub const DEFAULT_PASSKEY_NAME: &str = "Passkey";
+
+const CHALLENGE_BYTES: usize = 32;
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+const CEREMONY_REGISTRATION: &str = "registration";
+const CEREMONY_AUTHENTICATION: &str = "authentication";
+
+/// Relying party settings, passkeys only work on the configured domain
+#[derive(Debug, Clone)]
+pub struct WebAuthnConfig {
+  pub rp_id: String,
+  pub origins: Vec<String>,
+}
+
+impl WebAuthnConfig {
+  /// Reads WEBAUTHN_RP_ID (the domain, default localhost) and
+  /// WEBAUTHN_ORIGINS (comma separated origins the frontend is served from)
+  pub fn from_env() -> Self {
+    dotenv().ok();
+
+    WebAuthnConfig {
+      rp_id: env::var("WEBAUTHN_RP_ID").unwrap_or("localhost".to_string()),
+      origins: env::var("WEBAUTHN_ORIGINS")
+        .unwrap_or("http://localhost:3000,http://localhost:5173".to_string())
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect(),
+    }
+  }
+}
+
+/// A registered passkey, the public key is a COSE key in base64url
+#[derive(Debug, Serialize, Insertable, Queryable)]
+#[diesel(table_name = credentials)]
+pub struct Credential {
+  pub id: String,
+  pub user_id: String,
+  pub name: String,
+  #[serde(skip)]
+  pub public_key: String,
+  #[serde(skip)]
+  pub sign_count: i64,
+  pub created_at: i64,
+  pub last_used_at: Option<i64>,
+}
+
+#[derive(Debug, Insertable, Queryable)]
+#[diesel(table_name = webauthn_challenges)]
+struct StoredChallenge {
+  challenge: String,
+  user_id: Option<String>,
+  ceremony: String,
+  expires_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelyingParty {
+  pub id: String,
+  pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+  /// base64url of the user's ID, returned as the user handle on login
+  pub id: String,
+  pub name: String,
+  pub display_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialParameters {
+  #[serde(rename = "type")]
+  pub kind: String,
+  pub alg: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CredentialDescriptor {
+  #[serde(rename = "type")]
+  pub kind: String,
+  pub id: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+  pub resident_key: String,
+  pub user_verification: String,
+}
+
+/// `PublicKeyCredentialCreationOptions` in the WebAuthn JSON format
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+  pub challenge: String,
+  pub rp: RelyingParty,
+  pub user: UserEntity,
+  pub pub_key_cred_params: Vec<CredentialParameters>,
+  pub timeout: i64,
+  pub exclude_credentials: Vec<CredentialDescriptor>,
+  pub authenticator_selection: AuthenticatorSelection,
+  pub attestation: String,
+}
+
+/// `PublicKeyCredentialRequestOptions` in the WebAuthn JSON format,
+/// no credentials are listed so any passkey for the domain can be used
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+  pub challenge: String,
+  pub rp_id: String,
+  pub timeout: i64,
+  pub user_verification: String,
+  pub allow_credentials: Vec<CredentialDescriptor>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+  #[serde(rename = "clientDataJSON")]
+  pub client_data_json: String,
+  pub attestation_object: String,
+}
+
+/// Result of `navigator.credentials.create()`, binary fields in base64url
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RegistrationResponse {
+  pub id: String,
+  /// Name shown in the list of passkeys
+  pub name: Option<String>,
+  pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+  #[serde(rename = "clientDataJSON")]
+  pub client_data_json: String,
+  pub authenticator_data: String,
+  pub signature: String,
+  pub user_handle: Option<String>,
+}
+
+/// Result of `navigator.credentials.get()`, binary fields in base64url
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuthenticationResponse {
+  pub id: String,
+  pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+  #[serde(rename = "type")]
+  kind: String,
+  challenge: String,
+  origin: String,
+}
+
+struct AuthenticatorData {
+  rp_id_hash: Vec<u8>,
+  flags: u8,
+  sign_count: u32,
+  /// Credential ID and COSE public key, only present on registration
+  attested_credential: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+pub fn base64url_encode(bytes: &[u8]) -> String {
+  URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Decodes base64url with or without padding
+pub fn base64url_decode(value: &str) -> Result<Vec<u8>, APIError> {
+  match URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')) {
+    Ok(bytes) => Ok(bytes),
+    Err(_) => Err(APIError::BadRequest),
+  }
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData, APIError> {
+  if data.len() < 37 {
+    return Err(APIError::InvalidPasskey);
+  }
+
+  let flags = data[32];
+  let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+  let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+    // 16 byte AAGUID, 2 byte length, credential ID, COSE key
+    let rest = data.get(37 + 16..).ok_or(APIError::InvalidPasskey)?;
+    let (length, rest) = match rest {
+      [high, low, rest @ ..] => (u16::from_be_bytes([*high, *low]) as usize, rest),
+      _ => return Err(APIError::InvalidPasskey),
+    };
+    if rest.len() < length {
+      return Err(APIError::InvalidPasskey);
+    }
+    let (credential_id, rest) = rest.split_at(length);
+    let (_, key_length) = cbor::decode_prefix(rest).ok_or(APIError::InvalidPasskey)?;
+    Some((credential_id.to_vec(), rest[..key_length].to_vec()))
+  } else {
+    None
+  };
+
+  Ok(AuthenticatorData {
+    rp_id_hash: data[..32].to_vec(),
+    flags,
+    sign_count,
+    attested_credential,
+  })
+}
+
+/// Public key of an ES256 COSE key
+fn parse_public_key(cose_key: &[u8]) -> Result<VerifyingKey, APIError> {
+  let key = cbor::decode(cose_key).ok_or(APIError::InvalidPasskey)?;
+
+  let kty = key.get_int(1).and_then(|kty| kty.as_integer());
+  let alg = key.get_int(3).and_then(|alg| alg.as_integer());
+  let crv = key.get_int(-1).and_then(|crv| crv.as_integer());
+  let x = key.get_int(-2).and_then(|x| x.as_bytes());
+  let y = key.get_int(-3).and_then(|y| y.as_bytes());
+
+  match (kty, alg, crv, x, y) {
+    (Some(2), Some(COSE_ALG_ES256), Some(1), Some(x), Some(y))
+      if x.len() == 32 && y.len() == 32 =>
+    {
+      // rejects coordinates that are not a point on the curve
+      let point = EncodedPoint::from_affine_coordinates(
+        FieldBytes::from_slice(x),
+        FieldBytes::from_slice(y),
+        false,
+      );
+      VerifyingKey::from_encoded_point(&point).map_err(|_| APIError::InvalidPasskey)
+    }
+    _ => Err(APIError::InvalidPasskey),
+  }
+}
+
+fn generate_challenge() -> String {
+  let mut bytes = [0u8; CHALLENGE_BYTES];
+  OsRng.fill_bytes(&mut bytes);
+  base64url_encode(&bytes)
+}
+
+fn store_challenge(
+  user_id: Option<&str>,
+  ceremony: &str,
+  clock: &dyn Clock,
+) -> Result<String, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  // expired challenges of any user are removed as new ones are created
+  match diesel::delete(
+    webauthn_challenges::table.filter(webauthn_challenges::expires_at.le(clock.now_ms())),
+  )
+  .execute(&mut conn)
+  {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let stored = StoredChallenge {
+    challenge: generate_challenge(),
+    user_id: user_id.map(String::from),
+    ceremony: ceremony.to_string(),
+    expires_at: clock.now_ms() + CEREMONY_TIMEOUT_MS,
+  };
+
+  match diesel::insert_into(webauthn_challenges::table)
+    .values(&stored)
+    .execute(&mut conn)
+  {
+    Ok(_) => Ok(stored.challenge),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Checks the client data of a ceremony and uses up its challenge,
+/// returns the user the challenge was created for
+fn verify_client_data(
+  client_data_json: &[u8],
+  ceremony: &str,
+  config: &WebAuthnConfig,
+  clock: &dyn Clock,
+) -> Result<Option<String>, APIError> {
+  let client_data: ClientData = match serde_json::from_slice(client_data_json) {
+    Ok(client_data) => client_data,
+    Err(_) => return Err(APIError::BadRequest),
+  };
+
+  let expected_type = match ceremony {
+    CEREMONY_REGISTRATION => "webauthn.create",
+    _ => "webauthn.get",
+  };
+  if client_data.kind != expected_type || !config.origins.contains(&client_data.origin) {
+    return Err(APIError::InvalidPasskey);
+  }
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  // deleting the challenge makes it single use
+  match diesel::delete(
+    webauthn_challenges::table.filter(
+      webauthn_challenges::challenge
+        .eq(&client_data.challenge)
+        .and(webauthn_challenges::ceremony.eq(ceremony)),
+    ),
+  )
+  .get_result::<StoredChallenge>(&mut conn)
+  .optional()
+  {
+    Ok(Some(stored)) if stored.expires_at > clock.now_ms() => Ok(stored.user_id),
+    Ok(_) => Err(APIError::PasskeyChallengeExpired),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+fn verify_rp_id_hash(authenticator_data: &AuthenticatorData, config: &WebAuthnConfig) -> bool {
+  authenticator_data.rp_id_hash == Sha256::digest(config.rp_id.as_bytes()).to_vec()
+}
+
+pub fn get_user_credentials(user_id: &str) -> Result<Vec<Credential>, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match credentials::table
+    .filter(credentials::user_id.eq(user_id))
+    .order(credentials::created_at.asc())
+    .load::<Credential>(&mut conn)
+  {
+    Ok(credentials) => Ok(credentials),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Deletes a passkey of a user, other users' passkeys are not found
+pub fn revoke_credential(user_id: &str, credential_id: &str) -> Result<(), APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::delete(
+    credentials::table
+      .filter(credentials::id.eq(credential_id))
+      .filter(credentials::user_id.eq(user_id)),
+  )
+  .execute(&mut conn)
+  {
+    Ok(0) => Err(APIError::PasskeyNotFound),
+    Ok(_) => Ok(()),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Starts registering a passkey for a logged in user
+pub fn begin_registration(
+  user_id: &str,
+  config: &WebAuthnConfig,
+  clock: &dyn Clock,
+) -> Result<CreationOptions, APIError> {
+  let user = user::get_user(user_id)?;
+  let challenge = store_challenge(Some(user_id), CEREMONY_REGISTRATION, clock)?;
+
+  let exclude_credentials = get_user_credentials(user_id)?
+    .into_iter()
+    .map(|credential| CredentialDescriptor {
+      kind: "public-key".to_string(),
+      id: credential.id,
+    })
+    .collect();
+
+  Ok(CreationOptions {
+    challenge,
+    rp: RelyingParty {
+      id: config.rp_id.clone(),
+      name: RP_NAME.to_string(),
+    },
+    user: UserEntity {
+      id: base64url_encode(user_id.as_bytes()),
+      name: user.email,
+      display_name: user.name,
+    },
+    pub_key_cred_params: vec![CredentialParameters {
+      kind: "public-key".to_string(),
+      alg: COSE_ALG_ES256,
+    }],
+    timeout: CEREMONY_TIMEOUT_MS,
+    exclude_credentials,
+    authenticator_selection: AuthenticatorSelection {
+      resident_key: "required".to_string(),
+      user_verification: "preferred".to_string(),
+    },
+    attestation: "none".to_string(),
+  })
+}
+
+/// Verifies a new passkey and stores its public key. Attestation
+/// statements are not checked, any authenticator is accepted.
+pub fn finish_registration(
+  user_id: &str,
+  registration: RegistrationResponse,
+  config: &WebAuthnConfig,
+  clock: &dyn Clock,
+) -> Result<Credential, APIError> {
+  let client_data_json = base64url_decode(&registration.response.client_data_json)?;
+  let attestation_object = base64url_decode(&registration.response.attestation_object)?;
+
+  if verify_client_data(&client_data_json, CEREMONY_REGISTRATION, config, clock)?.as_deref()
+    != Some(user_id)
+  {
+    return Err(APIError::PasskeyChallengeExpired);
+  }
+
+  let attestation = cbor::decode(&attestation_object).ok_or(APIError::BadRequest)?;
+  let auth_data = attestation
+    .get_text("authData")
+    .and_then(|auth_data| auth_data.as_bytes())
+    .ok_or(APIError::BadRequest)?;
+  let authenticator_data = parse_authenticator_data(auth_data)?;
+
+  if !verify_rp_id_hash(&authenticator_data, config)
+    || authenticator_data.flags & FLAG_USER_PRESENT == 0
+  {
+    return Err(APIError::InvalidPasskey);
+  }
+
+  let (credential_id, cose_key) = authenticator_data
+    .attested_credential
+    .ok_or(APIError::InvalidPasskey)?;
+  parse_public_key(&cose_key)?;
+
+  let id = base64url_encode(&credential_id);
+  if id != registration.id.trim_end_matches('=') {
+    return Err(APIError::InvalidPasskey);
+  }
+
+  let name = match registration.name.as_deref().map(str::trim) {
+    Some(name) if !name.is_empty() => name.chars().take(255).collect(),
+    _ => DEFAULT_PASSKEY_NAME.to_string(),
+  };
+
+  let credential = Credential {
+    id,
+    user_id: user_id.to_string(),
+    name,
+    public_key: base64url_encode(&cose_key),
+    sign_count: authenticator_data.sign_count as i64,
+    created_at: clock.now_ms(),
+    last_used_at: None,
+  };
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::insert_into(credentials::table)
+    .values(&credential)
+    .on_conflict_do_nothing()
+    .execute(&mut conn)
+  {
+    Ok(0) => Err(APIError::InvalidPasskey),
+    Ok(_) => Ok(credential),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Starts a passkey login
+pub fn begin_authentication(
+  config: &WebAuthnConfig,
+  clock: &dyn Clock,
+) -> Result<RequestOptions, APIError> {
+  Ok(RequestOptions {
+    challenge: store_challenge(None, CEREMONY_AUTHENTICATION, clock)?,
+    rp_id: config.rp_id.clone(),
+    timeout: CEREMONY_TIMEOUT_MS,
+    user_verification: "preferred".to_string(),
+    allow_credentials: vec![],
+  })
+}
+
+/// Verifies a passkey login and creates the session. Passkeys that did not
+/// verify the user (PIN or biometrics) still need the user's TOTP code when
+/// two-factor authentication is enabled.
+pub fn finish_authentication(
+  assertion: AuthenticationResponse,
+  metadata: SessionMetadata,
+  config: &WebAuthnConfig,
+  clock: &dyn Clock,
+) -> Result<LoginResult, APIError> {
+  let client_data_json = base64url_decode(&assertion.response.client_data_json)?;
+  let auth_data = base64url_decode(&assertion.response.authenticator_data)?;
+  let signature = base64url_decode(&assertion.response.signature)?;
+
+  verify_client_data(&client_data_json, CEREMONY_AUTHENTICATION, config, clock)?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let credential = match credentials::table
+    .filter(credentials::id.eq(assertion.id.trim_end_matches('=')))
+    .first::<Credential>(&mut conn)
+    .optional()
+  {
+    Ok(Some(credential)) => credential,
+    Ok(None) => return Err(APIError::InvalidPasskey),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  if let Some(user_handle) = &assertion.response.user_handle {
+    if base64url_decode(user_handle)? != credential.user_id.as_bytes() {
+      return Err(APIError::InvalidPasskey);
+    }
+  }
+
+  let authenticator_data = parse_authenticator_data(&auth_data)?;
+  if !verify_rp_id_hash(&authenticator_data, config)
+    || authenticator_data.flags & FLAG_USER_PRESENT == 0
+  {
+    return Err(APIError::InvalidPasskey);
+  }
+
+  let public_key = parse_public_key(&base64url_decode(&credential.public_key)?)?;
+  let mut signed = auth_data.clone();
+  signed.extend(Sha256::digest(&client_data_json));
+  let verified = DerSignature::from_bytes(&signature)
+    .is_ok_and(|signature| public_key.verify(&signed, &signature).is_ok());
+  if !verified {
+    return Err(APIError::InvalidPasskey);
+  }
+
+  // a counter that does not increase can mean the passkey was cloned
+  let sign_count = authenticator_data.sign_count as i64;
+  if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+    return Err(APIError::InvalidPasskey);
+  }
+
+  // only update a counter that is still lower, so parallel logins with the
+  // same counter can not both succeed. Passkeys without a counter stay at 0
+  let filter = credentials::table
+    .filter(credentials::id.eq(&credential.id))
+    .filter(
+      credentials::sign_count.lt(sign_count).or(
+        credentials::sign_count
+          .eq(sign_count)
+          .and(credentials::sign_count.eq(0)),
+      ),
+    );
+  match diesel::update(filter)
+    .set((
+      credentials::sign_count.eq(sign_count),
+      credentials::last_used_at.eq(clock.now_ms()),
+    ))
+    .execute(&mut conn)
+  {
+    Ok(0) => return Err(APIError::InvalidPasskey),
+    Ok(_) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  if authenticator_data.flags & FLAG_USER_VERIFIED == 0
+    && two_factor::is_enabled(&credential.user_id)?
+  {
+    return Ok(LoginResult::TwoFactorChallenge(
+      two_factor::create_challenge(&credential.user_id, clock)?,
+    ));
+  }
+
+  Ok(LoginResult::Session(auth::create_session_for_user(
+    &credential.user_id,
+    metadata,
+    clock,
+  )?))
+}
@@ -0,0 +1,440 @@
+use crate::{services::auth, util::error::APIError};
+use dotenvy::dotenv;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::{
+  collections::HashSet,
+  env, fs,
+  path::{Path, PathBuf},
+  sync::OnceLock,
+};
+
+/// Default minimum password length in characters
+pub const DEFAULT_MIN_LENGTH: usize = 7;
+/// Highest strength score, see `strength`
+pub const MAX_SCORE: u8 = 4;
+
+/// Very common passwords and words, matched anywhere in a password
+#[rustfmt::skip]
+const COMMON_PASSWORDS: &[&str] = &[
+  "password", "123456", "12345678", "qwerty", "123456789", "12345", "1234", "111111", "1234567",
+  "dragon", "123123", "baseball", "abc123", "football", "monkey", "letmein", "696969", "shadow",
+  "master", "666666", "qwertyuiop", "123321", "mustang", "1234567890", "michael", "654321",
+  "superman", "1qaz2wsx", "7777777", "121212", "000000", "qazwsx", "123qwe", "killer", "trustno1",
+  "jordan", "jennifer", "zxcvbnm", "asdfgh", "hunter", "buster", "soccer", "harley", "batman",
+  "andrew", "tigger", "sunshine", "iloveyou", "2000", "charlie", "robert", "thomas", "hockey",
+  "ranger", "daniel", "starwars", "klaster", "112233", "george", "computer", "michelle", "jessica",
+  "pepper", "1111", "zxcvbn", "555555", "11111111", "131313", "freedom", "777777", "pass", "maggie",
+  "159753", "aaaaaa", "ginger", "princess", "joshua", "cheese", "amanda", "summer", "love",
+  "ashley", "nicole", "chelsea", "biteme", "matthew", "access", "yankees", "987654321", "dallas",
+  "austin", "thunder", "taylor", "matrix", "welcome", "admin", "login", "secret", "diary",
+  "journal", "mood", "passw0rd", "hello", "test", "monday", "winter", "spring", "autumn",
+  "changeme",
+];
+
+/// Keyboard rows walked in passwords like `qwerty` or `asdf`
+const KEYBOARD_ROWS: &[&str] = &["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// Password rules, configured with PASSWORD_MIN_LENGTH, PASSWORD_MIN_SCORE
+/// and BREACHED_PASSWORDS_FILE
+#[derive(Debug)]
+pub struct PasswordPolicy {
+  pub min_length: usize,
+  /// Longest password in bytes, set by the password hasher
+  pub max_length: usize,
+  /// Minimum `strength` score from 0 to 4, 0 accepts any password
+  pub min_score: u8,
+  pub breached: Option<BreachedList>,
+}
+
+/// Password rules clients can check before submitting
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct PasswordRequirements {
+  pub min_length: usize,
+  pub max_length: usize,
+  pub min_score: u8,
+}
+
+impl Default for PasswordPolicy {
+  fn default() -> Self {
+    PasswordPolicy {
+      min_length: DEFAULT_MIN_LENGTH,
+      max_length: auth::ARGON2_MAX_PASSWORD_BYTES,
+      min_score: 0,
+      breached: None,
+    }
+  }
+}
+
+impl PasswordPolicy {
+  pub fn from_env() -> Self {
+    dotenv().ok();
+
+    let breached = match env::var("BREACHED_PASSWORDS_FILE") {
+      Ok(path) if !path.is_empty() => match BreachedList::load(Path::new(&path)) {
+        Ok(list) => Some(list),
+        Err(_) => {
+          tracing::event!(
+            tracing::Level::ERROR,
+            "could not read BREACHED_PASSWORDS_FILE {path}, breached passwords are not checked"
+          );
+          None
+        }
+      },
+      _ => None,
+    };
+
+    let max_length = auth::password_hasher().max_password_bytes();
+
+    PasswordPolicy {
+      min_length: env::var("PASSWORD_MIN_LENGTH")
+        .ok()
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MIN_LENGTH)
+        .clamp(1, max_length),
+      max_length,
+      min_score: env::var("PASSWORD_MIN_SCORE")
+        .ok()
+        .and_then(|score| score.parse::<u8>().ok())
+        .unwrap_or(0)
+        .min(MAX_SCORE),
+      breached,
+    }
+  }
+
+  pub fn requirements(&self) -> PasswordRequirements {
+    PasswordRequirements {
+      min_length: self.min_length,
+      max_length: self.max_length,
+      min_score: self.min_score,
+    }
+  }
+
+  /// Checks a new password. `user_inputs` like the name and email count as
+  /// easy to guess
+  pub fn check(&self, password: &str, user_inputs: &[&str]) -> Result<(), APIError> {
+    if password.chars().count() < self.min_length || password.len() > self.max_length {
+      return Err(APIError::WeakPassword);
+    }
+
+    if self.min_score > 0 && strength(password, user_inputs) < self.min_score {
+      return Err(APIError::WeakPassword);
+    }
+
+    match &self.breached {
+      Some(list) if list.contains(password) => Err(APIError::BreachedPassword),
+      _ => Ok(()),
+    }
+  }
+}
+
+/// Policy from the environment, loaded once since the breached list can be large
+pub fn policy() -> &'static PasswordPolicy {
+  static POLICY: OnceLock<PasswordPolicy> = OnceLock::new();
+  POLICY.get_or_init(PasswordPolicy::from_env)
+}
+
+/// Offline list of breached passwords as SHA-1 hashes, in the format of the
+/// Have I Been Pwned downloads. Either a file with one `HASH` or `HASH:COUNT`
+/// per line, or a directory of range files named by the first 5 hex
+/// characters of the hash holding `SUFFIX:COUNT` lines. Range files are read
+/// per lookup, so only hashes sharing the 5 character prefix are ever loaded
+#[derive(Debug)]
+pub enum BreachedList {
+  Hashes(HashSet<[u8; 20]>),
+  Ranges(PathBuf),
+}
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+  if hex.len() != N * 2 {
+    return None;
+  }
+
+  let mut bytes = [0u8; N];
+  for (index, byte) in bytes.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(hex.get(index * 2..index * 2 + 2)?, 16).ok()?;
+  }
+  Some(bytes)
+}
+
+impl BreachedList {
+  pub fn load(path: &Path) -> std::io::Result<Self> {
+    if path.is_dir() {
+      return Ok(BreachedList::Ranges(path.to_path_buf()));
+    }
+
+    let hashes = fs::read_to_string(path)?
+      .lines()
+      .filter_map(|line| decode_hex::<20>(line.split(':').next().unwrap_or("").trim()))
+      .collect();
+
+    Ok(BreachedList::Hashes(hashes))
+  }
+
+  pub fn contains(&self, password: &str) -> bool {
+    let hash: [u8; 20] = Sha1::digest(password.as_bytes()).into();
+
+    match self {
+      BreachedList::Hashes(hashes) => hashes.contains(&hash),
+      BreachedList::Ranges(directory) => {
+        let hex: String = hash.iter().map(|byte| format!("{byte:02X}")).collect();
+        let (prefix, suffix) = hex.split_at(5);
+
+        let range = fs::read_to_string(directory.join(prefix))
+          .or_else(|_| fs::read_to_string(directory.join(format!("{prefix}.txt"))));
+
+        match range {
+          Ok(range) => range.lines().any(|line| {
+            line
+              .split(':')
+              .next()
+              .is_some_and(|candidate| candidate.trim().eq_ignore_ascii_case(suffix))
+          }),
+          Err(_) => false,
+        }
+      }
+    }
+  }
+}
+
+/// Undoes common character substitutions like `p@ssw0rd`
+fn unleet(c: char) -> char {
+  match c {
+    '4' | '@' => 'a',
+    '3' => 'e',
+    '1' | '!' => 'i',
+    '0' => 'o',
+    '$' | '5' => 's',
+    '7' => 't',
+    _ => c,
+  }
+}
+
+fn cardinality(c: char) -> f64 {
+  match c {
+    'a'..='z' | 'A'..='Z' => 26.0,
+    '0'..='9' => 10.0,
+    _ if c.is_ascii() => 33.0,
+    _ => 100.0,
+  }
+}
+
+/// Guesses for a dictionary word at `start`, the longest match wins
+fn dictionary_match(
+  lower: &[char],
+  unleeted: &[char],
+  start: usize,
+  words: &[(String, f64)],
+) -> Option<(usize, f64)> {
+  let mut best: Option<(usize, f64)> = None;
+
+  for (word, rank) in words {
+    let word: Vec<char> = word.chars().collect();
+    let end = start + word.len();
+    if end > lower.len() || best.is_some_and(|(length, _)| length >= word.len()) {
+      continue;
+    }
+
+    if lower[start..end] == word[..] {
+      best = Some((word.len(), *rank));
+    } else if unleeted[start..end] == word[..] {
+      best = Some((word.len(), *rank * 2.0));
+    }
+  }
+
+  best
+}
+
+/// Length of a repeated, sequential or keyboard run at `start`, with its guesses
+fn run_match(lower: &[char], start: usize) -> Option<(usize, f64)> {
+  let run = |step: &dyn Fn(char, char) -> bool| {
+    let mut end = start + 1;
+    while end < lower.len() && step(lower[end - 1], lower[end]) {
+      end += 1;
+    }
+    end - start
+  };
+
+  let repeat = run(&|a, b| a == b);
+  let ascending = run(&|a, b| b as u32 == a as u32 + 1);
+  let descending = run(&|a, b| a as u32 == b as u32 + 1);
+  let keyboard = run(&|a, b| {
+    KEYBOARD_ROWS.iter().any(|row| {
+      row
+        .find(a)
+        .is_some_and(|index| row[index..].chars().nth(1) == Some(b))
+    })
+  });
+
+  let base = cardinality(lower[start]);
+  [
+    (repeat, base),
+    (ascending, base / 2.0),
+    (descending, base),
+    (keyboard, 40.0),
+  ]
+  .into_iter()
+  .filter(|(length, _)| *length >= 3)
+  .max_by_key(|(length, _)| *length)
+  .map(|(length, base)| (length, base * length as f64))
+}
+
+/// Estimated number of guesses to find a password, as log10. Patterns like
+/// common passwords, repeats, sequences and keyboard walks are much cheaper
+/// to guess than random characters
+pub fn guesses_log10(password: &str, user_inputs: &[&str]) -> f64 {
+  let original: Vec<char> = password.chars().collect();
+  // lowercase each character on its own, some characters lowercase to
+  // several, which would shift the positions against `original`
+  let lower: Vec<char> = original
+    .iter()
+    .map(|c| c.to_lowercase().next().unwrap_or(*c))
+    .collect();
+  let unleeted: Vec<char> = lower.iter().map(|c| unleet(*c)).collect();
+
+  // rank 1 for the user's own details, so they are tried first
+  let words: Vec<(String, f64)> = user_inputs
+    .iter()
+    .flat_map(|input| {
+      input
+        .to_lowercase()
+        .split(['@', '.', ' ', '-', '_'])
+        .map(String::from)
+        .collect::<Vec<_>>()
+    })
+    .filter(|input| input.chars().count() >= 3)
+    .map(|input| (input, 1.0))
+    .chain(
+      COMMON_PASSWORDS
+        .iter()
+        .enumerate()
+        .map(|(rank, word)| (word.to_string(), rank as f64 + 1.0)),
+    )
+    .collect();
+
+  // cheapest way to guess the first `index` characters
+  let mut best = vec![f64::INFINITY; lower.len() + 1];
+  best[0] = 0.0;
+
+  for start in 0..lower.len() {
+    if best[start].is_infinite() {
+      continue;
+    }
+
+    let mut candidates = vec![(1, cardinality(original[start]).log10())];
+    if let Some((length, guesses)) = dictionary_match(&lower, &unleeted, start, &words) {
+      // capitalised words are a little harder to guess
+      let capitals = original[start..start + length]
+        .iter()
+        .any(|c| c.is_uppercase());
+      candidates.push((length, (guesses * if capitals { 2.0 } else { 1.0 }).log10()));
+    }
+    if let Some((length, guesses)) = run_match(&lower, start) {
+      candidates.push((length, guesses.log10()));
+    }
+
+    for (length, guesses) in candidates {
+      let total = best[start] + guesses;
+      if total < best[start + length] {
+        best[start + length] = total;
+      }
+    }
+  }
+
+  best[lower.len()]
+}
+
+/// Strength score from 0 (too guessable) to 4 (very unguessable), using the
+/// thresholds of zxcvbn
+pub fn strength(password: &str, user_inputs: &[&str]) -> u8 {
+  match guesses_log10(password, user_inputs) {
+    guesses if guesses < 3.0 => 0,
+    guesses if guesses < 6.0 => 1,
+    guesses if guesses < 8.0 => 2,
+    guesses if guesses < 10.0 => 3,
+    _ => 4,
+  }
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  #[test]
+  fn test_strength() {
+    assert_eq!(strength("password", &[]), 0);
+    assert_eq!(strength("P@ssw0rd", &[]), 0);
+    assert_eq!(strength("aaaaaaaaaaaa", &[]), 0);
+    assert_eq!(strength("abcdefghijk", &[]), 0);
+    assert_eq!(strength("qwertyuiop", &[]), 0);
+    assert!(strength("password123", &[]) <= 1);
+    assert!(strength("alice2024", &["Alice", "alice@example.com"]) <= 1);
+    assert!(strength("alice2024", &[]) >= 2);
+    assert_eq!(strength("correct horse battery staple", &[]), 4);
+    assert_eq!(strength("vK8#qZ!2mW", &[]), 4);
+    // lowercases to more characters than it has
+    assert!(strength("İaaaaaaa", &[]) <= 1);
+  }
+
+  #[test]
+  fn test_check() {
+    let policy = PasswordPolicy {
+      min_length: 8,
+      max_length: auth::BCRYPT_MAX_PASSWORD_BYTES,
+      min_score: 3,
+      breached: None,
+    };
+
+    assert_eq!(policy.check("Kx9!", &[]), Err(APIError::WeakPassword));
+    assert_eq!(
+      policy.check("password1234", &[]),
+      Err(APIError::WeakPassword)
+    );
+    assert_eq!(
+      policy.check(&"x".repeat(73), &[]),
+      Err(APIError::WeakPassword)
+    );
+    assert!(policy.check("tulip-orbit-canyon", &[]).is_ok());
+
+    // Argon2id allows longer passwords than bcrypt
+    let default = PasswordPolicy::default();
+    assert!(default.check("password", &[]).is_ok());
+    assert!(default.check(&"x".repeat(73), &[]).is_ok());
+    assert!(default.check(&"x".repeat(1025), &[]).is_err());
+  }
+
+  #[test]
+  fn test_breached_list() {
+    let directory = std::env::temp_dir().join(format!("breached-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&directory).unwrap();
+
+    // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+    let file = directory.join("list.txt");
+    fs::write(&file, "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\n").unwrap();
+    let list = BreachedList::load(&file).unwrap();
+    assert!(list.contains("password"));
+    assert!(!list.contains("tulip-orbit-canyon"));
+
+    let ranges = directory.join("ranges");
+    fs::create_dir_all(&ranges).unwrap();
+    fs::write(
+      ranges.join("5BAA6"),
+      "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n",
+    )
+    .unwrap();
+    let list = BreachedList::load(&ranges).unwrap();
+    assert!(list.contains("password"));
+    assert!(!list.contains("tulip-orbit-canyon"));
+
+    let policy = PasswordPolicy {
+      breached: Some(list),
+      ..Default::default()
+    };
+    assert_eq!(
+      policy.check("password", &[]),
+      Err(APIError::BreachedPassword)
+    );
+
+    fs::remove_dir_all(&directory).ok();
+  }
+}
@@ -0,0 +1,126 @@
+use crate::{
+  establish_connection, schema,
+  services::{
+    api_token,
+    auth::{self, delete_all_user_sessions},
+    mail::{app_url, Email, Mailer},
+    password_policy,
+    signed_token::{self, TokenPurpose},
+    user,
+  },
+  util::{clock::Clock, error::APIError},
+};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// How long a password reset link works
+pub const RESET_TOKEN_LIFETIME_MS: i64 = 60 * 60 * 1000;
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct PasswordResetRequest {
+  #[validate(email)]
+  pub email: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PasswordReset {
+  pub token: String,
+  /// Checked against the password policy
+  pub password: String,
+}
+
+/// Reset tokens are bound to the email and password hash, so a link stops
+/// working once the password or email changes
+fn reset_binding(email: &str, password_hash: &str) -> String {
+  signed_token::binding(&format!("{email}\n{password_hash}"))
+}
+
+/// Emails a password reset link. Succeeds for unknown emails as well, so the
+/// response does not reveal which emails have an account
+pub fn request_password_reset(
+  request: PasswordResetRequest,
+  mailer: &dyn Mailer,
+  clock: &dyn Clock,
+) -> Result<(), APIError> {
+  match request.validate() {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::BadRequest),
+  }
+
+  let user_id = match user::get_user_id(&request.email) {
+    Ok(user_id) => user_id,
+    Err(APIError::UserNotFound) => return Ok(()),
+    Err(error) => return Err(error),
+  };
+  let user = user::get_user(&user_id)?;
+  let password_hash = user::get_password_hash(&user_id)?;
+
+  let token = signed_token::sign(
+    TokenPurpose::PasswordReset,
+    &user_id,
+    &reset_binding(&user.email, &password_hash),
+    RESET_TOKEN_LIFETIME_MS,
+    clock,
+  )?;
+
+  let email = Email {
+    to: user.email,
+    subject: "Reset your diary.computer password".to_string(),
+    body: format!(
+      "Hi {},\n\nSomeone asked to reset the password of your diary.computer account. Open this link within an hour to choose a new password:\n\n{}/reset-password?token={token}\n\nIf this was not you, you can ignore this email.",
+      user.name,
+      app_url(),
+    ),
+  };
+
+  if mailer.send(&email).is_err() {
+    tracing::event!(tracing::Level::ERROR, "could not send password reset email");
+  }
+
+  Ok(())
+}
+
+/// Sets a new password with a reset token. The token can only be used once,
+/// all sessions and API tokens are revoked and the email counts as verified.
+/// Returns the user's ID
+pub fn reset_password(reset: PasswordReset, clock: &dyn Clock) -> Result<String, APIError> {
+  let claims = signed_token::verify(&reset.token, TokenPurpose::PasswordReset, clock)?;
+
+  let user = match user::get_user(&claims.user_id) {
+    Ok(user) => user,
+    Err(APIError::UserNotFound) => return Err(APIError::InvalidToken),
+    Err(error) => return Err(error),
+  };
+  let password_hash = user::get_password_hash(&user.id)?;
+
+  if claims.binding != reset_binding(&user.email, &password_hash) {
+    return Err(APIError::InvalidToken);
+  }
+
+  password_policy::policy().check(&reset.password, &[&user.name, &user.email])?;
+
+  signed_token::consume(&reset.token, &claims, clock)?;
+
+  let password_hash = auth::hash_password(&reset.password)?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::update(schema::users::table.filter(schema::users::id.eq(&user.id)))
+    .set((
+      schema::users::password.eq(&password_hash),
+      schema::users::email_verified.eq(true),
+    ))
+    .execute(&mut conn)
+  {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  api_token::revoke_all_api_tokens(&user.id)?;
+
+  delete_all_user_sessions(&user.id, None).map(|_| user.id)
+}
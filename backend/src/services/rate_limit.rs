@@ -0,0 +1,534 @@
+use crate::{
+  establish_connection,
+  schema::login_attempts,
+  util::{
+    clock::{Clock, SystemClock},
+    error::APIError,
+  },
+};
+use diesel::{
+  dsl::case_when,
+  prelude::{Insertable, Queryable},
+  sql_types::BigInt,
+  BoolExpressionMethods, ExpressionMethods, IntoSql, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use dotenvy::dotenv;
+use std::{
+  collections::HashMap,
+  env,
+  sync::{Arc, Mutex},
+};
+
+/// Default failed logins per email before it is locked
+pub const DEFAULT_MAX_EMAIL_ATTEMPTS: i32 = 5;
+/// Default failed logins per IP address before it is locked
+pub const DEFAULT_MAX_IP_ATTEMPTS: i32 = 20;
+/// Default first lockout in seconds, doubled for every further failure
+pub const DEFAULT_LOCKOUT: i64 = 30;
+/// Default longest lockout in seconds (15 minutes)
+pub const DEFAULT_MAX_LOCKOUT: i64 = 60 * 15;
+/// Default time in seconds after the last failure when failures are forgotten (1 hour)
+pub const DEFAULT_ATTEMPT_WINDOW: i64 = 60 * 60;
+
+/// Default emails per address before more are locked
+pub const DEFAULT_MAX_MAIL_EMAIL_ATTEMPTS: i32 = 3;
+/// Default emails requested per IP address before it is locked
+pub const DEFAULT_MAX_MAIL_IP_ATTEMPTS: i32 = 10;
+/// Default first email lockout in seconds
+pub const DEFAULT_MAIL_LOCKOUT: i64 = 60;
+/// Default longest email lockout and time until requests are forgotten in
+/// seconds (1 hour)
+pub const DEFAULT_MAIL_WINDOW: i64 = 60 * 60;
+
+/// Failed login attempts for a key, an IP address or an email
+#[derive(Debug, Clone, PartialEq, Insertable, Queryable)]
+#[diesel(table_name = login_attempts)]
+pub struct LoginAttempts {
+  pub key: String,
+  pub failures: i32,
+  pub last_failure_at: i64,
+  pub locked_until: i64,
+}
+
+/// Login rate limits, durations in milliseconds
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+  /// Failed logins per email before it is locked, 0 disables the limit
+  pub max_email_attempts: i32,
+  /// Failed logins per IP address before it is locked, 0 disables the limit
+  pub max_ip_attempts: i32,
+  pub lockout_ms: i64,
+  pub max_lockout_ms: i64,
+  pub window_ms: i64,
+}
+
+fn env_number(name: &str, default: i64) -> i64 {
+  match env::var(name) {
+    Ok(val) => val.parse::<i64>().unwrap_or(default).max(0),
+    Err(_) => default,
+  }
+}
+
+impl RateLimitConfig {
+  /// Reads LOGIN_MAX_EMAIL_ATTEMPTS, LOGIN_MAX_IP_ATTEMPTS and
+  /// LOGIN_LOCKOUT, LOGIN_MAX_LOCKOUT, LOGIN_ATTEMPT_WINDOW (in seconds)
+  pub fn from_env() -> Self {
+    dotenv().ok();
+
+    RateLimitConfig {
+      max_email_attempts: env_number(
+        "LOGIN_MAX_EMAIL_ATTEMPTS",
+        DEFAULT_MAX_EMAIL_ATTEMPTS as i64,
+      ) as i32,
+      max_ip_attempts: env_number("LOGIN_MAX_IP_ATTEMPTS", DEFAULT_MAX_IP_ATTEMPTS as i64) as i32,
+      lockout_ms: env_number("LOGIN_LOCKOUT", DEFAULT_LOCKOUT) * 1000,
+      max_lockout_ms: env_number("LOGIN_MAX_LOCKOUT", DEFAULT_MAX_LOCKOUT) * 1000,
+      window_ms: env_number("LOGIN_ATTEMPT_WINDOW", DEFAULT_ATTEMPT_WINDOW) * 1000,
+    }
+  }
+
+  /// Limits for requests that send emails, counting every request. Reads
+  /// MAIL_MAX_EMAIL_ATTEMPTS, MAIL_MAX_IP_ATTEMPTS and MAIL_LOCKOUT,
+  /// MAIL_MAX_LOCKOUT, MAIL_ATTEMPT_WINDOW (in seconds)
+  pub fn mail_from_env() -> Self {
+    dotenv().ok();
+
+    RateLimitConfig {
+      max_email_attempts: env_number(
+        "MAIL_MAX_EMAIL_ATTEMPTS",
+        DEFAULT_MAX_MAIL_EMAIL_ATTEMPTS as i64,
+      ) as i32,
+      max_ip_attempts: env_number("MAIL_MAX_IP_ATTEMPTS", DEFAULT_MAX_MAIL_IP_ATTEMPTS as i64)
+        as i32,
+      lockout_ms: env_number("MAIL_LOCKOUT", DEFAULT_MAIL_LOCKOUT) * 1000,
+      max_lockout_ms: env_number("MAIL_MAX_LOCKOUT", DEFAULT_MAIL_WINDOW) * 1000,
+      window_ms: env_number("MAIL_ATTEMPT_WINDOW", DEFAULT_MAIL_WINDOW) * 1000,
+    }
+  }
+
+  /// Lockout after `failures` failed attempts, None while below `max_attempts`
+  pub fn lockout_ms(&self, failures: i32, max_attempts: i32) -> Option<i64> {
+    if max_attempts == 0 || failures < max_attempts {
+      return None;
+    }
+
+    let doublings = (failures - max_attempts).min(30) as u32;
+    Some(
+      self
+        .lockout_ms
+        .saturating_mul(2_i64.pow(doublings))
+        .min(self.max_lockout_ms),
+    )
+  }
+}
+
+/// Storage for failed login attempts. Counting and locking are single
+/// atomic operations, so concurrent attempts can not read the same count
+pub trait AttemptStore: Send + Sync {
+  fn get(&self, key: &str) -> Result<Option<LoginAttempts>, APIError>;
+  /// Counts a failure, starting over when the last one was before
+  /// `window_start`, and returns the attempts after counting
+  fn increment(&self, key: &str, now: i64, window_start: i64) -> Result<LoginAttempts, APIError>;
+  /// Locks a key until `locked_until` unless it is still locked at `now`,
+  /// returns whether it was locked by this call
+  fn lock(&self, key: &str, now: i64, locked_until: i64) -> Result<bool, APIError>;
+  /// Takes back a counted failure, unlocking the key when it drops below
+  /// `max_attempts`
+  fn release(&self, key: &str, max_attempts: i32) -> Result<(), APIError>;
+  fn remove(&self, key: &str) -> Result<(), APIError>;
+  /// Removes attempts that failed last before `before` and are no longer locked
+  fn prune(&self, before: i64, now: i64) -> Result<(), APIError>;
+}
+
+/// Keeps attempts in memory, limits are per server
+#[derive(Default)]
+pub struct MemoryAttemptStore {
+  attempts: Mutex<HashMap<String, LoginAttempts>>,
+}
+
+impl AttemptStore for MemoryAttemptStore {
+  fn get(&self, key: &str) -> Result<Option<LoginAttempts>, APIError> {
+    match self.attempts.lock() {
+      Ok(attempts) => Ok(attempts.get(key).cloned()),
+      Err(_) => Err(APIError::InternalServerError),
+    }
+  }
+
+  fn increment(&self, key: &str, now: i64, window_start: i64) -> Result<LoginAttempts, APIError> {
+    let mut attempts = match self.attempts.lock() {
+      Ok(attempts) => attempts,
+      Err(_) => return Err(APIError::InternalServerError),
+    };
+
+    let counted = attempts
+      .entry(key.to_string())
+      .and_modify(|attempts| {
+        attempts.failures = match attempts.last_failure_at >= window_start {
+          true => attempts.failures + 1,
+          false => 1,
+        };
+        attempts.last_failure_at = now;
+      })
+      .or_insert(LoginAttempts {
+        key: key.to_string(),
+        failures: 1,
+        last_failure_at: now,
+        locked_until: 0,
+      });
+    Ok(counted.clone())
+  }
+
+  fn lock(&self, key: &str, now: i64, locked_until: i64) -> Result<bool, APIError> {
+    match self.attempts.lock() {
+      Ok(mut attempts) => match attempts.get_mut(key) {
+        Some(attempts) if attempts.locked_until <= now => {
+          attempts.locked_until = locked_until;
+          Ok(true)
+        }
+        _ => Ok(false),
+      },
+      Err(_) => Err(APIError::InternalServerError),
+    }
+  }
+
+  fn release(&self, key: &str, max_attempts: i32) -> Result<(), APIError> {
+    match self.attempts.lock() {
+      Ok(mut attempts) => {
+        if let Some(attempts) = attempts.get_mut(key) {
+          attempts.failures = (attempts.failures - 1).max(0);
+          if attempts.failures < max_attempts {
+            attempts.locked_until = 0;
+          }
+        }
+        Ok(())
+      }
+      Err(_) => Err(APIError::InternalServerError),
+    }
+  }
+
+  fn remove(&self, key: &str) -> Result<(), APIError> {
+    match self.attempts.lock() {
+      Ok(mut attempts) => {
+        attempts.remove(key);
+        Ok(())
+      }
+      Err(_) => Err(APIError::InternalServerError),
+    }
+  }
+
+  fn prune(&self, before: i64, now: i64) -> Result<(), APIError> {
+    match self.attempts.lock() {
+      Ok(mut attempts) => {
+        attempts
+          .retain(|_, attempts| attempts.last_failure_at >= before || attempts.locked_until > now);
+        Ok(())
+      }
+      Err(_) => Err(APIError::InternalServerError),
+    }
+  }
+}
+
+/// Keeps attempts in the `login_attempts` table, limits are shared between servers
+pub struct PostgresAttemptStore;
+
+impl AttemptStore for PostgresAttemptStore {
+  fn get(&self, key: &str) -> Result<Option<LoginAttempts>, APIError> {
+    let mut conn = match establish_connection() {
+      Ok(connection) => connection,
+      Err(_) => return Err(APIError::DatabaseError),
+    };
+
+    match login_attempts::table
+      .filter(login_attempts::key.eq(key))
+      .first::<LoginAttempts>(&mut conn)
+      .optional()
+    {
+      Ok(attempts) => Ok(attempts),
+      Err(_) => Err(APIError::DatabaseError),
+    }
+  }
+
+  fn increment(&self, key: &str, now: i64, window_start: i64) -> Result<LoginAttempts, APIError> {
+    let mut conn = match establish_connection() {
+      Ok(connection) => connection,
+      Err(_) => return Err(APIError::DatabaseError),
+    };
+
+    match diesel::insert_into(login_attempts::table)
+      .values(&LoginAttempts {
+        key: key.to_string(),
+        failures: 1,
+        last_failure_at: now,
+        locked_until: 0,
+      })
+      .on_conflict(login_attempts::key)
+      .do_update()
+      .set((
+        login_attempts::failures.eq(
+          case_when(
+            login_attempts::last_failure_at.ge(window_start),
+            login_attempts::failures + 1,
+          )
+          .otherwise(1),
+        ),
+        login_attempts::last_failure_at.eq(now),
+      ))
+      .get_result::<LoginAttempts>(&mut conn)
+    {
+      Ok(attempts) => Ok(attempts),
+      Err(_) => Err(APIError::DatabaseError),
+    }
+  }
+
+  fn lock(&self, key: &str, now: i64, locked_until: i64) -> Result<bool, APIError> {
+    let mut conn = match establish_connection() {
+      Ok(connection) => connection,
+      Err(_) => return Err(APIError::DatabaseError),
+    };
+
+    match diesel::update(
+      login_attempts::table.filter(
+        login_attempts::key
+          .eq(key)
+          .and(login_attempts::locked_until.le(now)),
+      ),
+    )
+    .set(login_attempts::locked_until.eq(locked_until))
+    .execute(&mut conn)
+    {
+      Ok(updated) => Ok(updated > 0),
+      Err(_) => Err(APIError::DatabaseError),
+    }
+  }
+
+  fn release(&self, key: &str, max_attempts: i32) -> Result<(), APIError> {
+    let mut conn = match establish_connection() {
+      Ok(connection) => connection,
+      Err(_) => return Err(APIError::DatabaseError),
+    };
+
+    match diesel::update(login_attempts::table.filter(login_attempts::key.eq(key)))
+      .set((
+        login_attempts::failures
+          .eq(case_when(login_attempts::failures.gt(0), login_attempts::failures - 1).otherwise(0)),
+        login_attempts::locked_until.eq(
+          case_when(
+            login_attempts::failures.le(max_attempts),
+            0_i64.into_sql::<BigInt>(),
+          )
+          .otherwise(login_attempts::locked_until),
+        ),
+      ))
+      .execute(&mut conn)
+    {
+      Ok(_) => Ok(()),
+      Err(_) => Err(APIError::DatabaseError),
+    }
+  }
+
+  fn remove(&self, key: &str) -> Result<(), APIError> {
+    let mut conn = match establish_connection() {
+      Ok(connection) => connection,
+      Err(_) => return Err(APIError::DatabaseError),
+    };
+
+    match diesel::delete(login_attempts::table.filter(login_attempts::key.eq(key)))
+      .execute(&mut conn)
+    {
+      Ok(_) => Ok(()),
+      Err(_) => Err(APIError::DatabaseError),
+    }
+  }
+
+  fn prune(&self, before: i64, now: i64) -> Result<(), APIError> {
+    let mut conn = match establish_connection() {
+      Ok(connection) => connection,
+      Err(_) => return Err(APIError::DatabaseError),
+    };
+
+    match diesel::delete(
+      login_attempts::table.filter(
+        login_attempts::last_failure_at
+          .lt(before)
+          .and(login_attempts::locked_until.le(now)),
+      ),
+    )
+    .execute(&mut conn)
+    {
+      Ok(_) => Ok(()),
+      Err(_) => Err(APIError::DatabaseError),
+    }
+  }
+}
+
+/// Counts failed logins per IP address and per email, locking them out
+/// with an exponential back-off once their limit is reached
+pub struct LoginRateLimiter {
+  config: RateLimitConfig,
+  store: Box<dyn AttemptStore>,
+  clock: Arc<dyn Clock>,
+  prefix: &'static str,
+}
+
+fn ip_key(prefix: &str, ip_address: &str) -> String {
+  format!("{prefix}ip:{ip_address}")
+}
+
+fn email_key(prefix: &str, email: &str) -> String {
+  format!("{prefix}email:{}", email.trim().to_lowercase())
+}
+
+/// LOGIN_RATE_LIMIT_STORE selects the store: `memory` (default) or
+/// `postgres` for multiple servers
+fn store_from_env() -> Box<dyn AttemptStore> {
+  dotenv().ok();
+
+  match env::var("LOGIN_RATE_LIMIT_STORE") {
+    Ok(store) if store == "postgres" => Box::new(PostgresAttemptStore),
+    _ => Box::<MemoryAttemptStore>::default(),
+  }
+}
+
+impl LoginRateLimiter {
+  pub fn new(config: RateLimitConfig, store: Box<dyn AttemptStore>, clock: Arc<dyn Clock>) -> Self {
+    LoginRateLimiter {
+      config,
+      store,
+      clock,
+      prefix: "",
+    }
+  }
+
+  /// Prefixes the keys, so limiters for different requests can share a store
+  pub fn with_prefix(self, prefix: &'static str) -> Self {
+    LoginRateLimiter { prefix, ..self }
+  }
+
+  /// Limiter configured from the environment
+  pub fn from_env() -> Self {
+    LoginRateLimiter::new(
+      RateLimitConfig::from_env(),
+      store_from_env(),
+      Arc::new(SystemClock),
+    )
+  }
+
+  /// Limiter for requests that send emails, configured from the environment
+  pub fn mail_from_env() -> Self {
+    LoginRateLimiter::new(
+      RateLimitConfig::mail_from_env(),
+      store_from_env(),
+      Arc::new(SystemClock),
+    )
+    .with_prefix("mail:")
+  }
+
+  fn keys(&self, ip_address: &str, email: Option<&str>) -> Vec<(String, i32)> {
+    let mut keys = vec![(ip_key(self.prefix, ip_address), self.config.max_ip_attempts)];
+    if let Some(email) = email {
+      keys.push((
+        email_key(self.prefix, email),
+        self.config.max_email_attempts,
+      ));
+    }
+    keys
+      .into_iter()
+      .filter(|(_, max_attempts)| *max_attempts > 0)
+      .collect()
+  }
+
+  /// Milliseconds until the IP address or email may try again, None when not locked
+  pub fn retry_after(
+    &self,
+    ip_address: &str,
+    email: Option<&str>,
+  ) -> Result<Option<i64>, APIError> {
+    let now = self.clock.now_ms();
+    let mut retry_after = None;
+
+    for (key, _) in self.keys(ip_address, email) {
+      if let Some(attempts) = self.store.get(&key)? {
+        if attempts.locked_until > now {
+          retry_after = retry_after.max(Some(attempts.locked_until - now));
+        }
+      }
+    }
+
+    Ok(retry_after)
+  }
+
+  /// Records a failed login for the IP address and email
+  pub fn record_failure(&self, ip_address: &str, email: Option<&str>) -> Result<(), APIError> {
+    let now = self.clock.now_ms();
+
+    self.store.prune(now - self.config.window_ms, now)?;
+
+    for (key, max_attempts) in self.keys(ip_address, email) {
+      let attempts = self
+        .store
+        .increment(&key, now, now - self.config.window_ms)?;
+      if let Some(lockout) = self.config.lockout_ms(attempts.failures, max_attempts) {
+        self.store.lock(&key, now, now + lockout)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Counts a login as failed before it is tried, so concurrent attempts
+  /// can not all pass the check. The attempt that reaches the limit locks
+  /// the IP address or email right away, any others running at the same
+  /// time are turned away. Returns the milliseconds until the IP address or
+  /// email may try again when it may not try now, otherwise the attempt has
+  /// to be finished with `record_success` or `cancel_attempt` unless it
+  /// failed
+  pub fn begin_attempt(
+    &self,
+    ip_address: &str,
+    email: Option<&str>,
+  ) -> Result<Option<i64>, APIError> {
+    let now = self.clock.now_ms();
+
+    self.store.prune(now - self.config.window_ms, now)?;
+
+    let keys = self.keys(ip_address, email);
+    for (index, (key, max_attempts)) in keys.iter().enumerate() {
+      let attempts = self
+        .store
+        .increment(key, now, now - self.config.window_ms)?;
+
+      let allowed = match self.config.lockout_ms(attempts.failures, *max_attempts) {
+        _ if attempts.locked_until > now => false,
+        Some(lockout) => self.store.lock(key, now, now + lockout)?,
+        None => true,
+      };
+
+      if !allowed {
+        for (key, max_attempts) in &keys[..=index] {
+          self.store.release(key, *max_attempts)?;
+        }
+        return Ok(Some(
+          self
+            .retry_after(ip_address, email)?
+            .unwrap_or(self.config.lockout_ms),
+        ));
+      }
+    }
+
+    Ok(None)
+  }
+
+  /// Takes back an attempt from `begin_attempt` that did not fail
+  pub fn cancel_attempt(&self, ip_address: &str, email: Option<&str>) -> Result<(), APIError> {
+    for (key, max_attempts) in self.keys(ip_address, email) {
+      self.store.release(&key, max_attempts)?;
+    }
+    Ok(())
+  }
+
+  /// Clears failed logins for an email after a successful login, failures of
+  /// the IP address are kept so other accounts can not be tried in between
+  pub fn record_success(&self, email: &str) -> Result<(), APIError> {
+    self.store.remove(&email_key(self.prefix, email))
+  }
+}
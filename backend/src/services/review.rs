@@ -0,0 +1,388 @@
+use crate::{
+  establish_connection, schema,
+  services::{
+    tag::{get_tags, Tag},
+    user::get_user,
+  },
+  util::{
+    error::APIError,
+    html::escape_html,
+    math::{average, median, round_to},
+    text,
+  },
+};
+use chrono::{Datelike, NaiveDate};
+use diesel::{ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Number of tags included in `top_tags`
+pub const REVIEW_TOP_TAGS: usize = 5;
+
+const WEEKDAYS: [&str; 7] = [
+  "monday",
+  "tuesday",
+  "wednesday",
+  "thursday",
+  "friday",
+  "saturday",
+  "sunday",
+];
+
+const MONTHS: [&str; 12] = [
+  "January",
+  "February",
+  "March",
+  "April",
+  "May",
+  "June",
+  "July",
+  "August",
+  "September",
+  "October",
+  "November",
+  "December",
+];
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReviewDay {
+  pub date: NaiveDate,
+  pub mood: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReviewMonth {
+  pub month: u32,
+  pub entry_count: i64,
+  pub average_mood: f64,
+  pub median_mood: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TagImpact {
+  pub tag_id: String,
+  pub entry_count: i64,
+  pub average_mood: f64,
+  pub mood_impact: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Streak {
+  pub length: i64,
+  pub from_date: NaiveDate,
+  pub to_date: NaiveDate,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WordCount {
+  pub total: i64,
+  pub average_per_entry: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct YearReview {
+  pub year: i32,
+  pub entry_count: i64,
+  pub average_mood: f64,
+  pub days: Vec<ReviewDay>,
+  pub best_month: Option<ReviewMonth>,
+  pub worst_month: Option<ReviewMonth>,
+  pub top_tags: Vec<TagImpact>,
+  pub longest_streak: Option<Streak>,
+  pub word_count: WordCount,
+  pub busiest_weekday: Option<String>,
+}
+
+/// Get the year in review for a user
+/// - days: every day of the year with the mood logged for it, if any
+/// - best_month / worst_month: months with the highest and lowest average mood
+/// - top_tags: tags with the largest difference from the yearly average mood
+/// - longest_streak: longest run of consecutive days with an entry
+/// - word_count: words written in entry text
+/// - busiest_weekday: weekday with the most entries
+pub fn year_review(user_id: &str, year: i32) -> Result<YearReview, APIError> {
+  let (first_day, last_day) = match (
+    NaiveDate::from_ymd_opt(year, 1, 1),
+    NaiveDate::from_ymd_opt(year, 12, 31),
+  ) {
+    (Some(first_day), Some(last_day)) => (first_day, last_day),
+    _ => return Err(APIError::BadRequest),
+  };
+
+  let user = get_user(user_id);
+
+  if user.is_err() {
+    return Err(APIError::UserNotFound);
+  }
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let entries = match schema::entries::table
+    .filter(schema::entries::user_id.eq(user_id))
+    .filter(schema::entries::date.ge(first_day))
+    .filter(schema::entries::date.le(last_day))
+    .order(schema::entries::date.asc())
+    .select((
+      schema::entries::date,
+      schema::entries::mood,
+      schema::entries::entry,
+    ))
+    .load::<(NaiveDate, i32, Option<String>)>(&mut conn)
+  {
+    Ok(entries) => entries,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let entry_tags = match schema::entry_tags::table
+    .inner_join(schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)))
+    .filter(schema::entries::user_id.eq(user_id))
+    .filter(schema::entries::date.ge(first_day))
+    .filter(schema::entries::date.le(last_day))
+    .select((schema::entry_tags::tag_id, schema::entries::mood))
+    .load::<(String, i32)>(&mut conn)
+  {
+    Ok(entry_tags) => entry_tags,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let moods: Vec<i32> = entries.iter().map(|(_, mood, _)| *mood).collect();
+  let average_mood = average(&moods).unwrap_or(0.0);
+
+  let moods_by_date: HashMap<NaiveDate, i32> = entries
+    .iter()
+    .map(|(date, mood, _)| (*date, *mood))
+    .collect();
+  let days = first_day
+    .iter_days()
+    .take_while(|date| *date <= last_day)
+    .map(|date| ReviewDay {
+      date,
+      mood: moods_by_date.get(&date).copied(),
+    })
+    .collect();
+
+  let mut months: Vec<ReviewMonth> = (1..=12)
+    .filter_map(|month| {
+      let mut month_moods: Vec<i32> = entries
+        .iter()
+        .filter(|(date, _, _)| date.month() == month)
+        .map(|(_, mood, _)| *mood)
+        .collect();
+
+      if month_moods.is_empty() {
+        return None;
+      }
+
+      Some(ReviewMonth {
+        month,
+        entry_count: month_moods.len() as i64,
+        average_mood: round_to(average(&month_moods).unwrap_or(0.0), 2),
+        median_mood: median(&mut month_moods).unwrap_or(0),
+      })
+    })
+    .collect();
+
+  // stable sort keeps the earliest month first when averages are equal
+  months.sort_by(|a, b| b.average_mood.total_cmp(&a.average_mood));
+  let best_month = months.first().cloned();
+  let worst_month = months.last().cloned();
+
+  let mut tag_moods: HashMap<String, Vec<i32>> = HashMap::new();
+  for (tag_id, mood) in entry_tags {
+    tag_moods.entry(tag_id).or_default().push(mood);
+  }
+  let mut top_tags: Vec<TagImpact> = tag_moods
+    .into_iter()
+    .map(|(tag_id, moods)| {
+      let tag_average = average(&moods).unwrap_or(0.0);
+      TagImpact {
+        tag_id,
+        entry_count: moods.len() as i64,
+        average_mood: round_to(tag_average, 2),
+        mood_impact: round_to(tag_average - average_mood, 2),
+      }
+    })
+    .collect();
+  top_tags.sort_by(|a, b| {
+    b.mood_impact
+      .abs()
+      .total_cmp(&a.mood_impact.abs())
+      .then(b.entry_count.cmp(&a.entry_count))
+      .then(a.tag_id.cmp(&b.tag_id))
+  });
+  top_tags.truncate(REVIEW_TOP_TAGS);
+
+  let mut longest_streak: Option<Streak> = None;
+  let mut current_streak: Option<Streak> = None;
+  for (date, _, _) in &entries {
+    current_streak = match current_streak {
+      Some(streak) if streak.to_date.succ_opt() == Some(*date) => Some(Streak {
+        length: streak.length + 1,
+        from_date: streak.from_date,
+        to_date: *date,
+      }),
+      _ => Some(Streak {
+        length: 1,
+        from_date: *date,
+        to_date: *date,
+      }),
+    };
+
+    if let Some(streak) = &current_streak {
+      if longest_streak
+        .as_ref()
+        .is_none_or(|longest| streak.length > longest.length)
+      {
+        longest_streak = Some(streak.clone());
+      }
+    }
+  }
+
+  let total_words = entries
+    .iter()
+    .filter_map(|(_, _, entry)| entry.as_ref())
+    // counted like /stats/words, so both report the same totals
+    .map(|entry| text::words(entry).len() as i64)
+    .sum::<i64>();
+
+  let mut weekday_counts = [0i64; 7];
+  for (date, _, _) in &entries {
+    weekday_counts[date.weekday().num_days_from_monday() as usize] += 1;
+  }
+  let busiest_weekday = weekday_counts
+    .iter()
+    .enumerate()
+    .filter(|(_, count)| **count > 0)
+    .fold(
+      None,
+      |busiest: Option<(usize, i64)>, (day, count)| match busiest {
+        Some((_, busiest_count)) if busiest_count >= *count => busiest,
+        _ => Some((day, *count)),
+      },
+    )
+    .map(|(day, _)| WEEKDAYS[day].to_string());
+
+  Ok(YearReview {
+    year,
+    entry_count: entries.len() as i64,
+    average_mood: round_to(average_mood, 2),
+    days,
+    best_month,
+    worst_month,
+    top_tags,
+    longest_streak,
+    word_count: WordCount {
+      total: total_words,
+      average_per_entry: if entries.is_empty() {
+        0.0
+      } else {
+        round_to(total_words as f64 / entries.len() as f64, 2)
+      },
+    },
+    busiest_weekday,
+  })
+}
+
+/// Colors used for each mood level in the html review
+fn mood_color(mood: Option<i32>) -> &'static str {
+  match mood {
+    Some(1) => "#e5484d",
+    Some(2) => "#f76b15",
+    Some(3) => "#ffc53d",
+    Some(4) => "#99d52a",
+    Some(5) => "#30a46c",
+    _ => "#e8e8e8",
+  }
+}
+
+/// Render the year in review as a standalone html page that can be downloaded and shared
+pub fn year_review_html(user_id: &str, year: i32) -> Result<String, APIError> {
+  let review = year_review(user_id, year)?;
+
+  let tag_ids: Vec<&str> = review.top_tags.iter().map(|t| t.tag_id.as_str()).collect();
+  let tags: HashMap<String, Tag> = get_tags(tag_ids, user_id)?
+    .into_iter()
+    .map(|tag| (tag.id.clone(), tag))
+    .collect();
+
+  let mut grid = String::new();
+  for (index, name) in MONTHS.iter().enumerate() {
+    grid.push_str(&format!("<tr><th>{}</th>", &name[..3]));
+    for day in review
+      .days
+      .iter()
+      .filter(|day| day.date.month0() as usize == index)
+    {
+      grid.push_str(&format!(
+        "<td title=\"{}\" style=\"background:{}\"></td>",
+        day.date,
+        mood_color(day.mood)
+      ));
+    }
+    grid.push_str("</tr>");
+  }
+
+  let month_name = |month: &Option<ReviewMonth>| match month {
+    Some(month) => format!(
+      "{} ({})",
+      MONTHS[month.month as usize - 1],
+      month.average_mood
+    ),
+    None => "-".to_string(),
+  };
+
+  let mut top_tags = String::new();
+  for tag in &review.top_tags {
+    let name = tags
+      .get(&tag.tag_id)
+      .map(|t| escape_html(&t.name))
+      .unwrap_or_default();
+    top_tags.push_str(&format!(
+      "<li>{} ({:+}, {} entries)</li>",
+      name, tag.mood_impact, tag.entry_count
+    ));
+  }
+
+  let streak = match &review.longest_streak {
+    Some(streak) => format!(
+      "{} days ({} to {})",
+      streak.length, streak.from_date, streak.to_date
+    ),
+    None => "-".to_string(),
+  };
+
+  Ok(format!(
+    "<!doctype html>\
+<html lang=\"en\">\
+<head><meta charset=\"utf-8\"><title>{year} in review - diary.computer</title>\
+<style>\
+body{{font-family:sans-serif;margin:2rem;color:#202020}}\
+table{{border-spacing:2px}}\
+td{{width:12px;height:12px;border-radius:2px}}\
+th{{font-weight:normal;text-align:left;padding-right:.5rem}}\
+</style></head>\
+<body>\
+<h1>{year} in review</h1>\
+<table>{grid}</table>\
+<dl>\
+<dt>Entries</dt><dd>{entry_count}</dd>\
+<dt>Average mood</dt><dd>{average_mood}</dd>\
+<dt>Best month</dt><dd>{best_month}</dd>\
+<dt>Worst month</dt><dd>{worst_month}</dd>\
+<dt>Longest streak</dt><dd>{streak}</dd>\
+<dt>Words written</dt><dd>{words}</dd>\
+<dt>Busiest weekday</dt><dd>{busiest_weekday}</dd>\
+</dl>\
+<h2>Top tags</h2><ul>{top_tags}</ul>\
+</body></html>",
+    entry_count = review.entry_count,
+    average_mood = review.average_mood,
+    best_month = month_name(&review.best_month),
+    worst_month = month_name(&review.worst_month),
+    words = review.word_count.total,
+    busiest_weekday = review.busiest_weekday.as_deref().unwrap_or("-"),
+  ))
+}
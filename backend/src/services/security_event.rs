@@ -0,0 +1,512 @@
+use crate::{
+  establish_connection,
+  schema::security_events,
+  services::{
+    auth::SessionMetadata,
+    mail::{self, Email, Mailer},
+    pagination::{Paginated, PaginationObject},
+    user,
+  },
+  util::{
+    clock::Clock,
+    error::APIError,
+    geoip::{self, GeoIp},
+  },
+};
+use diesel::{deserialize::Queryable, ExpressionMethods, Insertable, QueryDsl, RunQueryDsl};
+use dotenvy::dotenv;
+use poem::Request;
+use serde::{Deserialize, Serialize};
+use std::{env, path::Path, sync::OnceLock};
+use uuid::Uuid;
+
+/// Default number of events per page
+pub const DEFAULT_EVENTS_LIMIT: i64 = 50;
+/// Most events returned per page
+pub const MAX_EVENTS_LIMIT: i64 = 200;
+/// Previous logins a new login is compared against
+const KNOWN_LOGINS: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityEventKind {
+  Login,
+  /// A login from a device or place the account was never used from
+  NewDeviceLogin,
+  PasswordChanged,
+  PasswordReset,
+  SessionRevoked,
+  /// All sessions, or all but the current one, were revoked
+  SessionsRevoked,
+  ApiTokenRevoked,
+}
+
+impl SecurityEventKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      SecurityEventKind::Login => "login",
+      SecurityEventKind::NewDeviceLogin => "new_device_login",
+      SecurityEventKind::PasswordChanged => "password_changed",
+      SecurityEventKind::PasswordReset => "password_reset",
+      SecurityEventKind::SessionRevoked => "session_revoked",
+      SecurityEventKind::SessionsRevoked => "sessions_revoked",
+      SecurityEventKind::ApiTokenRevoked => "api_token_revoked",
+    }
+  }
+
+  pub fn parse(value: &str) -> Option<SecurityEventKind> {
+    match value {
+      "login" => Some(SecurityEventKind::Login),
+      "new_device_login" => Some(SecurityEventKind::NewDeviceLogin),
+      "password_changed" => Some(SecurityEventKind::PasswordChanged),
+      "password_reset" => Some(SecurityEventKind::PasswordReset),
+      "session_revoked" => Some(SecurityEventKind::SessionRevoked),
+      "sessions_revoked" => Some(SecurityEventKind::SessionsRevoked),
+      "api_token_revoked" => Some(SecurityEventKind::ApiTokenRevoked),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Insertable, Queryable)]
+#[diesel(table_name = security_events)]
+struct StoredSecurityEvent {
+  id: String,
+  user_id: String,
+  kind: String,
+  created_at: i64,
+  ip_address: String,
+  user_agent: String,
+  device: String,
+  location: Option<String>,
+  details: Option<String>,
+}
+
+/// Something that happened to an account, with where it came from
+#[derive(Debug, Serialize)]
+pub struct SecurityEvent {
+  pub id: String,
+  pub user_id: String,
+  pub kind: SecurityEventKind,
+  pub created_at: i64,
+  pub ip_address: String,
+  pub user_agent: String,
+  /// Browser and operating system, like `Firefox on Linux`
+  pub device: String,
+  /// Country code from the GeoIP database, None without one
+  pub location: Option<String>,
+  pub details: Option<String>,
+}
+
+impl TryFrom<StoredSecurityEvent> for SecurityEvent {
+  type Error = APIError;
+
+  fn try_from(stored: StoredSecurityEvent) -> Result<Self, APIError> {
+    Ok(SecurityEvent {
+      kind: SecurityEventKind::parse(&stored.kind).ok_or(APIError::InternalServerError)?,
+      id: stored.id,
+      user_id: stored.user_id,
+      created_at: stored.created_at,
+      ip_address: stored.ip_address,
+      user_agent: stored.user_agent,
+      device: stored.device,
+      location: stored.location,
+      details: stored.details,
+    })
+  }
+}
+
+/// Where a request came from, resolved once for an event
+#[derive(Debug, Clone)]
+pub struct Origin {
+  pub ip_address: String,
+  pub user_agent: String,
+  pub device: String,
+  pub location: Option<String>,
+}
+
+impl Origin {
+  pub fn new(metadata: &SessionMetadata, geoip: Option<&GeoIp>) -> Self {
+    let ip = geoip::parse_ip(&metadata.ip_address);
+
+    Origin {
+      ip_address: ip
+        .map(|ip| ip.to_string())
+        .unwrap_or(metadata.ip_address.clone()),
+      user_agent: metadata.user_agent.chars().take(255).collect(),
+      device: device_name(&metadata.user_agent),
+      location: match (geoip, ip) {
+        (Some(geoip), Some(ip)) => geoip.country(ip).map(str::to_string),
+        _ => None,
+      },
+    }
+  }
+
+  fn network(&self) -> String {
+    match geoip::parse_ip(&self.ip_address) {
+      Some(ip) => geoip::network(ip),
+      None => self.ip_address.clone(),
+    }
+  }
+
+  /// Same country when both are known, otherwise the same network
+  fn same_place(&self, other: &Origin) -> bool {
+    match (&self.location, &other.location) {
+      (Some(location), Some(other_location)) if location == other_location => true,
+      _ => self.network() == other.network(),
+    }
+  }
+}
+
+/// Short name of the browser and operating system in a user agent. Versions
+/// are left out so browser updates do not count as new devices
+pub fn device_name(user_agent: &str) -> String {
+  // order matters, Edge and Opera also claim to be Chrome and Safari
+  let browser = [
+    ("Edg/", "Edge"),
+    ("OPR/", "Opera"),
+    ("Firefox/", "Firefox"),
+    ("FxiOS/", "Firefox"),
+    ("CriOS/", "Chrome"),
+    ("Chrome/", "Chrome"),
+    ("Safari/", "Safari"),
+    ("curl/", "curl"),
+  ]
+  .iter()
+  .find(|(token, _)| user_agent.contains(token))
+  .map(|(_, name)| *name);
+
+  let os = [
+    ("Windows", "Windows"),
+    ("Android", "Android"),
+    ("iPhone", "iOS"),
+    ("iPad", "iPadOS"),
+    ("CrOS", "ChromeOS"),
+    ("Mac OS X", "macOS"),
+    ("Macintosh", "macOS"),
+    ("Linux", "Linux"),
+  ]
+  .iter()
+  .find(|(token, _)| user_agent.contains(token))
+  .map(|(_, name)| *name);
+
+  match (browser, os) {
+    (Some(browser), Some(os)) => format!("{browser} on {os}"),
+    (Some(name), None) | (None, Some(name)) => name.to_string(),
+    (None, None) => "Unknown device".to_string(),
+  }
+}
+
+/// GeoIP database from GEOIP_DATABASE, loaded once. None when it is not set
+/// or can not be read, locations are then left out
+pub fn geoip_database() -> Option<&'static GeoIp> {
+  static GEOIP: OnceLock<Option<GeoIp>> = OnceLock::new();
+  GEOIP
+    .get_or_init(|| {
+      dotenv().ok();
+
+      let path = env::var("GEOIP_DATABASE")
+        .ok()
+        .filter(|path| !path.is_empty())?;
+      match GeoIp::load(Path::new(&path)) {
+        Ok(geoip) => Some(geoip),
+        Err(_) => {
+          tracing::event!(
+            tracing::Level::ERROR,
+            "could not read GEOIP_DATABASE {path}, login locations are not looked up"
+          );
+          None
+        }
+      }
+    })
+    .as_ref()
+}
+
+/// Mailer for new device notifications, None unless NEW_DEVICE_EMAIL is
+/// `true`. Emails are queued, logins do not wait for them to be sent
+pub fn new_device_mailer() -> Option<&'static dyn Mailer> {
+  dotenv().ok();
+
+  match env::var("NEW_DEVICE_EMAIL").unwrap_or_default() == "true" {
+    true => Some(mail::queued_mailer()),
+    false => None,
+  }
+}
+
+fn insert(
+  user_id: &str,
+  kind: SecurityEventKind,
+  origin: &Origin,
+  details: Option<String>,
+  clock: &dyn Clock,
+) -> Result<SecurityEvent, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let stored = StoredSecurityEvent {
+    id: Uuid::new_v4().to_string(),
+    user_id: user_id.to_string(),
+    kind: kind.as_str().to_string(),
+    created_at: clock.now_ms(),
+    ip_address: origin.ip_address.chars().take(255).collect(),
+    user_agent: origin.user_agent.clone(),
+    device: origin.device.clone(),
+    location: origin.location.clone(),
+    details: details.map(|details| details.chars().take(255).collect()),
+  };
+
+  match diesel::insert_into(security_events::table)
+    .values(&stored)
+    .execute(&mut conn)
+  {
+    Ok(_) => SecurityEvent::try_from(stored),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Records an event, like a password change, for a user
+pub fn record(
+  user_id: &str,
+  kind: SecurityEventKind,
+  metadata: &SessionMetadata,
+  details: Option<String>,
+  clock: &dyn Clock,
+) -> Result<SecurityEvent, APIError> {
+  insert(
+    user_id,
+    kind,
+    &Origin::new(metadata, geoip_database()),
+    details,
+    clock,
+  )
+}
+
+/// Records an event for a request. The action it belongs to already happened,
+/// so failures are only logged
+pub async fn record_request(
+  request: &Request,
+  user_id: &str,
+  kind: SecurityEventKind,
+  details: Option<String>,
+  clock: &dyn Clock,
+) {
+  let metadata = crate::services::auth::session_metadata(request).await;
+
+  if let Err(error) = record(user_id, kind, &metadata, details, clock) {
+    tracing::event!(
+      tracing::Level::ERROR,
+      "could not record {} security event: {error:?}",
+      kind.as_str()
+    );
+  }
+}
+
+/// Records a login. Logins from a device or place the user never logged in
+/// from before are `NewDeviceLogin` events, which are emailed to the user when
+/// a mailer is given. The first login of an account is never new
+pub fn record_login(
+  user_id: &str,
+  metadata: &SessionMetadata,
+  geoip: Option<&GeoIp>,
+  mailer: Option<&dyn Mailer>,
+  clock: &dyn Clock,
+) -> Result<SecurityEvent, APIError> {
+  let origin = Origin::new(metadata, geoip);
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let known = match security_events::table
+    .filter(security_events::user_id.eq(user_id))
+    .filter(security_events::kind.eq_any([
+      SecurityEventKind::Login.as_str(),
+      SecurityEventKind::NewDeviceLogin.as_str(),
+    ]))
+    .order(security_events::created_at.desc())
+    .limit(KNOWN_LOGINS)
+    .load::<StoredSecurityEvent>(&mut conn)
+  {
+    Ok(events) => events,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+  let known: Vec<Origin> = known
+    .into_iter()
+    .map(|event| Origin {
+      ip_address: event.ip_address,
+      user_agent: event.user_agent,
+      device: event.device,
+      location: event.location,
+    })
+    .collect();
+
+  let new_device = !known.iter().any(|known| known.device == origin.device);
+  let new_place = !known.iter().any(|known| known.same_place(&origin));
+
+  let (kind, details) = match (known.is_empty(), new_device, new_place) {
+    (true, _, _) | (false, false, false) => (SecurityEventKind::Login, None),
+    (false, true, true) => (
+      SecurityEventKind::NewDeviceLogin,
+      Some("new device and location"),
+    ),
+    (false, true, false) => (SecurityEventKind::NewDeviceLogin, Some("new device")),
+    (false, false, true) => (SecurityEventKind::NewDeviceLogin, Some("new location")),
+  };
+
+  let event = insert(user_id, kind, &origin, details.map(str::to_string), clock)?;
+
+  // the event is recorded either way, the email is only queued
+  if let (SecurityEventKind::NewDeviceLogin, Some(mailer)) = (kind, mailer) {
+    let sent =
+      user::get_user(user_id).and_then(|user| mailer.send(&new_device_email(&user, &event)));
+    if sent.is_err() {
+      tracing::event!(tracing::Level::ERROR, "could not send new device email");
+    }
+  }
+
+  Ok(event)
+}
+
+fn new_device_email(user: &user::UserDetails, event: &SecurityEvent) -> Email {
+  let location = match &event.location {
+    Some(location) => format!(", {location}"),
+    None => String::new(),
+  };
+
+  Email {
+    to: user.email.clone(),
+    subject: "New login to your diary.computer account".to_string(),
+    body: format!(
+      "Hi {},\n\nYour diary.computer account was logged in to from a device or place it was not used from before:\n\n{} ({}{location})\n\nIf this was you, you can ignore this email. Otherwise change your password and log out the other sessions on your account page:\n\n{}/app/user",
+      user.name,
+      event.device,
+      event.ip_address,
+      mail::app_url(),
+    ),
+  }
+}
+
+pub fn get_security_events(
+  user_id: &str,
+  limit: Option<i64>,
+  offset: Option<i64>,
+) -> Result<Paginated<SecurityEvent>, APIError> {
+  let limit = limit
+    .unwrap_or(DEFAULT_EVENTS_LIMIT)
+    .clamp(1, MAX_EVENTS_LIMIT);
+  let offset = offset.unwrap_or(0).max(0);
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let total_count = match security_events::table
+    .filter(security_events::user_id.eq(user_id))
+    .count()
+    .get_result::<i64>(&mut conn)
+  {
+    Ok(count) => count,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let events = match security_events::table
+    .filter(security_events::user_id.eq(user_id))
+    .order(security_events::created_at.desc())
+    .limit(limit)
+    .offset(offset)
+    .load::<StoredSecurityEvent>(&mut conn)
+  {
+    Ok(events) => events,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  Ok(Paginated {
+    data: events
+      .into_iter()
+      .map(SecurityEvent::try_from)
+      .collect::<Result<_, _>>()?,
+    pagination: PaginationObject {
+      limit,
+      offset,
+      total_count,
+    },
+  })
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  #[test]
+  fn test_device_name() {
+    for (user_agent, device) in [
+      (
+        "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0",
+        "Firefox on Linux",
+      ),
+      (
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36 Edg/126.0.0.0",
+        "Edge on Windows",
+      ),
+      (
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1",
+        "Safari on iOS",
+      ),
+      (
+        "Mozilla/5.0 (Linux; Android 14) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Mobile Safari/537.36",
+        "Chrome on Android",
+      ),
+      ("curl/8.5.0", "curl"),
+      ("unknown", "Unknown device"),
+    ] {
+      assert_eq!(device_name(user_agent), device);
+    }
+
+    for kind in [
+      SecurityEventKind::Login,
+      SecurityEventKind::NewDeviceLogin,
+      SecurityEventKind::PasswordChanged,
+      SecurityEventKind::PasswordReset,
+      SecurityEventKind::SessionRevoked,
+      SecurityEventKind::SessionsRevoked,
+      SecurityEventKind::ApiTokenRevoked,
+    ] {
+      assert_eq!(SecurityEventKind::parse(kind.as_str()), Some(kind));
+      assert_eq!(
+        serde_json::to_string(&kind).unwrap(),
+        format!("\"{}\"", kind.as_str())
+      );
+    }
+  }
+
+  #[test]
+  fn test_same_place() {
+    let origin = |ip: &str, location: Option<&str>| Origin {
+      ip_address: ip.to_string(),
+      user_agent: "unknown".to_string(),
+      device: "Unknown device".to_string(),
+      location: location.map(str::to_string),
+    };
+
+    assert!(origin("10.0.0.1", None).same_place(&origin("10.0.0.200", None)));
+    assert!(!origin("10.0.0.1", None).same_place(&origin("10.0.1.1", None)));
+    assert!(origin("10.0.0.1", Some("NL")).same_place(&origin("10.9.0.1", Some("NL"))));
+    assert!(!origin("10.0.0.1", Some("NL")).same_place(&origin("10.9.0.1", Some("DE"))));
+    // a country unknown to the database falls back to the network
+    assert!(origin("10.0.0.1", None).same_place(&origin("10.0.0.2", Some("NL"))));
+
+    let metadata = SessionMetadata {
+      ip_address: "socket://10.0.0.1:4000".to_string(),
+      user_agent: "curl/8.5.0".to_string(),
+    };
+    let geoip = GeoIp::parse("10.0.0.0,10.0.0.255,NL");
+    let resolved = Origin::new(&metadata, Some(&geoip));
+    assert_eq!(resolved.ip_address, "10.0.0.1");
+    assert_eq!(resolved.device, "curl");
+    assert_eq!(resolved.location.as_deref(), Some("NL"));
+  }
+}
@@ -0,0 +1,276 @@
+use crate::{
+  services::{
+    auth::{NewSession, Session, SessionConfig},
+    mail,
+  },
+  util::error::APIError,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dotenvy::dotenv;
+use hmac::{Hmac, Mac};
+use poem::{http::Method, Request};
+use serde::Serialize;
+use sha2::Sha256;
+use std::env;
+
+/// Name of the HttpOnly cookie holding the session token
+pub const SESSION_COOKIE_NAME: &str = "diary_session";
+/// Header carrying the CSRF token on cookie-authenticated requests
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// A newly created session in cookie mode, the token is only sent in the
+/// cookie and the client gets the CSRF token instead
+#[derive(Debug, Serialize)]
+pub struct CookieSession {
+  #[serde(flatten)]
+  pub session: Session,
+  pub csrf_token: String,
+}
+
+impl CookieSession {
+  pub fn new(session: NewSession) -> Result<(Self, String), APIError> {
+    let csrf_token = csrf_token(&session.token)?;
+    Ok((
+      CookieSession {
+        session: session.session,
+        csrf_token,
+      },
+      session.token,
+    ))
+  }
+}
+
+/// Cookie session mode, where the session token is kept in an HttpOnly cookie
+/// instead of being handed to the client
+#[derive(Debug, Clone)]
+pub struct SessionCookieConfig {
+  pub enabled: bool,
+  /// `Strict`, `Lax` or `None`
+  pub same_site: String,
+  /// Cookie lifetime, None makes it a browser session cookie
+  pub max_age_ms: Option<i64>,
+  /// Origins allowed to make cookie-authenticated requests besides the
+  /// server's own
+  pub trusted_origins: Vec<String>,
+}
+
+impl SessionCookieConfig {
+  /// Reads SESSION_COOKIE (`true` enables cookie mode), SESSION_COOKIE_SAMESITE
+  /// (default `Strict`) and TRUSTED_ORIGINS (comma separated, default APP_URL).
+  /// The cookie lives as long as SESSION_MAX_AGE
+  pub fn from_env() -> Self {
+    dotenv().ok();
+
+    let same_site = match env::var("SESSION_COOKIE_SAMESITE")
+      .unwrap_or_default()
+      .to_lowercase()
+      .as_str()
+    {
+      "lax" => "Lax",
+      "none" => "None",
+      _ => "Strict",
+    };
+
+    let trusted_origins = match env::var("TRUSTED_ORIGINS") {
+      Ok(origins) => origins
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect(),
+      Err(_) => vec![mail::app_url()],
+    };
+
+    SessionCookieConfig {
+      enabled: env::var("SESSION_COOKIE").unwrap_or_default() == "true",
+      same_site: same_site.to_string(),
+      max_age_ms: SessionConfig::from_env().max_age_ms,
+      trusted_origins,
+    }
+  }
+
+  /// `Set-Cookie` value storing a session token
+  pub fn cookie(&self, token: &str) -> String {
+    let max_age = match self.max_age_ms {
+      Some(max_age_ms) => format!("; Max-Age={}", max_age_ms / 1000),
+      None => String::new(),
+    };
+
+    format!(
+      "{SESSION_COOKIE_NAME}={token}; Path=/{max_age}; Secure; HttpOnly; SameSite={}",
+      self.same_site
+    )
+  }
+
+  /// `Set-Cookie` value removing the session cookie
+  pub fn removal_cookie(&self) -> String {
+    format!(
+      "{SESSION_COOKIE_NAME}=; Path=/; Max-Age=0; Secure; HttpOnly; SameSite={}",
+      self.same_site
+    )
+  }
+}
+
+/// Value of a cookie sent with the request, None when it is missing or empty
+pub fn cookie_value(request: &Request, cookie_name: &str) -> Option<String> {
+  request
+    .headers()
+    .get_all("Cookie")
+    .iter()
+    .filter_map(|header| header.to_str().ok())
+    .flat_map(|header| header.split(';'))
+    .filter_map(|cookie| cookie.trim().split_once('='))
+    .find(|(name, value)| *name == cookie_name && !value.is_empty())
+    .map(|(_, value)| value.to_string())
+}
+
+/// Extracts the session token from the request's cookies
+pub fn token_from_cookie(request: &Request) -> Option<String> {
+  cookie_value(request, SESSION_COOKIE_NAME)
+}
+
+fn csrf_mac(session_token: &str) -> Result<Hmac<Sha256>, APIError> {
+  let mut mac = match Hmac::<Sha256>::new_from_slice(session_token.as_bytes()) {
+    Ok(mac) => mac,
+    Err(_) => return Err(APIError::InternalServerError),
+  };
+  mac.update(b"csrf");
+  Ok(mac)
+}
+
+/// CSRF token of a session. It is derived from the session token, so it
+/// needs no storage and stops working with the session
+pub fn csrf_token(session_token: &str) -> Result<String, APIError> {
+  Ok(URL_SAFE_NO_PAD.encode(csrf_mac(session_token)?.finalize().into_bytes()))
+}
+
+/// Whether the request comes from the server's own origin or a trusted one.
+/// Requests without an `Origin` header are left to the CSRF token
+fn origin_allowed(request: &Request, config: &SessionCookieConfig) -> bool {
+  let origin = match request.header("Origin") {
+    Some(origin) => origin.trim_end_matches('/'),
+    None => return true,
+  };
+
+  let same_origin = match (origin.split_once("://"), request.header("Host")) {
+    (Some((_, host)), Some(request_host)) => host == request_host,
+    _ => false,
+  };
+
+  same_origin
+    || config
+      .trusted_origins
+      .iter()
+      .any(|trusted| trusted == origin)
+}
+
+/// Checks cookie-authenticated requests for cross-site request forgery.
+/// Requests that change something need a trusted `Origin`, if sent, and the
+/// session's CSRF token in the `X-CSRF-Token` header
+pub fn check_csrf(
+  request: &Request,
+  session_token: &str,
+  config: &SessionCookieConfig,
+) -> Result<(), APIError> {
+  if matches!(
+    *request.method(),
+    Method::GET | Method::HEAD | Method::OPTIONS
+  ) {
+    return Ok(());
+  }
+
+  if !origin_allowed(request, config) {
+    return Err(APIError::InvalidCsrfToken);
+  }
+
+  let token = match request
+    .header(CSRF_HEADER)
+    .and_then(|token| URL_SAFE_NO_PAD.decode(token.trim()).ok())
+  {
+    Some(token) => token,
+    None => return Err(APIError::InvalidCsrfToken),
+  };
+
+  // compared in constant time
+  match csrf_mac(session_token)?.verify_slice(&token) {
+    Ok(_) => Ok(()),
+    Err(_) => Err(APIError::InvalidCsrfToken),
+  }
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  fn config() -> SessionCookieConfig {
+    SessionCookieConfig {
+      enabled: true,
+      same_site: "Strict".to_string(),
+      max_age_ms: Some(60_000),
+      trusted_origins: vec!["https://diary.example".to_string()],
+    }
+  }
+
+  #[test]
+  fn test_cookie() {
+    let config = config();
+    assert_eq!(
+      config.cookie("abc"),
+      "diary_session=abc; Path=/; Max-Age=60; Secure; HttpOnly; SameSite=Strict"
+    );
+    assert_eq!(
+      config.removal_cookie(),
+      "diary_session=; Path=/; Max-Age=0; Secure; HttpOnly; SameSite=Strict"
+    );
+
+    let request = Request::builder()
+      .header("Cookie", "theme=dark; diary_session=abc")
+      .finish();
+    assert_eq!(token_from_cookie(&request), Some("abc".to_string()));
+    let request = Request::builder()
+      .header("Cookie", "diary_session=")
+      .finish();
+    assert_eq!(token_from_cookie(&request), None);
+  }
+
+  #[test]
+  fn test_check_csrf() {
+    let config = config();
+    let csrf = csrf_token("session").unwrap();
+    assert_ne!(csrf, csrf_token("other").unwrap());
+
+    // reads need no token
+    let request = Request::builder().method(Method::GET).finish();
+    assert_eq!(check_csrf(&request, "session", &config), Ok(()));
+
+    let request = Request::builder().method(Method::POST).finish();
+    assert_eq!(
+      check_csrf(&request, "session", &config),
+      Err(APIError::InvalidCsrfToken)
+    );
+
+    let request = Request::builder()
+      .method(Method::POST)
+      .header(CSRF_HEADER, &csrf)
+      .finish();
+    assert_eq!(check_csrf(&request, "session", &config), Ok(()));
+    assert_eq!(
+      check_csrf(&request, "other", &config),
+      Err(APIError::InvalidCsrfToken)
+    );
+
+    // same and trusted origins are allowed, others are not
+    for (origin, allowed) in [
+      ("http://localhost:3000", true),
+      ("https://diary.example", true),
+      ("https://evil.example", false),
+    ] {
+      let request = Request::builder()
+        .method(Method::DELETE)
+        .header("Host", "localhost:3000")
+        .header("Origin", origin)
+        .header(CSRF_HEADER, &csrf)
+        .finish();
+      assert_eq!(check_csrf(&request, "session", &config).is_ok(), allowed);
+    }
+  }
+}
@@ -0,0 +1,224 @@
+use crate::{
+  establish_connection,
+  schema::used_tokens,
+  services::auth::hash_token,
+  util::{clock::Clock, error::APIError},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use dotenvy::dotenv;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{env, sync::OnceLock};
+
+/// What a token can be used for, a token for one purpose is rejected for others
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+  PasswordReset,
+  EmailVerification,
+}
+
+/// Signed contents of a token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenClaims {
+  pub purpose: TokenPurpose,
+  pub user_id: String,
+  pub expires_at: i64,
+  /// Hash of a value the token is bound to, such as the email being verified,
+  /// the token stops working when the value changes
+  pub binding: String,
+  pub nonce: String,
+}
+
+/// Key for signing tokens from TOKEN_SECRET. Without it a random key is
+/// used, tokens then stop working on restart and are not shared between servers
+fn secret() -> &'static [u8] {
+  static SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+  SECRET.get_or_init(|| {
+    dotenv().ok();
+
+    match env::var("TOKEN_SECRET") {
+      Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+      _ => {
+        tracing::event!(
+          tracing::Level::WARN,
+          "TOKEN_SECRET is not set, emailed links will stop working on restart"
+        );
+        let mut secret = vec![0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        secret
+      }
+    }
+  })
+}
+
+fn signature(payload: &str) -> Result<Vec<u8>, APIError> {
+  let mut mac = match Hmac::<Sha256>::new_from_slice(secret()) {
+    Ok(mac) => mac,
+    Err(_) => return Err(APIError::InternalServerError),
+  };
+  mac.update(payload.as_bytes());
+  Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Hash used as a token binding
+pub fn binding(value: &str) -> String {
+  Sha256::digest(value.as_bytes())
+    .iter()
+    .take(16)
+    .map(|byte| format!("{byte:02x}"))
+    .collect()
+}
+
+/// Creates a token, `payload.signature` both in base64url
+pub fn sign(
+  purpose: TokenPurpose,
+  user_id: &str,
+  binding: &str,
+  lifetime_ms: i64,
+  clock: &dyn Clock,
+) -> Result<String, APIError> {
+  let mut nonce = [0u8; 16];
+  OsRng.fill_bytes(&mut nonce);
+
+  let claims = TokenClaims {
+    purpose,
+    user_id: user_id.to_string(),
+    expires_at: clock.now_ms() + lifetime_ms,
+    binding: binding.to_string(),
+    nonce: URL_SAFE_NO_PAD.encode(nonce),
+  };
+
+  let payload = match serde_json::to_vec(&claims) {
+    Ok(payload) => URL_SAFE_NO_PAD.encode(payload),
+    Err(_) => return Err(APIError::InternalServerError),
+  };
+  let signature = URL_SAFE_NO_PAD.encode(signature(&payload)?);
+
+  Ok(format!("{payload}.{signature}"))
+}
+
+/// Checks the signature, purpose and expiry of a token
+pub fn verify(
+  token: &str,
+  purpose: TokenPurpose,
+  clock: &dyn Clock,
+) -> Result<TokenClaims, APIError> {
+  let (payload, signature) = token.trim().split_once('.').ok_or(APIError::InvalidToken)?;
+
+  let signature = URL_SAFE_NO_PAD
+    .decode(signature)
+    .map_err(|_| APIError::InvalidToken)?;
+  let mut mac = match Hmac::<Sha256>::new_from_slice(secret()) {
+    Ok(mac) => mac,
+    Err(_) => return Err(APIError::InternalServerError),
+  };
+  mac.update(payload.as_bytes());
+  // compared in constant time
+  if mac.verify_slice(&signature).is_err() {
+    return Err(APIError::InvalidToken);
+  }
+
+  let claims: TokenClaims = URL_SAFE_NO_PAD
+    .decode(payload)
+    .ok()
+    .and_then(|payload| serde_json::from_slice(&payload).ok())
+    .ok_or(APIError::InvalidToken)?;
+
+  if claims.purpose != purpose || claims.expires_at <= clock.now_ms() {
+    return Err(APIError::InvalidToken);
+  }
+
+  Ok(claims)
+}
+
+/// Marks a token as used, fails if it was used before.
+/// Used tokens are kept until they expire.
+pub fn consume(token: &str, claims: &TokenClaims, clock: &dyn Clock) -> Result<(), APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::delete(used_tokens::table.filter(used_tokens::expires_at.le(clock.now_ms())))
+    .execute(&mut conn)
+  {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::insert_into(used_tokens::table)
+    .values((
+      used_tokens::token_hash.eq(hash_token(token.trim())),
+      used_tokens::expires_at.eq(claims.expires_at),
+    ))
+    .on_conflict_do_nothing()
+    .execute(&mut conn)
+  {
+    Ok(0) => Err(APIError::InvalidToken),
+    Ok(_) => Ok(()),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+  use crate::util::clock::ManualClock;
+
+  #[test]
+  fn test_sign_and_verify() {
+    let clock = ManualClock::new(1_000_000);
+    let token = sign(
+      TokenPurpose::PasswordReset,
+      "user",
+      "binding",
+      60_000,
+      &clock,
+    )
+    .unwrap();
+
+    let claims = verify(&token, TokenPurpose::PasswordReset, &clock).unwrap();
+    assert_eq!(claims.user_id, "user");
+    assert_eq!(claims.binding, "binding");
+    assert_eq!(claims.expires_at, 1_060_000);
+
+    // other purposes, tampered and expired tokens are rejected
+    assert_eq!(
+      verify(&token, TokenPurpose::EmailVerification, &clock).unwrap_err(),
+      APIError::InvalidToken
+    );
+    let (payload, signature) = token.split_once('.').unwrap();
+    let other = sign(
+      TokenPurpose::PasswordReset,
+      "other",
+      "binding",
+      60_000,
+      &clock,
+    )
+    .unwrap();
+    let (other_payload, _) = other.split_once('.').unwrap();
+    assert_eq!(
+      verify(
+        &format!("{other_payload}.{signature}"),
+        TokenPurpose::PasswordReset,
+        &clock
+      )
+      .unwrap_err(),
+      APIError::InvalidToken
+    );
+    assert_eq!(
+      verify(payload, TokenPurpose::PasswordReset, &clock).unwrap_err(),
+      APIError::InvalidToken
+    );
+
+    clock.advance(60_000);
+    assert_eq!(
+      verify(&token, TokenPurpose::PasswordReset, &clock).unwrap_err(),
+      APIError::InvalidToken
+    );
+  }
+}
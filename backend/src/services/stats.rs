@@ -1,10 +1,19 @@
-use crate::{establish_connection, schema, services::user::get_user, util::error::APIError};
+use crate::{
+  establish_connection, schema,
+  services::{tag::Tag, user::get_user},
+  util::{
+    error::APIError,
+    math::{average, median, round_to},
+  },
+};
 use bigdecimal::{BigDecimal, ToPrimitive};
 use diesel::{
-  dsl::{avg, count_star, sql},
-  ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl,
+  dsl::{avg, count, count_star, sql},
+  BoolExpressionMethods, ExpressionMethods, JoinOnDsl, NullableExpressionMethods, QueryDsl,
+  RunQueryDsl,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MoodCount {
@@ -33,6 +42,9 @@ pub struct MoodStatsWithCount {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TagStats {
   pub tag_id: String,
+  pub name: String,
+  pub color: String,
+  pub category_id: String,
   pub entry_count: i64,
   pub average_mood: f64,
   pub median_mood: i32,
@@ -41,12 +53,140 @@ pub struct TagStats {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TagStatsWithCount {
   pub tag_id: String,
+  pub name: String,
+  pub color: String,
+  pub category_id: String,
   pub entry_count: i64,
   pub average_mood: f64,
   pub median_mood: i32,
   pub mood_entry_count: MoodCount,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CategoryTagStats<T> {
+  pub category_id: String,
+  pub name: String,
+  pub tags: Vec<T>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TagPairStats {
+  pub tag_id: String,
+  pub paired_tag_id: String,
+  pub entry_count: i64,
+  pub average_mood: f64,
+  pub median_mood: i32,
+  pub lift: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TagCooccurrence {
+  pub entry_count: i64,
+  pub tags: Vec<TagStats>,
+  pub pairs: Vec<TagPairStats>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LaggedTagStats {
+  pub tag_id: String,
+  pub name: String,
+  pub color: String,
+  pub category_id: String,
+  pub lag: i32,
+  pub entry_count: i64,
+  pub average_mood: f64,
+  pub median_mood: i32,
+  pub average_mood_shift: f64,
+}
+
+/// A logged day in the calendar, serialized as a `[date, mood, tag_count]` tuple
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CalendarDay(pub chrono::NaiveDate, pub i32, pub i64);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CalendarStats {
+  pub year: i32,
+  pub days: Vec<CalendarDay>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TagUsage {
+  pub tag_id: String,
+  pub name: String,
+  pub entry_count: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CategoryStats {
+  pub category_id: String,
+  pub name: String,
+  pub entry_count: i64,
+  pub entry_share: f64,
+  pub with_category: MoodStats,
+  pub without_category: MoodStats,
+  pub most_used_tag: Option<TagUsage>,
+  pub least_used_tag: Option<TagUsage>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CategoryStatsWithCount {
+  pub category_id: String,
+  pub name: String,
+  pub entry_count: i64,
+  pub entry_share: f64,
+  pub with_category: MoodStatsWithCount,
+  pub without_category: MoodStatsWithCount,
+  pub most_used_tag: Option<TagUsage>,
+  pub least_used_tag: Option<TagUsage>,
+}
+
+/// Mood statistics of a compared period, the average and median are None
+/// without entries
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PeriodStats {
+  pub entry_count: i64,
+  pub average_mood: Option<f64>,
+  pub median_mood: Option<i32>,
+  pub mood_entry_count: MoodCount,
+}
+
+/// Changes between periods, the average and median are None when either
+/// period has no entries
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MoodStatsDelta {
+  pub entry_count: i64,
+  pub average_mood: Option<f64>,
+  pub median_mood: Option<i32>,
+  pub mood_entry_count: MoodCount,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TagUsageChange {
+  pub tag_id: String,
+  pub name: String,
+  pub first_entry_count: i64,
+  pub second_entry_count: i64,
+  pub entry_count_change: i64,
+  pub first_average_mood: Option<f64>,
+  pub second_average_mood: Option<f64>,
+  pub average_mood_change: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PeriodComparison {
+  pub first: PeriodStats,
+  pub second: PeriodStats,
+  pub delta: MoodStatsDelta,
+  pub tags: Vec<TagUsageChange>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct StatsOptions {
+  pub from_date: Option<String>,
+  pub to_date: Option<String>,
+  pub category_id: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct WeekdayStats {
   pub monday: MoodStats,
@@ -69,9 +209,35 @@ pub struct WeekdayStatsWithCount {
   pub sunday: MoodStatsWithCount,
 }
 
-/// Format average mood to two decimal places
-pub fn format_average_mood(value: f64) -> f64 {
-  (value * 100.0).round() / 100.0
+/// Mood statistics computed from a list of moods, the median uses the same
+/// semantics as `PERCENTILE_DISC(0.5)`
+fn mood_stats_from_moods(moods: &mut [i32]) -> MoodStatsWithCount {
+  let median_mood = median(moods).unwrap_or(0);
+  let count = |mood: i32| moods.iter().filter(|m| **m == mood).count() as i64;
+
+  MoodStatsWithCount {
+    entry_count: moods.len() as i64,
+    average_mood: round_to(average(moods).unwrap_or(0.0), 2),
+    median_mood,
+    mood_entry_count: MoodCount {
+      mood_1: count(1),
+      mood_2: count(2),
+      mood_3: count(3),
+      mood_4: count(4),
+      mood_5: count(5),
+    },
+  }
+}
+
+/// Parse an optional `YYYY-MM-DD` date from stats options
+pub fn parse_date(date: &Option<String>) -> Result<Option<chrono::NaiveDate>, APIError> {
+  match date {
+    Some(date) => match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+      Ok(date) => Ok(Some(date)),
+      Err(_) => Err(APIError::BadRequest),
+    },
+    None => Ok(None),
+  }
 }
 
 /// Get mood statistics for a user
@@ -79,7 +245,7 @@ pub fn format_average_mood(value: f64) -> f64 {
 /// - average_mood: average mood value across all entries
 /// - median_mood: median mood value across all entries
 pub fn mood_stats(user_id: &str) -> Result<MoodStats, APIError> {
-  let user = get_user(&user_id);
+  let user = get_user(user_id);
 
   if user.is_err() {
     return Err(APIError::UserNotFound);
@@ -108,7 +274,7 @@ pub fn mood_stats(user_id: &str) -> Result<MoodStats, APIError> {
 
   Ok(MoodStats {
     entry_count,
-    average_mood: format_average_mood(average_mood.and_then(|v| v.to_f64()).unwrap_or(0.0)),
+    average_mood: round_to(average_mood.and_then(|v| v.to_f64()).unwrap_or(0.0), 2),
     median_mood: median_mood.unwrap_or(0),
   })
 }
@@ -119,7 +285,7 @@ pub fn mood_stats(user_id: &str) -> Result<MoodStats, APIError> {
 /// - median_mood: median mood value across all entries
 /// - mood_entry_count: count of entries for each mood level (1-5)
 pub fn mood_stats_with_count(user_id: &str) -> Result<MoodStatsWithCount, APIError> {
-  let user = get_user(&user_id);
+  let user = get_user(user_id);
 
   if user.is_err() {
     return Err(APIError::UserNotFound);
@@ -191,24 +357,35 @@ pub fn mood_stats_with_count(user_id: &str) -> Result<MoodStatsWithCount, APIErr
 
 /// Get tag statistics for a user
 ///
-/// Returns a vector of TagStats, each containing:
+/// Every tag the user has is included, tags without entries in the date window
+/// have an entry_count of 0. Returns a vector of TagStats ordered by tag name,
+/// each containing:
 /// - tag_id: ID of the tag
+/// - name, color, category_id: tag metadata
 /// - entry_count: number of entries associated with the tag
 /// - average_mood: average mood of entries associated with the tag
 /// - median_mood: median mood of entries associated with the tag
-pub fn tag_stats(user_id: &str) -> Result<Vec<TagStats>, APIError> {
-  let user = get_user(&user_id);
+pub fn tag_stats(user_id: &str, options: StatsOptions) -> Result<Vec<TagStats>, APIError> {
+  let user = get_user(user_id);
 
   if user.is_err() {
     return Err(APIError::UserNotFound);
   }
 
+  let from_date = parse_date(&options.from_date)?;
+  let to_date = parse_date(&options.to_date)?;
+
   let mut conn = match establish_connection() {
     Ok(connection) => connection,
     Err(_) => return Err(APIError::DatabaseError),
   };
 
-  let results = schema::entry_tags::table
+  let mut tags_query = schema::tags::table
+    .filter(schema::tags::user_id.eq(user_id))
+    .order((schema::tags::name.asc(), schema::tags::id.asc()))
+    .into_boxed();
+
+  let mut query = schema::entry_tags::table
     .inner_join(schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)))
     .filter(schema::entries::user_id.eq(user_id))
     .group_by(schema::entry_tags::tag_id)
@@ -220,130 +397,843 @@ pub fn tag_stats(user_id: &str) -> Result<Vec<TagStats>, APIError> {
         "PERCENTILE_DISC(0.5) WITHIN GROUP (ORDER BY entries.mood)",
       ),
     ))
-    .load::<(String, i64, Option<BigDecimal>, Option<i32>)>(&mut conn);
+    .into_boxed();
+
+  if let Some(from_date) = from_date {
+    query = query.filter(schema::entries::date.ge(from_date));
+  }
+
+  if let Some(to_date) = to_date {
+    query = query.filter(schema::entries::date.le(to_date));
+  }
+
+  if let Some(category_id) = &options.category_id {
+    tags_query = tags_query.filter(schema::tags::category_id.eq(category_id));
+  }
 
-  match results {
-    Ok(rows) => Ok(
-      rows
+  let tags = match tags_query.load::<Tag>(&mut conn) {
+    Ok(tags) => tags,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut results: HashMap<String, (i64, Option<BigDecimal>, Option<i32>)> =
+    match query.load::<(String, i64, Option<BigDecimal>, Option<i32>)>(&mut conn) {
+      Ok(rows) => rows
         .into_iter()
-        .map(
-          |(tag_id, entry_count, average_mood, median_mood)| TagStats {
-            tag_id,
-            entry_count,
-            average_mood: format_average_mood(average_mood.and_then(|v| v.to_f64()).unwrap_or(0.0)),
-            median_mood: median_mood.unwrap_or(0),
-          },
-        )
+        .map(|(tag_id, entry_count, average_mood, median_mood)| {
+          (tag_id, (entry_count, average_mood, median_mood))
+        })
         .collect(),
-    ),
-    Err(_) => Err(APIError::DatabaseError),
-  }
+      Err(_) => return Err(APIError::DatabaseError),
+    };
+
+  Ok(
+    tags
+      .into_iter()
+      .map(|tag| {
+        let (entry_count, average_mood, median_mood) =
+          results.remove(&tag.id).unwrap_or((0, None, None));
+
+        TagStats {
+          tag_id: tag.id,
+          name: tag.name,
+          color: tag.color,
+          category_id: tag.category_id,
+          entry_count,
+          average_mood: round_to(average_mood.and_then(|v| v.to_f64()).unwrap_or(0.0), 2),
+          median_mood: median_mood.unwrap_or(0),
+        }
+      })
+      .collect(),
+  )
 }
 
 /// Get tag statistics for a user
 ///
-/// Returns a vector of TagStats, each containing:
+/// Returns a vector of TagStatsWithCount, each containing:
 /// - tag_id: ID of the tag
+/// - name, color, category_id: tag metadata
 /// - entry_count: number of entries associated with the tag
 /// - average_mood: average mood of entries associated with the tag
 /// - median_mood: median mood of entries associated with the tag
 /// - mood_entry_count: count of entries for each mood level (1-5) associated
-pub fn tag_stats_with_count(user_id: &str) -> Result<Vec<TagStatsWithCount>, APIError> {
-  let user = get_user(&user_id);
+pub fn tag_stats_with_count(
+  user_id: &str,
+  options: StatsOptions,
+) -> Result<Vec<TagStatsWithCount>, APIError> {
+  let from_date = parse_date(&options.from_date)?;
+  let to_date = parse_date(&options.to_date)?;
+
+  let tag_stats = tag_stats(user_id, options)?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut query = schema::entry_tags::table
+    .inner_join(schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)))
+    .filter(schema::entries::user_id.eq(user_id))
+    .select((schema::entry_tags::tag_id, schema::entries::mood))
+    .into_boxed();
+
+  if let Some(from_date) = from_date {
+    query = query.filter(schema::entries::date.ge(from_date));
+  }
+
+  if let Some(to_date) = to_date {
+    query = query.filter(schema::entries::date.le(to_date));
+  }
+
+  // count the moods for every tag from a single query
+  let mut mood_counts: HashMap<String, MoodCount> = HashMap::new();
+  match query.load::<(String, i32)>(&mut conn) {
+    Ok(rows) => {
+      for (tag_id, mood) in rows {
+        let mood_count = mood_counts.entry(tag_id).or_insert(MoodCount {
+          mood_1: 0,
+          mood_2: 0,
+          mood_3: 0,
+          mood_4: 0,
+          mood_5: 0,
+        });
+
+        match mood {
+          1 => mood_count.mood_1 += 1,
+          2 => mood_count.mood_2 += 1,
+          3 => mood_count.mood_3 += 1,
+          4 => mood_count.mood_4 += 1,
+          5 => mood_count.mood_5 += 1,
+          _ => (),
+        }
+      }
+    }
+    Err(_) => return Err(APIError::DatabaseError),
+  }
+
+  Ok(
+    tag_stats
+      .into_iter()
+      .map(|tag_stats| TagStatsWithCount {
+        mood_entry_count: mood_counts.remove(&tag_stats.tag_id).unwrap_or(MoodCount {
+          mood_1: 0,
+          mood_2: 0,
+          mood_3: 0,
+          mood_4: 0,
+          mood_5: 0,
+        }),
+        tag_id: tag_stats.tag_id,
+        name: tag_stats.name,
+        color: tag_stats.color,
+        category_id: tag_stats.category_id,
+        entry_count: tag_stats.entry_count,
+        average_mood: tag_stats.average_mood,
+        median_mood: tag_stats.median_mood,
+      })
+      .collect(),
+  )
+}
+
+/// Group tag statistics by the category of each tag
+///
+/// Categories are ordered by name, when filtering by category only that
+/// category is included.
+fn group_by_category<T>(
+  user_id: &str,
+  category_id: Option<&str>,
+  tag_stats: Vec<T>,
+  tag_category_id: fn(&T) -> &str,
+) -> Result<Vec<CategoryTagStats<T>>, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut categories_query = schema::categories::table
+    .filter(schema::categories::user_id.eq(user_id))
+    .order((schema::categories::name.asc(), schema::categories::id.asc()))
+    .select((schema::categories::id, schema::categories::name))
+    .into_boxed();
+
+  if let Some(category_id) = category_id {
+    categories_query = categories_query.filter(schema::categories::id.eq(category_id));
+  }
+
+  let categories = match categories_query.load::<(String, String)>(&mut conn) {
+    Ok(categories) => categories,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut grouped: Vec<CategoryTagStats<T>> = categories
+    .into_iter()
+    .map(|(category_id, name)| CategoryTagStats {
+      category_id,
+      name,
+      tags: Vec::new(),
+    })
+    .collect();
+
+  for tag in tag_stats {
+    if let Some(category) = grouped
+      .iter_mut()
+      .find(|category| category.category_id == tag_category_id(&tag))
+    {
+      category.tags.push(tag);
+    }
+  }
+
+  Ok(grouped)
+}
+
+/// Get tag statistics for a user grouped by category
+pub fn tag_stats_by_category(
+  user_id: &str,
+  options: StatsOptions,
+) -> Result<Vec<CategoryTagStats<TagStats>>, APIError> {
+  let category_id = options.category_id.clone();
+  let tag_stats = tag_stats(user_id, options)?;
+
+  group_by_category(user_id, category_id.as_deref(), tag_stats, |tag| {
+    &tag.category_id
+  })
+}
+
+/// Get tag statistics with mood counts for a user grouped by category
+pub fn tag_stats_with_count_by_category(
+  user_id: &str,
+  options: StatsOptions,
+) -> Result<Vec<CategoryTagStats<TagStatsWithCount>>, APIError> {
+  let category_id = options.category_id.clone();
+  let tag_stats = tag_stats_with_count(user_id, options)?;
+
+  group_by_category(user_id, category_id.as_deref(), tag_stats, |tag| {
+    &tag.category_id
+  })
+}
+
+/// Get category statistics with mood counts for a user
+///
+/// Returns a vector of CategoryStatsWithCount ordered by category name, each containing:
+/// - category_id, name: category metadata
+/// - entry_count: number of entries with at least one tag from the category
+/// - entry_share: entry_count as a share of all entries in the date window (0-1)
+/// - with_category: mood statistics for entries with a tag from the category
+/// - without_category: mood statistics for entries without a tag from the category
+/// - most_used_tag / least_used_tag: tags in the category with the most and fewest
+///   entries, ties go to the tag that sorts first by name
+pub fn category_stats_with_count(
+  user_id: &str,
+  options: StatsOptions,
+) -> Result<Vec<CategoryStatsWithCount>, APIError> {
+  let from_date = parse_date(&options.from_date)?;
+  let to_date = parse_date(&options.to_date)?;
+  let category_id = options.category_id.clone();
+
+  let tag_stats = tag_stats_by_category(user_id, options)?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut entries_query = schema::entries::table
+    .filter(schema::entries::user_id.eq(user_id))
+    .select((schema::entries::id, schema::entries::mood))
+    .into_boxed();
+
+  let mut entry_categories_query = schema::entry_tags::table
+    .inner_join(schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)))
+    .inner_join(schema::tags::table.on(schema::entry_tags::tag_id.eq(schema::tags::id)))
+    .filter(schema::entries::user_id.eq(user_id))
+    .select((schema::tags::category_id, schema::entries::id))
+    .distinct()
+    .into_boxed();
+
+  if let Some(from_date) = from_date {
+    entries_query = entries_query.filter(schema::entries::date.ge(from_date));
+    entry_categories_query = entry_categories_query.filter(schema::entries::date.ge(from_date));
+  }
+
+  if let Some(to_date) = to_date {
+    entries_query = entries_query.filter(schema::entries::date.le(to_date));
+    entry_categories_query = entry_categories_query.filter(schema::entries::date.le(to_date));
+  }
+
+  if let Some(category_id) = &category_id {
+    entry_categories_query =
+      entry_categories_query.filter(schema::tags::category_id.eq(category_id));
+  }
+
+  let entries = match entries_query.load::<(String, i32)>(&mut conn) {
+    Ok(entries) => entries,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut category_entries: HashMap<String, HashSet<String>> = HashMap::new();
+  match entry_categories_query.load::<(String, String)>(&mut conn) {
+    Ok(rows) => {
+      for (category_id, entry_id) in rows {
+        category_entries
+          .entry(category_id)
+          .or_default()
+          .insert(entry_id);
+      }
+    }
+    Err(_) => return Err(APIError::DatabaseError),
+  }
+
+  Ok(
+    tag_stats
+      .into_iter()
+      .map(|category| {
+        let tagged_entries = category_entries
+          .remove(&category.category_id)
+          .unwrap_or_default();
+
+        let (mut with_moods, mut without_moods): (Vec<i32>, Vec<i32>) = (vec![], vec![]);
+        for (entry_id, mood) in &entries {
+          if tagged_entries.contains(entry_id) {
+            with_moods.push(*mood);
+          } else {
+            without_moods.push(*mood);
+          }
+        }
+
+        let tag_usage = |tag: &TagStats| TagUsage {
+          tag_id: tag.tag_id.clone(),
+          name: tag.name.clone(),
+          entry_count: tag.entry_count,
+        };
+        // tags are ordered by name, so the first tag wins ties
+        let most_used_tag = category
+          .tags
+          .iter()
+          .rev()
+          .max_by_key(|tag| tag.entry_count)
+          .map(tag_usage);
+        let least_used_tag = category
+          .tags
+          .iter()
+          .min_by_key(|tag| tag.entry_count)
+          .map(tag_usage);
+
+        CategoryStatsWithCount {
+          category_id: category.category_id,
+          name: category.name,
+          entry_count: with_moods.len() as i64,
+          entry_share: if entries.is_empty() {
+            0.0
+          } else {
+            round_to(with_moods.len() as f64 / entries.len() as f64, 4)
+          },
+          with_category: mood_stats_from_moods(&mut with_moods),
+          without_category: mood_stats_from_moods(&mut without_moods),
+          most_used_tag,
+          least_used_tag,
+        }
+      })
+      .collect(),
+  )
+}
+
+/// Get category statistics for a user
+///
+/// Same as `category_stats_with_count` without the mood counts
+pub fn category_stats(
+  user_id: &str,
+  options: StatsOptions,
+) -> Result<Vec<CategoryStats>, APIError> {
+  let without_count = |stats: MoodStatsWithCount| MoodStats {
+    entry_count: stats.entry_count,
+    average_mood: stats.average_mood,
+    median_mood: stats.median_mood,
+  };
+
+  Ok(
+    category_stats_with_count(user_id, options)?
+      .into_iter()
+      .map(|category| CategoryStats {
+        category_id: category.category_id,
+        name: category.name,
+        entry_count: category.entry_count,
+        entry_share: category.entry_share,
+        with_category: without_count(category.with_category),
+        without_category: without_count(category.without_category),
+        most_used_tag: category.most_used_tag,
+        least_used_tag: category.least_used_tag,
+      })
+      .collect(),
+  )
+}
+
+/// Get mood statistics with mood counts for a user's entries in the date window of `options`
+fn mood_stats_with_count_in_window(
+  user_id: &str,
+  options: &StatsOptions,
+) -> Result<MoodStatsWithCount, APIError> {
+  let from_date = parse_date(&options.from_date)?;
+  let to_date = parse_date(&options.to_date)?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut query = schema::entries::table
+    .filter(schema::entries::user_id.eq(user_id))
+    .select(schema::entries::mood)
+    .into_boxed();
+
+  if let Some(from_date) = from_date {
+    query = query.filter(schema::entries::date.ge(from_date));
+  }
+
+  if let Some(to_date) = to_date {
+    query = query.filter(schema::entries::date.le(to_date));
+  }
+
+  match query.load::<i32>(&mut conn) {
+    Ok(mut moods) => Ok(mood_stats_from_moods(&mut moods)),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+fn period_stats(stats: MoodStatsWithCount) -> PeriodStats {
+  let has_entries = stats.entry_count > 0;
+
+  PeriodStats {
+    entry_count: stats.entry_count,
+    average_mood: has_entries.then_some(stats.average_mood),
+    median_mood: has_entries.then_some(stats.median_mood),
+    mood_entry_count: stats.mood_entry_count,
+  }
+}
+
+/// Compare mood statistics between two date windows
+///
+/// Returns a PeriodComparison containing:
+/// - first / second: mood statistics with counts for each period
+/// - delta: change from the first to the second period for the entry count,
+///   average, median and each mood level, the average and median only when
+///   both periods have entries
+/// - tags: usage changes for every tag used in either period, ordered by the
+///   size of the change in entry count
+pub fn compare_periods(
+  user_id: &str,
+  first: StatsOptions,
+  second: StatsOptions,
+) -> Result<PeriodComparison, APIError> {
+  let user = get_user(user_id);
 
   if user.is_err() {
     return Err(APIError::UserNotFound);
   }
 
+  for period in [&first, &second] {
+    if let (Some(from_date), Some(to_date)) =
+      (parse_date(&period.from_date)?, parse_date(&period.to_date)?)
+    {
+      if from_date > to_date {
+        return Err(APIError::BadRequest);
+      }
+    }
+  }
+
+  let first_stats = mood_stats_with_count_in_window(user_id, &first)?;
+  let second_stats = mood_stats_with_count_in_window(user_id, &second)?;
+
+  let mut second_tags: HashMap<String, TagStatsWithCount> = tag_stats_with_count(user_id, second)?
+    .into_iter()
+    .map(|tag| (tag.tag_id.clone(), tag))
+    .collect();
+
+  let mut tags: Vec<TagUsageChange> = tag_stats_with_count(user_id, first)?
+    .into_iter()
+    .filter_map(|first_tag| {
+      let second_tag = second_tags.remove(&first_tag.tag_id)?;
+
+      if first_tag.entry_count == 0 && second_tag.entry_count == 0 {
+        return None;
+      }
+
+      let first_average_mood = (first_tag.entry_count > 0).then_some(first_tag.average_mood);
+      let second_average_mood = (second_tag.entry_count > 0).then_some(second_tag.average_mood);
+
+      Some(TagUsageChange {
+        entry_count_change: second_tag.entry_count - first_tag.entry_count,
+        average_mood_change: first_average_mood
+          .zip(second_average_mood)
+          .map(|(first, second)| round_to(second - first, 2)),
+        tag_id: first_tag.tag_id,
+        name: first_tag.name,
+        first_entry_count: first_tag.entry_count,
+        second_entry_count: second_tag.entry_count,
+        first_average_mood,
+        second_average_mood,
+      })
+    })
+    .collect();
+
+  // stable sort keeps tags with the same change ordered by name
+  tags.sort_by_key(|tag| std::cmp::Reverse(tag.entry_count_change.abs()));
+
+  let first_stats = period_stats(first_stats);
+  let second_stats = period_stats(second_stats);
+
+  let first_count = &first_stats.mood_entry_count;
+  let second_count = &second_stats.mood_entry_count;
+  let delta = MoodStatsDelta {
+    entry_count: second_stats.entry_count - first_stats.entry_count,
+    average_mood: first_stats
+      .average_mood
+      .zip(second_stats.average_mood)
+      .map(|(first, second)| round_to(second - first, 2)),
+    median_mood: first_stats
+      .median_mood
+      .zip(second_stats.median_mood)
+      .map(|(first, second)| second - first),
+    mood_entry_count: MoodCount {
+      mood_1: second_count.mood_1 - first_count.mood_1,
+      mood_2: second_count.mood_2 - first_count.mood_2,
+      mood_3: second_count.mood_3 - first_count.mood_3,
+      mood_4: second_count.mood_4 - first_count.mood_4,
+      mood_5: second_count.mood_5 - first_count.mood_5,
+    },
+  };
+
+  Ok(PeriodComparison {
+    first: first_stats,
+    second: second_stats,
+    delta,
+    tags,
+  })
+}
+
+/// Get tag co-occurrence statistics for a user
+///
+/// Pairs are computed from a self-join on entry_tags, each pair is reported once
+/// with the lower tag id as `tag_id`. Returns a TagCooccurrence containing:
+/// - entry_count: number of entries in the date window
+/// - tags: TagStats for every tag, including tags not used in the date window
+/// - pairs: TagPairStats for every pair of tags used on the same entry
+///
+/// lift is how much more often the pair occurs than if the two tags were
+/// independent: `(pair_count * entry_count) / (tag_count * paired_tag_count)`
+pub fn tag_cooccurrence(user_id: &str, options: StatsOptions) -> Result<TagCooccurrence, APIError> {
+  let from_date = parse_date(&options.from_date)?;
+  let to_date = parse_date(&options.to_date)?;
+  let category_id = options.category_id.clone();
+
+  let tags = tag_stats(user_id, options)?;
+
   let mut conn = match establish_connection() {
     Ok(connection) => connection,
     Err(_) => return Err(APIError::DatabaseError),
   };
 
-  match tag_stats(user_id) {
-    Ok(rows) => Ok(
-      rows
+  let mut entry_count_query = schema::entries::table
+    .filter(schema::entries::user_id.eq(user_id))
+    .count()
+    .into_boxed();
+
+  let paired_tags = diesel::alias!(schema::entry_tags as paired_tags);
+
+  let mut pairs_query = schema::entry_tags::table
+    .inner_join(schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)))
+    .inner_join(
+      paired_tags.on(
+        paired_tags
+          .field(schema::entry_tags::entry_id)
+          .eq(schema::entry_tags::entry_id),
+      ),
+    )
+    .filter(schema::entries::user_id.eq(user_id))
+    .filter(schema::entry_tags::tag_id.lt(paired_tags.field(schema::entry_tags::tag_id)))
+    // diesel cannot group by aliased columns, so the pair key is written as sql
+    .group_by(sql::<diesel::sql_types::Text>(
+      "entry_tags.tag_id, paired_tags.tag_id",
+    ))
+    .select((
+      sql::<diesel::sql_types::Text>("entry_tags.tag_id"),
+      sql::<diesel::sql_types::Text>("paired_tags.tag_id"),
+      count_star(),
+      avg(schema::entries::mood),
+      sql::<diesel::sql_types::Nullable<diesel::sql_types::Integer>>(
+        "PERCENTILE_DISC(0.5) WITHIN GROUP (ORDER BY entries.mood)",
+      ),
+    ))
+    .into_boxed();
+
+  if let Some(from_date) = from_date {
+    entry_count_query = entry_count_query.filter(schema::entries::date.ge(from_date));
+    pairs_query = pairs_query.filter(schema::entries::date.ge(from_date));
+  }
+
+  if let Some(to_date) = to_date {
+    entry_count_query = entry_count_query.filter(schema::entries::date.le(to_date));
+    pairs_query = pairs_query.filter(schema::entries::date.le(to_date));
+  }
+
+  if let Some(category_id) = &category_id {
+    let category_tags = schema::tags::table
+      .filter(schema::tags::user_id.eq(user_id))
+      .filter(schema::tags::category_id.eq(category_id))
+      .select(schema::tags::id);
+
+    pairs_query = pairs_query
+      .filter(schema::entry_tags::tag_id.eq_any(category_tags))
+      .filter(
+        paired_tags
+          .field(schema::entry_tags::tag_id)
+          .eq_any(category_tags),
+      );
+  }
+
+  let entry_count = match entry_count_query.get_result::<i64>(&mut conn) {
+    Ok(count) => count,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let tag_counts: HashMap<&str, i64> = tags
+    .iter()
+    .map(|tag| (tag.tag_id.as_str(), tag.entry_count))
+    .collect();
+
+  let pairs =
+    match pairs_query.load::<(String, String, i64, Option<BigDecimal>, Option<i32>)>(&mut conn) {
+      Ok(rows) => rows
         .into_iter()
         .map(
-          |TagStats {
-             tag_id,
-             entry_count,
-             average_mood,
-             median_mood,
-           }| {
-            // Get mood counts for this tag
-            let mood_1 = schema::entry_tags::table
-              .inner_join(
-                schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)),
-              )
-              .filter(schema::entries::user_id.eq(user_id))
-              .filter(schema::entry_tags::tag_id.eq(&tag_id))
-              .filter(schema::entries::mood.eq(1))
-              .count()
-              .get_result::<i64>(&mut conn)
-              .unwrap_or(0);
-
-            let mood_2 = schema::entry_tags::table
-              .inner_join(
-                schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)),
-              )
-              .filter(schema::entries::user_id.eq(user_id))
-              .filter(schema::entry_tags::tag_id.eq(&tag_id))
-              .filter(schema::entries::mood.eq(2))
-              .count()
-              .get_result::<i64>(&mut conn)
-              .unwrap_or(0);
-
-            let mood_3 = schema::entry_tags::table
-              .inner_join(
-                schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)),
-              )
-              .filter(schema::entries::user_id.eq(user_id))
-              .filter(schema::entry_tags::tag_id.eq(&tag_id))
-              .filter(schema::entries::mood.eq(3))
-              .count()
-              .get_result::<i64>(&mut conn)
-              .unwrap_or(0);
-
-            let mood_4 = schema::entry_tags::table
-              .inner_join(
-                schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)),
-              )
-              .filter(schema::entries::user_id.eq(user_id))
-              .filter(schema::entry_tags::tag_id.eq(&tag_id))
-              .filter(schema::entries::mood.eq(4))
-              .count()
-              .get_result::<i64>(&mut conn)
-              .unwrap_or(0);
-
-            let mood_5 = schema::entry_tags::table
-              .inner_join(
-                schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)),
-              )
-              .filter(schema::entries::user_id.eq(user_id))
-              .filter(schema::entry_tags::tag_id.eq(&tag_id))
-              .filter(schema::entries::mood.eq(5))
-              .count()
-              .get_result::<i64>(&mut conn)
-              .unwrap_or(0);
-
-            TagStatsWithCount {
+          |(tag_id, paired_tag_id, pair_count, average_mood, median_mood)| {
+            let tag_count = tag_counts.get(tag_id.as_str()).copied().unwrap_or(0);
+            let paired_tag_count = tag_counts.get(paired_tag_id.as_str()).copied().unwrap_or(0);
+
+            let lift = if tag_count > 0 && paired_tag_count > 0 {
+              (pair_count * entry_count) as f64 / (tag_count * paired_tag_count) as f64
+            } else {
+              0.0
+            };
+
+            TagPairStats {
               tag_id,
-              entry_count,
-              average_mood,
-              median_mood,
-              mood_entry_count: MoodCount {
-                mood_1,
-                mood_2,
-                mood_3,
-                mood_4,
-                mood_5,
-              },
+              paired_tag_id,
+              entry_count: pair_count,
+              average_mood: round_to(average_mood.and_then(|v| v.to_f64()).unwrap_or(0.0), 2),
+              median_mood: median_mood.unwrap_or(0),
+              lift: round_to(lift, 2),
             }
           },
         )
         .collect(),
-    ),
+      Err(_) => return Err(APIError::DatabaseError),
+    };
+
+  Ok(TagCooccurrence {
+    entry_count,
+    tags,
+    pairs,
+  })
+}
+
+/// Maximum number of days an entry can be compared against
+pub const MAX_LAG_DAYS: i32 = 365;
+
+/// Get lagged tag statistics for a user
+///
+/// Each tagged entry is joined to the entry `lag` days later, days without an
+/// entry are skipped. Returns a vector of LaggedTagStats ordered by tag name,
+/// for the tags with at least one such entry, each containing:
+/// - tag_id: ID of the tag
+/// - name, color, category_id: tag metadata
+/// - lag: number of days between the tagged entry and the compared entry
+/// - entry_count: number of tagged entries that have an entry `lag` days later
+/// - average_mood: average mood of the entries `lag` days later
+/// - median_mood: median mood of the entries `lag` days later
+/// - average_mood_shift: average change in mood from the tagged entry to the later entry
+pub fn lagged_tag_stats(
+  user_id: &str,
+  lag: i32,
+  options: StatsOptions,
+) -> Result<Vec<LaggedTagStats>, APIError> {
+  if !(1..=MAX_LAG_DAYS).contains(&lag) {
+    return Err(APIError::BadRequest);
+  }
+
+  let user = get_user(user_id);
+
+  if user.is_err() {
+    return Err(APIError::UserNotFound);
+  }
+
+  let from_date = parse_date(&options.from_date)?;
+  let to_date = parse_date(&options.to_date)?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut tags_query = schema::tags::table
+    .filter(schema::tags::user_id.eq(user_id))
+    .order((schema::tags::name.asc(), schema::tags::id.asc()))
+    .into_boxed();
+
+  let lagged_entries = diesel::alias!(schema::entries as lagged_entries);
+
+  let mut query = schema::entry_tags::table
+    .inner_join(schema::entries::table.on(schema::entry_tags::entry_id.eq(schema::entries::id)))
+    .inner_join(
+      lagged_entries.on(
+        lagged_entries
+          .field(schema::entries::user_id)
+          .eq(schema::entries::user_id)
+          .and(
+            sql::<diesel::sql_types::Bool>("lagged_entries.date = entries.date + ")
+              .bind::<diesel::sql_types::Integer, _>(lag),
+          ),
+      ),
+    )
+    .filter(schema::entries::user_id.eq(user_id))
+    .group_by(schema::entry_tags::tag_id)
+    .select((
+      schema::entry_tags::tag_id,
+      count_star(),
+      avg(lagged_entries.field(schema::entries::mood)),
+      sql::<diesel::sql_types::Nullable<diesel::sql_types::Integer>>(
+        "PERCENTILE_DISC(0.5) WITHIN GROUP (ORDER BY lagged_entries.mood)",
+      ),
+      avg(lagged_entries.field(schema::entries::mood) - schema::entries::mood),
+    ))
+    .into_boxed();
+
+  if let Some(from_date) = from_date {
+    query = query.filter(schema::entries::date.ge(from_date));
+  }
+
+  if let Some(to_date) = to_date {
+    query = query.filter(schema::entries::date.le(to_date));
+  }
+
+  if let Some(category_id) = &options.category_id {
+    tags_query = tags_query.filter(schema::tags::category_id.eq(category_id));
+  }
+
+  let tags = match tags_query.load::<Tag>(&mut conn) {
+    Ok(tags) => tags,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut results: HashMap<String, _> = match query.load::<(
+    String,
+    i64,
+    Option<BigDecimal>,
+    Option<i32>,
+    Option<BigDecimal>,
+  )>(&mut conn)
+  {
+    Ok(rows) => rows
+      .into_iter()
+      .map(
+        |(tag_id, entry_count, average_mood, median_mood, average_mood_shift)| {
+          (
+            tag_id,
+            (entry_count, average_mood, median_mood, average_mood_shift),
+          )
+        },
+      )
+      .collect(),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  Ok(
+    tags
+      .into_iter()
+      .filter_map(|tag| {
+        let (entry_count, average_mood, median_mood, average_mood_shift) =
+          results.remove(&tag.id)?;
+
+        Some(LaggedTagStats {
+          tag_id: tag.id,
+          name: tag.name,
+          color: tag.color,
+          category_id: tag.category_id,
+          lag,
+          entry_count,
+          average_mood: round_to(average_mood.and_then(|v| v.to_f64()).unwrap_or(0.0), 2),
+          median_mood: median_mood.unwrap_or(0),
+          average_mood_shift: round_to(
+            average_mood_shift.and_then(|v| v.to_f64()).unwrap_or(0.0),
+            2,
+          ),
+        })
+      })
+      .collect(),
+  )
+}
+
+/// Get calendar data for a user
+///
+/// Returns every logged day in the year as a CalendarDay tuple of date, mood and
+/// the number of tags on the entry, ordered by date. When tag ids are given only
+/// days that have all of the tags are returned.
+pub fn calendar_stats(
+  user_id: &str,
+  year: i32,
+  tags: Option<Vec<String>>,
+) -> Result<CalendarStats, APIError> {
+  let (first_day, last_day) = match (
+    chrono::NaiveDate::from_ymd_opt(year, 1, 1),
+    chrono::NaiveDate::from_ymd_opt(year, 12, 31),
+  ) {
+    (Some(first_day), Some(last_day)) => (first_day, last_day),
+    _ => return Err(APIError::BadRequest),
+  };
+
+  let user = get_user(user_id);
+
+  if user.is_err() {
+    return Err(APIError::UserNotFound);
+  }
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut query = schema::entries::table
+    .left_join(schema::entry_tags::table.on(schema::entries::id.eq(schema::entry_tags::entry_id)))
+    .filter(schema::entries::user_id.eq(user_id))
+    .filter(schema::entries::date.ge(first_day))
+    .filter(schema::entries::date.le(last_day))
+    .group_by(schema::entries::id)
+    .select((
+      schema::entries::date,
+      schema::entries::mood,
+      count(schema::entry_tags::id.nullable()),
+    ))
+    .order(schema::entries::date.asc())
+    .into_boxed();
+
+  let tagged_entries = diesel::alias!(schema::entry_tags as tagged_entries);
+
+  for tag_id in tags.unwrap_or_default() {
+    query = query.filter(
+      schema::entries::id.eq_any(
+        tagged_entries
+          .filter(tagged_entries.field(schema::entry_tags::tag_id).eq(tag_id))
+          .select(tagged_entries.field(schema::entry_tags::entry_id)),
+      ),
+    );
+  }
+
+  match query.load::<(chrono::NaiveDate, i32, i64)>(&mut conn) {
+    Ok(rows) => Ok(CalendarStats {
+      year,
+      days: rows
+        .into_iter()
+        .map(|(date, mood, tag_count)| CalendarDay(date, mood, tag_count))
+        .collect(),
+    }),
     Err(_) => Err(APIError::DatabaseError),
   }
 }
@@ -374,7 +1264,7 @@ fn mood_stats_for_weekday(
 
   MoodStats {
     entry_count,
-    average_mood: format_average_mood(average_mood.and_then(|v| v.to_f64()).unwrap_or(0.0)),
+    average_mood: round_to(average_mood.and_then(|v| v.to_f64()).unwrap_or(0.0), 2),
     median_mood: median_mood.unwrap_or(0),
   }
 }
@@ -461,7 +1351,7 @@ fn mood_stats_for_weekday_with_count(
 
   MoodStatsWithCount {
     entry_count,
-    average_mood: format_average_mood(average_mood.and_then(|v| v.to_f64()).unwrap_or(0.0)),
+    average_mood: round_to(average_mood.and_then(|v| v.to_f64()).unwrap_or(0.0), 2),
     median_mood: median_mood.unwrap_or(0),
     mood_entry_count: MoodCount {
       mood_1,
@@ -475,7 +1365,7 @@ fn mood_stats_for_weekday_with_count(
 
 /// Get weekday statistics for a user
 pub fn weekday_stats(user_id: &str) -> Result<WeekdayStats, APIError> {
-  let user = get_user(&user_id);
+  let user = get_user(user_id);
 
   if user.is_err() {
     return Err(APIError::UserNotFound);
@@ -499,7 +1389,7 @@ pub fn weekday_stats(user_id: &str) -> Result<WeekdayStats, APIError> {
 
 /// Get weekday statistics for a user
 pub fn weekday_stats_with_count(user_id: &str) -> Result<WeekdayStatsWithCount, APIError> {
-  let user = get_user(&user_id);
+  let user = get_user(user_id);
 
   if user.is_err() {
     return Err(APIError::UserNotFound);
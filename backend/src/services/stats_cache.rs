@@ -0,0 +1,250 @@
+use crate::util::{error::APIError, unix_time::unix_ms};
+use serde::Serialize;
+use std::{
+  collections::HashMap,
+  hash::{DefaultHasher, Hash, Hasher},
+  sync::{Mutex, OnceLock},
+};
+
+/// Maximum number of cached responses kept per user, the user's cache is
+/// cleared when it is exceeded
+pub const MAX_CACHED_PER_USER: usize = 64;
+/// Maximum number of users with cached responses, the least recently used
+/// user is removed when it is exceeded
+pub const MAX_CACHED_USERS: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct CachedStats {
+  pub body: String,
+  pub etag: String,
+  pub last_modified: i64,
+}
+
+struct UserCache {
+  generation: u64,
+  last_modified: i64,
+  accessed_at: i64,
+  stats: HashMap<String, CachedStats>,
+}
+
+#[derive(Default)]
+struct Cache {
+  users: HashMap<String, UserCache>,
+  /// Latest Last-Modified of a removed user, users cached again start after
+  /// it so clients can not get a 304 for data that changed while removed
+  evicted_last_modified: i64,
+}
+
+impl Cache {
+  fn user(&mut self, user_id: &str, now: i64) -> &mut UserCache {
+    if !self.users.contains_key(user_id) && self.users.len() >= MAX_CACHED_USERS {
+      let least_recent = self
+        .users
+        .iter()
+        .min_by_key(|(_, user_cache)| user_cache.accessed_at)
+        .map(|(user_id, _)| user_id.clone());
+      if let Some(user_cache) = least_recent.and_then(|user_id| self.users.remove(&user_id)) {
+        self.evicted_last_modified = self.evicted_last_modified.max(user_cache.last_modified);
+      }
+    }
+
+    let evicted_last_modified = self.evicted_last_modified;
+    let user_cache = self
+      .users
+      .entry(user_id.to_string())
+      .or_insert_with(|| UserCache {
+        generation: 0,
+        last_modified: match evicted_last_modified {
+          0 => now,
+          evicted => now.max((evicted / 1000 + 1) * 1000),
+        },
+        accessed_at: now,
+        stats: HashMap::new(),
+      });
+    user_cache.accessed_at = now;
+    user_cache
+  }
+}
+
+static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+
+fn cache() -> &'static Mutex<Cache> {
+  CACHE.get_or_init(|| Mutex::new(Cache::default()))
+}
+
+fn etag(body: &str) -> String {
+  let mut hasher = DefaultHasher::new();
+  body.hash(&mut hasher);
+  format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Get cached stats for a user, computing and caching them when missing
+///
+/// `key` identifies the stats and their options, e.g. the request path and query.
+/// Stats computed while the user's data changes are returned but not cached.
+pub fn cached<T: Serialize>(
+  user_id: &str,
+  key: &str,
+  compute: impl FnOnce() -> Result<T, APIError>,
+) -> Result<CachedStats, APIError> {
+  cached_body(user_id, key, || match serde_json::to_string(&compute()?) {
+    Ok(body) => Ok(body),
+    Err(_) => Err(APIError::InternalServerError),
+  })
+}
+
+/// Like `cached` for stats that are already rendered, e.g. as html
+pub fn cached_body(
+  user_id: &str,
+  key: &str,
+  compute: impl FnOnce() -> Result<String, APIError>,
+) -> Result<CachedStats, APIError> {
+  let (generation, last_modified) = {
+    let mut cache = match cache().lock() {
+      Ok(cache) => cache,
+      Err(_) => return Err(APIError::InternalServerError),
+    };
+
+    let user_cache = cache.user(user_id, unix_ms());
+
+    if let Some(stats) = user_cache.stats.get(key) {
+      return Ok(stats.clone());
+    }
+
+    (user_cache.generation, user_cache.last_modified)
+  };
+
+  // computed without holding the lock so other users are not blocked
+  let body = compute()?;
+
+  let stats = CachedStats {
+    etag: etag(&body),
+    body,
+    last_modified,
+  };
+
+  let mut cache = match cache().lock() {
+    Ok(cache) => cache,
+    Err(_) => return Err(APIError::InternalServerError),
+  };
+
+  // a user removed in the meantime may have changed, so is not cached again
+  if let Some(user_cache) = cache.users.get_mut(user_id) {
+    if user_cache.generation == generation {
+      if user_cache.stats.len() >= MAX_CACHED_PER_USER {
+        user_cache.stats.clear();
+      }
+      user_cache.stats.insert(key.to_string(), stats.clone());
+    }
+  }
+
+  Ok(stats)
+}
+
+/// Clear the cached stats for a user, called whenever entries, tags or
+/// categories change. Users without cached stats are not added, they start
+/// with a new Last-Modified when they are cached
+pub fn invalidate(user_id: &str) {
+  let mut cache = match cache().lock() {
+    Ok(cache) => cache,
+    Err(_) => return,
+  };
+
+  let user_cache = match cache.users.get_mut(user_id) {
+    Some(user_cache) => user_cache,
+    None => return,
+  };
+
+  // Last-Modified has second precision, so always move to a later second
+  let now = unix_ms();
+  user_cache.generation += 1;
+  user_cache.last_modified = now.max((user_cache.last_modified / 1000 + 1) * 1000);
+  user_cache.stats.clear();
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  #[test]
+  fn test_cached_until_invalidated() {
+    let user_id = "stats-cache-test-user";
+    let mut calls = 0;
+
+    let first = cached(user_id, "/stats/mood", || {
+      calls += 1;
+      Ok(vec![1, 2, 3])
+    })
+    .unwrap();
+    let second = cached(user_id, "/stats/mood", || {
+      calls += 1;
+      Ok(vec![4, 5, 6])
+    })
+    .unwrap();
+
+    assert_eq!(calls, 1);
+    assert_eq!(first.body, "[1,2,3]");
+    assert_eq!(second.body, first.body);
+    assert_eq!(second.etag, first.etag);
+
+    invalidate(user_id);
+
+    let third = cached(user_id, "/stats/mood", || Ok(vec![4, 5, 6])).unwrap();
+    assert_eq!(third.body, "[4,5,6]");
+    assert_ne!(third.etag, first.etag);
+    assert!(third.last_modified / 1000 > first.last_modified / 1000);
+  }
+
+  #[test]
+  fn test_users_are_bounded() {
+    let mut cache = Cache::default();
+
+    for user in 0..MAX_CACHED_USERS as i64 {
+      cache.user(&format!("user-{user}"), user);
+    }
+    // the first user was used again, so the second is removed
+    cache.user("user-0", MAX_CACHED_USERS as i64);
+    let last_modified = cache.users["user-1"].last_modified;
+    cache.user("new-user", MAX_CACHED_USERS as i64 + 1);
+
+    assert_eq!(cache.users.len(), MAX_CACHED_USERS);
+    assert!(cache.users.contains_key("user-0"));
+    assert!(!cache.users.contains_key("user-1"));
+    assert!(cache.users["new-user"].last_modified / 1000 > last_modified / 1000);
+  }
+
+  #[test]
+  fn test_invalidate_does_not_add_users() {
+    let user_id = "stats-cache-invalidated-user";
+
+    invalidate(user_id);
+    assert!(!cache().lock().unwrap().users.contains_key(user_id));
+  }
+
+  #[test]
+  fn test_errors_are_not_cached() {
+    let user_id = "stats-cache-error-user";
+
+    let error = cached::<i32>(user_id, "/stats/mood", || Err(APIError::DatabaseError));
+    assert_eq!(error.unwrap_err(), APIError::DatabaseError);
+
+    let stats = cached(user_id, "/stats/mood", || Ok(1)).unwrap();
+    assert_eq!(stats.body, "1");
+  }
+
+  #[test]
+  fn test_cached_body() {
+    let user_id = "stats-cache-body-user";
+
+    let html = cached_body(user_id, "/stats/review/2024/html", || {
+      Ok("<html>".to_string())
+    })
+    .unwrap();
+    assert_eq!(html.body, "<html>");
+
+    // cached apart from the json stats
+    let json = cached(user_id, "/stats/review/2024", || Ok("<html>")).unwrap();
+    assert_eq!(json.body, "\"<html>\"");
+    assert_ne!(json.etag, html.etag);
+  }
+}
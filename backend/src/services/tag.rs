@@ -1,7 +1,7 @@
 use crate::{
   establish_connection,
   schema::tags,
-  services::{category::get_category, user::get_user},
+  services::{category::get_category, stats_cache, user::get_user},
   util::{self, color::Color, error::APIError},
 };
 use diesel::{
@@ -87,7 +87,10 @@ pub fn create_tag(tag: CreateTag) -> Result<Tag, APIError> {
     .values(&tag)
     .execute(&mut conn)
   {
-    Ok(_) => Ok(tag),
+    Ok(_) => {
+      stats_cache::invalidate(&tag.user_id);
+      Ok(tag)
+    }
     _ => Err(APIError::DatabaseError),
   }
 }
@@ -140,7 +143,10 @@ pub fn edit_tag(tag: EditTag) -> Result<Tag, APIError> {
   ))
   .execute(&mut conn)
   {
-    Ok(_) => get_tag(&tag.id, &tag.user_id),
+    Ok(_) => {
+      stats_cache::invalidate(&tag.user_id);
+      get_tag(&tag.id, &tag.user_id)
+    }
     Err(_) => Err(APIError::DatabaseError),
   }
 }
@@ -209,7 +215,10 @@ pub fn delete_tag(tag_id: &str, user_id: &str) -> Result<bool, APIError> {
   )
   .execute(&mut conn)
   {
-    Ok(count) => Ok(count > 0),
+    Ok(count) => {
+      stats_cache::invalidate(user_id);
+      Ok(count > 0)
+    }
     Err(_) => Err(APIError::DatabaseError),
   }
 }
@@ -0,0 +1,255 @@
+use crate::{
+  establish_connection, schema,
+  services::{
+    stats::{parse_date, StatsOptions},
+    user::get_user,
+  },
+  util::{
+    error::APIError,
+    math::{average, round_to},
+    text,
+  },
+};
+use chrono::{Datelike, NaiveDate};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Number of words included in `frequent_words`
+pub const TOP_WORDS: usize = 25;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EntryTextStats {
+  pub entry_id: String,
+  pub date: NaiveDate,
+  pub mood: i32,
+  pub word_count: i64,
+  pub sentiment: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MonthTextStats {
+  pub year: i32,
+  pub month: u32,
+  pub entry_count: i64,
+  pub word_count: i64,
+  pub average_word_count: f64,
+  pub average_sentiment: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MoodSentiment {
+  pub mood: i32,
+  pub entry_count: i64,
+  pub average_word_count: f64,
+  pub average_sentiment: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WordFrequency {
+  pub word: String,
+  pub count: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TextStats {
+  pub entry_count: i64,
+  pub word_count: i64,
+  pub average_word_count: f64,
+  pub length_mood_correlation: Option<f64>,
+  pub average_sentiment: Option<f64>,
+  pub sentiment_mood_correlation: Option<f64>,
+  pub frequent_words: Vec<WordFrequency>,
+  pub moods: Vec<MoodSentiment>,
+  pub months: Vec<MonthTextStats>,
+  pub entries: Vec<EntryTextStats>,
+}
+
+/// Pearson correlation coefficient, None with fewer than two pairs or no variance
+fn correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+  if pairs.len() < 2 {
+    return None;
+  }
+
+  let n = pairs.len() as f64;
+  let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+  let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+
+  let covariance = pairs
+    .iter()
+    .map(|(x, y)| (x - mean_x) * (y - mean_y))
+    .sum::<f64>();
+  let variance_x = pairs.iter().map(|(x, _)| (x - mean_x).powi(2)).sum::<f64>();
+  let variance_y = pairs.iter().map(|(_, y)| (y - mean_y).powi(2)).sum::<f64>();
+
+  if variance_x == 0.0 || variance_y == 0.0 {
+    return None;
+  }
+
+  Some(covariance / (variance_x * variance_y).sqrt())
+}
+
+/// Get statistics computed from the text of a user's entries
+///
+/// Only entries with text are included. Sentiment is scored locally with a word
+/// lexicon, see `util::text::sentiment`. Returns a TextStats containing:
+/// - entry_count, word_count, average_word_count: words written in the date window
+/// - length_mood_correlation: correlation between word count and mood (-1 to 1)
+/// - average_sentiment: average sentiment of entries with scored words (-1 to 1)
+/// - sentiment_mood_correlation: correlation between sentiment and mood (-1 to 1)
+/// - frequent_words: most used words with stopwords removed
+/// - moods: word count and sentiment for each mood level
+/// - months: word count and sentiment for each month with entries
+/// - entries: word count and sentiment for each entry, ordered by date
+pub fn text_stats(user_id: &str, options: StatsOptions) -> Result<TextStats, APIError> {
+  let user = get_user(user_id);
+
+  if user.is_err() {
+    return Err(APIError::UserNotFound);
+  }
+
+  let from_date = parse_date(&options.from_date)?;
+  let to_date = parse_date(&options.to_date)?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut query = schema::entries::table
+    .filter(schema::entries::user_id.eq(user_id))
+    .filter(schema::entries::entry.is_not_null())
+    .order(schema::entries::date.asc())
+    .select((
+      schema::entries::id,
+      schema::entries::date,
+      schema::entries::mood,
+      schema::entries::entry,
+    ))
+    .into_boxed();
+
+  if let Some(from_date) = from_date {
+    query = query.filter(schema::entries::date.ge(from_date));
+  }
+
+  if let Some(to_date) = to_date {
+    query = query.filter(schema::entries::date.le(to_date));
+  }
+
+  let rows = match query.load::<(String, NaiveDate, i32, Option<String>)>(&mut conn) {
+    Ok(rows) => rows,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let mut word_counts: HashMap<String, i64> = HashMap::new();
+  let mut entries: Vec<EntryTextStats> = vec![];
+
+  for (entry_id, date, mood, entry) in rows {
+    let entry = entry.unwrap_or_default();
+    let words = text::words(&entry);
+
+    if words.is_empty() {
+      continue;
+    }
+
+    for word in &words {
+      if !text::is_stopword(word) && !word.chars().all(|c| c.is_numeric()) {
+        *word_counts.entry(word.clone()).or_default() += 1;
+      }
+    }
+
+    entries.push(EntryTextStats {
+      entry_id,
+      date,
+      mood,
+      word_count: words.len() as i64,
+      sentiment: text::sentiment(&entry).map(|value| round_to(value, 2)),
+    });
+  }
+
+  let mut frequent_words: Vec<WordFrequency> = word_counts
+    .into_iter()
+    .map(|(word, count)| WordFrequency { word, count })
+    .collect();
+  frequent_words.sort_by(|a, b| b.count.cmp(&a.count).then(a.word.cmp(&b.word)));
+  frequent_words.truncate(TOP_WORDS);
+
+  let word_count = entries.iter().map(|e| e.word_count).sum::<i64>();
+  let sentiments: Vec<f64> = entries.iter().filter_map(|e| e.sentiment).collect();
+
+  let length_mood_correlation = correlation(
+    &entries
+      .iter()
+      .map(|e| (e.word_count as f64, e.mood as f64))
+      .collect::<Vec<(f64, f64)>>(),
+  );
+  let sentiment_mood_correlation = correlation(
+    &entries
+      .iter()
+      .filter_map(|e| e.sentiment.map(|sentiment| (sentiment, e.mood as f64)))
+      .collect::<Vec<(f64, f64)>>(),
+  );
+
+  let moods = (1..=5)
+    .map(|mood| {
+      let mood_entries: Vec<&EntryTextStats> = entries.iter().filter(|e| e.mood == mood).collect();
+      let word_counts: Vec<f64> = mood_entries.iter().map(|e| e.word_count as f64).collect();
+      let sentiments: Vec<f64> = mood_entries.iter().filter_map(|e| e.sentiment).collect();
+
+      MoodSentiment {
+        mood,
+        entry_count: mood_entries.len() as i64,
+        average_word_count: round_to(average(&word_counts).unwrap_or(0.0), 2),
+        average_sentiment: average(&sentiments).map(|value| round_to(value, 2)),
+      }
+    })
+    .collect();
+
+  // entries are ordered by date, so months are too
+  let mut months: Vec<MonthTextStats> = vec![];
+  let mut month_sentiments: Vec<Vec<f64>> = vec![];
+  for entry in &entries {
+    let (year, month) = (entry.date.year(), entry.date.month());
+    if months
+      .last()
+      .is_none_or(|m| m.year != year || m.month != month)
+    {
+      months.push(MonthTextStats {
+        year,
+        month,
+        entry_count: 0,
+        word_count: 0,
+        average_word_count: 0.0,
+        average_sentiment: None,
+      });
+      month_sentiments.push(vec![]);
+    }
+
+    if let (Some(month), Some(sentiments)) = (months.last_mut(), month_sentiments.last_mut()) {
+      month.entry_count += 1;
+      month.word_count += entry.word_count;
+      sentiments.extend(entry.sentiment);
+    }
+  }
+  for (month, sentiments) in months.iter_mut().zip(month_sentiments) {
+    month.average_word_count = round_to(month.word_count as f64 / month.entry_count as f64, 2);
+    month.average_sentiment = average(&sentiments).map(|value| round_to(value, 2));
+  }
+
+  Ok(TextStats {
+    entry_count: entries.len() as i64,
+    word_count,
+    average_word_count: if entries.is_empty() {
+      0.0
+    } else {
+      round_to(word_count as f64 / entries.len() as f64, 2)
+    },
+    length_mood_correlation: length_mood_correlation.map(|value| round_to(value, 2)),
+    average_sentiment: average(&sentiments).map(|value| round_to(value, 2)),
+    sentiment_mood_correlation: sentiment_mood_correlation.map(|value| round_to(value, 2)),
+    frequent_words,
+    moods,
+    months,
+    entries,
+  })
+}
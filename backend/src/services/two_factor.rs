@@ -0,0 +1,481 @@
+use crate::{
+  establish_connection,
+  schema::{recovery_codes, totp_credentials, two_factor_challenges},
+  services::{
+    auth::{self, NewSession, SessionMetadata},
+    user,
+  },
+  util::{clock::Clock, error::APIError, totp},
+};
+use diesel::{
+  prelude::{Insertable, Queryable},
+  Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use qrcode::{render::svg, QrCode};
+use rand::{rngs::OsRng, Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Issuer shown in authenticator apps
+pub const TOTP_ISSUER: &str = "diary.computer";
+/// Number of random bytes in a TOTP secret (160 bits, as recommended by RFC 4226)
+pub const SECRET_BYTES: usize = 20;
+/// Number of recovery codes generated when 2FA is enabled
+pub const RECOVERY_CODE_COUNT: usize = 10;
+/// Time to complete a login challenge in milliseconds (5 minutes)
+pub const CHALLENGE_LIFETIME_MS: i64 = 5 * 60 * 1000;
+/// Wrong codes accepted for a challenge before it is removed
+pub const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// Recovery codes avoid characters that are easy to confuse
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+#[derive(Debug, Insertable, Queryable)]
+#[diesel(table_name = totp_credentials)]
+pub struct TotpCredential {
+  pub user_id: String,
+  pub secret: String,
+  pub enabled: bool,
+  pub created_at: i64,
+  /// Last time step a code was accepted for, codes can not be reused
+  pub last_used_step: i64,
+}
+
+#[derive(Debug, Insertable, Queryable)]
+#[diesel(table_name = recovery_codes)]
+pub struct RecoveryCode {
+  pub id: String,
+  pub user_id: String,
+  pub code_hash: String,
+  pub used_at: Option<i64>,
+}
+
+#[derive(Debug, Insertable, Queryable)]
+#[diesel(table_name = two_factor_challenges)]
+pub struct StoredChallenge {
+  pub token_hash: String,
+  pub user_id: String,
+  pub expires_at: i64,
+  pub attempts: i32,
+}
+
+/// Returned when enrolment starts, the secret is shown as text for manual entry
+#[derive(Debug, Serialize)]
+pub struct TotpEnrolment {
+  pub secret: String,
+  pub otpauth_uri: String,
+  pub qr_svg: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TotpCode {
+  pub code: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DisableTwoFactor {
+  pub current_password: Option<String>,
+}
+
+/// Recovery codes are only shown once, when they are generated
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodes {
+  pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorStatus {
+  pub enabled: bool,
+  pub recovery_codes_remaining: i64,
+}
+
+/// Returned by the first login step when 2FA is enabled
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallenge {
+  pub two_factor_required: bool,
+  pub challenge: String,
+  pub expires_at: i64,
+}
+
+/// Second login step, either a TOTP code or a recovery code
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChallengeResponse {
+  pub challenge: String,
+  pub code: Option<String>,
+  pub recovery_code: Option<String>,
+}
+
+fn get_credential(user_id: &str) -> Result<Option<TotpCredential>, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match totp_credentials::table
+    .filter(totp_credentials::user_id.eq(user_id))
+    .first::<TotpCredential>(&mut conn)
+    .optional()
+  {
+    Ok(credential) => Ok(credential),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+pub fn is_enabled(user_id: &str) -> Result<bool, APIError> {
+  Ok(get_credential(user_id)?.is_some_and(|credential| credential.enabled))
+}
+
+pub fn status(user_id: &str) -> Result<TwoFactorStatus, APIError> {
+  let enabled = is_enabled(user_id)?;
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match recovery_codes::table
+    .filter(recovery_codes::user_id.eq(user_id))
+    .filter(recovery_codes::used_at.is_null())
+    .count()
+    .get_result::<i64>(&mut conn)
+  {
+    Ok(recovery_codes_remaining) => Ok(TwoFactorStatus {
+      enabled,
+      recovery_codes_remaining,
+    }),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Starts enrolment with a new secret, replacing any unconfirmed one.
+/// 2FA is only enabled once a code is confirmed with `confirm_enrolment`.
+pub fn begin_enrolment(user_id: &str, clock: &dyn Clock) -> Result<TotpEnrolment, APIError> {
+  let user = user::get_user(user_id)?;
+
+  if is_enabled(user_id)? {
+    return Err(APIError::TwoFactorAlreadyEnabled);
+  }
+
+  let mut secret_bytes = [0u8; SECRET_BYTES];
+  OsRng.fill_bytes(&mut secret_bytes);
+  let secret = totp::base32_encode(&secret_bytes);
+
+  let credential = TotpCredential {
+    user_id: user_id.to_string(),
+    secret: secret.clone(),
+    enabled: false,
+    created_at: clock.now_ms(),
+    last_used_step: 0,
+  };
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::insert_into(totp_credentials::table)
+    .values(&credential)
+    .on_conflict(totp_credentials::user_id)
+    .do_update()
+    .set((
+      totp_credentials::secret.eq(&credential.secret),
+      totp_credentials::created_at.eq(credential.created_at),
+      totp_credentials::last_used_step.eq(0),
+    ))
+    .execute(&mut conn)
+  {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let otpauth_uri = totp::otpauth_uri(TOTP_ISSUER, &user.email, &secret);
+  let qr_svg = match QrCode::new(otpauth_uri.as_bytes()) {
+    Ok(qr_code) => qr_code
+      .render::<svg::Color>()
+      .min_dimensions(200, 200)
+      .build(),
+    Err(_) => return Err(APIError::InternalServerError),
+  };
+
+  Ok(TotpEnrolment {
+    secret,
+    otpauth_uri,
+    qr_svg,
+  })
+}
+
+/// Checks a TOTP code for a credential, accepted codes can not be used again
+fn verify_code(
+  credential: &TotpCredential,
+  code: &str,
+  clock: &dyn Clock,
+) -> Result<bool, APIError> {
+  let step = match totp::verify(&credential.secret, code, clock.now_ms()) {
+    Some(step) if step > credential.last_used_step => step,
+    _ => return Ok(false),
+  };
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  // only one request can move the step forward
+  match diesel::update(
+    totp_credentials::table
+      .filter(totp_credentials::user_id.eq(&credential.user_id))
+      .filter(totp_credentials::last_used_step.lt(step)),
+  )
+  .set(totp_credentials::last_used_step.eq(step))
+  .execute(&mut conn)
+  {
+    Ok(rows_affected) => Ok(rows_affected > 0),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+fn generate_recovery_code() -> String {
+  let code: String = (0..10)
+    .map(|_| RECOVERY_CODE_ALPHABET[OsRng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+    .collect();
+  format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn hash_recovery_code(code: &str) -> String {
+  let normalized: String = code
+    .chars()
+    .filter(|c| c.is_alphanumeric())
+    .collect::<String>()
+    .to_lowercase();
+  auth::hash_token(&normalized)
+}
+
+/// Replaces a user's recovery codes, returning the new codes
+fn replace_recovery_codes(user_id: &str) -> Result<RecoveryCodes, APIError> {
+  let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+    .map(|_| generate_recovery_code())
+    .collect();
+  let stored: Vec<RecoveryCode> = codes
+    .iter()
+    .map(|code| RecoveryCode {
+      id: Uuid::new_v4().to_string(),
+      user_id: user_id.to_string(),
+      code_hash: hash_recovery_code(code),
+      used_at: None,
+    })
+    .collect();
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let replaced = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+    diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+      .execute(conn)?;
+    diesel::insert_into(recovery_codes::table)
+      .values(&stored)
+      .execute(conn)
+  });
+
+  match replaced {
+    Ok(_) => Ok(RecoveryCodes {
+      recovery_codes: codes,
+    }),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Enables 2FA when the code matches the enrolled secret,
+/// returns the recovery codes
+pub fn confirm_enrolment(
+  user_id: &str,
+  code: &str,
+  clock: &dyn Clock,
+) -> Result<RecoveryCodes, APIError> {
+  let credential = match get_credential(user_id)? {
+    Some(credential) if credential.enabled => return Err(APIError::TwoFactorAlreadyEnabled),
+    Some(credential) => credential,
+    None => return Err(APIError::TwoFactorNotEnabled),
+  };
+
+  if !verify_code(&credential, code, clock)? {
+    return Err(APIError::InvalidTwoFactorCode);
+  }
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::update(totp_credentials::table.filter(totp_credentials::user_id.eq(user_id)))
+    .set(totp_credentials::enabled.eq(true))
+    .execute(&mut conn)
+  {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  replace_recovery_codes(user_id)
+}
+
+/// Disables 2FA after checking the user's current password
+pub fn disable(user_id: &str, current_password: Option<&str>) -> Result<(), APIError> {
+  user::verify_current_password(user_id, current_password)?;
+
+  if get_credential(user_id)?.is_none() {
+    return Err(APIError::TwoFactorNotEnabled);
+  }
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let disabled = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+    diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+      .execute(conn)?;
+    diesel::delete(two_factor_challenges::table.filter(two_factor_challenges::user_id.eq(user_id)))
+      .execute(conn)?;
+    diesel::delete(totp_credentials::table.filter(totp_credentials::user_id.eq(user_id)))
+      .execute(conn)
+  });
+
+  match disabled {
+    Ok(_) => Ok(()),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Creates a challenge for a user whose password was verified,
+/// only the hash of the challenge token is stored
+pub fn create_challenge(user_id: &str, clock: &dyn Clock) -> Result<TwoFactorChallenge, APIError> {
+  let mut token_bytes = [0u8; auth::SESSION_TOKEN_BYTES];
+  OsRng.fill_bytes(&mut token_bytes);
+  let challenge: String = token_bytes
+    .iter()
+    .map(|byte| format!("{byte:02x}"))
+    .collect();
+  let now = clock.now_ms();
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  // expired challenges of any user are removed as new ones are created
+  match diesel::delete(
+    two_factor_challenges::table.filter(two_factor_challenges::expires_at.le(now)),
+  )
+  .execute(&mut conn)
+  {
+    Ok(_) => (),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let stored = StoredChallenge {
+    token_hash: auth::hash_token(&challenge),
+    user_id: user_id.to_string(),
+    expires_at: now + CHALLENGE_LIFETIME_MS,
+    attempts: 0,
+  };
+
+  match diesel::insert_into(two_factor_challenges::table)
+    .values(&stored)
+    .execute(&mut conn)
+  {
+    Ok(_) => Ok(TwoFactorChallenge {
+      two_factor_required: true,
+      challenge,
+      expires_at: stored.expires_at,
+    }),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Marks an unused recovery code as used, returns false if it does not match
+fn use_recovery_code(user_id: &str, code: &str, clock: &dyn Clock) -> Result<bool, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::update(
+    recovery_codes::table
+      .filter(recovery_codes::user_id.eq(user_id))
+      .filter(recovery_codes::code_hash.eq(hash_recovery_code(code)))
+      .filter(recovery_codes::used_at.is_null()),
+  )
+  .set(recovery_codes::used_at.eq(clock.now_ms()))
+  .execute(&mut conn)
+  {
+    Ok(rows_affected) => Ok(rows_affected > 0),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Completes a login challenge with a TOTP or recovery code and creates the session.
+/// Challenges are single use and removed after `MAX_CHALLENGE_ATTEMPTS` wrong codes.
+pub fn complete_challenge(
+  response: ChallengeResponse,
+  metadata: SessionMetadata,
+  clock: &dyn Clock,
+) -> Result<NewSession, APIError> {
+  if response.code.is_none() && response.recovery_code.is_none() {
+    return Err(APIError::BadRequest);
+  }
+
+  let token_hash = auth::hash_token(&response.challenge);
+
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let challenge_filter =
+    two_factor_challenges::table.filter(two_factor_challenges::token_hash.eq(&token_hash));
+
+  // the attempt is counted before the code is checked, in one statement, so
+  // parallel guesses can not get more than MAX_CHALLENGE_ATTEMPTS
+  let challenge = match diesel::update(
+    challenge_filter
+      .filter(two_factor_challenges::expires_at.gt(clock.now_ms()))
+      .filter(two_factor_challenges::attempts.lt(MAX_CHALLENGE_ATTEMPTS)),
+  )
+  .set(two_factor_challenges::attempts.eq(two_factor_challenges::attempts + 1))
+  .get_result::<StoredChallenge>(&mut conn)
+  .optional()
+  {
+    Ok(Some(challenge)) => challenge,
+    Ok(None) => return Err(APIError::TwoFactorChallengeExpired),
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  let valid = match (&response.code, &response.recovery_code) {
+    (Some(code), _) => match get_credential(&challenge.user_id)? {
+      Some(credential) if credential.enabled => verify_code(&credential, code, clock)?,
+      _ => false,
+    },
+    (None, Some(recovery_code)) => use_recovery_code(&challenge.user_id, recovery_code, clock)?,
+    (None, None) => return Err(APIError::BadRequest),
+  };
+
+  if !valid {
+    let recorded = match challenge.attempts >= MAX_CHALLENGE_ATTEMPTS {
+      true => diesel::delete(challenge_filter).execute(&mut conn),
+      false => Ok(0),
+    };
+
+    return match recorded {
+      Ok(_) => Err(APIError::InvalidTwoFactorCode),
+      Err(_) => Err(APIError::DatabaseError),
+    };
+  }
+
+  match diesel::delete(challenge_filter).execute(&mut conn) {
+    // a challenge completed at the same time by another request
+    Ok(0) => Err(APIError::TwoFactorChallengeExpired),
+    Ok(_) => auth::create_session_for_user(&challenge.user_id, metadata, clock),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
@@ -1,7 +1,14 @@
 use crate::{
   establish_connection,
   schema::{self, users},
-  services::{auth::delete_all_user_sessions, log, log::create_default_data},
+  services::{
+    api_token, auth,
+    auth::delete_all_user_sessions,
+    email_verification, log,
+    log::create_default_data,
+    mail::{self, Mailer},
+    password_policy,
+  },
   util::{self, error::APIError},
 };
 use diesel::{
@@ -20,7 +27,7 @@ pub struct CreateUser {
   pub name: String,
   #[validate(email)]
   pub email: String,
-  #[validate(length(min = 7, max = 72))]
+  /// Checked against the password policy
   pub password: String,
   pub invite: Option<String>,
 }
@@ -29,7 +36,7 @@ pub struct CreateUser {
 pub struct AuthUser {
   #[validate(email)]
   pub email: String,
-  #[validate(length(min = 7, max = 72))]
+  #[validate(length(min = 1, max = 1024))]
   pub password: String,
 }
 
@@ -39,12 +46,20 @@ pub struct UpdateUser {
   pub name: String,
   #[validate(email)]
   pub email: String,
+  /// Required when the email is changed
+  pub current_password: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Validate)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct UpdatePassword {
-  #[validate(length(min = 7, max = 72))]
+  /// Checked against the password policy
   pub password: String,
+  pub current_password: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DeleteUser {
+  pub current_password: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Insertable, Queryable)]
@@ -55,6 +70,7 @@ pub struct User {
   pub email: String,
   pub password: String,
   pub invite: Option<String>,
+  pub email_verified: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Queryable)]
@@ -64,6 +80,7 @@ pub struct UserDetails {
   pub name: String,
   pub email: String,
   pub invite: Option<String>,
+  pub email_verified: bool,
 }
 
 pub fn get_user_id(email: &str) -> Result<String, APIError> {
@@ -96,6 +113,7 @@ pub fn get_user(id: &str) -> Result<UserDetails, APIError> {
       schema::users::name,
       schema::users::email,
       schema::users::invite,
+      schema::users::email_verified,
     ))
     .first(&mut conn)
   {
@@ -119,42 +137,123 @@ pub fn get_password_hash(id: &str) -> Result<String, APIError> {
   }
 }
 
+/// bcrypt cost for new password hashes when PASSWORD_HASHER is `bcrypt`,
+/// read from BCRYPT_COST
+pub fn bcrypt_cost() -> u32 {
+  dotenv().ok();
+
+  match env::var("BCRYPT_COST") {
+    Ok(val) => match val.parse::<u32>() {
+      Ok(parsed) => parsed,
+      Err(_) => bcrypt::DEFAULT_COST,
+    },
+    Err(_) => bcrypt::DEFAULT_COST,
+  }
+}
+
+/// Replaces the stored password hash of a user
+pub fn set_password_hash(id: &str, password_hash: &str) -> Result<(), APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  match diesel::update(schema::users::table.filter(schema::users::id.eq(id)))
+    .set(schema::users::password.eq(password_hash))
+    .execute(&mut conn)
+  {
+    Ok(_) => Ok(()),
+    Err(_) => Err(APIError::DatabaseError),
+  }
+}
+
+/// Whether registration and email changes hide if an email is already in use,
+/// read from HIDE_ACCOUNT_EXISTENCE. New accounts are then not logged in
+/// automatically, so registering a new and an existing email respond the same.
+pub fn hide_account_existence() -> bool {
+  dotenv().ok();
+
+  env::var("HIDE_ACCOUNT_EXISTENCE").unwrap_or("false".to_string()) == "true"
+}
+
+/// Mailer for notices to the owners of emails in use, None unless
+/// HIDE_ACCOUNT_EXISTENCE is enabled
+pub fn email_in_use_mailer() -> Option<&'static dyn Mailer> {
+  if hide_account_existence() {
+    Some(mail::queued_mailer())
+  } else {
+    None
+  }
+}
+
+/// Checks the current password of a user before a sensitive change
+pub fn verify_current_password(id: &str, current_password: Option<&str>) -> Result<(), APIError> {
+  let current_password = match current_password {
+    Some(password) if !password.is_empty() => password,
+    _ => return Err(APIError::CurrentPasswordRequired),
+  };
+
+  let password_hash = get_password_hash(id)?;
+
+  match auth::verify_password(current_password, &password_hash) {
+    Ok(true) => Ok(()),
+    Ok(false) => Err(APIError::IncorrectCurrentPassword),
+    Err(error) => Err(error),
+  }
+}
+
 pub fn create_user(user: CreateUser) -> Result<UserDetails, APIError> {
   match user.validate() {
     Ok(_) => (),
     Err(_) => return Err(APIError::BadRequest),
   }
 
+  password_policy::policy().check(&user.password, &[&user.name, &user.email])?;
+
   if get_user_id(&user.email).is_ok() {
     return Err(APIError::EmailAlreadyInUse);
   }
 
-  let mut conn = match establish_connection() {
-    Ok(connection) => connection,
-    Err(_) => return Err(APIError::DatabaseError),
-  };
+  let password_hash = auth::hash_password(&user.password)?;
 
-  dotenv().ok();
+  insert_user(user.name, user.email, user.invite, password_hash, false)
+}
 
-  let cost = match env::var("BCRYPT_COST") {
-    Ok(val) => match val.parse::<u32>() {
-      Ok(parsed) => parsed,
-      Err(_) => bcrypt::DEFAULT_COST,
-    },
-    Err(_) => bcrypt::DEFAULT_COST,
-  };
+/// Creates a user signing up through a login provider. The provider verified
+/// the email, the password is random and can be set with a password reset
+pub fn create_provider_user(
+  name: String,
+  email: String,
+  invite: Option<String>,
+) -> Result<UserDetails, APIError> {
+  if get_user_id(&email).is_ok() {
+    return Err(APIError::EmailAlreadyInUse);
+  }
 
-  let password_hash = match bcrypt::hash(&user.password, cost) {
-    Ok(hash) => hash,
-    Err(_) => return Err(APIError::InternalServerError),
+  let password_hash = auth::hash_password(&auth::generate_token())?;
+
+  insert_user(name, email, invite, password_hash, true)
+}
+
+fn insert_user(
+  name: String,
+  email: String,
+  invite: Option<String>,
+  password_hash: String,
+  email_verified: bool,
+) -> Result<UserDetails, APIError> {
+  let mut conn = match establish_connection() {
+    Ok(connection) => connection,
+    Err(_) => return Err(APIError::DatabaseError),
   };
 
   let user_details = UserDetails {
     id: Uuid::new_v4().to_string(),
     created_at: util::unix_time::unix_ms(),
-    name: user.name,
-    email: user.email,
-    invite: user.invite,
+    name,
+    email,
+    invite,
+    email_verified,
   };
 
   let new_user = User {
@@ -164,6 +263,7 @@ pub fn create_user(user: CreateUser) -> Result<UserDetails, APIError> {
     email: user_details.email.clone(),
     password: password_hash,
     invite: user_details.invite.clone(),
+    email_verified,
   };
 
   match diesel::insert_into(schema::users::table)
@@ -188,7 +288,7 @@ pub fn delete_user(id: &str) -> Result<bool, APIError> {
     Err(_) => return Err(APIError::DatabaseError),
   };
 
-  match delete_all_user_sessions(id) {
+  match delete_all_user_sessions(id, None) {
     Ok(_) => (),
     Err(_) => return Err(APIError::DatabaseError),
   };
@@ -204,15 +304,43 @@ pub fn delete_user(id: &str) -> Result<bool, APIError> {
   }
 }
 
-pub fn update_user(id: &str, user: UpdateUser) -> Result<bool, APIError> {
+/// Deletes a user after checking their current password
+pub fn delete_user_with_password(id: &str, user: DeleteUser) -> Result<bool, APIError> {
+  verify_current_password(id, user.current_password.as_deref())?;
+
+  delete_user(id)
+}
+
+/// Updates a user's name and email, changing the email requires the current
+/// password and marks the new email as unverified. With `email_in_use_mailer`
+/// an email of another account is not revealed, the email is left unchanged
+/// and its owner is notified instead
+pub fn update_user(
+  id: &str,
+  user: UpdateUser,
+  email_in_use_mailer: Option<&dyn Mailer>,
+) -> Result<bool, APIError> {
   match user.validate() {
     Ok(_) => (),
     Err(_) => return Err(APIError::BadRequest),
   }
 
-  if let Ok(existing_user_id) = get_user_id(&user.email) {
+  let current_user = get_user(id)?;
+
+  if current_user.email != user.email {
+    verify_current_password(id, user.current_password.as_deref())?;
+  }
+
+  let mut email = user.email;
+  if let Ok(existing_user_id) = get_user_id(&email) {
     if existing_user_id != id {
-      return Err(APIError::EmailAlreadyInUse);
+      let Some(mailer) = email_in_use_mailer else {
+        return Err(APIError::EmailAlreadyInUse);
+      };
+      if email_verification::send_email_in_use_notice(&existing_user_id, mailer).is_err() {
+        tracing::event!(tracing::Level::ERROR, "could not send email in use notice");
+      }
+      email = current_user.email.clone();
     }
   }
 
@@ -224,7 +352,8 @@ pub fn update_user(id: &str, user: UpdateUser) -> Result<bool, APIError> {
   match diesel::update(schema::users::table.filter(schema::users::id.eq(id)))
     .set((
       schema::users::name.eq(&user.name),
-      schema::users::email.eq(&user.email),
+      schema::users::email.eq(&email),
+      schema::users::email_verified.eq(current_user.email_verified && current_user.email == email),
     ))
     .execute(&mut conn)
   {
@@ -233,23 +362,38 @@ pub fn update_user(id: &str, user: UpdateUser) -> Result<bool, APIError> {
   }
 }
 
-pub fn update_password(id: &str, password: UpdatePassword) -> Result<bool, APIError> {
+/// Changes a user's password after checking the current one, all sessions
+/// except `keep_session_id` and all API tokens are revoked
+pub fn update_password(
+  id: &str,
+  password: UpdatePassword,
+  keep_session_id: Option<&str>,
+) -> Result<bool, APIError> {
+  verify_current_password(id, password.current_password.as_deref())?;
+
+  let user = get_user(id)?;
+  password_policy::policy().check(&password.password, &[&user.name, &user.email])?;
+
   let mut conn = match establish_connection() {
     Ok(connection) => connection,
     Err(_) => return Err(APIError::DatabaseError),
   };
 
-  let password_hash = match bcrypt::hash(&password.password, bcrypt::DEFAULT_COST) {
-    Ok(hash) => hash,
-    Err(_) => return Err(APIError::InternalServerError),
-  };
+  let password_hash = auth::hash_password(&password.password)?;
 
-  match diesel::update(schema::users::table.filter(schema::users::id.eq(id)))
+  let updated = match diesel::update(schema::users::table.filter(schema::users::id.eq(id)))
     .set(schema::users::password.eq(&password_hash))
     .execute(&mut conn)
   {
-    Ok(rows_affected) => Ok(rows_affected > 0),
-    Err(_) => Err(APIError::DatabaseError),
+    Ok(rows_affected) => rows_affected > 0,
+    Err(_) => return Err(APIError::DatabaseError),
+  };
+
+  api_token::revoke_all_api_tokens(id)?;
+
+  match delete_all_user_sessions(id, keep_session_id) {
+    Ok(_) => Ok(updated),
+    Err(error) => Err(error),
   }
 }
 
@@ -1,5 +1,13 @@
+pub mod cbor;
+pub mod client_ip;
+pub mod clock;
 pub mod color;
 pub mod error;
+pub mod geoip;
+pub mod html;
 pub mod invite_code;
+pub mod math;
 pub mod response;
+pub mod text;
+pub mod totp;
 pub mod unix_time;
@@ -0,0 +1,241 @@
+/// Minimal CBOR (RFC 8949) for WebAuthn attestation objects and COSE keys.
+/// Only definite lengths are supported, floats and tags are rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Integer(i64),
+  Bytes(Vec<u8>),
+  Text(String),
+  Array(Vec<Value>),
+  Map(Vec<(Value, Value)>),
+  Bool(bool),
+  Null,
+}
+
+impl Value {
+  /// Looks up a map entry by integer key, as used by COSE keys
+  pub fn get_int(&self, key: i64) -> Option<&Value> {
+    self.get(&Value::Integer(key))
+  }
+
+  /// Looks up a map entry by text key
+  pub fn get_text(&self, key: &str) -> Option<&Value> {
+    self.get(&Value::Text(key.to_string()))
+  }
+
+  fn get(&self, key: &Value) -> Option<&Value> {
+    match self {
+      Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+      _ => None,
+    }
+  }
+
+  pub fn as_bytes(&self) -> Option<&[u8]> {
+    match self {
+      Value::Bytes(bytes) => Some(bytes),
+      _ => None,
+    }
+  }
+
+  pub fn as_integer(&self) -> Option<i64> {
+    match self {
+      Value::Integer(integer) => Some(*integer),
+      _ => None,
+    }
+  }
+
+  pub fn as_text(&self) -> Option<&str> {
+    match self {
+      Value::Text(text) => Some(text),
+      _ => None,
+    }
+  }
+}
+
+/// Nesting limit so malicious input can not overflow the stack
+const MAX_DEPTH: usize = 16;
+
+struct Decoder<'a> {
+  input: &'a [u8],
+  position: usize,
+}
+
+impl<'a> Decoder<'a> {
+  fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+    let end = self.position.checked_add(length)?;
+    let bytes = self.input.get(self.position..end)?;
+    self.position = end;
+    Some(bytes)
+  }
+
+  fn argument(&mut self, info: u8) -> Option<u64> {
+    let length = match info {
+      0..=23 => return Some(info as u64),
+      24 => 1,
+      25 => 2,
+      26 => 4,
+      27 => 8,
+      _ => return None,
+    };
+    Some(
+      self
+        .take(length)?
+        .iter()
+        .fold(0u64, |value, byte| (value << 8) | *byte as u64),
+    )
+  }
+
+  fn value(&mut self, depth: usize) -> Option<Value> {
+    if depth > MAX_DEPTH {
+      return None;
+    }
+
+    let initial = *self.take(1)?.first()?;
+    let (major, info) = (initial >> 5, initial & 0x1f);
+    let argument = self.argument(info)?;
+
+    match major {
+      0 => Some(Value::Integer(i64::try_from(argument).ok()?)),
+      1 => Some(Value::Integer(-1 - i64::try_from(argument).ok()?)),
+      2 => Some(Value::Bytes(self.take(argument as usize)?.to_vec())),
+      3 => Some(Value::Text(
+        String::from_utf8(self.take(argument as usize)?.to_vec()).ok()?,
+      )),
+      4 => {
+        let mut items = vec![];
+        for _ in 0..argument {
+          items.push(self.value(depth + 1)?);
+        }
+        Some(Value::Array(items))
+      }
+      5 => {
+        let mut entries = vec![];
+        for _ in 0..argument {
+          entries.push((self.value(depth + 1)?, self.value(depth + 1)?));
+        }
+        Some(Value::Map(entries))
+      }
+      7 => match info {
+        20 => Some(Value::Bool(false)),
+        21 => Some(Value::Bool(true)),
+        22 => Some(Value::Null),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+}
+
+/// Decodes the first value of the input, returns it and the number of bytes read
+pub fn decode_prefix(input: &[u8]) -> Option<(Value, usize)> {
+  let mut decoder = Decoder { input, position: 0 };
+  let value = decoder.value(0)?;
+  Some((value, decoder.position))
+}
+
+/// Decodes input that holds exactly one value
+pub fn decode(input: &[u8]) -> Option<Value> {
+  match decode_prefix(input)? {
+    (value, length) if length == input.len() => Some(value),
+    _ => None,
+  }
+}
+
+fn encode_head(major: u8, argument: u64, output: &mut Vec<u8>) {
+  let major = major << 5;
+  match argument {
+    0..=23 => output.push(major | argument as u8),
+    24..=0xff => output.extend([major | 24, argument as u8]),
+    0x100..=0xffff => {
+      output.push(major | 25);
+      output.extend((argument as u16).to_be_bytes());
+    }
+    0x10000..=0xffff_ffff => {
+      output.push(major | 26);
+      output.extend((argument as u32).to_be_bytes());
+    }
+    _ => {
+      output.push(major | 27);
+      output.extend(argument.to_be_bytes());
+    }
+  }
+}
+
+fn encode_into(value: &Value, output: &mut Vec<u8>) {
+  match value {
+    Value::Integer(integer) if *integer >= 0 => encode_head(0, *integer as u64, output),
+    Value::Integer(integer) => encode_head(1, (-1 - *integer) as u64, output),
+    Value::Bytes(bytes) => {
+      encode_head(2, bytes.len() as u64, output);
+      output.extend(bytes);
+    }
+    Value::Text(text) => {
+      encode_head(3, text.len() as u64, output);
+      output.extend(text.as_bytes());
+    }
+    Value::Array(items) => {
+      encode_head(4, items.len() as u64, output);
+      items.iter().for_each(|item| encode_into(item, output));
+    }
+    Value::Map(entries) => {
+      encode_head(5, entries.len() as u64, output);
+      for (key, value) in entries {
+        encode_into(key, output);
+        encode_into(value, output);
+      }
+    }
+    Value::Bool(false) => output.push(0xf4),
+    Value::Bool(true) => output.push(0xf5),
+    Value::Null => output.push(0xf6),
+  }
+}
+
+pub fn encode(value: &Value) -> Vec<u8> {
+  let mut output = vec![];
+  encode_into(value, &mut output);
+  output
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  #[test]
+  fn test_decode() {
+    // {"fmt": "none", 1: -7, "b": h'0102', "a": [true, null]}
+    let input = [
+      0xa4, 0x63, b'f', b'm', b't', 0x64, b'n', b'o', b'n', b'e', 0x01, 0x26, 0x61, b'b', 0x42,
+      0x01, 0x02, 0x61, b'a', 0x82, 0xf5, 0xf6,
+    ];
+    let value = decode(&input).unwrap();
+
+    assert_eq!(value.get_text("fmt").unwrap().as_text(), Some("none"));
+    assert_eq!(value.get_int(1).unwrap().as_integer(), Some(-7));
+    assert_eq!(value.get_text("b").unwrap().as_bytes(), Some(&[1u8, 2][..]));
+    assert_eq!(
+      value.get_text("a"),
+      Some(&Value::Array(vec![Value::Bool(true), Value::Null]))
+    );
+    assert_eq!(encode(&value), input);
+  }
+
+  #[test]
+  fn test_invalid_input() {
+    // truncated byte string
+    assert_eq!(decode(&[0x42, 0x01]), None);
+    // trailing bytes
+    assert_eq!(decode(&[0x01, 0x02]), None);
+    assert_eq!(decode_prefix(&[0x01, 0x02]), Some((Value::Integer(1), 1)));
+    // floats are not supported
+    assert_eq!(decode(&[0xf9, 0x3c, 0x00]), None);
+    // nesting limit
+    assert_eq!(decode(&[0x81; 64]), None);
+  }
+
+  #[test]
+  fn test_integer_lengths() {
+    for integer in [0, 23, 24, 255, 256, 65_536, -1, -25, -1_000_000] {
+      let value = Value::Integer(integer);
+      assert_eq!(decode(&encode(&value)), Some(value));
+    }
+  }
+}
@@ -0,0 +1,199 @@
+use crate::util::geoip::parse_ip;
+use dotenvy::dotenv;
+use poem::Request;
+use std::{env, net::IpAddr};
+
+// The address a request came from. `X-Forwarded-For` and `X-Real-IP` can be
+// set by anyone, so they are only read when the connection comes from one of
+// the proxies in TRUSTED_PROXIES. Otherwise rate limits per address could be
+// escaped by sending a different header with every request.
+
+/// An address or a CIDR range like `10.0.0.0/8`, IPv4 is stored as
+/// IPv4-mapped IPv6
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Network {
+  address: u128,
+  prefix: u32,
+}
+
+fn to_u128(ip: IpAddr) -> u128 {
+  match ip {
+    IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+    IpAddr::V6(ip) => u128::from(ip),
+  }
+}
+
+fn mask(prefix: u32) -> u128 {
+  u128::MAX.checked_shl(128 - prefix).unwrap_or(0)
+}
+
+/// IPv4 addresses of dual-stack sockets arrive IPv4-mapped
+fn unmap(ip: IpAddr) -> IpAddr {
+  match ip {
+    IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+    ip => ip,
+  }
+}
+
+impl Network {
+  pub fn parse(value: &str) -> Option<Self> {
+    let (address, prefix) = match value.trim().split_once('/') {
+      Some((address, prefix)) => (address, Some(prefix.parse::<u32>().ok()?)),
+      None => (value.trim(), None),
+    };
+    let ip = address.parse::<IpAddr>().ok()?;
+
+    let prefix = match (ip, prefix) {
+      (IpAddr::V4(_), Some(prefix)) if prefix <= 32 => prefix + 96,
+      (IpAddr::V6(_), Some(prefix)) if prefix <= 128 => prefix,
+      (_, None) => 128,
+      _ => return None,
+    };
+
+    Some(Network {
+      address: to_u128(ip) & mask(prefix),
+      prefix,
+    })
+  }
+
+  pub fn contains(&self, ip: IpAddr) -> bool {
+    to_u128(unmap(ip)) & mask(self.prefix) == self.address
+  }
+}
+
+/// Proxies allowed to forward the client address, from TRUSTED_PROXIES
+/// (comma separated addresses and CIDR ranges)
+pub fn trusted_proxies() -> Vec<Network> {
+  dotenv().ok();
+
+  env::var("TRUSTED_PROXIES")
+    .unwrap_or_default()
+    .split(',')
+    .filter_map(Network::parse)
+    .collect()
+}
+
+/// Client address from the connection's address and the forwarding headers.
+/// `X-Forwarded-For` is read from the right, every proxy appends the address
+/// it got the request from, so the first one that is not a trusted proxy is
+/// the client
+pub fn resolve(
+  remote: Option<IpAddr>,
+  forwarded_for: Option<&str>,
+  real_ip: Option<&str>,
+  trusted: &[Network],
+) -> Option<IpAddr> {
+  let is_trusted = |ip: IpAddr| trusted.iter().any(|network| network.contains(ip));
+
+  let remote = unmap(remote?);
+  if !is_trusted(remote) {
+    return Some(remote);
+  }
+
+  let hops: Vec<IpAddr> = forwarded_for
+    .unwrap_or_default()
+    .split(',')
+    .filter_map(parse_ip)
+    .collect();
+  match hops.iter().rev().find(|hop| !is_trusted(**hop)) {
+    Some(client) => Some(*client),
+    // every hop is a trusted proxy, the first is as close as it gets
+    None => hops
+      .first()
+      .copied()
+      .or_else(|| real_ip.and_then(parse_ip))
+      .or(Some(remote)),
+  }
+}
+
+/// Client address of a request, the connection's address when it is not
+/// an IP address
+pub fn client_ip(request: &Request) -> String {
+  let forwarded_for = request
+    .headers()
+    .get_all("X-Forwarded-For")
+    .iter()
+    .filter_map(|header| header.to_str().ok())
+    .collect::<Vec<&str>>()
+    .join(",");
+
+  match resolve(
+    request.remote_addr().as_socket_addr().map(|addr| addr.ip()),
+    Some(&forwarded_for),
+    request.header("X-Real-IP"),
+    &trusted_proxies(),
+  ) {
+    Some(ip) => ip.to_string(),
+    None => request.remote_addr().to_string(),
+  }
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  fn ip(value: &str) -> IpAddr {
+    value.parse().unwrap()
+  }
+
+  #[test]
+  fn test_network() {
+    let network = Network::parse("10.0.0.0/8").unwrap();
+    assert!(network.contains(ip("10.200.0.1")));
+    assert!(network.contains(ip("::ffff:10.0.0.1")));
+    assert!(!network.contains(ip("11.0.0.1")));
+
+    assert!(Network::parse("2001:db8::/32")
+      .unwrap()
+      .contains(ip("2001:db8:1::1")));
+    assert!(Network::parse("127.0.0.1")
+      .unwrap()
+      .contains(ip("127.0.0.1")));
+    assert!(!Network::parse("127.0.0.1")
+      .unwrap()
+      .contains(ip("127.0.0.2")));
+    assert!(Network::parse("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+    assert_eq!(Network::parse("10.0.0.0/33"), None);
+    assert_eq!(Network::parse("proxy"), None);
+  }
+
+  #[test]
+  fn test_resolve() {
+    let trusted = [Network::parse("10.0.0.0/8").unwrap()];
+
+    // headers from clients are ignored
+    assert_eq!(
+      resolve(
+        Some(ip("203.0.113.7")),
+        Some("198.51.100.1"),
+        Some("198.51.100.2"),
+        &trusted
+      ),
+      Some(ip("203.0.113.7"))
+    );
+    assert_eq!(
+      resolve(Some(ip("10.0.0.1")), None, None, &[]),
+      Some(ip("10.0.0.1"))
+    );
+
+    // behind a trusted proxy, addresses the client made up are skipped
+    assert_eq!(
+      resolve(
+        Some(ip("10.0.0.1")),
+        Some("198.51.100.1, 203.0.113.7, 10.0.0.2"),
+        None,
+        &trusted
+      ),
+      Some(ip("203.0.113.7"))
+    );
+    assert_eq!(
+      resolve(Some(ip("10.0.0.1")), None, Some("203.0.113.7"), &trusted),
+      Some(ip("203.0.113.7"))
+    );
+    assert_eq!(
+      resolve(Some(ip("10.0.0.1")), Some("10.0.0.2"), None, &trusted),
+      Some(ip("10.0.0.2"))
+    );
+    assert_eq!(resolve(None, Some("203.0.113.7"), None, &trusted), None);
+  }
+}
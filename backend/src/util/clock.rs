@@ -0,0 +1,62 @@
+use crate::util::unix_time::unix_ms;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// Source of the current time, so time dependent code can be tested
+/// without waiting for real time to pass
+pub trait Clock: Send + Sync {
+  /// Current unix time in milliseconds
+  fn now_ms(&self) -> i64;
+}
+
+/// Clock using the system time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now_ms(&self) -> i64 {
+    unix_ms()
+  }
+}
+
+/// Clock that only moves when it is set or advanced
+pub struct ManualClock {
+  now_ms: AtomicI64,
+}
+
+impl ManualClock {
+  pub fn new(now_ms: i64) -> Self {
+    ManualClock {
+      now_ms: AtomicI64::new(now_ms),
+    }
+  }
+
+  pub fn set(&self, now_ms: i64) {
+    self.now_ms.store(now_ms, Ordering::SeqCst);
+  }
+
+  pub fn advance(&self, ms: i64) {
+    self.now_ms.fetch_add(ms, Ordering::SeqCst);
+  }
+}
+
+impl Clock for ManualClock {
+  fn now_ms(&self) -> i64 {
+    self.now_ms.load(Ordering::SeqCst)
+  }
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  #[test]
+  fn test_manual_clock() {
+    let clock = ManualClock::new(1_000);
+    assert_eq!(clock.now_ms(), 1_000);
+
+    clock.advance(500);
+    assert_eq!(clock.now_ms(), 1_500);
+
+    clock.set(10);
+    assert_eq!(clock.now_ms(), 10);
+  }
+}
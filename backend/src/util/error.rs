@@ -10,14 +10,42 @@ pub enum APIError {
   UserNotFound,
   InviteNotFound,
   SessionNotFound,
+  SessionExpired,
   CategoryNotFound,
   TagNotFound,
   EntryNotFound,
   EmailAlreadyInUse,
-  InvalidPassword,
+  InvalidCredentials,
+  CurrentPasswordRequired,
+  IncorrectCurrentPassword,
   InviteUsed,
   BadRequest,
   EntryAlreadyExistsForDate,
+  TooManyLoginAttempts,
+  TwoFactorRequired,
+  TwoFactorAlreadyEnabled,
+  TwoFactorNotEnabled,
+  InvalidTwoFactorCode,
+  TwoFactorChallengeExpired,
+  InvalidPasskey,
+  PasskeyNotFound,
+  PasskeyChallengeExpired,
+  InvalidToken,
+  EmailAlreadyVerified,
+  MailError,
+  TooManyEmails,
+  WeakPassword,
+  BreachedPassword,
+  InvalidCsrfToken,
+  ApiTokenNotFound,
+  ApiTokenExpired,
+  InsufficientScope,
+  OidcProviderNotFound,
+  OidcProviderError,
+  OidcLoginExpired,
+  InvalidIdToken,
+  OidcEmailNotVerified,
+  OidcAccountNotVerified,
 }
 
 #[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
@@ -37,14 +65,44 @@ fn error_message(error: APIError) -> String {
     APIError::UserNotFound => "User not found",
     APIError::InviteNotFound => "Invite not found",
     APIError::SessionNotFound => "Session not found",
+    APIError::SessionExpired => "Session expired",
     APIError::CategoryNotFound => "Category not found",
     APIError::TagNotFound => "Tag not found",
     APIError::EntryNotFound => "Entry not found",
     APIError::EmailAlreadyInUse => "Email already in use",
-    APIError::InvalidPassword => "Invalid password",
+    APIError::InvalidCredentials => "Invalid email or password",
+    APIError::CurrentPasswordRequired => "Current password required",
+    APIError::IncorrectCurrentPassword => "Incorrect current password",
     APIError::InviteUsed => "Invite already used",
     APIError::BadRequest => "Bad request",
     APIError::EntryAlreadyExistsForDate => "An entry already exists for the given date",
+    APIError::TooManyLoginAttempts => "Too many login attempts, try again later",
+    APIError::TwoFactorRequired => "Two-factor authentication required",
+    APIError::TwoFactorAlreadyEnabled => "Two-factor authentication is already enabled",
+    APIError::TwoFactorNotEnabled => "Two-factor authentication is not enabled",
+    APIError::InvalidTwoFactorCode => "Invalid two-factor code",
+    APIError::TwoFactorChallengeExpired => "Two-factor challenge expired, log in again",
+    APIError::InvalidPasskey => "Invalid passkey",
+    APIError::PasskeyNotFound => "Passkey not found",
+    APIError::PasskeyChallengeExpired => "Passkey challenge expired, try again",
+    APIError::InvalidToken => "This link is invalid, has expired or was already used",
+    APIError::EmailAlreadyVerified => "Email is already verified",
+    APIError::MailError => "Could not send email",
+    APIError::TooManyEmails => "Too many emails requested, try again later",
+    APIError::WeakPassword => "Password is too short or too easy to guess",
+    APIError::BreachedPassword => "Password appeared in a data breach, choose a different one",
+    APIError::InvalidCsrfToken => "Missing or invalid CSRF token",
+    APIError::ApiTokenNotFound => "API token not found",
+    APIError::ApiTokenExpired => "API token expired",
+    APIError::InsufficientScope => "The API token does not have the scope for this request",
+    APIError::OidcProviderNotFound => "Login provider not found",
+    APIError::OidcProviderError => "Could not reach the login provider",
+    APIError::OidcLoginExpired => "The login has expired, try again",
+    APIError::InvalidIdToken => "The login provider's response could not be verified",
+    APIError::OidcEmailNotVerified => "The login provider did not confirm a verified email",
+    APIError::OidcAccountNotVerified => {
+      "An account with this email exists, log in and verify its email to link the provider"
+    }
     _ => "An error occurred",
   }
   .to_string()
@@ -56,14 +114,43 @@ fn status_code(error: APIError) -> StatusCode {
     APIError::UserNotFound => StatusCode::NOT_FOUND,
     APIError::InviteNotFound => StatusCode::NOT_FOUND,
     APIError::SessionNotFound => StatusCode::NOT_FOUND,
+    APIError::SessionExpired => StatusCode::UNAUTHORIZED,
     APIError::CategoryNotFound => StatusCode::NOT_FOUND,
     APIError::TagNotFound => StatusCode::NOT_FOUND,
     APIError::EntryNotFound => StatusCode::NOT_FOUND,
     APIError::EmailAlreadyInUse => StatusCode::CONFLICT,
-    APIError::InvalidPassword => StatusCode::UNAUTHORIZED,
+    APIError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+    APIError::CurrentPasswordRequired => StatusCode::BAD_REQUEST,
+    // not 401, the session itself is still valid
+    APIError::IncorrectCurrentPassword => StatusCode::FORBIDDEN,
     APIError::InviteUsed => StatusCode::CONFLICT,
     APIError::BadRequest => StatusCode::BAD_REQUEST,
     APIError::EntryAlreadyExistsForDate => StatusCode::CONFLICT,
+    APIError::TooManyLoginAttempts => StatusCode::TOO_MANY_REQUESTS,
+    APIError::TwoFactorRequired => StatusCode::FORBIDDEN,
+    APIError::TwoFactorAlreadyEnabled => StatusCode::CONFLICT,
+    APIError::TwoFactorNotEnabled => StatusCode::NOT_FOUND,
+    APIError::InvalidTwoFactorCode => StatusCode::UNAUTHORIZED,
+    APIError::TwoFactorChallengeExpired => StatusCode::UNAUTHORIZED,
+    APIError::InvalidPasskey => StatusCode::UNAUTHORIZED,
+    APIError::PasskeyNotFound => StatusCode::NOT_FOUND,
+    APIError::PasskeyChallengeExpired => StatusCode::UNAUTHORIZED,
+    APIError::InvalidToken => StatusCode::BAD_REQUEST,
+    APIError::EmailAlreadyVerified => StatusCode::CONFLICT,
+    APIError::MailError => StatusCode::INTERNAL_SERVER_ERROR,
+    APIError::TooManyEmails => StatusCode::TOO_MANY_REQUESTS,
+    APIError::WeakPassword => StatusCode::BAD_REQUEST,
+    APIError::BreachedPassword => StatusCode::BAD_REQUEST,
+    APIError::InvalidCsrfToken => StatusCode::FORBIDDEN,
+    APIError::ApiTokenNotFound => StatusCode::NOT_FOUND,
+    APIError::ApiTokenExpired => StatusCode::UNAUTHORIZED,
+    APIError::InsufficientScope => StatusCode::FORBIDDEN,
+    APIError::OidcProviderNotFound => StatusCode::NOT_FOUND,
+    APIError::OidcProviderError => StatusCode::BAD_GATEWAY,
+    APIError::OidcLoginExpired => StatusCode::UNAUTHORIZED,
+    APIError::InvalidIdToken => StatusCode::UNAUTHORIZED,
+    APIError::OidcEmailNotVerified => StatusCode::FORBIDDEN,
+    APIError::OidcAccountNotVerified => StatusCode::CONFLICT,
     _ => StatusCode::INTERNAL_SERVER_ERROR,
   }
 }
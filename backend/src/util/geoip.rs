@@ -0,0 +1,167 @@
+use std::{
+  fs,
+  net::{IpAddr, Ipv6Addr},
+  path::Path,
+};
+
+// Offline IP to country lookups from a CSV of address ranges, the format of
+// the free DB-IP "IP to Country Lite" (`start,end,country`) and IP2Location
+// LITE DB1 (`"from","to","country","name"`) downloads. Ranges are given as
+// addresses or as integers, IPv4 is stored as IPv4-mapped IPv6.
+
+/// Address ranges sorted by their first address
+#[derive(Debug, Default)]
+pub struct GeoIp {
+  ranges: Vec<(u128, u128, String)>,
+}
+
+fn to_u128(ip: IpAddr) -> u128 {
+  match ip {
+    IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+    IpAddr::V6(ip) => u128::from(ip),
+  }
+}
+
+/// An address, or an integer which is IPv4 when it fits in 32 bits
+fn parse_address(value: &str) -> Option<u128> {
+  let value = value.trim().trim_matches('"');
+  match value.parse::<IpAddr>() {
+    Ok(ip) => Some(to_u128(ip)),
+    Err(_) => match value.parse::<u128>().ok()? {
+      number if number <= u32::MAX as u128 => Some(to_u128(IpAddr::V4((number as u32).into()))),
+      number => Some(number),
+    },
+  }
+}
+
+/// Parses an address as sent by clients and proxies, which may have a port
+/// or a `socket://` prefix
+pub fn parse_ip(address: &str) -> Option<IpAddr> {
+  let address = address.trim().trim_start_matches("socket://");
+  address
+    .parse::<IpAddr>()
+    .ok()
+    .or_else(|| {
+      address
+        .parse::<std::net::SocketAddr>()
+        .ok()
+        .map(|addr| addr.ip())
+    })
+    .map(|ip| match ip {
+      IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+      ip => ip,
+    })
+}
+
+/// The /24 network of an IPv4 address or the /48 of an IPv6 address, to tell
+/// whether two logins came from the same place without a GeoIP database
+pub fn network(ip: IpAddr) -> String {
+  match ip {
+    IpAddr::V4(ip) => {
+      let [a, b, c, _] = ip.octets();
+      format!("{a}.{b}.{c}.0/24")
+    }
+    IpAddr::V6(ip) => {
+      let segments = ip.segments();
+      let prefix = Ipv6Addr::new(segments[0], segments[1], segments[2], 0, 0, 0, 0, 0);
+      format!("{prefix}/48")
+    }
+  }
+}
+
+impl GeoIp {
+  /// Parses the CSV, lines that are not a range with a country are skipped
+  pub fn parse(csv: &str) -> Self {
+    let mut ranges: Vec<(u128, u128, String)> = csv
+      .lines()
+      .filter_map(|line| {
+        let mut fields = line.split(',');
+        let start = parse_address(fields.next()?)?;
+        let end = parse_address(fields.next()?)?;
+        let country = fields.next()?.trim().trim_matches('"').to_uppercase();
+
+        // `-` and `ZZ` mark unassigned ranges
+        match country.len() == 2 && country != "ZZ" && start <= end {
+          true => Some((start, end, country)),
+          false => None,
+        }
+      })
+      .collect();
+    ranges.sort_unstable_by_key(|(start, _, _)| *start);
+
+    GeoIp { ranges }
+  }
+
+  pub fn load(path: &Path) -> std::io::Result<Self> {
+    Ok(GeoIp::parse(&fs::read_to_string(path)?))
+  }
+
+  pub fn len(&self) -> usize {
+    self.ranges.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.ranges.is_empty()
+  }
+
+  /// ISO 3166 country code of an address
+  pub fn country(&self, ip: IpAddr) -> Option<&str> {
+    let ip = to_u128(ip);
+    let index = self.ranges.partition_point(|(start, _, _)| *start <= ip);
+    match self.ranges.get(index.checked_sub(1)?) {
+      Some((_, end, country)) if ip <= *end => Some(country),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  #[test]
+  fn test_country() {
+    let geoip = GeoIp::parse(
+      "1.0.0.0,1.0.0.255,AU\n\
+       2001:db8::,2001:db8:ffff:ffff:ffff:ffff:ffff:ffff,DE\n\
+       \"3232235520\",\"3232301055\",\"NL\",\"Netherlands\"\n\
+       8.8.8.0,8.8.8.255,ZZ\n\
+       not,a range",
+    );
+    assert_eq!(geoip.len(), 3);
+
+    for (ip, country) in [
+      ("1.0.0.1", Some("AU")),
+      ("1.0.1.0", None),
+      ("2001:db8::1", Some("DE")),
+      ("192.168.100.7", Some("NL")),
+      ("8.8.8.8", None),
+      ("0.0.0.0", None),
+    ] {
+      assert_eq!(geoip.country(ip.parse().unwrap()), country, "{ip}");
+    }
+  }
+
+  #[test]
+  fn test_parse_ip() {
+    assert_eq!(
+      parse_ip("socket://127.0.0.1:3137"),
+      Some("127.0.0.1".parse().unwrap())
+    );
+    assert_eq!(
+      parse_ip("::ffff:10.0.0.1"),
+      Some("10.0.0.1".parse().unwrap())
+    );
+    assert_eq!(
+      parse_ip("[2001:db8::1]:443"),
+      Some("2001:db8::1".parse().unwrap())
+    );
+    assert_eq!(parse_ip("unknown"), None);
+
+    assert_eq!(network("10.1.2.3".parse().unwrap()), "10.1.2.0/24");
+    assert_eq!(
+      network("2001:db8:1:2::3".parse().unwrap()),
+      "2001:db8:1::/48"
+    );
+  }
+}
@@ -0,0 +1,48 @@
+/// Round a value to `places` decimal places
+pub fn round_to(value: f64, places: i32) -> f64 {
+  let factor = 10_f64.powi(places);
+  (value * factor).round() / factor
+}
+
+/// Mean of the values, None without values
+pub fn average<T: Copy + Into<f64>>(values: &[T]) -> Option<f64> {
+  if values.is_empty() {
+    return None;
+  }
+  Some(values.iter().map(|value| (*value).into()).sum::<f64>() / values.len() as f64)
+}
+
+/// Median using the same semantics as `PERCENTILE_DISC(0.5)`, for an even
+/// number of values the lower of the two middle values is used. Sorts the
+/// values, None without values
+pub fn median<T: Copy + Ord>(values: &mut [T]) -> Option<T> {
+  if values.is_empty() {
+    return None;
+  }
+  values.sort_unstable();
+  Some(values[(values.len() - 1) / 2])
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  #[test]
+  fn test_round_to() {
+    assert_eq!(round_to(2.333_333, 2), 2.33);
+    assert_eq!(round_to(0.666_666, 4), 0.6667);
+    assert_eq!(round_to(1.5, 0), 2.0);
+  }
+
+  #[test]
+  fn test_average_and_median() {
+    assert_eq!(average::<i32>(&[]), None);
+    assert_eq!(average(&[1, 2, 4]), Some(7.0 / 3.0));
+    assert_eq!(average(&[0.5, 1.5]), Some(1.0));
+
+    assert_eq!(median::<i32>(&mut []), None);
+    assert_eq!(median(&mut [5, 1, 3]), Some(3));
+    // the lower middle value, like PERCENTILE_DISC(0.5)
+    assert_eq!(median(&mut [4, 1, 2, 3]), Some(2));
+  }
+}
@@ -1,5 +1,5 @@
 use crate::util::error::{error_response, APIError};
-use poem::{http::StatusCode, Body, Response};
+use poem::{http::StatusCode, Body, Request, Response, ResponseBuilder};
 
 fn json_response(status_code: StatusCode, body: impl Into<Body>) -> Response {
   Response::builder()
@@ -19,6 +19,101 @@ pub fn empty_response(status_code: StatusCode) -> Response {
   json_response(status_code, ())
 }
 
+pub fn attachment_response(
+  status_code: StatusCode,
+  content_type: &str,
+  filename: &str,
+  body: impl Into<Body>,
+) -> Response {
+  Response::builder()
+    .status(status_code)
+    .header("Content-Type", content_type)
+    .header(
+      "Content-Disposition",
+      format!("attachment; filename=\"{filename}\""),
+    )
+    .body(body)
+}
+
+/// Format a unix timestamp in milliseconds as an HTTP date
+pub fn http_date(unix_ms: i64) -> String {
+  match chrono::DateTime::from_timestamp_millis(unix_ms) {
+    Some(date) => date.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+    None => String::new(),
+  }
+}
+
+/// Whether the request's conditional headers match the current version,
+/// `If-None-Match` takes precedence over `If-Modified-Since`
+fn not_modified(request: &Request, etag: &str, last_modified: i64) -> bool {
+  if let Some(if_none_match) = request.header("If-None-Match") {
+    return if_none_match.split(',').any(|tag| {
+      let tag = tag.trim();
+      tag == "*" || tag.trim_start_matches("W/") == etag
+    });
+  }
+
+  match request
+    .header("If-Modified-Since")
+    .and_then(|since| chrono::DateTime::parse_from_rfc2822(since).ok())
+  {
+    Some(since) => last_modified / 1000 <= since.timestamp(),
+    None => false,
+  }
+}
+
+/// JSON response with `ETag` and `Last-Modified` headers, answering
+/// conditional requests that match with 304 Not Modified
+pub fn conditional_response(
+  request: &Request,
+  body: String,
+  etag: &str,
+  last_modified: i64,
+) -> Response {
+  let builder = conditional_builder(etag, last_modified);
+
+  if not_modified(request, etag, last_modified) {
+    return builder.status(StatusCode::NOT_MODIFIED).body(());
+  }
+
+  builder
+    .status(StatusCode::OK)
+    .header("Content-Type", "application/json")
+    .body(body)
+}
+
+/// Like `conditional_response` for a download, see `attachment_response`
+pub fn conditional_attachment_response(
+  request: &Request,
+  content_type: &str,
+  filename: &str,
+  body: String,
+  etag: &str,
+  last_modified: i64,
+) -> Response {
+  let builder = conditional_builder(etag, last_modified);
+
+  if not_modified(request, etag, last_modified) {
+    return builder.status(StatusCode::NOT_MODIFIED).body(());
+  }
+
+  builder
+    .status(StatusCode::OK)
+    .header("Content-Type", content_type)
+    .header(
+      "Content-Disposition",
+      format!("attachment; filename=\"{filename}\""),
+    )
+    .body(body)
+}
+
+fn conditional_builder(etag: &str, last_modified: i64) -> ResponseBuilder {
+  Response::builder()
+    .header("ETag", etag)
+    .header("Last-Modified", http_date(last_modified))
+    .header("Cache-Control", "private, no-cache")
+}
+
 #[cfg(test)]
 mod ci_unit {
   use super::*;
@@ -33,6 +128,99 @@ mod ci_unit {
     );
   }
 
+  #[test]
+  fn test_attachment_response() {
+    let response = attachment_response(StatusCode::OK, "text/html", "review.html", "<html>");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("Content-Type").unwrap(), "text/html");
+    assert_eq!(
+      response.headers().get("Content-Disposition").unwrap(),
+      "attachment; filename=\"review.html\""
+    );
+  }
+
+  #[test]
+  fn test_http_date() {
+    assert_eq!(
+      http_date(1_700_000_000_500),
+      "Tue, 14 Nov 2023 22:13:20 GMT"
+    );
+  }
+
+  #[test]
+  fn test_conditional_response() {
+    let etag = "\"abc\"";
+    let last_modified = 1_700_000_000_500;
+
+    let request = Request::builder().finish();
+    let response = conditional_response(&request, "[]".to_string(), etag, last_modified);
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("ETag").unwrap(), etag);
+    assert_eq!(
+      response.headers().get("Last-Modified").unwrap(),
+      "Tue, 14 Nov 2023 22:13:20 GMT"
+    );
+
+    let request = Request::builder()
+      .header("If-None-Match", "\"xyz\", W/\"abc\"")
+      .finish();
+    let response = conditional_response(&request, "[]".to_string(), etag, last_modified);
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let request = Request::builder()
+      .header("If-None-Match", "\"xyz\"")
+      .header("If-Modified-Since", "Tue, 14 Nov 2023 22:13:20 GMT")
+      .finish();
+    let response = conditional_response(&request, "[]".to_string(), etag, last_modified);
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::builder()
+      .header("If-Modified-Since", "Tue, 14 Nov 2023 22:13:20 GMT")
+      .finish();
+    let response = conditional_response(&request, "[]".to_string(), etag, last_modified);
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let request = Request::builder()
+      .header("If-Modified-Since", "Tue, 14 Nov 2023 22:13:19 GMT")
+      .finish();
+    let response = conditional_response(&request, "[]".to_string(), etag, last_modified);
+    assert_eq!(response.status(), StatusCode::OK);
+  }
+
+  #[test]
+  fn test_conditional_attachment_response() {
+    let etag = "\"abc\"";
+    let last_modified = 1_700_000_000_500;
+
+    let request = Request::builder().finish();
+    let response = conditional_attachment_response(
+      &request,
+      "text/html",
+      "review.html",
+      "<html>".to_string(),
+      etag,
+      last_modified,
+    );
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("Content-Type").unwrap(), "text/html");
+    assert_eq!(
+      response.headers().get("Content-Disposition").unwrap(),
+      "attachment; filename=\"review.html\""
+    );
+    assert_eq!(response.headers().get("ETag").unwrap(), etag);
+
+    let request = Request::builder().header("If-None-Match", etag).finish();
+    let response = conditional_attachment_response(
+      &request,
+      "text/html",
+      "review.html",
+      "<html>".to_string(),
+      etag,
+      last_modified,
+    );
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+  }
+
   #[test]
   fn test_empty_response() {
     let response = empty_response(StatusCode::CREATED);
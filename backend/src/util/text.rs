@@ -0,0 +1,343 @@
+/// Common English words left out of word frequency statistics
+pub const STOPWORDS: &[&str] = &[
+  "a",
+  "about",
+  "above",
+  "after",
+  "again",
+  "against",
+  "all",
+  "also",
+  "am",
+  "an",
+  "and",
+  "any",
+  "are",
+  "as",
+  "at",
+  "be",
+  "because",
+  "been",
+  "before",
+  "being",
+  "below",
+  "between",
+  "both",
+  "but",
+  "by",
+  "can",
+  "could",
+  "did",
+  "do",
+  "does",
+  "doing",
+  "don't",
+  "down",
+  "during",
+  "each",
+  "even",
+  "few",
+  "for",
+  "from",
+  "further",
+  "get",
+  "got",
+  "had",
+  "has",
+  "have",
+  "having",
+  "he",
+  "her",
+  "here",
+  "hers",
+  "herself",
+  "him",
+  "himself",
+  "his",
+  "how",
+  "i",
+  "i'd",
+  "i'll",
+  "i'm",
+  "i've",
+  "if",
+  "in",
+  "into",
+  "is",
+  "isn't",
+  "it",
+  "it's",
+  "its",
+  "itself",
+  "just",
+  "let",
+  "me",
+  "more",
+  "most",
+  "much",
+  "my",
+  "myself",
+  "no",
+  "nor",
+  "not",
+  "now",
+  "of",
+  "off",
+  "on",
+  "once",
+  "only",
+  "or",
+  "other",
+  "our",
+  "ours",
+  "ourselves",
+  "out",
+  "over",
+  "own",
+  "really",
+  "same",
+  "she",
+  "so",
+  "some",
+  "still",
+  "such",
+  "than",
+  "that",
+  "that's",
+  "the",
+  "their",
+  "theirs",
+  "them",
+  "themselves",
+  "then",
+  "there",
+  "these",
+  "they",
+  "this",
+  "those",
+  "through",
+  "to",
+  "today",
+  "too",
+  "under",
+  "until",
+  "up",
+  "very",
+  "was",
+  "wasn't",
+  "we",
+  "went",
+  "were",
+  "what",
+  "when",
+  "where",
+  "which",
+  "while",
+  "who",
+  "whom",
+  "why",
+  "will",
+  "with",
+  "would",
+  "you",
+  "your",
+  "yours",
+  "yourself",
+];
+
+/// Words that flip the sentiment of the word that follows them
+const NEGATIONS: &[&str] = &[
+  "not", "no", "never", "don't", "didn't", "doesn't", "isn't", "wasn't", "can't", "couldn't",
+  "won't", "wouldn't",
+];
+
+/// Sentiment lexicon, scores range from -3 (very negative) to 3 (very positive)
+const LEXICON: &[(&str, i32)] = &[
+  ("afraid", -2),
+  ("amazing", 3),
+  ("angry", -3),
+  ("annoyed", -2),
+  ("anxious", -2),
+  ("ashamed", -2),
+  ("awesome", 3),
+  ("awful", -3),
+  ("bad", -2),
+  ("beautiful", 3),
+  ("best", 3),
+  ("better", 2),
+  ("bored", -2),
+  ("boring", -2),
+  ("brilliant", 3),
+  ("broke", -1),
+  ("broken", -2),
+  ("calm", 2),
+  ("cheerful", 2),
+  ("comfortable", 2),
+  ("confident", 2),
+  ("confused", -1),
+  ("crap", -3),
+  ("cried", -2),
+  ("cry", -2),
+  ("crying", -2),
+  ("delighted", 3),
+  ("depressed", -3),
+  ("disappointed", -2),
+  ("disappointing", -2),
+  ("disaster", -3),
+  ("disgusting", -3),
+  ("down", -1),
+  ("drained", -2),
+  ("dread", -2),
+  ("easy", 1),
+  ("energetic", 2),
+  ("enjoy", 2),
+  ("enjoyed", 2),
+  ("excellent", 3),
+  ("excited", 3),
+  ("exciting", 3),
+  ("exhausted", -2),
+  ("fail", -2),
+  ("failed", -2),
+  ("fantastic", 3),
+  ("fear", -2),
+  ("fine", 1),
+  ("frustrated", -2),
+  ("frustrating", -2),
+  ("fun", 2),
+  ("glad", 2),
+  ("good", 2),
+  ("grateful", 3),
+  ("great", 3),
+  ("grief", -3),
+  ("guilty", -2),
+  ("happy", 3),
+  ("hate", -3),
+  ("hated", -3),
+  ("healthy", 2),
+  ("helpful", 2),
+  ("hopeful", 2),
+  ("hopeless", -3),
+  ("horrible", -3),
+  ("hurt", -2),
+  ("ill", -2),
+  ("lonely", -2),
+  ("lost", -1),
+  ("love", 3),
+  ("loved", 3),
+  ("lovely", 3),
+  ("mad", -2),
+  ("miserable", -3),
+  ("miss", -1),
+  ("nervous", -2),
+  ("nice", 2),
+  ("overwhelmed", -2),
+  ("pain", -2),
+  ("painful", -2),
+  ("panic", -3),
+  ("peaceful", 2),
+  ("perfect", 3),
+  ("pleasant", 2),
+  ("pleased", 2),
+  ("poor", -2),
+  ("productive", 2),
+  ("proud", 2),
+  ("relaxed", 2),
+  ("relaxing", 2),
+  ("relieved", 2),
+  ("rested", 2),
+  ("sad", -2),
+  ("scared", -2),
+  ("sick", -2),
+  ("sore", -1),
+  ("stress", -2),
+  ("stressed", -2),
+  ("stressful", -2),
+  ("strong", 2),
+  ("stuck", -2),
+  ("success", 2),
+  ("successful", 3),
+  ("sucks", -2),
+  ("terrible", -3),
+  ("thankful", 2),
+  ("tired", -1),
+  ("upset", -2),
+  ("useless", -2),
+  ("win", 2),
+  ("wonderful", 3),
+  ("worried", -2),
+  ("worry", -2),
+  ("worse", -2),
+  ("worst", -3),
+];
+
+/// Split text into lowercase words, keeping apostrophes inside words
+pub fn words(text: &str) -> Vec<String> {
+  text
+    .split(|c: char| !(c.is_alphanumeric() || c == '\'' || c == '’'))
+    .map(|word| {
+      word
+        .trim_matches(|c| c == '\'' || c == '’')
+        .replace('’', "'")
+    })
+    .filter(|word| !word.is_empty())
+    .map(|word| word.to_lowercase())
+    .collect()
+}
+
+pub fn is_stopword(word: &str) -> bool {
+  STOPWORDS.contains(&word)
+}
+
+/// Sentiment score for text between -1 (negative) and 1 (positive)
+///
+/// Scores of lexicon words are summed, a negation before a word flips its score,
+/// and the sum is normalized with `sum / sqrt(sum^2 + 15)`. Returns None when the
+/// text contains no lexicon words.
+pub fn sentiment(text: &str) -> Option<f64> {
+  let words = words(text);
+  let mut sum = 0;
+  let mut scored = false;
+
+  for (index, word) in words.iter().enumerate() {
+    if let Some((_, score)) = LEXICON
+      .iter()
+      .find(|(lexicon_word, _)| lexicon_word == word)
+    {
+      let negated = index > 0 && NEGATIONS.contains(&words[index - 1].as_str());
+      sum += if negated { -score } else { *score };
+      scored = true;
+    }
+  }
+
+  if !scored {
+    return None;
+  }
+
+  let sum = sum as f64;
+  Some(sum / (sum * sum + 15.0).sqrt())
+}
+
+#[cfg(test)]
+mod ci_unit {
+  use super::*;
+
+  #[test]
+  fn test_words() {
+    assert_eq!(
+      words("Today I didn't sleep, at ALL... 'really' tired."),
+      vec!["today", "i", "didn't", "sleep", "at", "all", "really", "tired"]
+    );
+    assert!(words("  ... ").is_empty());
+  }
+
+  #[test]
+  fn test_sentiment() {
+    assert!(sentiment("What a great, happy day").unwrap() > 0.5);
+    assert!(sentiment("Felt sad and exhausted").unwrap() < -0.5);
+    assert!(sentiment("It was not good").unwrap() < 0.0);
+    assert_eq!(sentiment("Went to the shop"), None);
+
+    let score = sentiment("amazing amazing amazing amazing amazing").unwrap();
+    assert!(score > 0.9 && score < 1.0);
+  }
+}
@@ -0,0 +1,371 @@
+use diarycomputer::{
+  api,
+  services::{
+    api_token,
+    api_token::{CreateApiToken, Scope},
+    auth,
+    auth::{SessionConfig, SessionMetadata, UserCredentials},
+    mail::FileMailer,
+    password_reset,
+    password_reset::{PasswordReset, PasswordResetRequest},
+    user,
+    user::UpdatePassword,
+  },
+  util::{
+    clock::{Clock, ManualClock},
+    error::APIError,
+    unix_time::unix_ms,
+  },
+};
+use poem::{
+  http::{Method, StatusCode},
+  Endpoint, Request,
+};
+use std::fs;
+use uuid::Uuid;
+
+const DAY: i64 = 24 * 60 * 60 * 1000;
+
+fn create_session() -> auth::NewSession {
+  let random_name = Uuid::new_v4().to_string();
+  let email = format!("{random_name}@example.com");
+
+  user::create_user(user::CreateUser {
+    name: random_name,
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .expect("Failed to create test user");
+
+  auth::create_user_session(
+    UserCredentials {
+      email,
+      password: "password".to_string(),
+    },
+    SessionMetadata {
+      ip_address: "127.0.0.1".to_string(),
+      user_agent: "test".to_string(),
+    },
+  )
+  .expect("Failed to create test session")
+}
+
+/// Sends a request with a bearer token through the API routes
+async fn call_api(method: Method, uri: &str, token: &str, body: &str) -> (StatusCode, String) {
+  let request = Request::builder()
+    .method(method)
+    .uri(uri.parse().unwrap())
+    .header("Authorization", format!("Bearer {token}"))
+    .header("Content-Type", "application/json")
+    .body(body.to_string());
+
+  match api::index::endpoint().call(request).await {
+    Ok(response) => {
+      let status = response.status();
+      (status, response.into_body().into_string().await.unwrap())
+    }
+    Err(error) => (error.status(), String::new()),
+  }
+}
+
+#[tokio::test]
+async fn create_and_use_api_token() {
+  let session = create_session();
+
+  let (status, body) = call_api(
+    Method::POST,
+    "/v1/tokens",
+    &session.token,
+    r#"{"name": "script", "scopes": ["entries:read", "stats:read"], "current_password": "password"}"#,
+  )
+  .await;
+  assert_eq!(status, StatusCode::CREATED);
+  let created: serde_json::Value = serde_json::from_str(&body).unwrap();
+  let token = created["token"].as_str().unwrap().to_string();
+  assert!(token.starts_with(api_token::API_TOKEN_PREFIX));
+  assert_eq!(
+    created["scopes"],
+    serde_json::json!(["entries:read", "stats:read"])
+  );
+  assert!(created["last_used_at"].is_null());
+
+  // handlers with a granted scope accept the token
+  assert_eq!(
+    call_api(Method::GET, "/v1/entries", &token, "").await.0,
+    StatusCode::OK
+  );
+  assert_eq!(
+    call_api(Method::GET, "/v1/stats/weekday", &token, "")
+      .await
+      .0,
+    StatusCode::OK
+  );
+
+  // other scopes and session only handlers do not
+  assert_eq!(
+    call_api(
+      Method::POST,
+      "/v1/entry",
+      &token,
+      r#"{"date": "2024-01-01", "mood": 1, "selected_tags": []}"#
+    )
+    .await
+    .0,
+    StatusCode::FORBIDDEN
+  );
+  assert_eq!(
+    call_api(Method::GET, "/v1/stats/review/2024/html", &token, "")
+      .await
+      .0,
+    StatusCode::FORBIDDEN
+  );
+  assert_eq!(
+    call_api(Method::GET, "/v1/user", &token, "").await.0,
+    StatusCode::FORBIDDEN
+  );
+  assert_eq!(
+    call_api(Method::GET, "/v1/tokens", &token, "").await.0,
+    StatusCode::FORBIDDEN
+  );
+
+  // tokens are listed apart from sessions, without the token or its hash
+  let (status, body) = call_api(Method::GET, "/v1/tokens", &session.token, "").await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(!body.contains(&token));
+  assert!(!body.contains(&auth::hash_token(&token)));
+  let tokens: serde_json::Value = serde_json::from_str(&body).unwrap();
+  assert_eq!(tokens.as_array().unwrap().len(), 1);
+  assert!(tokens[0]["last_used_at"].is_i64());
+  let (_, body) = call_api(Method::GET, "/v1/sessions", &session.token, "").await;
+  assert!(!body.contains(created["id"].as_str().unwrap()));
+
+  // scopes can be changed
+  let id = created["id"].as_str().unwrap();
+  let (status, body) = call_api(
+    Method::PATCH,
+    &format!("/v1/token/{id}"),
+    &session.token,
+    r#"{"scopes": ["entries:write"]}"#,
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(body.contains("\"name\":\"script\""));
+  assert_eq!(
+    call_api(Method::GET, "/v1/entries", &token, "").await.0,
+    StatusCode::FORBIDDEN
+  );
+
+  assert_eq!(
+    call_api(
+      Method::DELETE,
+      &format!("/v1/token/{id}"),
+      &session.token,
+      ""
+    )
+    .await
+    .0,
+    StatusCode::NO_CONTENT
+  );
+  assert_eq!(
+    call_api(Method::GET, "/v1/entries", &token, "").await.0,
+    StatusCode::UNAUTHORIZED
+  );
+}
+
+#[tokio::test]
+async fn api_token_expires() {
+  let session = create_session();
+  let clock = ManualClock::new(unix_ms());
+
+  let created = api_token::create_api_token(
+    &session.session.user_id,
+    CreateApiToken {
+      name: "expiring".to_string(),
+      scopes: vec![Scope::EntriesRead],
+      expires_at: Some(clock.now_ms() + DAY),
+      current_password: Some("password".to_string()),
+    },
+    &clock,
+  )
+  .unwrap();
+  let request = Request::builder()
+    .header("Authorization", format!("Bearer {}", created.token))
+    .finish();
+  let config = SessionConfig::from_env();
+
+  let authorized = auth::authorize(&request, Some(Scope::EntriesRead), &clock, &config)
+    .await
+    .unwrap();
+  assert_eq!(authorized.user_id, session.session.user_id);
+
+  clock.advance(DAY);
+  assert_eq!(
+    auth::authorize(&request, Some(Scope::EntriesRead), &clock, &config)
+      .await
+      .unwrap_err(),
+    APIError::ApiTokenExpired
+  );
+
+  // tokens can not be created already expired or without scopes
+  for (scopes, expires_at) in [
+    (vec![Scope::EntriesRead], Some(clock.now_ms())),
+    (vec![], None),
+  ] {
+    assert_eq!(
+      api_token::create_api_token(
+        &session.session.user_id,
+        CreateApiToken {
+          name: "invalid".to_string(),
+          scopes,
+          expires_at,
+          current_password: Some("password".to_string()),
+        },
+        &clock,
+      )
+      .unwrap_err(),
+      APIError::BadRequest
+    );
+  }
+}
+
+#[tokio::test]
+async fn api_tokens_of_other_users() {
+  let session = create_session();
+  let other_session = create_session();
+
+  let created = api_token::create_api_token(
+    &session.session.user_id,
+    CreateApiToken {
+      name: "mine".to_string(),
+      scopes: vec![Scope::Export],
+      expires_at: None,
+      current_password: Some("password".to_string()),
+    },
+    &ManualClock::new(unix_ms()),
+  )
+  .unwrap();
+
+  assert_eq!(
+    api_token::revoke_api_token(&other_session.session.user_id, &created.api_token.id).unwrap_err(),
+    APIError::ApiTokenNotFound
+  );
+  assert!(
+    api_token::get_user_api_tokens(&other_session.session.user_id)
+      .unwrap()
+      .is_empty()
+  );
+  assert_eq!(
+    api_token::get_user_api_tokens(&session.session.user_id)
+      .unwrap()
+      .len(),
+    1
+  );
+}
+
+fn create_token(user_id: &str, current_password: Option<&str>) -> Result<String, APIError> {
+  api_token::create_api_token(
+    user_id,
+    CreateApiToken {
+      name: "script".to_string(),
+      scopes: vec![Scope::EntriesRead],
+      expires_at: None,
+      current_password: current_password.map(String::from),
+    },
+    &ManualClock::new(unix_ms()),
+  )
+  .map(|created| created.token)
+}
+
+#[tokio::test]
+async fn api_tokens_need_the_current_password() {
+  let session = create_session();
+  let user_id = &session.session.user_id;
+
+  assert_eq!(
+    create_token(user_id, None).unwrap_err(),
+    APIError::CurrentPasswordRequired
+  );
+  assert_eq!(
+    create_token(user_id, Some("wrong password")).unwrap_err(),
+    APIError::IncorrectCurrentPassword
+  );
+  assert_eq!(
+    call_api(
+      Method::POST,
+      "/v1/tokens",
+      &session.token,
+      r#"{"name": "script", "scopes": ["entries:read"]}"#,
+    )
+    .await
+    .0,
+    StatusCode::BAD_REQUEST
+  );
+  assert!(api_token::get_user_api_tokens(user_id).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn api_tokens_revoked_with_password() {
+  let session = create_session();
+  let user_id = &session.session.user_id;
+
+  // changing the password
+  let token = create_token(user_id, Some("password")).unwrap();
+  user::update_password(
+    user_id,
+    UpdatePassword {
+      password: "changed password".to_string(),
+      current_password: Some("password".to_string()),
+    },
+    Some(&session.session.id),
+  )
+  .unwrap();
+  assert_eq!(
+    call_api(Method::GET, "/v1/entries", &token, "").await.0,
+    StatusCode::UNAUTHORIZED
+  );
+
+  // resetting the password
+  let token = create_token(user_id, Some("changed password")).unwrap();
+  assert_eq!(
+    call_api(Method::GET, "/v1/entries", &token, "").await.0,
+    StatusCode::OK
+  );
+
+  let clock = ManualClock::new(unix_ms());
+  let mailer = FileMailer {
+    directory: std::env::temp_dir().join(format!("diary-mail-{}", Uuid::new_v4())),
+    from: "test <test@localhost>".to_string(),
+  };
+  password_reset::request_password_reset(
+    PasswordResetRequest {
+      email: user::get_user(user_id).unwrap().email,
+    },
+    &mailer,
+    &clock,
+  )
+  .unwrap();
+  let message = fs::read_dir(&mailer.directory)
+    .unwrap()
+    .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+    .next()
+    .unwrap();
+  let start = message.find("token=").unwrap() + "token=".len();
+  let reset_token = message[start..].split_whitespace().next().unwrap();
+
+  password_reset::reset_password(
+    PasswordReset {
+      token: reset_token.to_string(),
+      password: "reset password".to_string(),
+    },
+    &clock,
+  )
+  .unwrap();
+  assert_eq!(
+    call_api(Method::GET, "/v1/entries", &token, "").await.0,
+    StatusCode::UNAUTHORIZED
+  );
+  assert!(api_token::get_user_api_tokens(user_id).unwrap().is_empty());
+
+  fs::remove_dir_all(&mailer.directory).ok();
+}
@@ -0,0 +1,478 @@
+use diarycomputer::{
+  api,
+  services::{
+    auth,
+    auth::{LoginResult, SessionMetadata, UserCredentials},
+    email_verification,
+    email_verification::VerifyEmail,
+    mail::{Email, FileMailer, Mailer, QueuedMailer, SmtpMailer, SmtpTls},
+    password_reset,
+    password_reset::{PasswordReset, PasswordResetRequest},
+    user,
+  },
+  util::{clock::ManualClock, error::APIError, unix_time::unix_ms},
+};
+use poem::{
+  http::{Method, StatusCode},
+  Endpoint, Request,
+};
+use std::{
+  fs,
+  io::{BufRead, BufReader, Write},
+  net::TcpListener,
+  path::PathBuf,
+  thread,
+  time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+const HOUR: i64 = 60 * 60 * 1000;
+
+fn metadata() -> SessionMetadata {
+  SessionMetadata {
+    ip_address: "127.0.0.1".to_string(),
+    user_agent: "test".to_string(),
+  }
+}
+
+fn create_test_user() -> (String, String) {
+  let random_name = Uuid::new_v4().to_string();
+  let email = format!("{random_name}@example.com");
+
+  let user = user::create_user(user::CreateUser {
+    name: random_name,
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .expect("Failed to create test user");
+
+  (user.id, email)
+}
+
+fn file_mailer() -> FileMailer {
+  FileMailer {
+    directory: std::env::temp_dir().join(format!("diary-mail-{}", Uuid::new_v4())),
+    from: "test <test@localhost>".to_string(),
+  }
+}
+
+fn sent_messages(directory: &PathBuf) -> Vec<String> {
+  match fs::read_dir(directory) {
+    Ok(entries) => entries
+      .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+      .collect(),
+    Err(_) => Vec::new(),
+  }
+}
+
+/// Token from the link in the last sent email
+fn sent_token(directory: &PathBuf) -> String {
+  let messages = sent_messages(directory);
+  let message = messages.last().expect("No email was sent");
+  let start = message.find("token=").expect("No link in email") + "token=".len();
+
+  message[start..]
+    .split_whitespace()
+    .next()
+    .unwrap()
+    .to_string()
+}
+
+fn logs_in(email: &str, password: &str, clock: &ManualClock) -> bool {
+  matches!(
+    auth::log_in(
+      UserCredentials {
+        email: email.to_string(),
+        password: password.to_string(),
+      },
+      metadata(),
+      clock,
+    ),
+    Ok(LoginResult::Session(_))
+  )
+}
+
+fn reset(token: &str, password: &str) -> PasswordReset {
+  PasswordReset {
+    token: token.to_string(),
+    password: password.to_string(),
+  }
+}
+
+#[test]
+fn password_reset() {
+  let clock = ManualClock::new(unix_ms());
+  let mailer = file_mailer();
+  let (user_id, email) = create_test_user();
+  let session = auth::create_session_for_user(&user_id, metadata(), &clock).unwrap();
+
+  password_reset::request_password_reset(
+    PasswordResetRequest {
+      email: email.clone(),
+    },
+    &mailer,
+    &clock,
+  )
+  .unwrap();
+
+  let messages = sent_messages(&mailer.directory);
+  assert_eq!(messages.len(), 1);
+  assert!(messages[0].contains(&format!("To: {email}\r\n")));
+  let token = sent_token(&mailer.directory);
+
+  password_reset::reset_password(reset(&token, "new password"), &clock).unwrap();
+
+  assert!(logs_in(&email, "new password", &clock));
+  assert!(!logs_in(&email, "password", &clock));
+  assert!(auth::get_user_session_by_token(&session.token).is_err());
+  assert!(user::get_user(&user_id).unwrap().email_verified);
+
+  // tokens only work once
+  assert_eq!(
+    password_reset::reset_password(reset(&token, "other password"), &clock).unwrap_err(),
+    APIError::InvalidToken
+  );
+  assert!(logs_in(&email, "new password", &clock));
+
+  user::delete_user(&user_id).unwrap();
+  fs::remove_dir_all(&mailer.directory).ok();
+}
+
+#[test]
+fn password_reset_unknown_email() {
+  let clock = ManualClock::new(unix_ms());
+  let mailer = file_mailer();
+
+  password_reset::request_password_reset(
+    PasswordResetRequest {
+      email: format!("{}@example.com", Uuid::new_v4()),
+    },
+    &mailer,
+    &clock,
+  )
+  .unwrap();
+
+  assert!(sent_messages(&mailer.directory).is_empty());
+}
+
+#[test]
+fn password_reset_token_expiry_and_binding() {
+  let clock = ManualClock::new(unix_ms());
+  let mailer = file_mailer();
+  let (user_id, email) = create_test_user();
+  let request = || PasswordResetRequest {
+    email: email.clone(),
+  };
+
+  // expired after an hour
+  password_reset::request_password_reset(request(), &mailer, &clock).unwrap();
+  let expired = sent_token(&mailer.directory);
+  clock.advance(HOUR);
+  assert_eq!(
+    password_reset::reset_password(reset(&expired, "new password"), &clock).unwrap_err(),
+    APIError::InvalidToken
+  );
+
+  // invalid once the password was changed
+  fs::remove_dir_all(&mailer.directory).ok();
+  password_reset::request_password_reset(request(), &mailer, &clock).unwrap();
+  let token = sent_token(&mailer.directory);
+  user::update_password(
+    &user_id,
+    user::UpdatePassword {
+      password: "changed password".to_string(),
+      current_password: Some("password".to_string()),
+    },
+    None,
+  )
+  .unwrap();
+  assert_eq!(
+    password_reset::reset_password(reset(&token, "new password"), &clock).unwrap_err(),
+    APIError::InvalidToken
+  );
+  assert!(logs_in(&email, "changed password", &clock));
+
+  // tampered tokens are rejected
+  fs::remove_dir_all(&mailer.directory).ok();
+  password_reset::request_password_reset(request(), &mailer, &clock).unwrap();
+  let token = sent_token(&mailer.directory);
+  let tampered = format!("x{token}");
+  assert_eq!(
+    password_reset::reset_password(reset(&tampered, "new password"), &clock).unwrap_err(),
+    APIError::InvalidToken
+  );
+
+  user::delete_user(&user_id).unwrap();
+  fs::remove_dir_all(&mailer.directory).ok();
+}
+
+#[test]
+fn email_verification() {
+  let clock = ManualClock::new(unix_ms());
+  let mailer = file_mailer();
+  let (user_id, email) = create_test_user();
+  assert!(!user::get_user(&user_id).unwrap().email_verified);
+
+  email_verification::send_verification_email(&user_id, &mailer, &clock).unwrap();
+  let token = sent_token(&mailer.directory);
+
+  // verification tokens can not reset passwords
+  assert_eq!(
+    password_reset::reset_password(reset(&token, "new password"), &clock).unwrap_err(),
+    APIError::InvalidToken
+  );
+
+  email_verification::verify_email(
+    VerifyEmail {
+      token: token.clone(),
+    },
+    &clock,
+  )
+  .unwrap();
+  assert!(user::get_user(&user_id).unwrap().email_verified);
+
+  assert_eq!(
+    email_verification::verify_email(VerifyEmail { token }, &clock).unwrap_err(),
+    APIError::InvalidToken
+  );
+  assert_eq!(
+    email_verification::send_verification_email(&user_id, &mailer, &clock).unwrap_err(),
+    APIError::EmailAlreadyVerified
+  );
+
+  // a changed email has to be verified again, older links stop working
+  user::update_user(
+    &user_id,
+    user::UpdateUser {
+      name: "name".to_string(),
+      email: format!("new-{email}"),
+      current_password: Some("password".to_string()),
+    },
+    None,
+  )
+  .unwrap();
+  assert!(!user::get_user(&user_id).unwrap().email_verified);
+
+  fs::remove_dir_all(&mailer.directory).ok();
+  email_verification::send_verification_email(&user_id, &mailer, &clock).unwrap();
+  let token = sent_token(&mailer.directory);
+  user::update_user(
+    &user_id,
+    user::UpdateUser {
+      name: "name".to_string(),
+      email,
+      current_password: Some("password".to_string()),
+    },
+    None,
+  )
+  .unwrap();
+  assert_eq!(
+    email_verification::verify_email(VerifyEmail { token }, &clock).unwrap_err(),
+    APIError::InvalidToken
+  );
+
+  user::delete_user(&user_id).unwrap();
+  fs::remove_dir_all(&mailer.directory).ok();
+}
+
+/// A minimal stand-in for MailHog without TLS, records the session
+fn smtp_server() -> (u16, thread::JoinHandle<Vec<String>>) {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let port = listener.local_addr().unwrap().port();
+
+  let server = thread::spawn(move || {
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut transcript = Vec::new();
+    let mut in_data = false;
+
+    stream.write_all(b"220 localhost ESMTP\r\n").unwrap();
+    loop {
+      let mut line = String::new();
+      if reader.read_line(&mut line).unwrap_or(0) == 0 {
+        break;
+      }
+      let line = line.trim_end_matches("\r\n").to_string();
+      transcript.push(line.clone());
+
+      let reply: &[u8] = match (in_data, line.as_str()) {
+        (true, ".") => {
+          in_data = false;
+          b"250 queued\r\n"
+        }
+        (true, _) => continue,
+        (false, "DATA") => {
+          in_data = true;
+          b"354 go ahead\r\n"
+        }
+        (false, "QUIT") => {
+          stream.write_all(b"221 bye\r\n").ok();
+          break;
+        }
+        (false, line) if line.starts_with("EHLO") => b"250-localhost\r\n250 AUTH PLAIN\r\n",
+        (false, line) if line.starts_with("AUTH") => b"235 ok\r\n",
+        _ => b"250 ok\r\n",
+      };
+      if stream.write_all(reply).is_err() {
+        break;
+      }
+    }
+
+    transcript
+  });
+
+  (port, server)
+}
+
+fn smtp_mailer(port: u16, credentials: Option<(&str, &str)>, tls: SmtpTls) -> SmtpMailer {
+  SmtpMailer {
+    host: "127.0.0.1".to_string(),
+    port,
+    from: "diary.computer <no-reply@localhost>".to_string(),
+    credentials: credentials
+      .map(|(username, password)| (username.to_string(), password.to_string())),
+    tls,
+  }
+}
+
+fn test_email() -> Email {
+  Email {
+    to: "user@example.com".to_string(),
+    subject: "Hello".to_string(),
+    body: "first line\n.starts with a dot".to_string(),
+  }
+}
+
+#[test]
+fn smtp_mailer_plain() {
+  let (port, server) = smtp_server();
+  smtp_mailer(port, None, SmtpTls::None)
+    .send(&test_email())
+    .unwrap();
+
+  let transcript = server.join().unwrap();
+  assert_eq!(transcript[0], "EHLO diary.computer");
+  assert_eq!(transcript[1], "MAIL FROM:<no-reply@localhost>");
+  assert_eq!(transcript[2], "RCPT TO:<user@example.com>");
+  assert_eq!(transcript[3], "DATA");
+  assert!(transcript.contains(&"Subject: Hello".to_string()));
+  assert!(transcript.contains(&"..starts with a dot".to_string()));
+  assert!(!transcript.iter().any(|line| line.starts_with("AUTH")));
+  assert_eq!(transcript[transcript.len() - 2], ".");
+  assert_eq!(transcript[transcript.len() - 1], "QUIT");
+}
+
+#[test]
+fn smtp_mailer_credentials_need_tls() {
+  // credentials are never sent over a plain connection
+  assert_eq!(
+    smtp_mailer(1, Some(("user", "secret")), SmtpTls::None)
+      .send(&test_email())
+      .unwrap_err(),
+    APIError::MailError
+  );
+
+  // STARTTLS is required, a server that does not offer it gets nothing
+  let (port, server) = smtp_server();
+  assert_eq!(
+    smtp_mailer(port, Some(("user", "secret")), SmtpTls::StartTls)
+      .send(&test_email())
+      .unwrap_err(),
+    APIError::MailError
+  );
+  let transcript = server.join().unwrap();
+  assert_eq!(transcript[0], "EHLO diary.computer");
+  assert!(!transcript
+    .iter()
+    .any(|line| line.starts_with("AUTH") || line.starts_with("MAIL")));
+}
+
+/// A mailer as slow as a far away SMTP server
+struct SlowMailer(FileMailer);
+
+impl Mailer for SlowMailer {
+  fn send(&self, email: &Email) -> Result<(), APIError> {
+    thread::sleep(Duration::from_millis(500));
+    self.0.send(email)
+  }
+}
+
+#[test]
+fn queued_mailer() {
+  let file_mailer = file_mailer();
+  let directory = file_mailer.directory.clone();
+  let mailer = QueuedMailer::new(Box::new(SlowMailer(file_mailer)));
+
+  // sending does not wait for the transport
+  let started = Instant::now();
+  mailer.send(&test_email()).unwrap();
+  assert!(started.elapsed() < Duration::from_millis(250));
+  assert!(sent_messages(&directory).is_empty());
+
+  let deadline = Instant::now() + Duration::from_secs(5);
+  while sent_messages(&directory).is_empty() && Instant::now() < deadline {
+    thread::sleep(Duration::from_millis(50));
+  }
+  assert_eq!(sent_messages(&directory).len(), 1);
+  fs::remove_dir_all(&directory).ok();
+}
+
+#[test]
+fn email_in_use_is_not_revealed() {
+  let mailer = file_mailer();
+  let (user_id, email) = create_test_user();
+  let (other_user_id, other_email) = create_test_user();
+
+  let update = |mailer: Option<&dyn Mailer>| {
+    user::update_user(
+      &user_id,
+      user::UpdateUser {
+        name: "new name".to_string(),
+        email: other_email.clone(),
+        current_password: Some("password".to_string()),
+      },
+      mailer,
+    )
+  };
+
+  assert_eq!(update(None).unwrap_err(), APIError::EmailAlreadyInUse);
+  assert!(sent_messages(&mailer.directory).is_empty());
+
+  // the update looks successful, but only the name changes and the owner of
+  // the email is told
+  assert!(update(Some(&mailer)).unwrap());
+  let updated = user::get_user(&user_id).unwrap();
+  assert_eq!(updated.name, "new name");
+  assert_eq!(updated.email, email);
+
+  let messages = sent_messages(&mailer.directory);
+  assert_eq!(messages.len(), 1);
+  assert!(messages[0].contains(&format!("To: {other_email}\r\n")));
+  assert!(messages[0].contains("/reset-password"));
+
+  user::delete_user(&user_id).unwrap();
+  user::delete_user(&other_user_id).unwrap();
+  fs::remove_dir_all(&mailer.directory).ok();
+}
+
+#[tokio::test]
+async fn password_reset_route() {
+  let endpoint = api::index::endpoint();
+
+  let request = Request::builder()
+    .method(Method::POST)
+    .uri("/v1/auth/password-reset".parse().unwrap())
+    .header("Content-Type", "application/json")
+    .body(format!(r#"{{"email":"{}@example.com"}}"#, Uuid::new_v4()));
+  let response = endpoint.call(request).await.unwrap();
+  assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+  let request = Request::builder()
+    .method(Method::POST)
+    .uri("/v1/auth/password-reset/confirm".parse().unwrap())
+    .header("Content-Type", "application/json")
+    .body(r#"{"token":"invalid.token","password":"new password"}"#);
+  let response = endpoint.call(request).await.unwrap();
+  assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,301 @@
+use chrono::{Duration, NaiveDate};
+use diarycomputer::{
+  services::{entry, insights, insights::Insight, user},
+  util::error::APIError,
+};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+fn create_user() -> user::UserDetails {
+  let random_name = Uuid::new_v4().to_string();
+  let email = format!("{random_name}@example.com");
+
+  let user_data = user::CreateUser {
+    name: random_name.clone(),
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  };
+
+  user::create_user(user_data).expect("Failed to create test user")
+}
+
+fn create_entry(user_id: &str, date: NaiveDate, mood: i32) {
+  entry::create_entry(entry::CreateEntry {
+    date: date.to_string(),
+    mood,
+    entry: None,
+    selected_tags: vec![],
+    user_id: user_id.to_string(),
+  })
+  .unwrap();
+}
+
+fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+  NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+#[test]
+fn mood_drop() {
+  let user = create_user();
+  let today = date(2025, 6, 30);
+
+  // baseline of 20 days at mood 4, then a week at mood 2
+  for day in 7..27 {
+    create_entry(&user.id, today - Duration::days(day), 4);
+  }
+  for day in 0..7 {
+    create_entry(&user.id, today - Duration::days(day), 2);
+  }
+
+  let insights = insights::active_insights_at(&user.id, today).unwrap();
+
+  assert_eq!(
+    insights,
+    vec![Insight::MoodDrop {
+      from_date: date(2025, 6, 24),
+      to_date: today,
+      average_mood: 2.0,
+      baseline_mood: 4.0,
+      difference: 2.0,
+    }]
+  );
+
+  // a week later the drop is part of the baseline and the window is empty
+  let insights = insights::active_insights_at(&user.id, today + Duration::days(7)).unwrap();
+  assert!(insights.is_empty());
+}
+
+#[test]
+fn mood_drop_threshold() {
+  let user = create_user();
+  let today = date(2025, 6, 30);
+
+  for day in 7..27 {
+    create_entry(&user.id, today - Duration::days(day), 4);
+  }
+  for day in 0..7 {
+    create_entry(&user.id, today - Duration::days(day), 3);
+  }
+
+  // a drop of exactly one point is not more than the default threshold
+  assert!(insights::active_insights_at(&user.id, today)
+    .unwrap()
+    .is_empty());
+
+  insights::update_insight_settings(
+    &user.id,
+    insights::UpdateInsightSettings {
+      mood_drop_threshold: 0.5,
+      low_mood_level: 1,
+      low_mood_days: 3,
+      notifications_enabled: true,
+    },
+  )
+  .unwrap();
+
+  let insights = insights::active_insights_at(&user.id, today).unwrap();
+  assert_eq!(insights.len(), 1);
+  assert!(matches!(insights[0], Insight::MoodDrop { .. }));
+}
+
+#[test]
+fn mood_drop_needs_baseline() {
+  let user = create_user();
+  let today = date(2025, 6, 30);
+
+  // not enough baseline entries to compare against
+  for day in 7..10 {
+    create_entry(&user.id, today - Duration::days(day), 5);
+  }
+  for day in 0..7 {
+    create_entry(&user.id, today - Duration::days(day), 2);
+  }
+
+  let insights = insights::active_insights_at(&user.id, today).unwrap();
+  assert!(insights.is_empty());
+}
+
+#[test]
+fn low_mood_streak() {
+  let user = create_user();
+  let today = date(2025, 6, 30);
+
+  create_entry(&user.id, today - Duration::days(4), 1);
+  create_entry(&user.id, today - Duration::days(3), 3);
+  create_entry(&user.id, today - Duration::days(2), 1);
+  create_entry(&user.id, today - Duration::days(1), 1);
+
+  // two days is below the default of three
+  assert!(insights::active_insights_at(&user.id, today)
+    .unwrap()
+    .is_empty());
+
+  create_entry(&user.id, today, 1);
+
+  let insights = insights::active_insights_at(&user.id, today).unwrap();
+  assert_eq!(
+    insights,
+    vec![Insight::LowMoodStreak {
+      from_date: date(2025, 6, 28),
+      to_date: today,
+      length: 3,
+    }]
+  );
+
+  // the streak is still active the next day until an entry is written
+  let tomorrow = today + Duration::days(1);
+  let insights = insights::active_insights_at(&user.id, tomorrow).unwrap();
+  assert_eq!(insights.len(), 1);
+
+  create_entry(&user.id, tomorrow, 4);
+  let insights = insights::active_insights_at(&user.id, tomorrow).unwrap();
+  assert!(insights.is_empty());
+}
+
+#[test]
+fn low_mood_level() {
+  let user = create_user();
+  let today = date(2025, 6, 30);
+
+  create_entry(&user.id, today - Duration::days(1), 2);
+  create_entry(&user.id, today, 2);
+
+  assert!(insights::active_insights_at(&user.id, today)
+    .unwrap()
+    .is_empty());
+
+  insights::update_insight_settings(
+    &user.id,
+    insights::UpdateInsightSettings {
+      mood_drop_threshold: 1.0,
+      low_mood_level: 2,
+      low_mood_days: 2,
+      notifications_enabled: true,
+    },
+  )
+  .unwrap();
+
+  let insights = insights::active_insights_at(&user.id, today).unwrap();
+  assert_eq!(
+    insights,
+    vec![Insight::LowMoodStreak {
+      from_date: date(2025, 6, 29),
+      to_date: today,
+      length: 2,
+    }]
+  );
+}
+
+#[test]
+fn insight_settings() {
+  let user = create_user();
+
+  let settings = insights::get_insight_settings(&user.id).unwrap();
+  assert_eq!(settings.mood_drop_threshold, 1.0);
+  assert_eq!(settings.low_mood_level, 1);
+  assert_eq!(settings.low_mood_days, 3);
+  assert!(settings.notifications_enabled);
+
+  insights::update_insight_settings(
+    &user.id,
+    insights::UpdateInsightSettings {
+      mood_drop_threshold: 1.5,
+      low_mood_level: 2,
+      low_mood_days: 5,
+      notifications_enabled: false,
+    },
+  )
+  .unwrap();
+
+  let settings = insights::get_insight_settings(&user.id).unwrap();
+  assert_eq!(settings.mood_drop_threshold, 1.5);
+  assert_eq!(settings.low_mood_level, 2);
+  assert_eq!(settings.low_mood_days, 5);
+  assert!(!settings.notifications_enabled);
+
+  let invalid = insights::update_insight_settings(
+    &user.id,
+    insights::UpdateInsightSettings {
+      mood_drop_threshold: 1.0,
+      low_mood_level: 1,
+      low_mood_days: 1,
+      notifications_enabled: true,
+    },
+  );
+  assert_eq!(invalid.unwrap_err(), APIError::BadRequest);
+
+  // settings are removed with the user
+  user::delete_user(&user.id).unwrap();
+  assert_eq!(
+    insights::get_insight_settings(&user.id).unwrap_err(),
+    APIError::UserNotFound
+  );
+}
+
+struct RecordingNotifier(Arc<Mutex<Vec<(String, Insight)>>>);
+
+impl insights::InsightNotifier for RecordingNotifier {
+  fn notify(&self, user_id: &str, insights: &[Insight]) {
+    let mut recorded = self.0.lock().unwrap();
+    for insight in insights {
+      recorded.push((user_id.to_string(), insight.clone()));
+    }
+  }
+}
+
+#[test]
+fn notify_insights() {
+  let recorded = Arc::new(Mutex::new(vec![]));
+  assert!(insights::set_notifier(Box::new(RecordingNotifier(
+    recorded.clone()
+  ))));
+
+  let user = create_user();
+  let today = chrono::Utc::now().date_naive();
+
+  for day in 0..3 {
+    create_entry(&user.id, today - Duration::days(day), 1);
+  }
+
+  let notified = insights::notify_insights(&user.id).unwrap();
+  assert_eq!(notified.len(), 1);
+
+  let user_notifications = |recorded: &Arc<Mutex<Vec<(String, Insight)>>>| {
+    recorded
+      .lock()
+      .unwrap()
+      .iter()
+      .filter(|(user_id, _)| *user_id == user.id)
+      .count()
+  };
+  assert_eq!(user_notifications(&recorded), 1);
+
+  // insights that were already reported are not sent again
+  assert!(insights::notify_insights(&user.id).unwrap().is_empty());
+  assert_eq!(user_notifications(&recorded), 1);
+
+  // not even when the streak grows
+  create_entry(&user.id, today - Duration::days(3), 1);
+  assert!(insights::notify_insights(&user.id).unwrap().is_empty());
+  assert_eq!(user_notifications(&recorded), 1);
+
+  // nothing is sent when notifications are disabled
+  let other_user = create_user();
+  insights::update_insight_settings(
+    &other_user.id,
+    insights::UpdateInsightSettings {
+      mood_drop_threshold: 1.0,
+      low_mood_level: 1,
+      low_mood_days: 3,
+      notifications_enabled: false,
+    },
+  )
+  .unwrap();
+  for day in 0..3 {
+    create_entry(&other_user.id, today - Duration::days(day), 1);
+  }
+  assert!(insights::notify_insights(&other_user.id)
+    .unwrap()
+    .is_empty());
+}
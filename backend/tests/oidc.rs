@@ -0,0 +1,488 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use diarycomputer::{
+  services::{
+    auth::{LoginResult, SessionMetadata},
+    invite, oidc,
+    oidc::{OidcCallback, OidcConfig, OidcProvider},
+    user,
+  },
+  util::{
+    clock::{Clock, ManualClock},
+    error::APIError,
+    unix_time::unix_ms,
+  },
+};
+use poem::{
+  get, handler,
+  http::StatusCode,
+  listener::{Acceptor, Listener, TcpListener},
+  post,
+  web::{Data, Form, Json},
+  EndpointExt, Response, Route, Server,
+};
+use rsa::{
+  pkcs1v15::SigningKey,
+  signature::{SignatureEncoding, Signer},
+  BigUint, RsaPrivateKey,
+};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+const MINUTE: i64 = 60 * 1000;
+
+// generated with `openssl genrsa 2048`
+#[rustfmt::skip]
+const N: [&str; 8] = [
+  "e6c5709269b8c756e7faeabdffb0f40270ad887a067904cfddee44b38cf0ae8d",
+  "ea7816604161dbf4e8e8fc808e28a72f2c35cc296de09c4c0a833d2f750840ae",
+  "dc5594dfd060f6e91ebae99ad7786fb9e341a61de389e9ea9f710a2388859604",
+  "5d9d99a94c64eeeb77e4fbdd0f6fe04f68b3f51b94f0263a6427df6240b1413a",
+  "5d08fdd697846248704b7b86cf13fe3223cb868dfe291b0879635b73bd8cd78b",
+  "bf13c6b7f517efb5ee2c84f674d445b900d061057dc585fcac32561a84487cb5",
+  "5d354446ad3495b1e70f46bf46b334bddcabf030c3dd2c739c3dd2dbb6e6a5d8",
+  "c262a83490f74cdeb559cea9840167d5156468be9527afcb36297d5d4132604b",
+];
+#[rustfmt::skip]
+const D: [&str; 8] = [
+  "21c12b701f0aaa0daa2ce77d171358fdaa61979ed1a78deea1b5a252e900a367",
+  "9aae2a07b6488c15cca3979b6e2fc0148450f225f52e6f6e27f37e559cb6b24f",
+  "61671b39898f454e5ff2530d150b46bc57e3585af2d74f61c42492a913e50646",
+  "69330e30d73dc43ca8f2d36c483fc2fd9a81080f1abfedb788c2d447d8d29489",
+  "9fc9ec9cad19dd416d33403b96008e2ba3598000a94578a519d40fee14124193",
+  "b0dde719f6aecf8d70aa8f3e3c63dfa36002510168b0f2b1a4b90445081aed32",
+  "248d5780cc75a31c18422584c4c56b3f7dd62b1db2c12d16e5bcf9f0c5cf3b44",
+  "6e8258a511a4ecb69b3e37ea2391f4140bbed1d3400814d535cc0a07c137ed21",
+];
+
+fn decode_hex(chunks: &[&str]) -> Vec<u8> {
+  let value = chunks.concat();
+  (0..value.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+    .collect()
+}
+
+fn metadata() -> SessionMetadata {
+  SessionMetadata {
+    ip_address: "127.0.0.1".to_string(),
+    user_agent: "test".to_string(),
+  }
+}
+
+/// A login the user approved at the mock provider
+struct Grant {
+  code_challenge: String,
+  claims: serde_json::Value,
+}
+
+#[derive(Clone)]
+struct MockProvider {
+  issuer: String,
+  grants: Arc<Mutex<HashMap<String, Grant>>>,
+}
+
+#[handler]
+fn discovery(Data(provider): Data<&MockProvider>) -> Json<serde_json::Value> {
+  Json(serde_json::json!({
+    "issuer": provider.issuer,
+    "authorization_endpoint": format!("{}/authorize", provider.issuer),
+    "token_endpoint": format!("{}/token", provider.issuer),
+    "jwks_uri": format!("{}/jwks", provider.issuer),
+  }))
+}
+
+#[handler]
+fn jwks() -> Json<serde_json::Value> {
+  Json(serde_json::json!({
+    "keys": [{
+      "kty": "RSA",
+      "kid": "mock",
+      "use": "sig",
+      "n": URL_SAFE_NO_PAD.encode(decode_hex(&N)),
+      "e": "AQAB",
+    }]
+  }))
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+  grant_type: String,
+  code: String,
+  code_verifier: String,
+  client_id: String,
+}
+
+/// Signs an RS256 ID token for a grant, once, if the PKCE verifier matches
+#[handler]
+fn token(Data(provider): Data<&MockProvider>, Form(request): Form<TokenRequest>) -> Response {
+  let grant = provider.grants.lock().unwrap().remove(&request.code);
+  let claims = match grant {
+    Some(grant)
+      if request.grant_type == "authorization_code"
+        && request.client_id == "diary"
+        && oidc::code_challenge(&request.code_verifier) == grant.code_challenge =>
+    {
+      grant.claims
+    }
+    _ => {
+      return Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(r#"{"error": "invalid_grant"}"#)
+    }
+  };
+
+  let message = format!(
+    "{}.{}",
+    URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","kid":"mock"}"#),
+    URL_SAFE_NO_PAD.encode(claims.to_string())
+  );
+  let private_key = RsaPrivateKey::from_components(
+    BigUint::from_bytes_be(&decode_hex(&N)),
+    BigUint::from(65537u32),
+    BigUint::from_bytes_be(&decode_hex(&D)),
+    vec![],
+  )
+  .unwrap();
+  let signature = SigningKey::<Sha256>::new(private_key).sign(message.as_bytes());
+  let id_token = format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature.to_bytes()));
+
+  Response::builder()
+    .content_type("application/json")
+    .body(serde_json::json!({ "id_token": id_token, "token_type": "Bearer" }).to_string())
+}
+
+/// Starts a mock OpenID Connect provider on a random port
+async fn start_provider(invite_required: bool) -> (MockProvider, OidcConfig) {
+  let acceptor = TcpListener::bind("127.0.0.1:0")
+    .into_acceptor()
+    .await
+    .unwrap();
+  let address = acceptor.local_addr()[0].as_socket_addr().cloned().unwrap();
+
+  let provider = MockProvider {
+    issuer: format!("http://{address}"),
+    grants: Arc::new(Mutex::new(HashMap::new())),
+  };
+  let app = Route::new()
+    .at("/.well-known/openid-configuration", get(discovery))
+    .at("/jwks", get(jwks))
+    .at("/token", post(token))
+    .data(provider.clone());
+  tokio::spawn(Server::new_with_acceptor(acceptor).run(app));
+
+  let config = OidcConfig {
+    providers: vec![OidcProvider {
+      id: "mock".to_string(),
+      name: "Mock".to_string(),
+      issuer: provider.issuer.clone(),
+      client_id: "diary".to_string(),
+      client_secret: None,
+      scopes: "openid email profile".to_string(),
+    }],
+    redirect_url: "http://localhost:5173/login/oidc".to_string(),
+    invite_required,
+  };
+
+  (provider, config)
+}
+
+/// Starts a login and approves it at the mock provider with `claims`, the
+/// nonce is filled in unless set. Returns the callback of the redirect
+async fn authorize(
+  provider: &MockProvider,
+  config: &OidcConfig,
+  mut claims: serde_json::Value,
+  clock: &dyn Clock,
+) -> OidcCallback {
+  let authorization = oidc::begin_login("mock", config, clock).await.unwrap();
+  let url = reqwest::Url::parse(&authorization.authorization_url).unwrap();
+  assert!(url
+    .as_str()
+    .starts_with(&format!("{}/authorize?", provider.issuer)));
+  let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+  assert_eq!(params["code_challenge_method"], "S256");
+  assert_eq!(params["redirect_uri"], config.redirect_url);
+
+  let now = clock.now_ms() / 1000;
+  let defaults = serde_json::json!({
+    "iss": provider.issuer,
+    "aud": "diary",
+    "iat": now,
+    "exp": now + 300,
+    "nonce": params["nonce"],
+  });
+  for (name, value) in defaults.as_object().unwrap() {
+    if claims.get(name).is_none() {
+      claims[name] = value.clone();
+    }
+  }
+
+  let code = Uuid::new_v4().to_string();
+  provider.grants.lock().unwrap().insert(
+    code.clone(),
+    Grant {
+      code_challenge: params["code_challenge"].clone(),
+      claims,
+    },
+  );
+
+  OidcCallback {
+    state: params["state"].clone(),
+    code,
+    invite: None,
+    state_cookie: Some(authorization.state_cookie),
+  }
+}
+
+fn session_user_id(login: LoginResult) -> String {
+  match login {
+    LoginResult::Session(session) => session.session.user_id,
+    LoginResult::TwoFactorChallenge(_) => panic!("Expected a session"),
+  }
+}
+
+fn random_email() -> String {
+  format!("{}@example.com", Uuid::new_v4())
+}
+
+#[tokio::test]
+async fn sign_up_and_log_in() {
+  let (provider, config) = start_provider(false).await;
+  let clock = ManualClock::new(unix_ms());
+  let email = random_email();
+  let claims = serde_json::json!({
+    "sub": Uuid::new_v4().to_string(),
+    "email": email,
+    "email_verified": true,
+    "name": "Provider User",
+  });
+
+  let callback = authorize(&provider, &config, claims.clone(), &clock).await;
+  let (state, state_cookie) = (callback.state.clone(), callback.state_cookie.clone());
+  let user_id = session_user_id(
+    oidc::finish_login(callback, metadata(), &config, &clock)
+      .await
+      .unwrap(),
+  );
+  let created = user::get_user(&user_id).unwrap();
+  assert_eq!(created.email, email);
+  assert_eq!(created.name, "Provider User");
+  assert!(created.email_verified);
+
+  // states can only be used once
+  assert_eq!(
+    oidc::finish_login(
+      OidcCallback {
+        state,
+        code: "code".to_string(),
+        invite: None,
+        state_cookie,
+      },
+      metadata(),
+      &config,
+      &clock,
+    )
+    .await
+    .unwrap_err(),
+    APIError::OidcLoginExpired
+  );
+
+  // the provider account is linked, even after its email changed
+  let mut claims = claims;
+  claims["email"] = serde_json::json!(random_email());
+  let callback = authorize(&provider, &config, claims, &clock).await;
+  assert_eq!(
+    session_user_id(
+      oidc::finish_login(callback, metadata(), &config, &clock)
+        .await
+        .unwrap()
+    ),
+    user_id
+  );
+}
+
+#[tokio::test]
+async fn link_existing_account() {
+  let (provider, config) = start_provider(false).await;
+  let clock = ManualClock::new(unix_ms());
+
+  // accounts whose email was never verified could belong to anyone
+  let email = random_email();
+  user::create_user(user::CreateUser {
+    name: "unverified".to_string(),
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .unwrap();
+  let claims = serde_json::json!({
+    "sub": Uuid::new_v4().to_string(),
+    "email": email,
+    "email_verified": true,
+  });
+  let callback = authorize(&provider, &config, claims, &clock).await;
+  assert_eq!(
+    oidc::finish_login(callback, metadata(), &config, &clock)
+      .await
+      .unwrap_err(),
+    APIError::OidcAccountNotVerified
+  );
+
+  let email = random_email();
+  let existing = user::create_provider_user("verified".to_string(), email.clone(), None).unwrap();
+
+  // the provider has to have verified the email too
+  let claims = serde_json::json!({
+    "sub": Uuid::new_v4().to_string(),
+    "email": email,
+    "email_verified": false,
+  });
+  let callback = authorize(&provider, &config, claims, &clock).await;
+  assert_eq!(
+    oidc::finish_login(callback, metadata(), &config, &clock)
+      .await
+      .unwrap_err(),
+    APIError::OidcEmailNotVerified
+  );
+
+  let claims = serde_json::json!({
+    "sub": Uuid::new_v4().to_string(),
+    "email": email,
+    "email_verified": true,
+  });
+  let callback = authorize(&provider, &config, claims, &clock).await;
+  assert_eq!(
+    session_user_id(
+      oidc::finish_login(callback, metadata(), &config, &clock)
+        .await
+        .unwrap()
+    ),
+    existing.id
+  );
+}
+
+#[tokio::test]
+async fn reject_invalid_logins() {
+  let (provider, config) = start_provider(false).await;
+  let clock = ManualClock::new(unix_ms());
+  let claims = || {
+    serde_json::json!({
+      "sub": Uuid::new_v4().to_string(),
+      "email": random_email(),
+      "email_verified": true,
+    })
+  };
+
+  // ID tokens of another login, issuer or client
+  for (name, value) in [
+    ("nonce", serde_json::json!("replayed")),
+    ("iss", serde_json::json!("http://evil.example")),
+    ("aud", serde_json::json!("other")),
+    ("exp", serde_json::json!(clock.now_ms() / 1000 - 600)),
+  ] {
+    let mut claims = claims();
+    claims[name] = value;
+    let callback = authorize(&provider, &config, claims, &clock).await;
+    assert_eq!(
+      oidc::finish_login(callback, metadata(), &config, &clock)
+        .await
+        .unwrap_err(),
+      APIError::InvalidIdToken,
+      "{name}"
+    );
+  }
+
+  // codes only work with the login's PKCE verifier
+  let mut callback = authorize(&provider, &config, claims(), &clock).await;
+  let other = authorize(&provider, &config, claims(), &clock).await;
+  callback.code = other.code;
+  assert_eq!(
+    oidc::finish_login(callback, metadata(), &config, &clock)
+      .await
+      .unwrap_err(),
+    APIError::OidcLoginExpired
+  );
+
+  // callbacks opened in a browser that did not start the login (login CSRF)
+  let mut callback = authorize(&provider, &config, claims(), &clock).await;
+  let state_cookie = callback.state_cookie.clone();
+  for other_cookie in [
+    None,
+    authorize(&provider, &config, claims(), &clock)
+      .await
+      .state_cookie,
+  ] {
+    callback.state_cookie = other_cookie;
+    assert_eq!(
+      oidc::finish_login(
+        OidcCallback {
+          state: callback.state.clone(),
+          code: callback.code.clone(),
+          invite: None,
+          state_cookie: callback.state_cookie.clone(),
+        },
+        metadata(),
+        &config,
+        &clock
+      )
+      .await
+      .unwrap_err(),
+      APIError::OidcLoginExpired
+    );
+  }
+  callback.state_cookie = state_cookie;
+  assert!(oidc::finish_login(callback, metadata(), &config, &clock)
+    .await
+    .is_ok());
+
+  // logins have to be finished in time
+  let callback = authorize(&provider, &config, claims(), &clock).await;
+  clock.advance(11 * MINUTE);
+  assert_eq!(
+    oidc::finish_login(callback, metadata(), &config, &clock)
+      .await
+      .unwrap_err(),
+    APIError::OidcLoginExpired
+  );
+
+  assert_eq!(
+    oidc::begin_login("unknown", &config, &clock)
+      .await
+      .unwrap_err(),
+    APIError::OidcProviderNotFound
+  );
+}
+
+#[tokio::test]
+async fn sign_up_requires_invite() {
+  let (provider, config) = start_provider(true).await;
+  let clock = ManualClock::new(unix_ms());
+  let claims = serde_json::json!({
+    "sub": Uuid::new_v4().to_string(),
+    "email": random_email(),
+    "email_verified": true,
+  });
+
+  let callback = authorize(&provider, &config, claims.clone(), &clock).await;
+  assert_eq!(
+    oidc::finish_login(callback, metadata(), &config, &clock)
+      .await
+      .unwrap_err(),
+    APIError::InviteNotFound
+  );
+
+  let invite = invite::generate_invite(None).unwrap();
+  let mut callback = authorize(&provider, &config, claims, &clock).await;
+  callback.invite = Some(invite.code.clone());
+  let user_id = session_user_id(
+    oidc::finish_login(callback, metadata(), &config, &clock)
+      .await
+      .unwrap(),
+  );
+  assert_eq!(user::get_user(&user_id).unwrap().invite, Some(invite.id));
+}
@@ -0,0 +1,455 @@
+use diarycomputer::{
+  api,
+  services::{
+    auth,
+    auth::{LoginResult, SessionMetadata, UserCredentials},
+    passkey,
+    passkey::{
+      AssertionResponse, AttestationResponse, AuthenticationResponse, CreationOptions,
+      RegistrationResponse, RequestOptions, WebAuthnConfig,
+    },
+    two_factor, user,
+  },
+  util::{
+    cbor::{self, Value},
+    clock::{Clock, ManualClock},
+    error::APIError,
+    totp,
+    unix_time::unix_ms,
+  },
+};
+use p256::ecdsa::{signature::Signer, DerSignature, SigningKey};
+use poem::{
+  http::{Method, StatusCode},
+  Endpoint, Request,
+};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+const ORIGIN: &str = "http://localhost:3000";
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+fn config() -> WebAuthnConfig {
+  WebAuthnConfig {
+    rp_id: "localhost".to_string(),
+    origins: vec![ORIGIN.to_string()],
+  }
+}
+
+fn metadata() -> SessionMetadata {
+  SessionMetadata {
+    ip_address: "127.0.0.1".to_string(),
+    user_agent: "test".to_string(),
+  }
+}
+
+/// Passkey held in memory, answers ceremonies like a browser and authenticator would
+struct SoftwareAuthenticator {
+  credential_id: Vec<u8>,
+  private_key: SigningKey,
+  user_handle: String,
+  sign_count: u32,
+  /// Authenticators without a counter always send 0
+  counter: bool,
+  flags: u8,
+  origin: String,
+  rp_id: String,
+}
+
+impl SoftwareAuthenticator {
+  fn new() -> Self {
+    SoftwareAuthenticator {
+      credential_id: Uuid::new_v4().as_bytes().to_vec(),
+      private_key: SigningKey::random(&mut OsRng),
+      user_handle: String::new(),
+      sign_count: 0,
+      counter: true,
+      flags: FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+      origin: ORIGIN.to_string(),
+      rp_id: "localhost".to_string(),
+    }
+  }
+
+  fn client_data(&self, kind: &str, challenge: &str) -> Vec<u8> {
+    serde_json::json!({
+      "type": kind,
+      "challenge": challenge,
+      "origin": self.origin,
+      "crossOrigin": false,
+    })
+    .to_string()
+    .into_bytes()
+  }
+
+  fn authenticator_data(&self, flags: u8) -> Vec<u8> {
+    let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+    data.push(flags);
+    data.extend(self.sign_count.to_be_bytes());
+    data
+  }
+
+  fn cose_key(&self) -> Vec<u8> {
+    let point = self.private_key.verifying_key().to_encoded_point(false);
+    let (x, y) = (point.x().unwrap(), point.y().unwrap());
+    cbor::encode(&Value::Map(vec![
+      (Value::Integer(1), Value::Integer(2)),
+      (Value::Integer(3), Value::Integer(-7)),
+      (Value::Integer(-1), Value::Integer(1)),
+      (Value::Integer(-2), Value::Bytes(x.to_vec())),
+      (Value::Integer(-3), Value::Bytes(y.to_vec())),
+    ]))
+  }
+
+  fn register(&mut self, options: &CreationOptions) -> RegistrationResponse {
+    self.user_handle = options.user.id.clone();
+
+    let mut auth_data = self.authenticator_data(self.flags | FLAG_ATTESTED_CREDENTIAL);
+    auth_data.extend([0u8; 16]);
+    auth_data.extend((self.credential_id.len() as u16).to_be_bytes());
+    auth_data.extend(&self.credential_id);
+    auth_data.extend(self.cose_key());
+
+    let attestation_object = cbor::encode(&Value::Map(vec![
+      (
+        Value::Text("fmt".to_string()),
+        Value::Text("none".to_string()),
+      ),
+      (Value::Text("attStmt".to_string()), Value::Map(vec![])),
+      (Value::Text("authData".to_string()), Value::Bytes(auth_data)),
+    ]));
+
+    RegistrationResponse {
+      id: passkey::base64url_encode(&self.credential_id),
+      name: Some("Test key".to_string()),
+      response: AttestationResponse {
+        client_data_json: passkey::base64url_encode(
+          &self.client_data("webauthn.create", &options.challenge),
+        ),
+        attestation_object: passkey::base64url_encode(&attestation_object),
+      },
+    }
+  }
+
+  fn authenticate(&mut self, options: &RequestOptions) -> AuthenticationResponse {
+    if self.counter {
+      self.sign_count += 1;
+    }
+
+    let client_data = self.client_data("webauthn.get", &options.challenge);
+    let auth_data = self.authenticator_data(self.flags);
+    let mut signed = auth_data.clone();
+    signed.extend(Sha256::digest(&client_data));
+    let signature: DerSignature = self.private_key.sign(&signed);
+
+    AuthenticationResponse {
+      id: passkey::base64url_encode(&self.credential_id),
+      response: AssertionResponse {
+        client_data_json: passkey::base64url_encode(&client_data),
+        authenticator_data: passkey::base64url_encode(&auth_data),
+        signature: passkey::base64url_encode(&signature.to_bytes()),
+        user_handle: Some(self.user_handle.clone()),
+      },
+    }
+  }
+}
+
+fn create_session() -> auth::NewSession {
+  let random_name = Uuid::new_v4().to_string();
+  let email = format!("{random_name}@example.com");
+
+  user::create_user(user::CreateUser {
+    name: random_name,
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .expect("Failed to create test user");
+
+  auth::create_user_session(
+    UserCredentials {
+      email,
+      password: "password".to_string(),
+    },
+    metadata(),
+  )
+  .expect("Failed to create test session")
+}
+
+/// Registers a new software passkey for the user
+fn register(user_id: &str, clock: &ManualClock) -> SoftwareAuthenticator {
+  let mut authenticator = SoftwareAuthenticator::new();
+  let options = passkey::begin_registration(user_id, &config(), clock).unwrap();
+  let registration = authenticator.register(&options);
+  passkey::finish_registration(user_id, registration, &config(), clock).unwrap();
+  authenticator
+}
+
+fn log_in(
+  authenticator: &mut SoftwareAuthenticator,
+  clock: &ManualClock,
+) -> Result<LoginResult, APIError> {
+  let options = passkey::begin_authentication(&config(), clock).unwrap();
+  let assertion = authenticator.authenticate(&options);
+  passkey::finish_authentication(assertion, metadata(), &config(), clock)
+}
+
+#[test]
+fn register_and_log_in() {
+  let clock = ManualClock::new(unix_ms());
+  let session = create_session();
+  let user_id = &session.session.user_id;
+
+  let options = passkey::begin_registration(user_id, &config(), &clock).unwrap();
+  assert_eq!(options.rp.id, "localhost");
+  assert_eq!(options.pub_key_cred_params[0].alg, passkey::COSE_ALG_ES256);
+  assert!(options.exclude_credentials.is_empty());
+
+  let mut authenticator = SoftwareAuthenticator::new();
+  let registration = authenticator.register(&options);
+  let credential = passkey::finish_registration(user_id, registration, &config(), &clock).unwrap();
+  assert_eq!(credential.name, "Test key");
+
+  let credentials = passkey::get_user_credentials(user_id).unwrap();
+  assert_eq!(credentials.len(), 1);
+  assert_eq!(credentials[0].last_used_at, None);
+
+  // registered passkeys are excluded from further registrations
+  let options = passkey::begin_registration(user_id, &config(), &clock).unwrap();
+  assert_eq!(options.exclude_credentials[0].id, credential.id);
+
+  let new_session = match log_in(&mut authenticator, &clock).unwrap() {
+    LoginResult::Session(session) => session,
+    LoginResult::TwoFactorChallenge(_) => panic!("Expected a session"),
+  };
+  assert_eq!(&new_session.session.user_id, user_id);
+  assert!(auth::get_user_session_by_token(&new_session.token).is_ok());
+  assert_eq!(
+    passkey::get_user_credentials(user_id).unwrap()[0].last_used_at,
+    Some(clock.now_ms())
+  );
+}
+
+#[test]
+fn challenges_are_single_use_and_expire() {
+  let clock = ManualClock::new(unix_ms());
+  let session = create_session();
+  let mut authenticator = register(&session.session.user_id, &clock);
+
+  let options = passkey::begin_authentication(&config(), &clock).unwrap();
+  let assertion = authenticator.authenticate(&options);
+  let replayed = authenticator.authenticate(&options);
+  assert!(passkey::finish_authentication(assertion, metadata(), &config(), &clock).is_ok());
+  assert_eq!(
+    passkey::finish_authentication(replayed, metadata(), &config(), &clock).unwrap_err(),
+    APIError::PasskeyChallengeExpired
+  );
+
+  let options = passkey::begin_authentication(&config(), &clock).unwrap();
+  let assertion = authenticator.authenticate(&options);
+  clock.advance(passkey::CEREMONY_TIMEOUT_MS);
+  assert_eq!(
+    passkey::finish_authentication(assertion, metadata(), &config(), &clock).unwrap_err(),
+    APIError::PasskeyChallengeExpired
+  );
+
+  // a registration challenge is only valid for the user it was created for
+  let other_session = create_session();
+  let options = passkey::begin_registration(&session.session.user_id, &config(), &clock).unwrap();
+  let registration = SoftwareAuthenticator::new().register(&options);
+  assert_eq!(
+    passkey::finish_registration(
+      &other_session.session.user_id,
+      registration,
+      &config(),
+      &clock
+    )
+    .unwrap_err(),
+    APIError::PasskeyChallengeExpired
+  );
+}
+
+#[test]
+fn invalid_assertions() {
+  let clock = ManualClock::new(unix_ms());
+  let session = create_session();
+  let mut authenticator = register(&session.session.user_id, &clock);
+
+  authenticator.origin = "https://evil.example.com".to_string();
+  assert_eq!(
+    log_in(&mut authenticator, &clock).unwrap_err(),
+    APIError::InvalidPasskey
+  );
+  authenticator.origin = ORIGIN.to_string();
+
+  authenticator.rp_id = "evil.example.com".to_string();
+  assert_eq!(
+    log_in(&mut authenticator, &clock).unwrap_err(),
+    APIError::InvalidPasskey
+  );
+  authenticator.rp_id = "localhost".to_string();
+
+  // signed with a key that was not registered
+  let private_key = authenticator.private_key.clone();
+  authenticator.private_key = SigningKey::random(&mut OsRng);
+  assert_eq!(
+    log_in(&mut authenticator, &clock).unwrap_err(),
+    APIError::InvalidPasskey
+  );
+  authenticator.private_key = private_key;
+
+  // a counter that goes backwards suggests a cloned passkey
+  assert!(log_in(&mut authenticator, &clock).is_ok());
+  authenticator.sign_count -= 2;
+  assert_eq!(
+    log_in(&mut authenticator, &clock).unwrap_err(),
+    APIError::InvalidPasskey
+  );
+
+  authenticator.sign_count += 5;
+  assert!(log_in(&mut authenticator, &clock).is_ok());
+}
+
+#[test]
+fn parallel_assertions_with_the_same_counter() {
+  let clock = ManualClock::new(unix_ms());
+  let session = create_session();
+  let mut authenticator = register(&session.session.user_id, &clock);
+
+  // a cloned passkey used at the same time only logs in once
+  let assertions: Vec<_> = (0..10)
+    .map(|_| {
+      let options = passkey::begin_authentication(&config(), &clock).unwrap();
+      authenticator.sign_count = 0;
+      authenticator.authenticate(&options)
+    })
+    .collect();
+  let logins = std::thread::scope(|scope| {
+    let logins: Vec<_> = assertions
+      .into_iter()
+      .map(|assertion| {
+        scope.spawn(|| passkey::finish_authentication(assertion, metadata(), &config(), &clock))
+      })
+      .collect();
+    logins
+      .into_iter()
+      .map(|login| login.join().unwrap())
+      .filter(|login| login.is_ok())
+      .count()
+  });
+  assert_eq!(logins, 1);
+
+  // passkeys without a counter keep working
+  let mut counterless = register(&session.session.user_id, &clock);
+  counterless.counter = false;
+  assert!(log_in(&mut counterless, &clock).is_ok());
+  assert!(log_in(&mut counterless, &clock).is_ok());
+}
+
+#[test]
+fn revoke_passkey() {
+  let clock = ManualClock::new(unix_ms());
+  let session = create_session();
+  let other_session = create_session();
+  let mut authenticator = register(&session.session.user_id, &clock);
+  let credential_id = passkey::base64url_encode(&authenticator.credential_id);
+
+  assert_eq!(
+    passkey::revoke_credential(&other_session.session.user_id, &credential_id).unwrap_err(),
+    APIError::PasskeyNotFound
+  );
+  assert!(log_in(&mut authenticator, &clock).is_ok());
+
+  passkey::revoke_credential(&session.session.user_id, &credential_id).unwrap();
+  assert!(passkey::get_user_credentials(&session.session.user_id)
+    .unwrap()
+    .is_empty());
+  assert_eq!(
+    log_in(&mut authenticator, &clock).unwrap_err(),
+    APIError::InvalidPasskey
+  );
+}
+
+#[test]
+fn two_factor_without_user_verification() {
+  let clock = ManualClock::new(unix_ms());
+  let session = create_session();
+  let user_id = &session.session.user_id;
+  let mut authenticator = register(user_id, &clock);
+
+  let enrolment = two_factor::begin_enrolment(user_id, &clock).unwrap();
+  let code = totp::code_at(&enrolment.secret, clock.now_ms()).unwrap();
+  two_factor::confirm_enrolment(user_id, &code, &clock).unwrap();
+
+  // a verified passkey is enough on its own
+  assert!(matches!(
+    log_in(&mut authenticator, &clock).unwrap(),
+    LoginResult::Session(_)
+  ));
+
+  // without user verification the TOTP code is still needed
+  authenticator.flags = FLAG_USER_PRESENT;
+  assert!(matches!(
+    log_in(&mut authenticator, &clock).unwrap(),
+    LoginResult::TwoFactorChallenge(_)
+  ));
+}
+
+#[tokio::test]
+async fn passkey_routes() {
+  let clock = ManualClock::new(unix_ms());
+  let session = create_session();
+  let other_session = create_session();
+  let authenticator = register(&session.session.user_id, &clock);
+  let credential_id = passkey::base64url_encode(&authenticator.credential_id);
+
+  let call_api = |method: Method, uri: String, session: &auth::NewSession| {
+    Request::builder()
+      .method(method)
+      .uri(uri.parse().unwrap())
+      .header("Authorization", format!("Bearer {}", session.token))
+      .finish()
+  };
+
+  let response = api::index::endpoint()
+    .call(call_api(Method::GET, "/v1/passkeys".to_string(), &session))
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  let body: serde_json::Value =
+    serde_json::from_str(&response.into_body().into_string().await.unwrap()).unwrap();
+  assert_eq!(body[0]["id"], credential_id);
+  assert!(body[0].get("public_key").is_none());
+
+  let response = api::index::endpoint()
+    .call(call_api(
+      Method::POST,
+      "/v1/passkeys/register/begin".to_string(),
+      &session,
+    ))
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  let body: serde_json::Value =
+    serde_json::from_str(&response.into_body().into_string().await.unwrap()).unwrap();
+  assert!(body["pubKeyCredParams"].is_array());
+  assert_eq!(body["excludeCredentials"][0]["id"], credential_id);
+
+  let uri = format!("/v1/passkey/{credential_id}");
+  let status = match api::index::endpoint()
+    .call(call_api(Method::DELETE, uri.clone(), &other_session))
+    .await
+  {
+    Ok(response) => response.status(),
+    Err(error) => error.status(),
+  };
+  assert_eq!(status, StatusCode::NOT_FOUND);
+
+  let response = api::index::endpoint()
+    .call(call_api(Method::DELETE, uri, &session))
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
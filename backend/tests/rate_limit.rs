@@ -0,0 +1,312 @@
+use diarycomputer::{
+  api::v1,
+  middleware::rate_limit::{LoginRateLimit, MailRateLimit},
+  services::{
+    rate_limit::{
+      AttemptStore, LoginRateLimiter, MemoryAttemptStore, PostgresAttemptStore, RateLimitConfig,
+    },
+    user,
+  },
+  util::{clock::ManualClock, unix_time::unix_ms},
+};
+use poem::{
+  http::{header, Method, StatusCode},
+  post, Endpoint, EndpointExt, Request,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+const SECOND: i64 = 1000;
+
+fn config() -> RateLimitConfig {
+  RateLimitConfig {
+    max_email_attempts: 3,
+    max_ip_attempts: 10,
+    lockout_ms: 30 * SECOND,
+    max_lockout_ms: 100 * SECOND,
+    window_ms: 600 * SECOND,
+  }
+}
+
+fn limiter(store: Box<dyn AttemptStore>) -> (LoginRateLimiter, Arc<ManualClock>) {
+  let clock = Arc::new(ManualClock::new(unix_ms()));
+  (LoginRateLimiter::new(config(), store, clock.clone()), clock)
+}
+
+fn random_ip() -> String {
+  Uuid::new_v4().to_string()
+}
+
+#[test]
+fn lockout_back_off() {
+  let (limiter, clock) = limiter(Box::<MemoryAttemptStore>::default());
+  let ip = random_ip();
+  let email = Some("user@example.com");
+
+  for _ in 0..2 {
+    limiter.record_failure(&ip, email).unwrap();
+    assert_eq!(limiter.retry_after(&ip, email).unwrap(), None);
+  }
+
+  // locked from the third failure, doubling until the maximum
+  for lockout in [30, 60, 100, 100] {
+    limiter.record_failure(&ip, email).unwrap();
+    assert_eq!(
+      limiter.retry_after(&ip, email).unwrap(),
+      Some(lockout * SECOND)
+    );
+    // other emails from other addresses are not affected
+    assert_eq!(
+      limiter
+        .retry_after(&random_ip(), Some("other@example.com"))
+        .unwrap(),
+      None
+    );
+
+    clock.advance(lockout * SECOND);
+    assert_eq!(limiter.retry_after(&ip, email).unwrap(), None);
+  }
+
+  // emails are compared case insensitively
+  limiter.record_failure(&ip, email).unwrap();
+  assert!(limiter
+    .retry_after(&random_ip(), Some("User@Example.com"))
+    .unwrap()
+    .is_some());
+}
+
+#[test]
+fn ip_limit() {
+  let (limiter, _clock) = limiter(Box::<MemoryAttemptStore>::default());
+  let ip = random_ip();
+
+  // trying many emails from one address locks the address
+  for attempt in 0..10 {
+    assert_eq!(limiter.retry_after(&ip, None).unwrap(), None);
+    limiter
+      .record_failure(&ip, Some(&format!("user{attempt}@example.com")))
+      .unwrap();
+  }
+
+  assert_eq!(
+    limiter.retry_after(&ip, Some("new@example.com")).unwrap(),
+    Some(30 * SECOND)
+  );
+}
+
+#[test]
+fn failures_expire_after_window() {
+  let (limiter, clock) = limiter(Box::<MemoryAttemptStore>::default());
+  let ip = random_ip();
+  let email = Some("user@example.com");
+
+  limiter.record_failure(&ip, email).unwrap();
+  limiter.record_failure(&ip, email).unwrap();
+
+  clock.advance(601 * SECOND);
+  limiter.record_failure(&ip, email).unwrap();
+  assert_eq!(limiter.retry_after(&ip, email).unwrap(), None);
+}
+
+#[test]
+fn success_clears_email() {
+  let (limiter, _clock) = limiter(Box::<MemoryAttemptStore>::default());
+  let ip = random_ip();
+  let email = Some("user@example.com");
+
+  limiter.record_failure(&ip, email).unwrap();
+  limiter.record_failure(&ip, email).unwrap();
+  limiter.record_success("user@example.com").unwrap();
+
+  limiter.record_failure(&ip, email).unwrap();
+  assert_eq!(limiter.retry_after(&ip, email).unwrap(), None);
+}
+
+#[test]
+fn postgres_store() {
+  let (limiter, clock) = limiter(Box::new(PostgresAttemptStore));
+  let other_limiter =
+    LoginRateLimiter::new(config(), Box::new(PostgresAttemptStore), clock.clone());
+  let ip = random_ip();
+  let email = format!("{}@example.com", Uuid::new_v4());
+
+  for _ in 0..3 {
+    limiter.record_failure(&ip, Some(&email)).unwrap();
+  }
+
+  // the lockout is seen by other servers
+  assert_eq!(
+    other_limiter
+      .retry_after(&random_ip(), Some(&email))
+      .unwrap(),
+    Some(30 * SECOND)
+  );
+
+  // expired attempts are removed
+  clock.advance(700 * SECOND);
+  limiter.record_failure(&random_ip(), None).unwrap();
+  assert_eq!(
+    PostgresAttemptStore.get(&format!("email:{email}")).unwrap(),
+    None
+  );
+}
+
+#[tokio::test]
+async fn middleware() {
+  let random_name = Uuid::new_v4().to_string();
+  let email = format!("{random_name}@example.com");
+  user::create_user(user::CreateUser {
+    name: random_name,
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .unwrap();
+
+  let (limiter, clock) = limiter(Box::<MemoryAttemptStore>::default());
+  let endpoint = post(v1::auth::authenticate_user.with(LoginRateLimit::new(limiter)));
+
+  let log_in = |password: &str| {
+    Request::builder()
+      .method(Method::POST)
+      .header("Content-Type", "application/json")
+      .body(format!(
+        r#"{{"email": "{email}", "password": "{password}"}}"#
+      ))
+  };
+
+  for _ in 0..3 {
+    let response = endpoint.call(log_in("wrong password")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+  }
+
+  // the correct password is rejected while locked
+  let response = endpoint.call(log_in("password")).await.unwrap();
+  assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+  assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "30");
+
+  clock.advance(30 * SECOND);
+  let response = endpoint.call(log_in("password")).await.unwrap();
+  assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn middleware_ignores_forwarded_for() {
+  let (limiter, _clock) = limiter(Box::<MemoryAttemptStore>::default());
+  let endpoint = post(v1::auth::authenticate_user.with(LoginRateLimit::new(limiter)));
+
+  // without TRUSTED_PROXIES a new address in every request does not escape
+  // the limit per address
+  let log_in = |attempt: i32| {
+    Request::builder()
+      .method(Method::POST)
+      .header("Content-Type", "application/json")
+      .header("X-Forwarded-For", format!("10.0.0.{attempt}"))
+      .header("X-Real-IP", format!("10.0.0.{attempt}"))
+      .body(format!(
+        r#"{{"email": "{}@example.com", "password": "password"}}"#,
+        Uuid::new_v4()
+      ))
+  };
+
+  for attempt in 0..10 {
+    let response = endpoint.call(log_in(attempt)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+  }
+
+  let response = endpoint.call(log_in(10)).await.unwrap();
+  assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+fn concurrent_attempts(limiter: LoginRateLimiter) {
+  let ip = random_ip();
+  let email = format!("{}@example.com", Uuid::new_v4());
+
+  let allowed = std::thread::scope(|scope| {
+    let attempts: Vec<_> = (0..20)
+      .map(|_| scope.spawn(|| limiter.begin_attempt(&ip, Some(&email)).unwrap()))
+      .collect();
+    attempts
+      .into_iter()
+      .map(|attempt| attempt.join().unwrap())
+      .filter(Option::is_none)
+      .count()
+  });
+
+  // only as many as the limit get through at the same time, the lockout can
+  // be longer when attempts that are turned away were counted first
+  assert_eq!(allowed, 3);
+  assert!(limiter
+    .retry_after(&ip, Some(&email))
+    .unwrap()
+    .is_some_and(|retry_after| retry_after >= 30 * SECOND));
+}
+
+#[test]
+fn concurrent_attempts_memory() {
+  concurrent_attempts(limiter(Box::<MemoryAttemptStore>::default()).0);
+}
+
+#[test]
+fn concurrent_attempts_postgres() {
+  concurrent_attempts(limiter(Box::new(PostgresAttemptStore)).0);
+}
+
+#[test]
+fn cancelled_attempts() {
+  let (limiter, _clock) = limiter(Box::<MemoryAttemptStore>::default());
+  let ip = random_ip();
+  let email = Some("user@example.com");
+
+  // attempts that did not fail are taken back
+  for _ in 0..5 {
+    assert_eq!(limiter.begin_attempt(&ip, email).unwrap(), None);
+    limiter.cancel_attempt(&ip, email).unwrap();
+  }
+  assert_eq!(limiter.retry_after(&ip, email).unwrap(), None);
+}
+
+#[tokio::test]
+async fn mail_middleware() {
+  let (limiter, clock) = limiter(Box::<MemoryAttemptStore>::default());
+  let endpoint =
+    post(v1::auth::request_password_reset.with(MailRateLimit::new(limiter.with_prefix("mail:"))));
+
+  let request_reset = |email: &str| {
+    Request::builder()
+      .method(Method::POST)
+      .header("Content-Type", "application/json")
+      .body(format!(r#"{{"email": "{email}"}}"#))
+  };
+
+  // every request counts, not only failed ones
+  let email = format!("{}@example.com", Uuid::new_v4());
+  for _ in 0..3 {
+    let response = endpoint.call(request_reset(&email)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+  }
+  let response = endpoint.call(request_reset(&email)).await.unwrap();
+  assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+  assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "30");
+
+  // many emails from one address lock the address
+  for _ in 0..7 {
+    let response = endpoint
+      .call(request_reset(&format!("{}@example.com", Uuid::new_v4())))
+      .await
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+  }
+  let response = endpoint
+    .call(request_reset(&format!("{}@example.com", Uuid::new_v4())))
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+  clock.advance(30 * SECOND);
+  let response = endpoint
+    .call(request_reset(&format!("{}@example.com", Uuid::new_v4())))
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
@@ -0,0 +1,226 @@
+use diarycomputer::{
+  api,
+  services::{
+    auth, auth::SessionMetadata, mail::FileMailer, security_event,
+    security_event::SecurityEventKind, user,
+  },
+  util::{clock::ManualClock, geoip::GeoIp, unix_time::unix_ms},
+};
+use poem::{
+  http::{Method, StatusCode},
+  Endpoint, Request,
+};
+use std::{fs, path::PathBuf};
+use uuid::Uuid;
+
+const FIREFOX_LINUX: &str =
+  "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+const FIREFOX_LINUX_UPDATED: &str =
+  "Mozilla/5.0 (X11; Linux x86_64; rv:130.0) Gecko/20100101 Firefox/130.0";
+const CHROME_WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36";
+
+fn create_test_user() -> (String, String) {
+  let random_name = Uuid::new_v4().to_string();
+  let email = format!("{random_name}@example.com");
+
+  let user = user::create_user(user::CreateUser {
+    name: random_name,
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .expect("Failed to create test user");
+
+  (user.id, email)
+}
+
+fn metadata(ip_address: &str, user_agent: &str) -> SessionMetadata {
+  SessionMetadata {
+    ip_address: ip_address.to_string(),
+    user_agent: user_agent.to_string(),
+  }
+}
+
+fn sent_messages(directory: &PathBuf) -> Vec<String> {
+  match fs::read_dir(directory) {
+    Ok(entries) => entries
+      .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+      .collect(),
+    Err(_) => Vec::new(),
+  }
+}
+
+/// Sends a request with a bearer token through the API routes
+async fn call_api(method: Method, uri: &str, token: &str, body: &str) -> (StatusCode, String) {
+  let request = Request::builder()
+    .method(method)
+    .uri(uri.parse().unwrap())
+    .header("Authorization", format!("Bearer {token}"))
+    .header("Content-Type", "application/json")
+    .header("User-Agent", FIREFOX_LINUX)
+    .body(body.to_string());
+
+  match api::index::endpoint().call(request).await {
+    Ok(response) => {
+      let status = response.status();
+      (status, response.into_body().into_string().await.unwrap())
+    }
+    Err(error) => (error.status(), String::new()),
+  }
+}
+
+#[test]
+fn new_device_logins() {
+  let (user_id, email) = create_test_user();
+  let clock = ManualClock::new(unix_ms());
+  let mailer = FileMailer {
+    directory: std::env::temp_dir().join(format!("diary-mail-{}", Uuid::new_v4())),
+    from: "test <test@localhost>".to_string(),
+  };
+
+  let login = |ip_address: &str, user_agent: &str| {
+    clock.advance(1000);
+    security_event::record_login(
+      &user_id,
+      &metadata(ip_address, user_agent),
+      None,
+      Some(&mailer),
+      &clock,
+    )
+    .unwrap()
+  };
+
+  // the first login has nothing to compare against
+  let first = login("203.0.113.7", FIREFOX_LINUX);
+  assert_eq!(first.kind, SecurityEventKind::Login);
+  assert_eq!(first.device, "Firefox on Linux");
+
+  // browser updates and addresses in the same network are known
+  assert_eq!(
+    login("203.0.113.80", FIREFOX_LINUX_UPDATED).kind,
+    SecurityEventKind::Login
+  );
+  assert!(sent_messages(&mailer.directory).is_empty());
+
+  let event = login("203.0.113.7", CHROME_WINDOWS);
+  assert_eq!(event.kind, SecurityEventKind::NewDeviceLogin);
+  assert_eq!(event.details.as_deref(), Some("new device"));
+  let messages = sent_messages(&mailer.directory);
+  assert_eq!(messages.len(), 1);
+  assert!(messages[0].contains(&format!("To: {email}")));
+  assert!(messages[0].contains("Chrome on Windows (203.0.113.7)"));
+
+  let event = login("198.51.100.1", FIREFOX_LINUX);
+  assert_eq!(event.kind, SecurityEventKind::NewDeviceLogin);
+  assert_eq!(event.details.as_deref(), Some("new location"));
+
+  // once seen, the device and network are known
+  assert_eq!(
+    login("198.51.100.2", CHROME_WINDOWS).kind,
+    SecurityEventKind::Login
+  );
+  assert_eq!(sent_messages(&mailer.directory).len(), 2);
+
+  let events = security_event::get_security_events(&user_id, None, None).unwrap();
+  assert_eq!(events.pagination.total_count, 5);
+  assert_eq!(events.data[0].ip_address, "198.51.100.2");
+}
+
+#[test]
+fn new_location_with_geoip() {
+  let (user_id, _) = create_test_user();
+  let clock = ManualClock::new(unix_ms());
+  let geoip = GeoIp::parse("10.0.0.0,10.127.255.255,NL\n10.128.0.0,10.255.255.255,DE");
+
+  let login = |ip_address: &str| {
+    clock.advance(1000);
+    security_event::record_login(
+      &user_id,
+      &metadata(ip_address, FIREFOX_LINUX),
+      Some(&geoip),
+      None,
+      &clock,
+    )
+    .unwrap()
+  };
+
+  let first = login("10.0.0.1");
+  assert_eq!(first.location.as_deref(), Some("NL"));
+  // another network in the same country is not new
+  assert_eq!(login("10.64.0.1").kind, SecurityEventKind::Login);
+  assert_eq!(login("10.200.0.1").kind, SecurityEventKind::NewDeviceLogin);
+}
+
+#[tokio::test]
+async fn security_event_feed() {
+  let (user_id, _) = create_test_user();
+  let clock = ManualClock::new(unix_ms());
+  let session =
+    auth::create_session_for_user(&user_id, metadata("127.0.0.1", FIREFOX_LINUX), &clock).unwrap();
+  auth::create_session_for_user(&user_id, metadata("127.0.0.1", CHROME_WINDOWS), &clock).unwrap();
+
+  assert_eq!(
+    call_api(
+      Method::PATCH,
+      "/v1/user/password",
+      &session.token,
+      r#"{"current_password": "password", "password": "new password"}"#,
+    )
+    .await
+    .0,
+    StatusCode::NO_CONTENT
+  );
+  assert_eq!(
+    call_api(
+      Method::DELETE,
+      "/v1/sessions?except=current",
+      &session.token,
+      ""
+    )
+    .await
+    .0,
+    StatusCode::NO_CONTENT
+  );
+
+  let (status, body) = call_api(Method::GET, "/v1/user/security-events", &session.token, "").await;
+  assert_eq!(status, StatusCode::OK);
+  let events: serde_json::Value = serde_json::from_str(&body).unwrap();
+  let mut kinds: Vec<&str> = events["data"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|event| event["kind"].as_str().unwrap())
+    .collect();
+  kinds.sort_unstable();
+  assert_eq!(
+    kinds,
+    vec![
+      "login",
+      "new_device_login",
+      "password_changed",
+      "sessions_revoked"
+    ]
+  );
+  assert_eq!(events["pagination"]["total_count"], 4);
+
+  let (_, body) = call_api(
+    Method::GET,
+    "/v1/user/security-events?limit=1&offset=1",
+    &session.token,
+    "",
+  )
+  .await;
+  let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+  assert_eq!(page["data"].as_array().unwrap().len(), 1);
+  assert_eq!(page["pagination"]["limit"], 1);
+
+  // events of other users are not listed
+  let (other_id, _) = create_test_user();
+  assert_eq!(
+    security_event::get_security_events(&other_id, None, None)
+      .unwrap()
+      .pagination
+      .total_count,
+    0
+  );
+}
@@ -0,0 +1,392 @@
+use diarycomputer::{
+  api,
+  services::{
+    auth,
+    auth::{LoginResult, SessionConfig, SessionMetadata, UserCredentials},
+    session_cookie,
+    session_cookie::{CSRF_HEADER, SESSION_COOKIE_NAME},
+    user,
+  },
+  util::{
+    clock::{Clock, ManualClock},
+    error::APIError,
+    unix_time::unix_ms,
+  },
+};
+use poem::{
+  http::{Method, StatusCode},
+  Endpoint, Request,
+};
+use uuid::Uuid;
+
+const MINUTE: i64 = 60 * 1000;
+
+fn create_session() -> auth::NewSession {
+  let random_name = Uuid::new_v4().to_string();
+  let email = format!("{random_name}@example.com");
+
+  user::create_user(user::CreateUser {
+    name: random_name,
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .expect("Failed to create test user");
+
+  log_in(&email)
+}
+
+fn log_in(email: &str) -> auth::NewSession {
+  auth::create_user_session(
+    UserCredentials {
+      email: email.to_string(),
+      password: "password".to_string(),
+    },
+    SessionMetadata {
+      ip_address: "127.0.0.1".to_string(),
+      user_agent: "test".to_string(),
+    },
+  )
+  .expect("Failed to create test session")
+}
+
+fn request(session: &auth::NewSession) -> Request {
+  Request::builder()
+    .header("Authorization", format!("Bearer {}", session.token))
+    .finish()
+}
+
+/// Sends a request with the session's token through the API routes
+async fn call_api(method: Method, uri: &str, session: &auth::NewSession) -> StatusCode {
+  let request = Request::builder()
+    .method(method)
+    .uri(uri.parse().unwrap())
+    .header("Authorization", format!("Bearer {}", session.token))
+    .finish();
+
+  match api::index::endpoint().call(request).await {
+    Ok(response) => response.status(),
+    Err(error) => error.status(),
+  }
+}
+
+#[tokio::test]
+async fn idle_timeout() {
+  let session = create_session();
+  let clock = ManualClock::new(unix_ms());
+  let config = SessionConfig {
+    max_age_ms: None,
+    idle_timeout_ms: Some(30 * MINUTE),
+  };
+
+  // every request renews the idle timeout
+  for _ in 0..3 {
+    clock.advance(20 * MINUTE);
+    let authorized = auth::authorize_request_with_clock(&request(&session), &clock, &config)
+      .await
+      .unwrap();
+    assert_eq!(authorized.accessed_at, clock.now_ms());
+  }
+
+  clock.advance(31 * MINUTE);
+  let expired = auth::authorize_request_with_clock(&request(&session), &clock, &config).await;
+  assert_eq!(expired.unwrap_err(), APIError::SessionExpired);
+
+  // expired sessions are removed
+  assert_eq!(
+    auth::get_user_session_by_id(&session.session.id).unwrap_err(),
+    APIError::SessionNotFound
+  );
+  let removed = auth::authorize_request_with_clock(&request(&session), &clock, &config).await;
+  assert_eq!(removed.unwrap_err(), APIError::Unauthorized);
+}
+
+#[tokio::test]
+async fn max_age() {
+  let session = create_session();
+  let clock = ManualClock::new(session.session.created_at);
+  let config = SessionConfig {
+    max_age_ms: Some(60 * MINUTE),
+    idle_timeout_ms: Some(30 * MINUTE),
+  };
+
+  // renewing does not extend past the absolute lifetime
+  for _ in 0..3 {
+    clock.advance(20 * MINUTE);
+    assert!(
+      auth::authorize_request_with_clock(&request(&session), &clock, &config)
+        .await
+        .is_ok()
+    );
+  }
+
+  clock.advance(MINUTE);
+  let expired = auth::authorize_request_with_clock(&request(&session), &clock, &config).await;
+  assert_eq!(expired.unwrap_err(), APIError::SessionExpired);
+}
+
+#[tokio::test]
+async fn created_with_clock() {
+  let random_name = Uuid::new_v4().to_string();
+  let email = format!("{random_name}@example.com");
+  user::create_user(user::CreateUser {
+    name: random_name,
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .expect("Failed to create test user");
+
+  let clock = ManualClock::new(unix_ms() - 120 * MINUTE);
+  let session = match auth::log_in(
+    UserCredentials {
+      email,
+      password: "password".to_string(),
+    },
+    SessionMetadata {
+      ip_address: "127.0.0.1".to_string(),
+      user_agent: "test".to_string(),
+    },
+    &clock,
+  ) {
+    Ok(LoginResult::Session(session)) => session,
+    _ => panic!("Failed to log in"),
+  };
+  assert_eq!(session.session.created_at, clock.now_ms());
+  assert_eq!(session.session.accessed_at, clock.now_ms());
+
+  let config = SessionConfig {
+    max_age_ms: Some(60 * MINUTE),
+    idle_timeout_ms: None,
+  };
+  clock.advance(61 * MINUTE);
+  let expired = auth::authorize_request_with_clock(&request(&session), &clock, &config).await;
+  assert_eq!(expired.unwrap_err(), APIError::SessionExpired);
+}
+
+#[tokio::test]
+async fn disabled_limits() {
+  let session = create_session();
+  let clock = ManualClock::new(session.session.created_at + 365 * 24 * 60 * MINUTE);
+  let config = SessionConfig {
+    max_age_ms: None,
+    idle_timeout_ms: None,
+  };
+
+  assert!(
+    auth::authorize_request_with_clock(&request(&session), &clock, &config)
+      .await
+      .is_ok()
+  );
+}
+
+#[tokio::test]
+async fn delete_expired_sessions() {
+  let idle_session = create_session();
+  let active_session = create_session();
+  let config = SessionConfig {
+    max_age_ms: Some(120 * MINUTE),
+    idle_timeout_ms: Some(30 * MINUTE),
+  };
+
+  // last used an hour ago
+  let past = ManualClock::new(idle_session.session.created_at - 60 * MINUTE);
+  auth::authorize_request_with_clock(&request(&idle_session), &past, &config)
+    .await
+    .unwrap();
+
+  let clock = ManualClock::new(unix_ms());
+  let deleted = auth::delete_expired_sessions(&clock, &config).unwrap();
+
+  assert!(deleted >= 1);
+  assert!(auth::get_user_session_by_id(&idle_session.session.id).is_err());
+  assert!(auth::get_user_session_by_id(&active_session.session.id).is_ok());
+}
+
+#[tokio::test]
+async fn token_hashed_at_rest() {
+  let new_session = create_session();
+  let stored = auth::get_user_session_by_id(&new_session.session.id).unwrap();
+
+  assert_eq!(new_session.token.len(), 2 * auth::SESSION_TOKEN_BYTES);
+  assert_ne!(stored.id, new_session.token);
+  assert_eq!(stored.token_hash, auth::hash_token(&new_session.token));
+  assert_ne!(stored.token_hash, new_session.token);
+
+  // the token is never serialized with the session
+  let json = serde_json::to_value(&stored).unwrap();
+  assert!(json.get("token_hash").is_none());
+  assert!(json.get("token").is_none());
+
+  // only the token authorizes, not the session id or the stored hash
+  let config = SessionConfig {
+    max_age_ms: None,
+    idle_timeout_ms: None,
+  };
+  let clock = ManualClock::new(unix_ms());
+  let authorized = auth::authorize_request_with_clock(&request(&new_session), &clock, &config)
+    .await
+    .unwrap();
+  assert_eq!(authorized.id, new_session.session.id);
+
+  for bearer in [&stored.id, &stored.token_hash] {
+    let request = Request::builder()
+      .header("Authorization", format!("Bearer {bearer}"))
+      .finish();
+    let rejected = auth::authorize_request_with_clock(&request, &clock, &config).await;
+    assert_eq!(rejected.unwrap_err(), APIError::Unauthorized);
+  }
+}
+
+#[tokio::test]
+async fn revoke_session_of_other_user() {
+  let session = create_session();
+  let other_session = create_session();
+
+  assert_eq!(
+    auth::revoke_user_session(&session.session.user_id, &other_session.session.id).unwrap_err(),
+    APIError::SessionNotFound
+  );
+
+  let status = call_api(
+    Method::DELETE,
+    &format!("/v1/session/{}", other_session.session.id),
+    &session,
+  )
+  .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert!(auth::get_user_session_by_id(&other_session.session.id).is_ok());
+
+  // the owner can revoke it
+  let status = call_api(
+    Method::DELETE,
+    &format!("/v1/session/{}", other_session.session.id),
+    &other_session,
+  )
+  .await;
+  assert_eq!(status, StatusCode::NO_CONTENT);
+  assert!(auth::get_user_session_by_id(&other_session.session.id).is_err());
+}
+
+#[tokio::test]
+async fn logout() {
+  let session = create_session();
+  let other_session = create_session();
+
+  assert_eq!(
+    call_api(Method::DELETE, "/v1/auth", &session).await,
+    StatusCode::NO_CONTENT
+  );
+  assert!(auth::get_user_session_by_id(&session.session.id).is_err());
+  assert_eq!(
+    call_api(Method::DELETE, "/v1/auth", &session).await,
+    StatusCode::UNAUTHORIZED
+  );
+
+  // other users are not logged out
+  assert!(auth::get_user_session_by_id(&other_session.session.id).is_ok());
+}
+
+#[tokio::test]
+async fn delete_sessions_except_current() {
+  let session = create_session();
+  let email = user::get_user(&session.session.user_id).unwrap().email;
+  let second_session = log_in(&email);
+  let third_session = log_in(&email);
+  let other_user_session = create_session();
+
+  assert_eq!(
+    call_api(Method::DELETE, "/v1/sessions?except=other", &session).await,
+    StatusCode::BAD_REQUEST
+  );
+
+  assert_eq!(
+    call_api(Method::DELETE, "/v1/sessions?except=current", &session).await,
+    StatusCode::NO_CONTENT
+  );
+  assert!(auth::get_user_session_by_id(&session.session.id).is_ok());
+  assert!(auth::get_user_session_by_id(&second_session.session.id).is_err());
+  assert!(auth::get_user_session_by_id(&third_session.session.id).is_err());
+  assert!(auth::get_user_session_by_id(&other_user_session.session.id).is_ok());
+
+  // without except the current session is deleted as well
+  assert_eq!(
+    call_api(Method::DELETE, "/v1/sessions", &session).await,
+    StatusCode::NO_CONTENT
+  );
+  assert!(auth::get_user_session_by_id(&session.session.id).is_err());
+  assert!(auth::get_user_session_by_id(&other_user_session.session.id).is_ok());
+}
+
+/// Sends a request authenticated with the session cookie
+async fn call_api_with_cookie(
+  method: Method,
+  uri: &str,
+  session: &auth::NewSession,
+  headers: &[(&str, &str)],
+) -> StatusCode {
+  let mut request = Request::builder()
+    .method(method)
+    .uri(uri.parse().unwrap())
+    .header("Cookie", format!("{SESSION_COOKIE_NAME}={}", session.token));
+  for (name, value) in headers {
+    request = request.header(*name, *value);
+  }
+
+  match api::index::endpoint().call(request.finish()).await {
+    Ok(response) => response.status(),
+    Err(error) => error.status(),
+  }
+}
+
+#[tokio::test]
+async fn cookie_session() {
+  let session = create_session();
+  let csrf = session_cookie::csrf_token(&session.token).unwrap();
+
+  // reads need no CSRF token
+  assert_eq!(
+    call_api_with_cookie(Method::GET, "/v1/user", &session, &[]).await,
+    StatusCode::OK
+  );
+
+  // changes need the session's CSRF token and a trusted origin
+  assert_eq!(
+    call_api_with_cookie(Method::DELETE, "/v1/auth", &session, &[]).await,
+    StatusCode::FORBIDDEN
+  );
+  let other_csrf = session_cookie::csrf_token("other").unwrap();
+  assert_eq!(
+    call_api_with_cookie(
+      Method::DELETE,
+      "/v1/auth",
+      &session,
+      &[(CSRF_HEADER, &other_csrf)]
+    )
+    .await,
+    StatusCode::FORBIDDEN
+  );
+  assert_eq!(
+    call_api_with_cookie(
+      Method::DELETE,
+      "/v1/auth",
+      &session,
+      &[(CSRF_HEADER, &csrf), ("Origin", "https://evil.example")]
+    )
+    .await,
+    StatusCode::FORBIDDEN
+  );
+  assert!(auth::get_user_session_by_id(&session.session.id).is_ok());
+
+  assert_eq!(
+    call_api_with_cookie(
+      Method::DELETE,
+      "/v1/auth",
+      &session,
+      &[(CSRF_HEADER, &csrf)]
+    )
+    .await,
+    StatusCode::NO_CONTENT
+  );
+  assert!(auth::get_user_session_by_id(&session.session.id).is_err());
+}
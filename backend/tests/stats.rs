@@ -1,4 +1,7 @@
-use diarycomputer::services::{category, entry, stats, user};
+use diarycomputer::{
+  services::{category, entry, review, stats, stats_cache, tag, text_stats, user},
+  util::error::APIError,
+};
 use uuid::Uuid;
 
 fn create_user() -> user::UserDetails {
@@ -69,8 +72,10 @@ fn mood_stats_no_entries() {
   let stats = stats::mood_stats(&user.id).unwrap();
   assert_eq!(stats.entry_count, 0);
   assert_eq!(stats.average_mood, 0.0);
-  let tag_stats = stats::tag_stats(&user.id).unwrap();
-  assert!(tag_stats.is_empty());
+  // default tags are included without any entries
+  let tag_stats = stats::tag_stats(&user.id, stats::StatsOptions::default()).unwrap();
+  assert!(!tag_stats.is_empty());
+  assert!(tag_stats.iter().all(|t| t.entry_count == 0));
   let weekday_stats = stats::weekday_stats(&user.id).unwrap();
   assert_eq!(weekday_stats.monday.entry_count, 0);
   assert_eq!(weekday_stats.tuesday.entry_count, 0);
@@ -163,8 +168,9 @@ fn tag_stats() {
   create_entry_with_mood(2, 2, vec![tag2.id.clone()]);
   create_entry_with_mood(1, 2, vec![tag1.id.clone(), tag2.id.clone()]);
 
-  let stats = stats::tag_stats(&user.id).unwrap();
-  let stats_with_count = stats::tag_stats_with_count(&user.id).unwrap();
+  let stats = stats::tag_stats(&user.id, stats::StatsOptions::default()).unwrap();
+  let stats_with_count =
+    stats::tag_stats_with_count(&user.id, stats::StatsOptions::default()).unwrap();
 
   let tag1_stats = stats.iter().find(|s| s.tag_id == tag1.id).unwrap();
   assert_eq!(tag1_stats.entry_count, 9);
@@ -319,3 +325,875 @@ fn median_is_high() {
   assert_eq!(stats.average_mood, 4.64);
   assert_eq!(stats.median_mood, 5);
 }
+
+#[test]
+fn tag_cooccurrence() {
+  let user = create_user();
+  let category = category::create_category(category::CreateCategory {
+    name: "Test Category".to_string(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let other_category = category::create_category(category::CreateCategory {
+    name: "Other Category".to_string(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+
+  let create_tag = |name: &str, category_id: &str| {
+    tag::create_tag(tag::CreateTag {
+      name: name.to_string(),
+      color: "base".to_string(),
+      category_id: category_id.to_string(),
+      user_id: user.id.clone(),
+    })
+    .unwrap()
+  };
+
+  let work = create_tag("Work", &category.id);
+  let sick = create_tag("Sick", &category.id);
+  let travel = create_tag("Travel", &other_category.id);
+
+  // | date       | mood | tags                |
+  // |------------|------|---------------------|
+  // | 2025-01-01 | 5    | [work, sick]        |
+  // | 2025-01-02 | 3    | [work, sick, travel]|
+  // | 2025-01-03 | 1    | [work]              |
+  // | 2025-02-01 | 4    | [sick]              |
+  let entries = [
+    ("2025-01-01", 5, vec![work.id.clone(), sick.id.clone()]),
+    (
+      "2025-01-02",
+      3,
+      vec![work.id.clone(), sick.id.clone(), travel.id.clone()],
+    ),
+    ("2025-01-03", 1, vec![work.id.clone()]),
+    ("2025-02-01", 4, vec![sick.id.clone()]),
+  ];
+
+  for (date, mood, selected_tags) in entries {
+    entry::create_entry(entry::CreateEntry {
+      date: date.to_string(),
+      mood,
+      entry: None,
+      selected_tags,
+      user_id: user.id.clone(),
+    })
+    .unwrap();
+  }
+
+  let find_pair = |pairs: &[stats::TagPairStats], a: &str, b: &str| {
+    pairs
+      .iter()
+      .find(|p| (p.tag_id == a && p.paired_tag_id == b) || (p.tag_id == b && p.paired_tag_id == a))
+      .map(|p| (p.entry_count, p.average_mood, p.lift))
+  };
+
+  // work: 3, sick: 3, travel: 1, entries: 4
+  // work + sick: 2 entries, lift = (2 * 4) / (3 * 3) = 0.89
+  // work + travel: 1 entry, lift = (1 * 4) / (3 * 1) = 1.33
+  let cooccurrence = stats::tag_cooccurrence(&user.id, stats::StatsOptions::default()).unwrap();
+  assert_eq!(cooccurrence.entry_count, 4);
+  assert_eq!(
+    cooccurrence
+      .tags
+      .iter()
+      .filter(|t| t.entry_count > 0)
+      .count(),
+    3
+  );
+  assert_eq!(cooccurrence.pairs.len(), 3);
+  assert!(cooccurrence
+    .pairs
+    .iter()
+    .all(|p| p.tag_id < p.paired_tag_id));
+  assert_eq!(
+    find_pair(&cooccurrence.pairs, &work.id, &sick.id),
+    Some((2, 4.0, 0.89))
+  );
+  assert_eq!(
+    find_pair(&cooccurrence.pairs, &work.id, &travel.id),
+    Some((1, 3.0, 1.33))
+  );
+  assert_eq!(
+    find_pair(&cooccurrence.pairs, &sick.id, &travel.id),
+    Some((1, 3.0, 1.33))
+  );
+
+  // only pairs where both tags are in the category
+  let by_category = stats::tag_cooccurrence(
+    &user.id,
+    stats::StatsOptions {
+      category_id: Some(category.id.clone()),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(by_category.tags.len(), 2);
+  assert_eq!(by_category.pairs.len(), 1);
+  assert!(find_pair(&by_category.pairs, &work.id, &sick.id).is_some());
+
+  // january only, work: 3, sick: 2, entries: 3
+  // work + sick: lift = (2 * 3) / (3 * 2) = 1.0
+  let in_window = stats::tag_cooccurrence(
+    &user.id,
+    stats::StatsOptions {
+      from_date: Some("2025-01-01".to_string()),
+      to_date: Some("2025-01-31".to_string()),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(in_window.entry_count, 3);
+  assert_eq!(
+    find_pair(&in_window.pairs, &work.id, &sick.id),
+    Some((2, 4.0, 1.0))
+  );
+
+  let invalid_date = stats::tag_cooccurrence(
+    &user.id,
+    stats::StatsOptions {
+      from_date: Some("not a date".to_string()),
+      ..Default::default()
+    },
+  );
+  assert_eq!(invalid_date.err(), Some(APIError::BadRequest));
+}
+
+#[test]
+fn lagged_tag_stats() {
+  let user = create_user();
+  let category = category::create_category(category::CreateCategory {
+    name: "Test Category".to_string(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let late_night = tag::create_tag(tag::CreateTag {
+    name: "Late Night".to_string(),
+    color: "purple".to_string(),
+    category_id: category.id.clone(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+
+  // | date       | mood | tags         |
+  // |------------|------|--------------|
+  // | 2025-03-01 | 2    | [late_night] |
+  // | 2025-03-02 | 4    | []           |
+  // | 2025-03-03 | 1    | [late_night] |
+  // | 2025-03-04 | -    | no entry     |
+  // | 2025-03-05 | 5    | [late_night] |
+  // | 2025-03-06 | 5    | []           |
+  let entries = [
+    ("2025-03-01", 2, vec![late_night.id.clone()]),
+    ("2025-03-02", 4, vec![]),
+    ("2025-03-03", 1, vec![late_night.id.clone()]),
+    ("2025-03-05", 5, vec![late_night.id.clone()]),
+    ("2025-03-06", 5, vec![]),
+  ];
+
+  for (date, mood, selected_tags) in entries {
+    entry::create_entry(entry::CreateEntry {
+      date: date.to_string(),
+      mood,
+      entry: None,
+      selected_tags,
+      user_id: user.id.clone(),
+    })
+    .unwrap();
+  }
+
+  // lag 1: 03-01 -> 03-02 (4, +2), 03-03 -> 03-04 skipped, 03-05 -> 03-06 (5, +0)
+  let next_day = stats::lagged_tag_stats(&user.id, 1, stats::StatsOptions::default()).unwrap();
+  let next_day = next_day.iter().find(|s| s.tag_id == late_night.id).unwrap();
+  assert_eq!(next_day.name, "Late Night");
+  assert_eq!(next_day.color, "purple");
+  assert_eq!(next_day.category_id, category.id);
+  assert_eq!(next_day.lag, 1);
+  assert_eq!(next_day.entry_count, 2);
+  assert_eq!(next_day.average_mood, 4.5);
+  assert_eq!(next_day.average_mood_shift, 1.0);
+
+  // lag 2: 03-01 -> 03-03 (1, -1), 03-03 -> 03-05 (5, +4), 03-05 -> 03-07 skipped
+  let two_days = stats::lagged_tag_stats(&user.id, 2, stats::StatsOptions::default()).unwrap();
+  let two_days = two_days.iter().find(|s| s.tag_id == late_night.id).unwrap();
+  assert_eq!(two_days.entry_count, 2);
+  assert_eq!(two_days.average_mood, 3.0);
+  assert_eq!(two_days.average_mood_shift, 1.5);
+
+  // the window applies to the tagged entry, not the later entry
+  let in_window = stats::lagged_tag_stats(
+    &user.id,
+    1,
+    stats::StatsOptions {
+      from_date: Some("2025-03-05".to_string()),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(in_window.len(), 1);
+  assert_eq!(in_window[0].entry_count, 1);
+  assert_eq!(in_window[0].average_mood_shift, 0.0);
+
+  assert_eq!(
+    stats::lagged_tag_stats(&user.id, 0, stats::StatsOptions::default()).err(),
+    Some(APIError::BadRequest)
+  );
+  assert_eq!(
+    stats::lagged_tag_stats(
+      &user.id,
+      stats::MAX_LAG_DAYS + 1,
+      stats::StatsOptions::default()
+    )
+    .err(),
+    Some(APIError::BadRequest)
+  );
+}
+
+#[test]
+fn year_review() {
+  let user = create_user();
+  let category = category::create_category(category::CreateCategory {
+    name: "Test Category".to_string(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let sick = tag::create_tag(tag::CreateTag {
+    name: "Sick <3".to_string(),
+    color: "red".to_string(),
+    category_id: category.id.clone(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let travel = tag::create_tag(tag::CreateTag {
+    name: "Travel".to_string(),
+    color: "blue".to_string(),
+    category_id: category.id.clone(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+
+  // | date       | weekday   | mood | text            | tags     |
+  // |------------|-----------|------|-----------------|----------|
+  // | 2024-01-01 | monday    | 2    | "one two three" | [sick]   |
+  // | 2024-01-02 | tuesday   | 2    | "four"          | [sick]   |
+  // | 2024-01-03 | wednesday | 3    | -               | []       |
+  // | 2024-03-10 | sunday    | 5    | "five - six!"   | [travel] |
+  // | 2024-03-11 | monday    | 4    | -               | [travel] |
+  // | 2024-03-13 | wednesday | 5    | -               | []       |
+  // | 2025-01-01 | wednesday | 1    | -               | []       | (different year)
+  let entries = [
+    (
+      "2024-01-01",
+      2,
+      Some("one two three"),
+      vec![sick.id.clone()],
+    ),
+    ("2024-01-02", 2, Some("four"), vec![sick.id.clone()]),
+    ("2024-01-03", 3, None, vec![]),
+    (
+      "2024-03-10",
+      5,
+      Some("five - six!"),
+      vec![travel.id.clone()],
+    ),
+    ("2024-03-11", 4, None, vec![travel.id.clone()]),
+    ("2024-03-13", 5, None, vec![]),
+    ("2025-01-01", 1, None, vec![]),
+  ];
+
+  for (date, mood, text, selected_tags) in entries {
+    entry::create_entry(entry::CreateEntry {
+      date: date.to_string(),
+      mood,
+      entry: text.map(|t| t.to_string()),
+      selected_tags,
+      user_id: user.id.clone(),
+    })
+    .unwrap();
+  }
+
+  let review = review::year_review(&user.id, 2024).unwrap();
+
+  // average mood = (2 + 2 + 3 + 5 + 4 + 5) / 6 = 3.5
+  assert_eq!(review.year, 2024);
+  assert_eq!(review.entry_count, 6);
+  assert_eq!(review.average_mood, 3.5);
+
+  // leap year
+  assert_eq!(review.days.len(), 366);
+  assert_eq!(review.days[0].mood, Some(2));
+  assert_eq!(review.days[3].mood, None);
+
+  // january = 2.33, march = 4.67
+  let best_month = review.best_month.unwrap();
+  assert_eq!(best_month.month, 3);
+  assert_eq!(best_month.average_mood, 4.67);
+  let worst_month = review.worst_month.unwrap();
+  assert_eq!(worst_month.month, 1);
+  assert_eq!(worst_month.entry_count, 3);
+
+  // sick = 2.0 (-1.5), travel = 4.5 (+1.0)
+  assert_eq!(review.top_tags.len(), 2);
+  assert_eq!(review.top_tags[0].tag_id, sick.id);
+  assert_eq!(review.top_tags[0].mood_impact, -1.5);
+  assert_eq!(review.top_tags[1].tag_id, travel.id);
+  assert_eq!(review.top_tags[1].mood_impact, 1.0);
+
+  let longest_streak = review.longest_streak.unwrap();
+  assert_eq!(longest_streak.length, 3);
+  assert_eq!(longest_streak.from_date.to_string(), "2024-01-01");
+  assert_eq!(longest_streak.to_date.to_string(), "2024-01-03");
+
+  // punctuation is not a word, the same count as /stats/words
+  assert_eq!(review.word_count.total, 6);
+  assert_eq!(review.word_count.average_per_entry, 1.0);
+  let words = text_stats::text_stats(
+    &user.id,
+    stats::StatsOptions {
+      from_date: Some("2024-01-01".to_string()),
+      to_date: Some("2024-12-31".to_string()),
+      category_id: None,
+    },
+  )
+  .unwrap();
+  assert_eq!(words.word_count, review.word_count.total);
+
+  // monday and wednesday both have two entries, the first weekday wins
+  assert_eq!(review.busiest_weekday.as_deref(), Some("monday"));
+
+  let html = review::year_review_html(&user.id, 2024).unwrap();
+  assert!(html.contains("2024 in review"));
+  assert!(html.contains("Sick &lt;3"));
+  assert!(!html.contains("Sick <3"));
+}
+
+#[test]
+fn year_review_no_entries() {
+  let user = create_user();
+
+  let review = review::year_review(&user.id, 2023).unwrap();
+
+  assert_eq!(review.entry_count, 0);
+  assert_eq!(review.days.len(), 365);
+  assert!(review.days.iter().all(|day| day.mood.is_none()));
+  assert!(review.best_month.is_none());
+  assert!(review.worst_month.is_none());
+  assert!(review.longest_streak.is_none());
+  assert!(review.busiest_weekday.is_none());
+  assert_eq!(review.word_count.total, 0);
+
+  assert_eq!(
+    review::year_review(&user.id, 300_000).err(),
+    Some(APIError::BadRequest)
+  );
+}
+
+#[test]
+fn calendar_stats() {
+  let user = create_user();
+  let category = category::create_category(category::CreateCategory {
+    name: "Test Category".to_string(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let create_tag = |name: &str| {
+    tag::create_tag(tag::CreateTag {
+      name: name.to_string(),
+      color: "base".to_string(),
+      category_id: category.id.clone(),
+      user_id: user.id.clone(),
+    })
+    .unwrap()
+  };
+  let work = create_tag("Work");
+  let gym = create_tag("Gym");
+
+  let entries = [
+    ("2024-12-31", 1, vec![work.id.clone()]),
+    ("2025-01-02", 4, vec![work.id.clone(), gym.id.clone()]),
+    ("2025-01-01", 3, vec![]),
+    ("2025-06-15", 5, vec![gym.id.clone()]),
+  ];
+
+  for (date, mood, selected_tags) in entries {
+    entry::create_entry(entry::CreateEntry {
+      date: date.to_string(),
+      mood,
+      entry: None,
+      selected_tags,
+      user_id: user.id.clone(),
+    })
+    .unwrap();
+  }
+
+  let calendar = stats::calendar_stats(&user.id, 2025, None).unwrap();
+  assert_eq!(calendar.year, 2025);
+  let days: Vec<(String, i32, i64)> = calendar
+    .days
+    .iter()
+    .map(|day| (day.0.to_string(), day.1, day.2))
+    .collect();
+  assert_eq!(
+    days,
+    vec![
+      ("2025-01-01".to_string(), 3, 0),
+      ("2025-01-02".to_string(), 4, 2),
+      ("2025-06-15".to_string(), 5, 1),
+    ]
+  );
+
+  // serialized as compact tuples
+  let json = serde_json::to_value(&calendar).unwrap();
+  assert_eq!(json["days"][0], serde_json::json!(["2025-01-01", 3, 0]));
+
+  // tag count is not affected by the filter
+  let with_gym = stats::calendar_stats(&user.id, 2025, Some(vec![gym.id.clone()])).unwrap();
+  assert_eq!(with_gym.days.len(), 2);
+  assert_eq!(with_gym.days[0].2, 2);
+
+  let with_work_and_gym =
+    stats::calendar_stats(&user.id, 2025, Some(vec![work.id.clone(), gym.id.clone()])).unwrap();
+  assert_eq!(with_work_and_gym.days.len(), 1);
+  assert_eq!(with_work_and_gym.days[0].0.to_string(), "2025-01-02");
+
+  let empty_year = stats::calendar_stats(&user.id, 2023, None).unwrap();
+  assert!(empty_year.days.is_empty());
+}
+
+#[test]
+fn tag_stats_metadata_and_window() {
+  let user = create_user();
+  let category = category::create_category(category::CreateCategory {
+    name: "A Category".to_string(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let used = tag::create_tag(tag::CreateTag {
+    name: "Used".to_string(),
+    color: "green".to_string(),
+    category_id: category.id.clone(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let unused = tag::create_tag(tag::CreateTag {
+    name: "Unused".to_string(),
+    color: "pink".to_string(),
+    category_id: category.id.clone(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+
+  for (date, mood) in [("2025-01-10", 2), ("2025-02-10", 4), ("2025-02-11", 5)] {
+    entry::create_entry(entry::CreateEntry {
+      date: date.to_string(),
+      mood,
+      entry: None,
+      selected_tags: vec![used.id.clone()],
+      user_id: user.id.clone(),
+    })
+    .unwrap();
+  }
+
+  let tag_stats = stats::tag_stats(&user.id, stats::StatsOptions::default()).unwrap();
+  let used_stats = tag_stats.iter().find(|t| t.tag_id == used.id).unwrap();
+  assert_eq!(used_stats.name, "Used");
+  assert_eq!(used_stats.color, "green");
+  assert_eq!(used_stats.category_id, category.id);
+  assert_eq!(used_stats.entry_count, 3);
+  assert_eq!(used_stats.average_mood, 3.67);
+
+  let unused_stats = tag_stats.iter().find(|t| t.tag_id == unused.id).unwrap();
+  assert_eq!(unused_stats.name, "Unused");
+  assert_eq!(unused_stats.entry_count, 0);
+  assert_eq!(unused_stats.average_mood, 0.0);
+  assert_eq!(unused_stats.median_mood, 0);
+
+  let february = stats::StatsOptions {
+    from_date: Some("2025-02-01".to_string()),
+    to_date: Some("2025-02-28".to_string()),
+    ..Default::default()
+  };
+  let in_window = stats::tag_stats_with_count(&user.id, february).unwrap();
+  let used_in_window = in_window.iter().find(|t| t.tag_id == used.id).unwrap();
+  assert_eq!(used_in_window.entry_count, 2);
+  assert_eq!(used_in_window.average_mood, 4.5);
+  assert_eq!(used_in_window.mood_entry_count.mood_2, 0);
+  assert_eq!(used_in_window.mood_entry_count.mood_4, 1);
+  assert_eq!(used_in_window.mood_entry_count.mood_5, 1);
+  let unused_in_window = in_window.iter().find(|t| t.tag_id == unused.id).unwrap();
+  assert_eq!(unused_in_window.mood_entry_count.mood_5, 0);
+
+  // grouped by category, including the default categories, ordered by name
+  let by_category = stats::tag_stats_by_category(&user.id, stats::StatsOptions::default()).unwrap();
+  let category_names: Vec<&str> = by_category.iter().map(|c| c.name.as_str()).collect();
+  assert_eq!(category_names, vec!["A Category", "Activities", "Tags"]);
+  assert_eq!(by_category[0].category_id, category.id);
+  let tag_names: Vec<&str> = by_category[0]
+    .tags
+    .iter()
+    .map(|t| t.name.as_str())
+    .collect();
+  assert_eq!(tag_names, vec!["Unused", "Used"]);
+
+  let one_category = stats::tag_stats_with_count_by_category(
+    &user.id,
+    stats::StatsOptions {
+      category_id: Some(category.id.clone()),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(one_category.len(), 1);
+  assert_eq!(one_category[0].tags.len(), 2);
+}
+
+#[test]
+fn category_stats() {
+  let user = create_user();
+  let category = category::create_category(category::CreateCategory {
+    name: "Sleep".to_string(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let other_category = category::create_category(category::CreateCategory {
+    name: "Weather".to_string(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+
+  let create_tag = |name: &str, category_id: &str| {
+    tag::create_tag(tag::CreateTag {
+      name: name.to_string(),
+      color: "base".to_string(),
+      category_id: category_id.to_string(),
+      user_id: user.id.clone(),
+    })
+    .unwrap()
+  };
+  let good_sleep = create_tag("Good sleep", &category.id);
+  let long_sleep = create_tag("Long sleep", &category.id);
+  let nap = create_tag("Nap", &category.id);
+  let rain = create_tag("Rain", &other_category.id);
+
+  // entries with two tags from the same category are only counted once
+  for (date, mood, tags) in [
+    (
+      "2025-03-01",
+      5,
+      vec![good_sleep.id.clone(), long_sleep.id.clone()],
+    ),
+    ("2025-03-02", 4, vec![good_sleep.id.clone()]),
+    ("2025-03-03", 1, vec![]),
+    ("2025-03-04", 2, vec![rain.id.clone()]),
+  ] {
+    entry::create_entry(entry::CreateEntry {
+      date: date.to_string(),
+      mood,
+      entry: None,
+      selected_tags: tags,
+      user_id: user.id.clone(),
+    })
+    .unwrap();
+  }
+
+  let category_stats = stats::category_stats(&user.id, stats::StatsOptions::default()).unwrap();
+  let category_names: Vec<&str> = category_stats.iter().map(|c| c.name.as_str()).collect();
+  assert_eq!(
+    category_names,
+    vec!["Activities", "Sleep", "Tags", "Weather"]
+  );
+
+  let sleep = &category_stats[1];
+  assert_eq!(sleep.entry_count, 2);
+  assert_eq!(sleep.entry_share, 0.5);
+  assert_eq!(sleep.with_category.entry_count, 2);
+  assert_eq!(sleep.with_category.average_mood, 4.5);
+  assert_eq!(sleep.with_category.median_mood, 4);
+  assert_eq!(sleep.without_category.entry_count, 2);
+  assert_eq!(sleep.without_category.average_mood, 1.5);
+  assert_eq!(sleep.without_category.median_mood, 1);
+  assert_eq!(sleep.most_used_tag.as_ref().unwrap().tag_id, good_sleep.id);
+  assert_eq!(sleep.most_used_tag.as_ref().unwrap().entry_count, 2);
+  assert_eq!(sleep.least_used_tag.as_ref().unwrap().tag_id, nap.id);
+  assert_eq!(sleep.least_used_tag.as_ref().unwrap().entry_count, 0);
+
+  let weather = &category_stats[3];
+  assert_eq!(weather.entry_count, 1);
+  assert_eq!(weather.entry_share, 0.25);
+  assert_eq!(weather.with_category.average_mood, 2.0);
+  assert_eq!(weather.without_category.average_mood, 3.33);
+
+  // filtered to one category and date window, with mood counts
+  let with_count = stats::category_stats_with_count(
+    &user.id,
+    stats::StatsOptions {
+      from_date: Some("2025-03-02".to_string()),
+      to_date: Some("2025-03-04".to_string()),
+      category_id: Some(category.id.clone()),
+    },
+  )
+  .unwrap();
+  assert_eq!(with_count.len(), 1);
+  assert_eq!(with_count[0].entry_count, 1);
+  assert_eq!(with_count[0].entry_share, 0.3333);
+  assert_eq!(with_count[0].with_category.mood_entry_count.mood_4, 1);
+  assert_eq!(with_count[0].without_category.mood_entry_count.mood_1, 1);
+  assert_eq!(with_count[0].without_category.mood_entry_count.mood_2, 1);
+  assert_eq!(with_count[0].without_category.mood_entry_count.mood_5, 0);
+
+  // an empty category has no entries and no tags to report
+  let empty_category = category::create_category(category::CreateCategory {
+    name: "Empty".to_string(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let empty = stats::category_stats(
+    &user.id,
+    stats::StatsOptions {
+      category_id: Some(empty_category.id.clone()),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(empty[0].entry_count, 0);
+  assert_eq!(empty[0].without_category.entry_count, 4);
+  assert!(empty[0].most_used_tag.is_none());
+  assert!(empty[0].least_used_tag.is_none());
+}
+
+#[test]
+fn compare_periods() {
+  let user = create_user();
+  let category = category::create_category(category::CreateCategory {
+    name: "Activities".to_string(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let create_tag = |name: &str| {
+    tag::create_tag(tag::CreateTag {
+      name: name.to_string(),
+      color: "base".to_string(),
+      category_id: category.id.clone(),
+      user_id: user.id.clone(),
+    })
+    .unwrap()
+  };
+  let walk = create_tag("Walk");
+  let work = create_tag("Work");
+  let unused = create_tag("Unused");
+
+  // january: moods 2, 2, 3 - february: moods 4, 5, 5, 3
+  for (date, mood, tags) in [
+    ("2025-01-05", 2, vec![work.id.clone()]),
+    ("2025-01-06", 2, vec![work.id.clone()]),
+    ("2025-01-07", 3, vec![walk.id.clone(), work.id.clone()]),
+    ("2025-02-05", 4, vec![walk.id.clone()]),
+    ("2025-02-06", 5, vec![walk.id.clone()]),
+    ("2025-02-07", 5, vec![walk.id.clone()]),
+    ("2025-02-08", 3, vec![work.id.clone()]),
+  ] {
+    entry::create_entry(entry::CreateEntry {
+      date: date.to_string(),
+      mood,
+      entry: None,
+      selected_tags: tags,
+      user_id: user.id.clone(),
+    })
+    .unwrap();
+  }
+
+  let period = |from: &str, to: &str| stats::StatsOptions {
+    from_date: Some(from.to_string()),
+    to_date: Some(to.to_string()),
+    category_id: None,
+  };
+
+  let comparison = stats::compare_periods(
+    &user.id,
+    period("2025-01-01", "2025-01-31"),
+    period("2025-02-01", "2025-02-28"),
+  )
+  .unwrap();
+
+  assert_eq!(comparison.first.entry_count, 3);
+  assert_eq!(comparison.first.average_mood, Some(2.33));
+  assert_eq!(comparison.first.median_mood, Some(2));
+  assert_eq!(comparison.second.entry_count, 4);
+  assert_eq!(comparison.second.average_mood, Some(4.25));
+  assert_eq!(comparison.second.median_mood, Some(4));
+
+  assert_eq!(comparison.delta.entry_count, 1);
+  assert_eq!(comparison.delta.average_mood, Some(1.92));
+  assert_eq!(comparison.delta.median_mood, Some(2));
+  assert_eq!(comparison.delta.mood_entry_count.mood_2, -2);
+  assert_eq!(comparison.delta.mood_entry_count.mood_3, 0);
+  assert_eq!(comparison.delta.mood_entry_count.mood_4, 1);
+  assert_eq!(comparison.delta.mood_entry_count.mood_5, 2);
+
+  // tags unused in both periods are left out, largest change first
+  let tag_ids: Vec<&str> = comparison.tags.iter().map(|t| t.tag_id.as_str()).collect();
+  assert_eq!(tag_ids, vec![walk.id.as_str(), work.id.as_str()]);
+  assert!(!tag_ids.contains(&unused.id.as_str()));
+
+  let walk_change = &comparison.tags[0];
+  assert_eq!(walk_change.name, "Walk");
+  assert_eq!(walk_change.first_entry_count, 1);
+  assert_eq!(walk_change.second_entry_count, 3);
+  assert_eq!(walk_change.entry_count_change, 2);
+  assert_eq!(walk_change.first_average_mood, Some(3.0));
+  assert_eq!(walk_change.second_average_mood, Some(4.67));
+  assert_eq!(walk_change.average_mood_change, Some(1.67));
+
+  let work_change = &comparison.tags[1];
+  assert_eq!(work_change.entry_count_change, -2);
+  assert_eq!(work_change.average_mood_change, Some(0.67));
+
+  // without entries there is nothing to average, rather than a mood of 0
+  let empty = stats::compare_periods(
+    &user.id,
+    period("2024-01-01", "2024-01-31"),
+    period("2025-02-01", "2025-02-28"),
+  )
+  .unwrap();
+  assert_eq!(empty.first.entry_count, 0);
+  assert_eq!(empty.first.average_mood, None);
+  assert_eq!(empty.first.median_mood, None);
+  assert_eq!(empty.second.average_mood, Some(4.25));
+  assert_eq!(empty.delta.entry_count, 4);
+  assert_eq!(empty.delta.average_mood, None);
+  assert_eq!(empty.delta.median_mood, None);
+  assert_eq!(empty.tags[0].first_average_mood, None);
+  assert_eq!(empty.tags[0].average_mood_change, None);
+
+  // a period that ends before it starts is rejected
+  let invalid = stats::compare_periods(
+    &user.id,
+    period("2025-01-31", "2025-01-01"),
+    period("2025-02-01", "2025-02-28"),
+  );
+  assert_eq!(invalid.unwrap_err(), APIError::BadRequest);
+}
+
+#[test]
+fn stats_cache_invalidation() {
+  let user = create_user();
+  let key = "/stats/mood";
+
+  let create_entry = |date: &str, mood: i32| {
+    entry::create_entry(entry::CreateEntry {
+      date: date.to_string(),
+      mood,
+      entry: None,
+      selected_tags: vec![],
+      user_id: user.id.clone(),
+    })
+    .unwrap()
+  };
+
+  let created = create_entry("2025-04-01", 5);
+  let first = stats_cache::cached(&user.id, key, || stats::mood_stats(&user.id)).unwrap();
+  assert!(first.body.contains("\"entry_count\":1"));
+
+  // entry writes invalidate the cache
+  create_entry("2025-04-02", 1);
+  let second = stats_cache::cached(&user.id, key, || stats::mood_stats(&user.id)).unwrap();
+  assert!(second.body.contains("\"entry_count\":2"));
+  assert_ne!(second.etag, first.etag);
+  assert!(second.last_modified > first.last_modified);
+
+  entry::delete_entry(&created.id, &user.id).unwrap();
+  let third = stats_cache::cached(&user.id, key, || stats::mood_stats(&user.id)).unwrap();
+  assert!(third.body.contains("\"entry_count\":1"));
+
+  // tag writes invalidate the cache
+  let tag_key = "/stats/tags";
+  let tag_stats = || stats::tag_stats(&user.id, stats::StatsOptions::default());
+  let before = stats_cache::cached(&user.id, tag_key, tag_stats).unwrap();
+  let categories = category::get_all_categories(&user.id).unwrap();
+  tag::create_tag(tag::CreateTag {
+    name: "New tag".to_string(),
+    color: "base".to_string(),
+    category_id: categories[0].id.clone(),
+    user_id: user.id.clone(),
+  })
+  .unwrap();
+  let after = stats_cache::cached(&user.id, tag_key, tag_stats).unwrap();
+  assert!(!before.body.contains("New tag"));
+  assert!(after.body.contains("New tag"));
+}
+
+#[test]
+fn text_stats() {
+  let user = create_user();
+
+  for (date, mood, text) in [
+    (
+      "2025-05-01",
+      5,
+      Some("A great day at the beach, the beach was lovely"),
+    ),
+    ("2025-05-02", 1, Some("Sad")),
+    ("2025-05-03", 3, None),
+    ("2025-06-01", 4, Some("Good run along the beach")),
+    ("2025-06-02", 2, Some("Tired, not good")),
+  ] {
+    entry::create_entry(entry::CreateEntry {
+      date: date.to_string(),
+      mood,
+      entry: text.map(|t| t.to_string()),
+      selected_tags: vec![],
+      user_id: user.id.clone(),
+    })
+    .unwrap();
+  }
+
+  let stats = text_stats::text_stats(&user.id, stats::StatsOptions::default()).unwrap();
+
+  // entries without text are left out
+  assert_eq!(stats.entry_count, 4);
+  assert_eq!(stats.word_count, 10 + 1 + 5 + 3);
+  assert_eq!(stats.average_word_count, 4.75);
+  assert_eq!(stats.entries[0].word_count, 10);
+  assert_eq!(stats.entries[1].date.to_string(), "2025-05-02");
+
+  // longer entries have better moods here
+  assert!(stats.length_mood_correlation.unwrap() > 0.5);
+
+  // written tone follows the picked mood
+  assert!(stats.entries[0].sentiment.unwrap() > 0.5);
+  assert!(stats.entries[1].sentiment.unwrap() < 0.0);
+  assert!(stats.entries[3].sentiment.unwrap() < 0.0);
+  assert!(stats.sentiment_mood_correlation.unwrap() > 0.5);
+
+  // stopwords are removed from frequent words
+  assert_eq!(stats.frequent_words[0].word, "beach");
+  assert_eq!(stats.frequent_words[0].count, 3);
+  assert_eq!(stats.frequent_words[1].word, "good");
+  assert_eq!(stats.frequent_words[1].count, 2);
+  assert!(!stats.frequent_words.iter().any(|w| w.word == "the"));
+
+  let mood_1 = &stats.moods[0];
+  assert_eq!(mood_1.entry_count, 1);
+  assert_eq!(mood_1.average_word_count, 1.0);
+  let mood_3 = &stats.moods[2];
+  assert_eq!(mood_3.entry_count, 0);
+  assert_eq!(mood_3.average_sentiment, None);
+
+  assert_eq!(stats.months.len(), 2);
+  assert_eq!((stats.months[0].year, stats.months[0].month), (2025, 5));
+  assert_eq!(stats.months[0].entry_count, 2);
+  assert_eq!(stats.months[0].word_count, 11);
+  assert_eq!(stats.months[1].average_word_count, 4.0);
+
+  let june = text_stats::text_stats(
+    &user.id,
+    stats::StatsOptions {
+      from_date: Some("2025-06-01".to_string()),
+      ..Default::default()
+    },
+  )
+  .unwrap();
+  assert_eq!(june.entry_count, 2);
+  assert_eq!(june.months.len(), 1);
+}
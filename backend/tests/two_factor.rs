@@ -0,0 +1,309 @@
+use diarycomputer::{
+  api,
+  services::{
+    auth,
+    auth::{LoginResult, SessionMetadata, UserCredentials},
+    two_factor,
+    two_factor::{ChallengeResponse, TwoFactorChallenge},
+    user,
+  },
+  util::{
+    clock::{Clock, ManualClock},
+    error::APIError,
+    totp,
+    unix_time::unix_ms,
+  },
+};
+use poem::{
+  http::{Method, StatusCode},
+  Endpoint, Request,
+};
+use uuid::Uuid;
+
+const SECOND: i64 = 1000;
+
+fn metadata() -> SessionMetadata {
+  SessionMetadata {
+    ip_address: "127.0.0.1".to_string(),
+    user_agent: "test".to_string(),
+  }
+}
+
+fn credentials(email: &str) -> UserCredentials {
+  UserCredentials {
+    email: email.to_string(),
+    password: "password".to_string(),
+  }
+}
+
+/// Creates a user with 2FA enabled, returns the email, TOTP secret and recovery codes
+fn create_two_factor_user(clock: &ManualClock) -> (String, String, Vec<String>) {
+  let random_name = Uuid::new_v4().to_string();
+  let email = format!("{random_name}@example.com");
+
+  let user = user::create_user(user::CreateUser {
+    name: random_name,
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .expect("Failed to create test user");
+
+  let enrolment = two_factor::begin_enrolment(&user.id, clock).unwrap();
+  let code = totp::code_at(&enrolment.secret, clock.now_ms()).unwrap();
+  let recovery_codes = two_factor::confirm_enrolment(&user.id, &code, clock)
+    .unwrap()
+    .recovery_codes;
+
+  (email, enrolment.secret, recovery_codes)
+}
+
+fn challenge(email: &str, clock: &ManualClock) -> TwoFactorChallenge {
+  match auth::log_in(credentials(email), metadata(), clock).unwrap() {
+    LoginResult::TwoFactorChallenge(challenge) => challenge,
+    LoginResult::Session(_) => panic!("Expected a two-factor challenge"),
+  }
+}
+
+fn answer(challenge: &TwoFactorChallenge, code: &str) -> ChallengeResponse {
+  ChallengeResponse {
+    challenge: challenge.challenge.clone(),
+    code: Some(code.to_string()),
+    recovery_code: None,
+  }
+}
+
+#[test]
+fn enrolment() {
+  let clock = ManualClock::new(unix_ms());
+  let random_name = Uuid::new_v4().to_string();
+  let email = format!("{random_name}@example.com");
+  let user = user::create_user(user::CreateUser {
+    name: random_name,
+    email: email.clone(),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .unwrap();
+
+  let enrolment = two_factor::begin_enrolment(&user.id, &clock).unwrap();
+  assert!(enrolment
+    .otpauth_uri
+    .starts_with("otpauth://totp/diary.computer:"));
+  assert!(enrolment.otpauth_uri.contains(&enrolment.secret));
+  assert!(enrolment.qr_svg.contains("<svg"));
+
+  // 2FA is not enabled until a code is confirmed
+  assert!(!two_factor::status(&user.id).unwrap().enabled);
+  assert!(auth::create_user_session(credentials(&email), metadata()).is_ok());
+
+  assert_eq!(
+    two_factor::confirm_enrolment(&user.id, "000000", &clock).unwrap_err(),
+    APIError::InvalidTwoFactorCode
+  );
+
+  let code = totp::code_at(&enrolment.secret, clock.now_ms()).unwrap();
+  let recovery_codes = two_factor::confirm_enrolment(&user.id, &code, &clock).unwrap();
+  assert_eq!(recovery_codes.recovery_codes.len(), 10);
+
+  let status = two_factor::status(&user.id).unwrap();
+  assert!(status.enabled);
+  assert_eq!(status.recovery_codes_remaining, 10);
+
+  assert_eq!(
+    two_factor::begin_enrolment(&user.id, &clock).unwrap_err(),
+    APIError::TwoFactorAlreadyEnabled
+  );
+  // password only logins are refused
+  assert_eq!(
+    auth::create_user_session(credentials(&email), metadata()).unwrap_err(),
+    APIError::TwoFactorRequired
+  );
+}
+
+#[test]
+fn login_with_code() {
+  let clock = ManualClock::new(unix_ms());
+  let (email, secret, _) = create_two_factor_user(&clock);
+
+  let challenge = challenge(&email, &clock);
+  assert!(challenge.two_factor_required);
+  assert_eq!(
+    challenge.expires_at,
+    clock.now_ms() + two_factor::CHALLENGE_LIFETIME_MS
+  );
+
+  // the code used for enrolment can not be used again
+  let used_code = totp::code_at(&secret, clock.now_ms()).unwrap();
+  assert_eq!(
+    two_factor::complete_challenge(answer(&challenge, &used_code), metadata(), &clock).unwrap_err(),
+    APIError::InvalidTwoFactorCode
+  );
+
+  clock.advance(30 * SECOND);
+  let code = totp::code_at(&secret, clock.now_ms()).unwrap();
+  let session =
+    two_factor::complete_challenge(answer(&challenge, &code), metadata(), &clock).unwrap();
+  assert!(auth::get_user_session_by_token(&session.token).is_ok());
+
+  // challenges are single use
+  assert_eq!(
+    two_factor::complete_challenge(answer(&challenge, &code), metadata(), &clock).unwrap_err(),
+    APIError::TwoFactorChallengeExpired
+  );
+}
+
+#[test]
+fn recovery_codes_are_single_use() {
+  let clock = ManualClock::new(unix_ms());
+  let (email, _, recovery_codes) = create_two_factor_user(&clock);
+  let recovery_code = |challenge: &TwoFactorChallenge, code: &str| ChallengeResponse {
+    challenge: challenge.challenge.clone(),
+    code: None,
+    recovery_code: Some(code.to_string()),
+  };
+
+  // recovery codes are accepted without the dash and in any case
+  let code = recovery_codes[0].replace('-', "").to_uppercase();
+  let challenge = challenge(&email, &clock);
+  assert!(
+    two_factor::complete_challenge(recovery_code(&challenge, &code), metadata(), &clock).is_ok()
+  );
+
+  let challenge = self::challenge(&email, &clock);
+  assert_eq!(
+    two_factor::complete_challenge(recovery_code(&challenge, &code), metadata(), &clock)
+      .unwrap_err(),
+    APIError::InvalidTwoFactorCode
+  );
+  assert!(two_factor::complete_challenge(
+    recovery_code(&challenge, &recovery_codes[1]),
+    metadata(),
+    &clock
+  )
+  .is_ok());
+
+  let user_id = user::get_user_id(&email).unwrap();
+  assert_eq!(
+    two_factor::status(&user_id)
+      .unwrap()
+      .recovery_codes_remaining,
+    8
+  );
+}
+
+#[test]
+fn challenge_expiry_and_attempts() {
+  let clock = ManualClock::new(unix_ms());
+  let (email, secret, _) = create_two_factor_user(&clock);
+
+  let challenge = self::challenge(&email, &clock);
+  clock.advance(two_factor::CHALLENGE_LIFETIME_MS);
+  let code = totp::code_at(&secret, clock.now_ms()).unwrap();
+  assert_eq!(
+    two_factor::complete_challenge(answer(&challenge, &code), metadata(), &clock).unwrap_err(),
+    APIError::TwoFactorChallengeExpired
+  );
+
+  // the challenge is removed after too many wrong codes
+  let challenge = self::challenge(&email, &clock);
+  for _ in 0..two_factor::MAX_CHALLENGE_ATTEMPTS {
+    assert_eq!(
+      two_factor::complete_challenge(answer(&challenge, "000000"), metadata(), &clock).unwrap_err(),
+      APIError::InvalidTwoFactorCode
+    );
+  }
+  assert_eq!(
+    two_factor::complete_challenge(answer(&challenge, &code), metadata(), &clock).unwrap_err(),
+    APIError::TwoFactorChallengeExpired
+  );
+}
+
+#[test]
+fn parallel_challenge_attempts() {
+  let clock = ManualClock::new(unix_ms());
+  let (email, _, _) = create_two_factor_user(&clock);
+  let challenge = self::challenge(&email, &clock);
+
+  // guesses sent at the same time are counted too
+  let wrong_codes = std::thread::scope(|scope| {
+    let guesses: Vec<_> = (0..20)
+      .map(|_| {
+        scope.spawn(|| {
+          two_factor::complete_challenge(answer(&challenge, "000000"), metadata(), &clock)
+            .unwrap_err()
+        })
+      })
+      .collect();
+    guesses
+      .into_iter()
+      .map(|guess| guess.join().unwrap())
+      .filter(|error| *error == APIError::InvalidTwoFactorCode)
+      .count()
+  });
+
+  assert_eq!(wrong_codes, two_factor::MAX_CHALLENGE_ATTEMPTS as usize);
+}
+
+#[test]
+fn disable_requires_password() {
+  let clock = ManualClock::new(unix_ms());
+  let (email, _, _) = create_two_factor_user(&clock);
+  let user_id = user::get_user_id(&email).unwrap();
+
+  assert_eq!(
+    two_factor::disable(&user_id, None).unwrap_err(),
+    APIError::CurrentPasswordRequired
+  );
+  assert_eq!(
+    two_factor::disable(&user_id, Some("wrong password")).unwrap_err(),
+    APIError::IncorrectCurrentPassword
+  );
+  assert!(two_factor::is_enabled(&user_id).unwrap());
+
+  two_factor::disable(&user_id, Some("password")).unwrap();
+  let status = two_factor::status(&user_id).unwrap();
+  assert!(!status.enabled);
+  assert_eq!(status.recovery_codes_remaining, 0);
+  assert!(auth::create_user_session(credentials(&email), metadata()).is_ok());
+}
+
+#[tokio::test]
+async fn two_step_login_route() {
+  // enrol in the past so the current code has not been used yet
+  let clock = ManualClock::new(unix_ms() - 120 * SECOND);
+  let (email, secret, _) = create_two_factor_user(&clock);
+
+  let post_auth = |body: String| {
+    Request::builder()
+      .method(Method::POST)
+      .uri("/v1/auth".parse().unwrap())
+      .header("Content-Type", "application/json")
+      .body(body)
+  };
+
+  let response = api::index::endpoint()
+    .call(post_auth(format!(
+      r#"{{"email": "{email}", "password": "password"}}"#
+    )))
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  let body: serde_json::Value =
+    serde_json::from_str(&response.into_body().into_string().await.unwrap()).unwrap();
+  assert_eq!(body["two_factor_required"], true);
+  assert!(body.get("token").is_none());
+
+  let code = totp::code_at(&secret, unix_ms()).unwrap();
+  let response = api::index::endpoint()
+    .call(post_auth(format!(
+      r#"{{"challenge": {}, "code": "{code}"}}"#,
+      body["challenge"]
+    )))
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::CREATED);
+  let body: serde_json::Value =
+    serde_json::from_str(&response.into_body().into_string().await.unwrap()).unwrap();
+  assert!(body["token"].is_string());
+}
@@ -1,6 +1,34 @@
-use diarycomputer::services::{auth, category, tag, user};
+use diarycomputer::{
+  services::{auth, auth::PasswordHasher, category, tag, user},
+  util::error::APIError,
+};
 use uuid::Uuid;
 
+fn create_test_user() -> user::UserDetails {
+  let random_name = Uuid::new_v4().to_string();
+
+  user::create_user(user::CreateUser {
+    name: random_name.clone(),
+    email: format!("{random_name}@example.com"),
+    password: "password".to_string(),
+    invite: None,
+  })
+  .expect("Failed to create test user")
+}
+
+fn log_in(email: &str, password: &str) -> Result<auth::NewSession, APIError> {
+  auth::create_user_session(
+    auth::UserCredentials {
+      email: email.to_string(),
+      password: password.to_string(),
+    },
+    auth::SessionMetadata {
+      ip_address: "SYSTEM".to_string(),
+      user_agent: "SYSTEM".to_string(),
+    },
+  )
+}
+
 #[test]
 fn create_user() {
   let random_name = Uuid::new_v4().to_string();
@@ -101,9 +129,10 @@ fn updates_user() {
   let updated_user = user::UpdateUser {
     name: new_random_name.clone(),
     email: new_email.clone(),
+    current_password: Some("password".to_string()),
   };
 
-  let updated = user::update_user(&found_user.id, updated_user);
+  let updated = user::update_user(&found_user.id, updated_user, None);
 
   assert!(updated.is_ok());
 
@@ -147,7 +176,7 @@ fn creates_a_session() {
 
   assert!(session.is_ok());
 
-  let found_session = auth::get_user_session_by_id(&session.unwrap().id);
+  let found_session = auth::get_user_session_by_id(&session.unwrap().session.id);
 
   assert!(found_session.is_ok());
 }
@@ -186,7 +215,7 @@ fn deletes_a_session() {
 
   assert!(session.is_ok());
 
-  let session_id = session.unwrap().id;
+  let session_id = session.unwrap().session.id;
   let found_session = auth::get_user_session_by_id(&session_id);
 
   assert!(found_session.is_ok());
@@ -225,7 +254,7 @@ fn user_count() {
 fn too_long_password() {
   let random_name = Uuid::new_v4().to_string();
   let email = format!("{random_name}@example.com");
-  let long_password = "p".repeat(73); // 73 characters, exceeding the 72 character limit
+  let long_password = "p".repeat(1025); // 1025 bytes, exceeding the 1024 byte limit of Argon2id
 
   let user = user::CreateUser {
     name: random_name.clone(),
@@ -238,3 +267,230 @@ fn too_long_password() {
 
   assert!(created_user.is_err());
 }
+
+#[test]
+fn update_email_requires_current_password() {
+  let created_user = create_test_user();
+  let new_email = format!("{}@example.com", Uuid::new_v4());
+
+  let update = |email: &str, current_password: Option<&str>| {
+    user::update_user(
+      &created_user.id,
+      user::UpdateUser {
+        name: "new name".to_string(),
+        email: email.to_string(),
+        current_password: current_password.map(|password| password.to_string()),
+      },
+      None,
+    )
+  };
+
+  assert_eq!(
+    update(&new_email, None).unwrap_err(),
+    APIError::CurrentPasswordRequired
+  );
+  assert_eq!(
+    update(&new_email, Some("wrong password")).unwrap_err(),
+    APIError::IncorrectCurrentPassword
+  );
+  assert_eq!(
+    user::get_user(&created_user.id).unwrap().email,
+    created_user.email
+  );
+
+  // the name can be changed without the password
+  assert!(update(&created_user.email, None).unwrap());
+
+  assert!(update(&new_email, Some("password")).unwrap());
+  assert_eq!(user::get_user(&created_user.id).unwrap().email, new_email);
+}
+
+#[test]
+fn update_password_requires_current_password() {
+  let created_user = create_test_user();
+  let session = log_in(&created_user.email, "password").unwrap();
+
+  let update = |current_password: Option<&str>| {
+    user::update_password(
+      &created_user.id,
+      user::UpdatePassword {
+        password: "new password".to_string(),
+        current_password: current_password.map(|password| password.to_string()),
+      },
+      Some(&session.session.id),
+    )
+  };
+
+  assert_eq!(update(None).unwrap_err(), APIError::CurrentPasswordRequired);
+  assert_eq!(
+    update(Some("wrong password")).unwrap_err(),
+    APIError::IncorrectCurrentPassword
+  );
+  assert!(log_in(&created_user.email, "password").is_ok());
+  assert!(log_in(&created_user.email, "new password").is_err());
+}
+
+#[test]
+fn update_password_revokes_other_sessions() {
+  let created_user = create_test_user();
+  let session = log_in(&created_user.email, "password").unwrap();
+  let other_session = log_in(&created_user.email, "password").unwrap();
+  let other_user_session = log_in(&create_test_user().email, "password").unwrap();
+
+  let updated = user::update_password(
+    &created_user.id,
+    user::UpdatePassword {
+      password: "new password".to_string(),
+      current_password: Some("password".to_string()),
+    },
+    Some(&session.session.id),
+  );
+
+  assert!(updated.unwrap());
+  assert!(auth::get_user_session_by_id(&session.session.id).is_ok());
+  assert!(auth::get_user_session_by_id(&other_session.session.id).is_err());
+  assert!(auth::get_user_session_by_id(&other_user_session.session.id).is_ok());
+
+  assert!(log_in(&created_user.email, "password").is_err());
+  assert!(log_in(&created_user.email, "new password").is_ok());
+}
+
+#[test]
+fn delete_user_requires_current_password() {
+  let created_user = create_test_user();
+
+  assert_eq!(
+    user::delete_user_with_password(&created_user.id, user::DeleteUser::default()).unwrap_err(),
+    APIError::CurrentPasswordRequired
+  );
+  assert_eq!(
+    user::delete_user_with_password(
+      &created_user.id,
+      user::DeleteUser {
+        current_password: Some("wrong password".to_string()),
+      },
+    )
+    .unwrap_err(),
+    APIError::IncorrectCurrentPassword
+  );
+  assert!(user::get_user(&created_user.id).is_ok());
+
+  let deleted = user::delete_user_with_password(
+    &created_user.id,
+    user::DeleteUser {
+      current_password: Some("password".to_string()),
+    },
+  );
+
+  assert!(deleted.unwrap());
+  assert!(user::get_user(&created_user.id).is_err());
+}
+
+#[test]
+fn login_errors_are_uniform() {
+  let created_user = create_test_user();
+
+  let unknown_email = log_in(&format!("{}@example.com", Uuid::new_v4()), "password");
+  let wrong_password = log_in(&created_user.email, "wrong password");
+
+  assert_eq!(unknown_email.unwrap_err(), APIError::InvalidCredentials);
+  assert_eq!(wrong_password.unwrap_err(), APIError::InvalidCredentials);
+
+  // unknown emails are verified against a real hash
+  assert!(!auth::verify_password("password", auth::dummy_password_hash()).unwrap());
+}
+
+#[test]
+fn password_policy_is_enforced() {
+  let random_name = Uuid::new_v4().to_string();
+
+  let too_short = user::create_user(user::CreateUser {
+    name: random_name.clone(),
+    email: format!("{random_name}@example.com"),
+    password: "short".to_string(),
+    invite: None,
+  });
+  assert_eq!(too_short.unwrap_err(), APIError::WeakPassword);
+
+  let created_user = create_test_user();
+  let update = |password: &str| {
+    user::update_password(
+      &created_user.id,
+      user::UpdatePassword {
+        password: password.to_string(),
+        current_password: Some("password".to_string()),
+      },
+      None,
+    )
+  };
+
+  assert_eq!(update("short").unwrap_err(), APIError::WeakPassword);
+  assert_eq!(
+    update(&"x".repeat(1025)).unwrap_err(),
+    APIError::WeakPassword
+  );
+  assert!(update("new password").unwrap());
+
+  // changed passwords are hashed with the configured hasher as well
+  let hash = user::get_password_hash(&created_user.id).unwrap();
+  assert!(!auth::needs_rehash(&hash));
+
+  user::delete_user(&created_user.id).unwrap();
+}
+
+#[test]
+fn bcrypt_hash_upgraded_on_login() {
+  let created_user = create_test_user();
+
+  let old_hash = bcrypt::hash("password", 4).unwrap();
+  user::set_password_hash(&created_user.id, &old_hash).unwrap();
+  assert!(auth::needs_rehash(&old_hash));
+
+  // a wrong password leaves the hash alone
+  log_in(&created_user.email, "wrong password").unwrap_err();
+  assert_eq!(user::get_password_hash(&created_user.id).unwrap(), old_hash);
+
+  log_in(&created_user.email, "password").unwrap();
+  let new_hash = user::get_password_hash(&created_user.id).unwrap();
+  assert!(new_hash.starts_with("$argon2id$"));
+  assert!(!auth::needs_rehash(&new_hash));
+  assert!(log_in(&created_user.email, "password").is_ok());
+
+  user::delete_user(&created_user.id).unwrap();
+}
+
+#[test]
+fn password_hashers() {
+  let argon2 = auth::Argon2Hasher {
+    memory_kib: 1024,
+    iterations: 1,
+    parallelism: 1,
+  };
+  let bcrypt = auth::BcryptHasher { cost: 4 };
+
+  let argon2_hash = argon2.hash("password").unwrap();
+  let bcrypt_hash = bcrypt.hash("password").unwrap();
+
+  // each hasher only verifies its own hashes
+  assert_eq!(argon2.verify("password", &argon2_hash), Some(true));
+  assert_eq!(argon2.verify("wrong", &argon2_hash), Some(false));
+  assert_eq!(argon2.verify("password", &bcrypt_hash), None);
+  assert_eq!(bcrypt.verify("password", &bcrypt_hash), Some(true));
+  assert_eq!(bcrypt.verify("password", &argon2_hash), None);
+
+  // other algorithms and parameters need a new hash
+  assert!(!argon2.needs_rehash(&argon2_hash));
+  assert!(argon2.needs_rehash(&bcrypt_hash));
+  assert!(auth::Argon2Hasher {
+    memory_kib: 2048,
+    ..argon2
+  }
+  .needs_rehash(&argon2_hash));
+  assert!(!bcrypt.needs_rehash(&bcrypt_hash));
+  assert!(auth::BcryptHasher { cost: 5 }.needs_rehash(&bcrypt_hash));
+  assert!(bcrypt.needs_rehash(&argon2_hash));
+
+  // hashes of both algorithms keep working
+  assert!(auth::verify_password("password", &argon2_hash).unwrap());
+  assert!(auth::verify_password("password", &bcrypt_hash).unwrap());
+}
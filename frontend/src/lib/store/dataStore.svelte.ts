@@ -15,6 +15,7 @@ import type {
 } from '$lib/types/log'
 import { getEntries, type FetchEntriesOptions } from '$lib/utils/api'
 import { API_URL } from '$lib/utils/env'
+import { authHeaders } from '$lib/utils/session'
 import {
   calendarDefaults,
   currentDateObject,
@@ -72,7 +73,7 @@ let fetchedAt: number = $state(0)
 
 const fetchCategories = async () => {
   await fetch(API_URL('/v1/user/categories'), {
-    headers: { Authorization: `Bearer ${userStore?.sessionId}` },
+    headers: { ...authHeaders(userStore?.sessionId) },
   })
     .then(res => {
       if (!res.ok) {
@@ -175,7 +176,7 @@ const createEntry = async (entry: NewEntry): Promise<Entry | null> => {
     method: 'POST',
     headers: {
       'Content-Type': 'application/json',
-      Authorization: `Bearer ${userStore?.sessionId}`,
+      ...authHeaders(userStore?.sessionId),
     },
     body: JSON.stringify(entry),
   })
@@ -205,7 +206,7 @@ const updateEntry = async (entry: EditEntry): Promise<Entry | null> => {
     method: 'PATCH',
     headers: {
       'Content-Type': 'application/json',
-      Authorization: `Bearer ${userStore?.sessionId}`,
+      ...authHeaders(userStore?.sessionId),
     },
     body: JSON.stringify(entry),
   })
@@ -236,7 +237,7 @@ const deleteEntry = async (id: string): Promise<boolean | null> => {
   return fetch(API_URL(`/v1/entry/${id}`), {
     method: 'DELETE',
     headers: {
-      Authorization: `Bearer ${userStore?.sessionId}`,
+      ...authHeaders(userStore?.sessionId),
     },
   })
     .then(res => {
@@ -270,7 +271,7 @@ const createCategory = async (
     method: 'POST',
     headers: {
       'Content-Type': 'application/json',
-      Authorization: `Bearer ${userStore?.sessionId}`,
+      ...authHeaders(userStore?.sessionId),
     },
     body: JSON.stringify(category),
   })
@@ -299,7 +300,7 @@ const updateCategory = async (
     method: 'PATCH',
     headers: {
       'Content-Type': 'application/json',
-      Authorization: `Bearer ${userStore?.sessionId}`,
+      ...authHeaders(userStore?.sessionId),
     },
     body: JSON.stringify({ name: category.name }),
   })
@@ -333,7 +334,7 @@ const deleteCategory = async (id: string): Promise<boolean | null> => {
   return fetch(API_URL(`/v1/category/${id}`), {
     method: 'DELETE',
     headers: {
-      Authorization: `Bearer ${userStore?.sessionId}`,
+      ...authHeaders(userStore?.sessionId),
     },
   })
     .then(res => {
@@ -364,7 +365,7 @@ const createTag = async (tag: NewTag): Promise<Tag | null> => {
     method: 'POST',
     headers: {
       'Content-Type': 'application/json',
-      Authorization: `Bearer ${userStore?.sessionId}`,
+      ...authHeaders(userStore?.sessionId),
     },
     body: JSON.stringify(tag),
   })
@@ -394,7 +395,7 @@ const updateTag = async (tag: EditTag): Promise<Tag | null> => {
     method: 'PATCH',
     headers: {
       'Content-Type': 'application/json',
-      Authorization: `Bearer ${userStore?.sessionId}`,
+      ...authHeaders(userStore?.sessionId),
     },
     body: JSON.stringify({
       name: tag.name,
@@ -448,7 +449,7 @@ const deleteTag = async (id: string): Promise<boolean | null> => {
   return fetch(API_URL(`/v1/tag/${id}`), {
     method: 'DELETE',
     headers: {
-      Authorization: `Bearer ${userStore?.sessionId}`,
+      ...authHeaders(userStore?.sessionId),
     },
   })
     .then(res => {
@@ -2,29 +2,42 @@ import type { EditUserDetails, UserDetails } from '$lib/types/user'
 import { goto } from '$app/navigation'
 import { useDataStore, type DataState } from './dataStore.svelte'
 import { API_URL } from '$lib/utils/env'
+import { authHeaders, setCookieSession } from '$lib/utils/session'
 import { watch } from 'runed'
 
 let dataStore: DataState | null = null
 
 export type UserState = {
+  /** Bearer token of the current session, the CSRF token in cookie mode */
   sessionId: string | null
+  /** Non-secret id of the current session, used to list and revoke it */
+  currentSessionId: string | null
   userDetails: UserDetails | null
   logOut: () => void
-  logIn: (sessionId: string) => void
+  logIn: (token: string | undefined, id: string, csrfToken?: string) => void
   updateUserDetails: (
     details: Partial<UserDetails>,
+    currentPassword?: string,
   ) => Promise<UserDetails | null>
-  deleteAccount: () => Promise<boolean>
+  deleteAccount: (currentPassword: string) => Promise<boolean>
   revokeSession: (sessionId: string) => Promise<boolean>
 }
 
 let sessionId: string | null = $state(null)
+let currentSessionId: string | null = $state(null)
 let userDetails: UserDetails | null = $state(null)
 
 const logOut = async () => {
   if (sessionId) {
-    const revoked = await revokeSession(sessionId)
-    if (revoked) {
+    const loggedOut = await fetch(API_URL('/v1/auth'), {
+      method: 'DELETE',
+      headers: {
+        ...authHeaders(sessionId),
+      },
+    })
+      .then(res => res.ok)
+      .catch(() => false)
+    if (loggedOut) {
       deleleteData()
     } else {
       throw new Error('Failed to log out: could not revoke session')
@@ -33,7 +46,9 @@ const logOut = async () => {
 }
 
 const deleleteData = () => {
+  setCookieSession(false)
   sessionId = null
+  currentSessionId = null
   userDetails = null
   if (dataStore) {
     dataStore.deleteData()
@@ -41,26 +56,37 @@ const deleleteData = () => {
   goto('/')
 }
 
-const logIn = (newSessionId: string) => {
-  sessionId = newSessionId
+// sessions in cookie mode come with a CSRF token instead of the token
+const logIn = (token: string | undefined, id: string, csrfToken?: string) => {
+  setCookieSession(!token && !!csrfToken)
+  sessionId = token ?? csrfToken ?? null
+  currentSessionId = id
   if (dataStore) {
     dataStore.fetchCategories()
   }
   goto('/app')
 }
 
-const updateUserDetails = async (details: Partial<UserDetails>) => {
+const updateUserDetails = async (
+  details: Partial<UserDetails>,
+  currentPassword?: string,
+) => {
   if (userDetails) {
     const updatedDetails = { ...userDetails, ...details }
+    // a new email has to be verified again
+    if (updatedDetails.email !== userDetails.email) {
+      updatedDetails.email_verified = false
+    }
     const body: Partial<EditUserDetails> = {
       name: updatedDetails.name,
       email: updatedDetails.email,
+      current_password: currentPassword,
     }
     const res = await fetch(API_URL('/v1/user'), {
       method: 'PATCH',
       headers: {
         'Content-Type': 'application/json',
-        Authorization: `Bearer ${sessionId}`,
+        ...authHeaders(sessionId),
       },
       body: JSON.stringify(body),
     })
@@ -86,13 +112,15 @@ const updateUserDetails = async (details: Partial<UserDetails>) => {
   return null
 }
 
-const deleteAccount = async (): Promise<boolean> => {
+const deleteAccount = async (currentPassword: string): Promise<boolean> => {
   if (userDetails) {
     const res = await fetch(API_URL('/v1/user'), {
       method: 'DELETE',
       headers: {
-        Authorization: `Bearer ${sessionId}`,
+        'Content-Type': 'application/json',
+        ...authHeaders(sessionId),
       },
+      body: JSON.stringify({ current_password: currentPassword }),
     })
       .then(res => {
         if (!res.ok) {
@@ -108,7 +136,8 @@ const deleteAccount = async (): Promise<boolean> => {
         return false
       })
     if (res) {
-      logOut()
+      // the session was deleted with the account
+      deleleteData()
     }
     return res
   }
@@ -120,7 +149,7 @@ const getUserDetails = async () => {
   if (sessionId && !fetchingUserDetails) {
     fetchingUserDetails = true
     await fetch(API_URL('/v1/user'), {
-      headers: { Authorization: `Bearer ${sessionId}` },
+      headers: { ...authHeaders(sessionId) },
     })
       .then(res => {
         if (!res.ok) {
@@ -149,7 +178,7 @@ const revokeSession = async (revokeSessionId: string): Promise<boolean> => {
     const res = await fetch(API_URL(`/v1/session/${revokeSessionId}`), {
       method: 'DELETE',
       headers: {
-        Authorization: `Bearer ${sessionId}`,
+        ...authHeaders(sessionId),
       },
     })
       .then(res => {
@@ -193,6 +222,9 @@ export const useUserStore: () => UserState = () => {
     set sessionId(value) {
       sessionId = value
     },
+    get currentSessionId() {
+      return currentSessionId
+    },
     get userDetails() {
       return userDetails
     },
@@ -0,0 +1,26 @@
+export type MoodDropInsight = {
+  kind: 'mood_drop'
+  from_date: string
+  to_date: string
+  average_mood: number
+  baseline_mood: number
+  difference: number
+}
+
+export type LowMoodStreakInsight = {
+  kind: 'low_mood_streak'
+  from_date: string
+  to_date: string
+  length: number
+}
+
+export type Insight = MoodDropInsight | LowMoodStreakInsight
+
+export type InsightSettings = {
+  user_id: string
+  mood_drop_threshold: number
+  low_mood_level: number
+  low_mood_days: number
+  notifications_enabled: boolean
+  updated_at: number
+}
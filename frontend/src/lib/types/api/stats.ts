@@ -18,6 +18,9 @@ export type MoodStatsWithCount = MoodStats & {
 
 export type TagMoodStats = {
   tag_id: string
+  name: string
+  color: string
+  category_id: string
   entry_count: number
   average_mood: number
   median_mood: number
@@ -27,9 +30,51 @@ export type TagMoodStatsWithCount = TagMoodStats & {
   mood_entry_count: MoodCount
 }
 
+export type CategoryTagStats<T> = {
+  category_id: string
+  name: string
+  tags: T[]
+}
+
 export type TagStats = TagMoodStats[]
 export type TagStatsWithCount = TagMoodStatsWithCount[]
 
+export type TagStatsByCategory = CategoryTagStats<TagMoodStats>[]
+export type TagStatsWithCountByCategory =
+  CategoryTagStats<TagMoodStatsWithCount>[]
+
+export type TagPairStats = {
+  tag_id: string
+  paired_tag_id: string
+  entry_count: number
+  average_mood: number
+  median_mood: number
+  lift: number
+}
+
+export type TagCooccurrence = {
+  entry_count: number
+  tags: TagMoodStats[]
+  pairs: TagPairStats[]
+}
+
+/**
+ * [date YYYY-MM-DD, mood, tag count]
+ */
+export type CalendarDay = [string, number, number]
+
+export type CalendarStats = {
+  year: number
+  days: CalendarDay[]
+}
+
+export type LaggedTagMoodStats = TagMoodStats & {
+  lag: number
+  average_mood_shift: number
+}
+
+export type LaggedTagStats = LaggedTagMoodStats[]
+
 export type WeekdayStats = {
   monday: MoodStats
   tuesday: MoodStats
@@ -49,3 +94,141 @@ export type WeekdayStatsWithCount = {
   saturday: MoodStatsWithCount
   sunday: MoodStatsWithCount
 }
+
+export type ReviewDay = {
+  date: string
+  mood: number | null
+}
+
+export type ReviewMonth = {
+  month: number
+  entry_count: number
+  average_mood: number
+  median_mood: number
+}
+
+export type TagImpact = {
+  tag_id: string
+  entry_count: number
+  average_mood: number
+  mood_impact: number
+}
+
+export type Streak = {
+  length: number
+  from_date: string
+  to_date: string
+}
+
+export type YearReview = {
+  year: number
+  entry_count: number
+  average_mood: number
+  days: ReviewDay[]
+  best_month: ReviewMonth | null
+  worst_month: ReviewMonth | null
+  top_tags: TagImpact[]
+  longest_streak: Streak | null
+  word_count: {
+    total: number
+    average_per_entry: number
+  }
+  busiest_weekday: keyof WeekdayStats | null
+}
+
+export type TagUsage = {
+  tag_id: string
+  name: string
+  entry_count: number
+}
+
+export type CategoryMoodStats = {
+  category_id: string
+  name: string
+  entry_count: number
+  entry_share: number
+  with_category: MoodStats
+  without_category: MoodStats
+  most_used_tag: TagUsage | null
+  least_used_tag: TagUsage | null
+}
+
+export type CategoryMoodStatsWithCount = Omit<
+  CategoryMoodStats,
+  'with_category' | 'without_category'
+> & {
+  with_category: MoodStatsWithCount
+  without_category: MoodStatsWithCount
+}
+
+export type CategoryStats = CategoryMoodStats[]
+export type CategoryStatsWithCount = CategoryMoodStatsWithCount[]
+
+export type PeriodStats = {
+  entry_count: number
+  average_mood: number | null
+  median_mood: number | null
+  mood_entry_count: MoodCount
+}
+
+export type MoodStatsDelta = PeriodStats
+
+export type TagUsageChange = {
+  tag_id: string
+  name: string
+  first_entry_count: number
+  second_entry_count: number
+  entry_count_change: number
+  first_average_mood: number | null
+  second_average_mood: number | null
+  average_mood_change: number | null
+}
+
+export type PeriodComparison = {
+  first: PeriodStats
+  second: PeriodStats
+  delta: MoodStatsDelta
+  tags: TagUsageChange[]
+}
+
+export type EntryTextStats = {
+  entry_id: string
+  date: string
+  mood: number
+  word_count: number
+  sentiment: number | null
+}
+
+export type MonthTextStats = {
+  year: number
+  month: number
+  entry_count: number
+  word_count: number
+  average_word_count: number
+  average_sentiment: number | null
+}
+
+export type MoodSentiment = {
+  mood: number
+  entry_count: number
+  average_word_count: number
+  average_sentiment: number | null
+}
+
+export type WordFrequency = {
+  word: string
+  count: number
+}
+
+export type TextStats = {
+  entry_count: number
+  word_count: number
+  average_word_count: number
+  length_mood_correlation: number | null
+  average_sentiment: number | null
+  sentiment_mood_correlation: number | null
+  frequent_words: WordFrequency[]
+  moods: MoodSentiment[]
+  months: MonthTextStats[]
+  entries: EntryTextStats[]
+}
@@ -15,4 +15,6 @@ export type AuthModel = {
 
 export type AuthProps = {
   mode?: 'login' | 'register'
+  /** Two-factor challenge of a login that was started elsewhere */
+  challenge?: string
 }
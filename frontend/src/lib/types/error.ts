@@ -1 +1,12 @@
-export type ServerError = 'FETCH_CONFIG' | 'POST'
+export type ServerError =
+  | 'FETCH_CONFIG'
+  | 'POST'
+  | 'RATE_LIMITED'
+  | 'INVALID_CODE'
+  | 'CHALLENGE_EXPIRED'
+  | 'PASSKEY'
+  | 'WEAK_PASSWORD'
+  | 'BREACHED_PASSWORD'
+
+/** Error codes of new passwords rejected by the password policy */
+export type PasswordPolicyError = 'WeakPassword' | 'BreachedPassword'
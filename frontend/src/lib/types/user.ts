@@ -4,11 +4,14 @@ export type UserDetails = {
   name: string
   email: string
   invite?: string
+  email_verified: boolean
 }
 
 export type EditUserDetails = {
   name?: string
   email?: string
+  /** Required when the email is changed */
+  current_password?: string
 }
 
 export type Session = {
@@ -19,3 +22,73 @@ export type Session = {
   ip_address: string
   user_agent: string
 }
+
+export type TwoFactorStatus = {
+  enabled: boolean
+  recovery_codes_remaining: number
+}
+
+export type TotpEnrolment = {
+  secret: string
+  otpauth_uri: string
+  /** QR code of the otpauth URI */
+  qr_svg: string
+}
+
+export type Passkey = {
+  id: string
+  user_id: string
+  name: string
+  created_at: number
+  last_used_at: number | null
+}
+
+export type ApiScope = 'entries:read' | 'entries:write' | 'stats:read' | 'export'
+
+export type ApiToken = {
+  id: string
+  user_id: string
+  name: string
+  scopes: ApiScope[]
+  created_at: number
+  expires_at: number | null
+  last_used_at: number | null
+}
+
+/** A new API token, `token` is only returned once */
+export type NewApiToken = ApiToken & {
+  token: string
+}
+
+/** An OpenID Connect provider from `GET /v1/auth/config` */
+export type OidcProvider = {
+  id: string
+  name: string
+}
+
+export type OidcAuthorization = {
+  authorization_url: string
+}
+
+export type SecurityEventKind =
+  | 'login'
+  | 'new_device_login'
+  | 'password_changed'
+  | 'password_reset'
+  | 'session_revoked'
+  | 'sessions_revoked'
+  | 'api_token_revoked'
+
+export type SecurityEvent = {
+  id: string
+  user_id: string
+  kind: SecurityEventKind
+  created_at: number
+  ip_address: string
+  user_agent: string
+  /** Browser and operating system, like `Firefox on Linux` */
+  device: string
+  /** Country code, only set when the server has a GeoIP database */
+  location: string | null
+  details: string | null
+}
@@ -1,8 +1,40 @@
-import type { MoodStats, TagStats, WeekdayStats } from '$lib/types/api/stats'
+import type { Insight } from '$lib/types/api/insights'
+import type { PasswordPolicyError } from '$lib/types/error'
+import type {
+  CalendarStats,
+  MoodStats,
+  TagStats,
+  WeekdayStats,
+} from '$lib/types/api/stats'
 import type { Entry } from '$lib/types/log'
 import type { Paginated } from '$lib/types/paginated'
-import type { Session } from '$lib/types/user'
+import type {
+  ApiScope,
+  ApiToken,
+  NewApiToken,
+  OidcAuthorization,
+  Passkey,
+  SecurityEvent,
+  Session,
+  TotpEnrolment,
+  TwoFactorStatus,
+} from '$lib/types/user'
 import { API_URL } from './env'
+import { authHeaders } from './session'
+import { createPasskey, getPasskey } from './webauthn'
+
+/**
+ * Error code of a response rejecting a new password for the password policy
+ */
+const passwordPolicyError = async (
+  res: Response,
+): Promise<PasswordPolicyError | undefined> => {
+  const data = await res.json().catch(() => null)
+  if (data?.code === 'WeakPassword' || data?.code === 'BreachedPassword') {
+    return data.code
+  }
+  return undefined
+}
 
 export type FetchEntriesOptions = {
   from_date?: string
@@ -51,7 +83,7 @@ export const getEntries = async (
   url.search = params.toString()
 
   return fetch(url, {
-    headers: { Authorization: `Bearer ${sessionId}` },
+    headers: { ...authHeaders(sessionId) },
   })
     .then(res => {
       if (!res.ok) {
@@ -69,7 +101,7 @@ export const getEntries = async (
 
 export const getSessions = (sessionId: string) => {
   return fetch(API_URL('/v1/sessions'), {
-    headers: { Authorization: `Bearer ${sessionId}` },
+    headers: { ...authHeaders(sessionId) },
   })
     .then(res => {
       if (!res.ok) {
@@ -85,16 +117,63 @@ export const getSessions = (sessionId: string) => {
     })
 }
 
-export const updatePassword = (sessionId: string, newPassword: string) => {
+/** Gets the latest security events of the current user, newest first */
+export const getSecurityEvents = (sessionId: string, limit = 20) => {
+  return fetch(API_URL(`/v1/user/security-events?limit=${limit}`), {
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to fetch security events')
+      }
+      return res.json()
+    })
+    .then((data: Paginated<SecurityEvent>) => {
+      return data
+    })
+    .catch(err => {
+      console.error('Error fetching security events:', err)
+    })
+}
+
+export const deleteOtherSessions = (sessionId: string) => {
+  return fetch(API_URL('/v1/sessions?except=current'), {
+    method: 'DELETE',
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to delete sessions')
+      }
+      return true
+    })
+    .catch(err => {
+      console.error('Error deleting sessions:', err)
+      return false
+    })
+}
+
+export const updatePassword = (
+  sessionId: string,
+  newPassword: string,
+  currentPassword: string,
+) => {
   return fetch(API_URL('/v1/user/password'), {
     method: 'PATCH',
     headers: {
       'Content-Type': 'application/json',
-      Authorization: `Bearer ${sessionId}`,
+      ...authHeaders(sessionId),
     },
-    body: JSON.stringify({ password: newPassword }),
+    body: JSON.stringify({
+      password: newPassword,
+      current_password: currentPassword,
+    }),
   })
-    .then(res => {
+    .then(async res => {
+      const policyError = await passwordPolicyError(res)
+      if (policyError) {
+        return policyError
+      }
       if (!res.ok) {
         throw new Error('Failed to update password')
       }
@@ -109,11 +188,370 @@ export const updatePassword = (sessionId: string, newPassword: string) => {
     })
 }
 
+/** Emails a password reset link, succeeds for unknown emails as well */
+export const requestPasswordReset = (email: string) => {
+  return fetch(API_URL('/v1/auth/password-reset'), {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ email }),
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to request password reset')
+      }
+      return true
+    })
+    .catch(err => {
+      console.error('Error requesting password reset:', err)
+      return false
+    })
+}
+
+export const resetPassword = (token: string, password: string) => {
+  return fetch(API_URL('/v1/auth/password-reset/confirm'), {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ token, password }),
+  })
+    .then(async res => {
+      const policyError = await passwordPolicyError(res)
+      if (policyError) {
+        return policyError
+      }
+      if (!res.ok) {
+        throw new Error('Failed to reset password')
+      }
+      return true
+    })
+    .catch(err => {
+      console.error('Error resetting password:', err)
+      return false
+    })
+}
+
+export const sendVerificationEmail = (sessionId: string) => {
+  return fetch(API_URL('/v1/user/verify-email'), {
+    method: 'POST',
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to send verification email')
+      }
+      return true
+    })
+    .catch(err => {
+      console.error('Error sending verification email:', err)
+      return false
+    })
+}
+
+export const verifyEmail = (token: string) => {
+  return fetch(API_URL('/v1/user/verify-email/confirm'), {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ token }),
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to verify email')
+      }
+      return true
+    })
+    .catch(err => {
+      console.error('Error verifying email:', err)
+      return false
+    })
+}
+
+export const getTwoFactorStatus = (sessionId: string) => {
+  return fetch(API_URL('/v1/user/2fa'), {
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to fetch two-factor status')
+      }
+      return res.json()
+    })
+    .then((data: TwoFactorStatus) => {
+      return data
+    })
+    .catch(err => {
+      console.error('Error fetching two-factor status:', err)
+    })
+}
+
+export const beginTwoFactorEnrolment = (sessionId: string) => {
+  return fetch(API_URL('/v1/user/2fa'), {
+    method: 'POST',
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to start two-factor enrolment')
+      }
+      return res.json()
+    })
+    .then((data: TotpEnrolment) => {
+      return data
+    })
+    .catch(err => {
+      console.error('Error starting two-factor enrolment:', err)
+    })
+}
+
+/** Returns the recovery codes once the code is confirmed */
+export const confirmTwoFactorEnrolment = (sessionId: string, code: string) => {
+  return fetch(API_URL('/v1/user/2fa/verify'), {
+    method: 'POST',
+    headers: {
+      'Content-Type': 'application/json',
+      ...authHeaders(sessionId),
+    },
+    body: JSON.stringify({ code }),
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to confirm two-factor enrolment')
+      }
+      return res.json()
+    })
+    .then((data: { recovery_codes: string[] }) => {
+      return data.recovery_codes
+    })
+    .catch(err => {
+      console.error('Error confirming two-factor enrolment:', err)
+    })
+}
+
+export const disableTwoFactor = (
+  sessionId: string,
+  currentPassword: string,
+) => {
+  return fetch(API_URL('/v1/user/2fa'), {
+    method: 'DELETE',
+    headers: {
+      'Content-Type': 'application/json',
+      ...authHeaders(sessionId),
+    },
+    body: JSON.stringify({ current_password: currentPassword }),
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to disable two-factor authentication')
+      }
+      return true
+    })
+    .catch(err => {
+      console.error('Error disabling two-factor authentication:', err)
+      return false
+    })
+}
+
+export const getPasskeys = (sessionId: string) => {
+  return fetch(API_URL('/v1/passkeys'), {
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to fetch passkeys')
+      }
+      return res.json()
+    })
+    .then((data: Passkey[]) => {
+      return data
+    })
+    .catch(err => {
+      console.error('Error fetching passkeys:', err)
+    })
+}
+
+/** Runs the registration ceremony, returns the new passkey */
+export const registerPasskey = async (sessionId: string, name: string) => {
+  try {
+    const options = await fetch(API_URL('/v1/passkeys/register/begin'), {
+      method: 'POST',
+      headers: { ...authHeaders(sessionId) },
+    }).then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to start passkey registration')
+      }
+      return res.json()
+    })
+
+    const credential = await createPasskey(options)
+
+    const res = await fetch(API_URL('/v1/passkeys/register/finish'), {
+      method: 'POST',
+      headers: {
+        'Content-Type': 'application/json',
+        ...authHeaders(sessionId),
+      },
+      body: JSON.stringify({ ...credential, name }),
+    })
+    if (!res.ok) {
+      throw new Error('Failed to register passkey')
+    }
+    return (await res.json()) as Passkey
+  } catch (err) {
+    console.error('Error registering passkey:', err)
+  }
+}
+
+export const deletePasskey = (sessionId: string, id: string) => {
+  return fetch(API_URL(`/v1/passkey/${encodeURIComponent(id)}`), {
+    method: 'DELETE',
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to delete passkey')
+      }
+      return true
+    })
+    .catch(err => {
+      console.error('Error deleting passkey:', err)
+      return false
+    })
+}
+
+export const getApiTokens = (sessionId: string) => {
+  return fetch(API_URL('/v1/tokens'), {
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to fetch API tokens')
+      }
+      return res.json()
+    })
+    .then((data: ApiToken[]) => {
+      return data
+    })
+    .catch(err => {
+      console.error('Error fetching API tokens:', err)
+    })
+}
+
+/** Creates a personal API token, `expiresAt` in unix milliseconds */
+export const createApiToken = (
+  sessionId: string,
+  name: string,
+  scopes: ApiScope[],
+  expiresAt: number | null,
+  currentPassword: string,
+) => {
+  return fetch(API_URL('/v1/tokens'), {
+    method: 'POST',
+    headers: {
+      'Content-Type': 'application/json',
+      ...authHeaders(sessionId),
+    },
+    body: JSON.stringify({
+      name,
+      scopes,
+      expires_at: expiresAt,
+      current_password: currentPassword,
+    }),
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to create API token')
+      }
+      return res.json()
+    })
+    .then((data: NewApiToken) => {
+      return data
+    })
+    .catch(err => {
+      console.error('Error creating API token:', err)
+    })
+}
+
+export const deleteApiToken = (sessionId: string, id: string) => {
+  return fetch(API_URL(`/v1/token/${encodeURIComponent(id)}`), {
+    method: 'DELETE',
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to delete API token')
+      }
+      return true
+    })
+    .catch(err => {
+      console.error('Error deleting API token:', err)
+      return false
+    })
+}
+
+/**
+ * Runs the login ceremony, returns the response of
+ * `POST /v1/auth/passkey/finish`, a session or a two-factor challenge
+ */
+export const logInWithPasskey = async () => {
+  const options = await fetch(API_URL('/v1/auth/passkey/begin'), {
+    method: 'POST',
+  }).then(res => {
+    if (!res.ok) {
+      throw new Error('Failed to start passkey login')
+    }
+    return res.json()
+  })
+
+  const assertion = await getPasskey(options)
+
+  const res = await fetch(API_URL('/v1/auth/passkey/finish'), {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify(assertion),
+  })
+  if (!res.ok) {
+    throw new Error('Failed to log in with passkey')
+  }
+  return await res.json()
+}
+
+/** Starts an OpenID Connect login, returns the provider URL to go to */
+export const beginOidcLogin = async (provider: string) => {
+  const res = await fetch(
+    API_URL(`/v1/auth/oidc/${encodeURIComponent(provider)}/begin`),
+    { method: 'POST' },
+  )
+  if (!res.ok) {
+    throw new Error('Failed to start login')
+  }
+  return ((await res.json()) as OidcAuthorization).authorization_url
+}
+
+/**
+ * Finishes an OpenID Connect login with the parameters the provider
+ * redirected back with, returns a session or a two-factor challenge.
+ * Errors carry the server's error code
+ */
+export const finishOidcLogin = async (
+  state: string,
+  code: string,
+  invite?: string,
+) => {
+  const res = await fetch(API_URL('/v1/auth/oidc/finish'), {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ state, code, invite }),
+  })
+  if (!res.ok) {
+    const data = await res.json().catch(() => null)
+    throw new Error(data?.code ?? 'Failed to log in')
+  }
+  return await res.json()
+}
+
 export const getMoodStats = async (sessionId: string) => {
   const url = new URL(API_URL('/v1/stats/mood'))
 
   return fetch(url, {
-    headers: { Authorization: `Bearer ${sessionId}` },
+    headers: { ...authHeaders(sessionId) },
   })
     .then(res => {
       if (!res.ok) {
@@ -133,7 +571,7 @@ export const getTagStats = async (sessionId: string) => {
   const url = new URL(API_URL('/v1/stats/tags'))
 
   return fetch(url, {
-    headers: { Authorization: `Bearer ${sessionId}` },
+    headers: { ...authHeaders(sessionId) },
   })
     .then(res => {
       if (!res.ok) {
@@ -149,11 +587,44 @@ export const getTagStats = async (sessionId: string) => {
     })
 }
 
+export const getCalendarStats = async (
+  sessionId: string,
+  year: number,
+  tags?: string[],
+) => {
+  const params = new URLSearchParams()
+  params.append('year', `${year}`)
+  if (tags) {
+    const tagString = tags.join(',')
+    if (tagString) {
+      params.append('tags', tagString)
+    }
+  }
+  const url = new URL(API_URL('/v1/stats/calendar'))
+  url.search = params.toString()
+
+  return fetch(url, {
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to fetch calendar stats')
+      }
+      return res.json()
+    })
+    .then((data: CalendarStats) => {
+      return data
+    })
+    .catch(err => {
+      console.error('Error fetching calendar stats:', err)
+    })
+}
+
 export const getWeekdayStats = async (sessionId: string) => {
   const url = new URL(API_URL('/v1/stats/weekday'))
 
   return fetch(url, {
-    headers: { Authorization: `Bearer ${sessionId}` },
+    headers: { ...authHeaders(sessionId) },
   })
     .then(res => {
       if (!res.ok) {
@@ -168,3 +639,23 @@ export const getWeekdayStats = async (sessionId: string) => {
       console.error('Error fetching weekday stats:', err)
     })
 }
+
+export const getInsights = async (sessionId: string) => {
+  const url = new URL(API_URL('/v1/insights'))
+
+  return fetch(url, {
+    headers: { ...authHeaders(sessionId) },
+  })
+    .then(res => {
+      if (!res.ok) {
+        throw new Error('Failed to fetch insights')
+      }
+      return res.json()
+    })
+    .then((data: Insight[]) => {
+      return data
+    })
+    .catch(err => {
+      console.error('Error fetching insights:', err)
+    })
+}
@@ -0,0 +1,2 @@
+/** Session storage key of the invite code used for an OpenID Connect sign-up */
+export const OIDC_INVITE_KEY = 'oidc_invite'
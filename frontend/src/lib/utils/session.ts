@@ -0,0 +1,18 @@
+// set when the server keeps the session token in an HttpOnly cookie,
+// the store then holds the session's CSRF token instead of the token
+let cookieSession = false
+
+export const setCookieSession = (enabled: boolean) => {
+  cookieSession = enabled
+}
+
+/**
+ * Headers authenticating a request with the session credential from the store,
+ * the browser sends the cookie itself in cookie mode
+ */
+export const authHeaders = (
+  sessionId: string | null | undefined,
+): Record<string, string> =>
+  cookieSession
+    ? { 'X-CSRF-Token': `${sessionId}` }
+    : { Authorization: `Bearer ${sessionId}` }
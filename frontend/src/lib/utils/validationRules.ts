@@ -1,3 +1,4 @@
+import type { PasswordPolicyError } from '$lib/types/error'
 import type { InputState } from '$lib/types/input'
 
 /**
@@ -36,9 +37,18 @@ export const validateEmail = (
   return errors.length === 0 ? 'touched' : 'invalid'
 }
 
+/**
+ * Message for a new password rejected by the server's password policy
+ */
+export const passwordPolicyMessage = (error: PasswordPolicyError) => {
+  return error === 'BreachedPassword'
+    ? 'This password appeared in a data breach, please choose a different one'
+    : 'This password is too easy to guess, please choose a stronger one'
+}
+
 /**
  * Validation rule for password
- * Should be at least 7 characters, no more than 255 characters
+ * Should be at least 7 characters, no more than 1024 characters
  * Should include at least one uppercase letter, one lowercase letter, one number
  * Has a callback function which will pass any error messages
  */
@@ -50,8 +60,8 @@ export const validatePassword = (
   if (value.length < 7) {
     errors.push('Must be at least 7 characters')
   }
-  if (value.length > 72) {
-    errors.push('Must be less than 72 characters')
+  if (value.length > 1024) {
+    errors.push('Must be less than 1024 characters')
   }
   if (!/[A-Z]/.test(value)) {
     errors.push('Must include at least one uppercase letter')
@@ -0,0 +1,93 @@
+// the API sends and expects binary WebAuthn fields as base64url
+
+export const base64urlToBuffer = (value: string): ArrayBuffer => {
+  const base64 = value.replace(/-/g, '+').replace(/_/g, '/')
+  const padded = base64.padEnd(
+    base64.length + ((4 - (base64.length % 4)) % 4),
+    '=',
+  )
+  const binary = atob(padded)
+  const bytes = new Uint8Array(binary.length)
+  for (let i = 0; i < binary.length; i++) {
+    bytes[i] = binary.charCodeAt(i)
+  }
+  return bytes.buffer
+}
+
+export const bufferToBase64url = (buffer: ArrayBuffer): string => {
+  const bytes = new Uint8Array(buffer)
+  let binary = ''
+  for (const byte of bytes) {
+    binary += String.fromCharCode(byte)
+  }
+  return btoa(binary).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '')
+}
+
+export const passkeysSupported = () =>
+  typeof window !== 'undefined' && window.PublicKeyCredential !== undefined
+
+type CredentialDescriptorJSON = { type: 'public-key'; id: string }
+
+/** Creates a passkey from the options of `POST /v1/passkeys/register/begin` */
+export const createPasskey = async (
+  // eslint-disable-next-line @typescript-eslint/no-explicit-any
+  options: any,
+) => {
+  const credential = (await navigator.credentials.create({
+    publicKey: {
+      ...options,
+      challenge: base64urlToBuffer(options.challenge),
+      user: { ...options.user, id: base64urlToBuffer(options.user.id) },
+      excludeCredentials: options.excludeCredentials.map(
+        (descriptor: CredentialDescriptorJSON) => ({
+          ...descriptor,
+          id: base64urlToBuffer(descriptor.id),
+        }),
+      ),
+    },
+  })) as PublicKeyCredential | null
+
+  if (!credential) {
+    throw new Error('No passkey created')
+  }
+
+  const response = credential.response as AuthenticatorAttestationResponse
+  return {
+    id: credential.id,
+    response: {
+      clientDataJSON: bufferToBase64url(response.clientDataJSON),
+      attestationObject: bufferToBase64url(response.attestationObject),
+    },
+  }
+}
+
+/** Signs the options of `POST /v1/auth/passkey/begin` with a passkey */
+export const getPasskey = async (
+  // eslint-disable-next-line @typescript-eslint/no-explicit-any
+  options: any,
+) => {
+  const credential = (await navigator.credentials.get({
+    publicKey: {
+      ...options,
+      challenge: base64urlToBuffer(options.challenge),
+      allowCredentials: [],
+    },
+  })) as PublicKeyCredential | null
+
+  if (!credential) {
+    throw new Error('No passkey selected')
+  }
+
+  const response = credential.response as AuthenticatorAssertionResponse
+  return {
+    id: credential.id,
+    response: {
+      clientDataJSON: bufferToBase64url(response.clientDataJSON),
+      authenticatorData: bufferToBase64url(response.authenticatorData),
+      signature: bufferToBase64url(response.signature),
+      userHandle: response.userHandle
+        ? bufferToBase64url(response.userHandle)
+        : undefined,
+    },
+  }
+}